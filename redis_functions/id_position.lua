@@ -3,7 +3,15 @@
 --
 -- Return the position of a UUID in the queue.  If the UUID is in the store then the function returns 0 for the
 -- position.  As an optimization, if the UUID is not in the queue or the store, and the create flag is set, then it
--- is added.  This function takes into account  expiry dates, to prevent returning stale queue positions.
+-- is added.  Queue positions come from the rank of the UUID in the queue order sorted set, so they are always current.
+--
+-- If the queue is disabled then new UUIDs are added directly to the store (as if the store was infinite), and UUIDs
+-- already in the queue are handled by the disabled policy:
+-- * drain: the UUID is moved into the store
+-- * keep: the UUID is reported as in the store, but keeps its position in the queue in case the queue is re-enabled
+--
+-- UUIDs with a priority are placed ahead of every UUID with a lower priority, and behind UUIDs with the same priority
+-- that joined before them.  A UUID already in the queue is moved up if it is seen again with a higher priority.
 --
 -- ARGV[1]: prefix - STRING
 -- ARGV[2]: id - STRING
@@ -11,6 +19,8 @@
 -- ARGV[4]: validated_expiry - INTEGER
 -- ARGV[5]: quarantine_expiry - INTEGER
 -- ARGV[6]: create - INTEGER (0 = do not create if not present, 1 = create if not present)
+-- ARGV[7]: disabled_policy - INTEGER (0 = keep, 1 = drain)
+-- ARGV[8]: priority - INTEGER (optional, 0 = no priority, up to 1000)
 --
 -- RETURN: 2-Tuple of {status - INTEGER, position - INTEGER}
 -----------------------------------------------------------------------------------------------------------------------
@@ -30,18 +40,64 @@ if in_set == 1 then
 end
 
 
-local queue_expiry_secs_key = ARGV[1] .. ':queue_expiry_secs'
-local queue_position_cache_key = ARGV[1] .. ':queue_position_cache'
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
+local queue_join_counter_key = ARGV[1] .. ':queue_join_counter'
+local queue_enabled_key = ARGV[1] .. ':queue_enabled'
+local priority = tonumber(ARGV[8]) or 0
+
+-- The queue order score is the join counter, shifted down by the priority, so that higher priorities sort first
+local PRIORITY_SPAN = 1000000000000
+local function priority_score(join_order, id_priority)
+    -- Formatted as an integer, so that large scores are not truncated when converted to a string
+    return string.format('%.0f', join_order - id_priority * PRIORITY_SPAN)
+end
 
+-- Check if the queue is disabled (a missing key is considered disabled, matching the queue settings)
+local queue_disabled = redis.call('GET', queue_enabled_key) ~= '1'
 
 -- Check if uuid_id is in the queue
-local queue_position = redis.call('HGET', queue_position_cache_key, ARGV[2])
-if queue_position then
-    redis.call('HSET', queue_expiry_secs_key, ARGV[2], ARGV[3] + ARGV[4]) -- validated expiry
+local queue_rank = redis.call('ZRANK', queue_order_key, ARGV[2])
+if queue_rank and queue_disabled then
+    if tonumber(ARGV[7]) == 1 then
+        -- Drain: Move to the store
+        redis.call('ZREM', queue_order_key, ARGV[2])
+        redis.call('ZREM', queue_expiry_key, ARGV[2])
+        redis.call('SADD', store_ids_key, ARGV[2])
+        redis.call('HSET', store_expiry_secs_key, ARGV[2], ARGV[3] + ARGV[4]) -- validated expiry
+
+        local result = {}
+        result[1] = 2  -- Added
+        result[2] = 0
+        return result
+    end
+
+    -- Keep: Let through as if in the store, without giving up the position in the queue
+    redis.call('ZADD', queue_expiry_key, ARGV[3] + ARGV[4], ARGV[2]) -- validated expiry
+
+    local result = {}
+    result[1] = 1  -- Present
+    result[2] = 0
+    return result
+end
+
+if queue_rank then
+    redis.call('ZADD', queue_expiry_key, ARGV[3] + ARGV[4], ARGV[2]) -- validated expiry
+
+    -- Move up to a higher priority, keeping the original join order within the priority
+    if priority > 0 then
+        local score = tonumber(redis.call('ZSCORE', queue_order_key, ARGV[2]))
+        local join_order = score % PRIORITY_SPAN
+        local current_priority = (join_order - score) / PRIORITY_SPAN
+        if priority > current_priority then
+            redis.call('ZADD', queue_order_key, priority_score(join_order, priority), ARGV[2])
+            queue_rank = redis.call('ZRANK', queue_order_key, ARGV[2])
+        end
+    end
 
     local result = {}
     result[1] = 1  -- Present
-    result[2] = queue_position
+    result[2] = queue_rank + 1
     return result
 end
 
@@ -54,6 +110,19 @@ end
 
 -- DEV NOTE: All the code below handles adding new tokens to the queue, including quarantine -> validated upgrade
 
+-- Add the ID to the back of its priority in the queue, ordered by a join counter so that IDs that join within the same
+-- second keep their order
+local function queue_add()
+    local join_order = redis.call('INCR', queue_join_counter_key)
+    redis.call('ZADD', queue_order_key, priority_score(join_order, priority), ARGV[2])
+    redis.call('ZADD', queue_expiry_key, ARGV[3] + ARGV[5], ARGV[2]) -- quarantine expiry
+
+    local result = {}
+    result[1] = 2  -- Added
+    result[2] = redis.call('ZRANK', queue_order_key, ARGV[2]) + 1
+    return result
+end
+
 local store_capacity_key = ARGV[1] .. ':store_capacity'
 
 -- Check if the store size is -1 (this means the store size is infinite and we don't need to add the token to the queue)
@@ -65,8 +134,8 @@ else
     store_capacity = tonumber(store_capacity)
 end
 
--- Infinite store, so add to the store
-if store_capacity < 0 then
+-- Infinite store (or disabled queue), so add to the store
+if store_capacity < 0 or queue_disabled then
     redis.call('SADD', store_ids_key, ARGV[2])
     redis.call('HSET', store_expiry_secs_key, ARGV[2], ARGV[3] + ARGV[4]) -- validated expiry
 
@@ -76,19 +145,10 @@ if store_capacity < 0 then
     return result
 end
 
-local queue_ids_key = ARGV[1] .. ':queue_ids'
-
 -- Check if the queue is larger than 0 (if so we should add the token to the queue)
-local queue_size = redis.call('LLEN', queue_ids_key)
-if queue_size ~= nil and queue_size > 0 then
-    local pos = redis.call('RPUSH', queue_ids_key, ARGV[2])
-    redis.call('HSET', queue_position_cache_key, ARGV[2], pos)
-    redis.call('HSET', queue_expiry_secs_key, ARGV[2], ARGV[3] + ARGV[5]) -- quarantine expiry
-
-    local result = {}
-    result[1] = 2  -- Added
-    result[2] = pos
-    return result
+local queue_size = redis.call('ZCARD', queue_order_key)
+if queue_size > 0 then
+    return queue_add()
 end
 
 -- Check the number of store tokens and see if there is room to add the new token into the store (queue was 0 and
@@ -105,12 +165,5 @@ if current_size ~= nil and current_size < store_capacity then
     return result
 else
     -- The store did not have room, add the ID to the queue
-    local pos = redis.call('RPUSH', queue_ids_key, ARGV[2])
-    redis.call('HSET', queue_position_cache_key, ARGV[2], pos)
-    redis.call('HSET', queue_expiry_secs_key, ARGV[2], ARGV[3] + ARGV[5]) -- quarantine expiry
-
-    local result = {}
-    result[1] = 2 -- Added
-    result[2] = pos
-    return result
-end
\ No newline at end of file
+    return queue_add()
+end
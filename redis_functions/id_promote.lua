@@ -9,17 +9,14 @@
 -- ARGV[4]: validated_expiry - INTEGER
 -----------------------------------------------------------------------------------------------------------------------
 
-local queue_ids_key = ARGV[1] .. ':queue_ids'
-local queue_expiry_secs_key = ARGV[1] .. ':queue_expiry_secs'
-local queue_position_cache_key = ARGV[1] .. ':queue_position_cache'
-local store_capacity_key = ARGV[1] .. ':store_capacity'
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
 local store_ids_key = ARGV[1] .. ':store_ids'
 local store_expiry_secs_key = ARGV[1] .. ':store_expiry_secs'
 
 -- Remove the ID from the queue (if it exists)
-redis.call('LREM', queue_ids_key, 0, ARGV[2])
-redis.call('HDEL', queue_position_cache_key, ARGV[2])
-redis.call('HDEL', queue_expiry_secs_key, ARGV[2])
+redis.call('ZREM', queue_order_key, ARGV[2])
+redis.call('ZREM', queue_expiry_key, ARGV[2])
 
 -- Add the ID to the store
 redis.call('SADD', store_ids_key, ARGV[2])
@@ -3,24 +3,20 @@
 --
 -- Remove a UUID from the queue/store
 --
--- OPTIMIZATION: Removing an ID from the queue is too expensive in a single operation (See: store_promote), so queue
---               "removal" only marks the queue ID as immediately expired.
---
 -- ARGV[1]: prefix - STRING
 -- ARGV[2]: id - STRING
 -- ARGV[3]: time - INTEGER
 -----------------------------------------------------------------------------------------------------------------------
 
-local queue_expiry_secs_key = ARGV[1] .. ':queue_expiry_secs'
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
 local store_ids_key = ARGV[1] .. ':store_ids'
 local store_expiry_secs_key = ARGV[1] .. ':store_expiry_secs'
 
-local in_queue = redis.call('HEXISTS', queue_expiry_secs_key, ARGV[2])
-if in_queue ~= nil and in_queue == 1 then
-    -- In Queue: Mark as expired (1 second earlier than what is considered the current time)
-    redis.call('HSET', queue_expiry_secs_key, ARGV[2], ARGV[3] - 1)
-else
-    -- In Store: Remove from store
-    redis.call('HDEL', store_expiry_secs_key, ARGV[2])
-    redis.call('SREM', store_ids_key, ARGV[2])
-end
+-- In Queue: Remove from queue
+redis.call('ZREM', queue_order_key, ARGV[2])
+redis.call('ZREM', queue_expiry_key, ARGV[2])
+
+-- In Store: Remove from store
+redis.call('HDEL', store_expiry_secs_key, ARGV[2])
+redis.call('SREM', store_ids_key, ARGV[2])
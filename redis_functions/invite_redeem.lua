@@ -0,0 +1,46 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- INVITE REDEEM
+--
+-- Redeem a signed invite for an ID, promoting the ID into the store regardless of capacity.  Each invite can be
+-- redeemed by a limited number of IDs, and an ID that has already redeemed an invite can redeem it again without
+-- using up another redemption.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: invite - STRING
+-- ARGV[3]: id - STRING
+-- ARGV[4]: uses - INTEGER
+-- ARGV[5]: expires - INTEGER (TIME when the invite expires)
+-- ARGV[6]: time - INTEGER
+-- ARGV[7]: validated_expiry - INTEGER
+--
+-- Returns: Number of IDs that have redeemed the invite, or -1 if all redemptions have been used
+-----------------------------------------------------------------------------------------------------------------------
+
+local invite_redemptions_key = ARGV[1] .. ':invite_redemptions:' .. ARGV[2]
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
+local store_ids_key = ARGV[1] .. ':store_ids'
+local store_expiry_secs_key = ARGV[1] .. ':store_expiry_secs'
+
+local uses = tonumber(ARGV[4])
+
+-- Check that the invite has redemptions left, unless this ID has already redeemed it
+if redis.call('SISMEMBER', invite_redemptions_key, ARGV[3]) == 0 then
+    if redis.call('SCARD', invite_redemptions_key) >= uses then
+        return -1
+    end
+
+    redis.call('SADD', invite_redemptions_key, ARGV[3])
+    -- Redemptions are only needed until the invite itself expires
+    redis.call('EXPIREAT', invite_redemptions_key, ARGV[5])
+end
+
+-- Remove the ID from the queue (if it exists)
+redis.call('ZREM', queue_order_key, ARGV[3])
+redis.call('ZREM', queue_expiry_key, ARGV[3])
+
+-- Add the ID to the store
+redis.call('SADD', store_ids_key, ARGV[3])
+redis.call('HSET', store_expiry_secs_key, ARGV[3], ARGV[6] + ARGV[7])
+
+return redis.call('SCARD', invite_redemptions_key)
@@ -0,0 +1,41 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- QUEUE MIGRATE
+--
+-- Migrate a queue from the original LIST based keys (:queue_ids, :queue_expiry_secs, :queue_position_cache) to the
+-- sorted set keys (:queue_order, :queue_expiry), keeping the order and expiry of every ID.  IDs that are already in
+-- the sorted sets are left as they are, so the migration is safe to run more than once.
+--
+-- ARGV[1]: prefix - STRING
+--
+-- RETURN: number of IDs migrated - INTEGER
+-----------------------------------------------------------------------------------------------------------------------
+
+local legacy_queue_ids_key = ARGV[1] .. ':queue_ids'
+local legacy_queue_expiry_secs_key = ARGV[1] .. ':queue_expiry_secs'
+local legacy_queue_position_cache_key = ARGV[1] .. ':queue_position_cache'
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
+local queue_join_counter_key = ARGV[1] .. ':queue_join_counter'
+
+if redis.call('EXISTS', legacy_queue_ids_key) == 0 then
+    return 0
+end
+
+local migrated = 0
+local legacy_ids = redis.call('LRANGE', legacy_queue_ids_key, 0, -1)
+for index, uuid_id in ipairs(legacy_ids) do
+    if not redis.call('ZSCORE', queue_order_key, uuid_id) then
+        local join_order = redis.call('INCR', queue_join_counter_key)
+        redis.call('ZADD', queue_order_key, join_order, uuid_id)
+
+        -- IDs without an expiry are expired immediately, and removed on the next rotation
+        local expiry = redis.call('HGET', legacy_queue_expiry_secs_key, uuid_id)
+        redis.call('ZADD', queue_expiry_key, tonumber(expiry) or 0, uuid_id)
+
+        migrated = migrated + 1
+    end
+end
+
+redis.call('DEL', legacy_queue_ids_key, legacy_queue_expiry_secs_key, legacy_queue_position_cache_key)
+
+return migrated
@@ -0,0 +1,46 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- SCHEDULE APPLY
+--
+-- Apply one step of a schedule to the queue settings.  The last step applied for each schedule is tracked, so when
+-- several servers rotate the same queue only the first to reach a step applies it, and a step is never applied twice
+-- (leaving manual changes made after the step alone).  Removed schedules are never applied.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: schedule - STRING
+-- ARGV[3]: step - STRING (unique to the settings of the step)
+-- ARGV[4]: queue_enabled - INTEGER (-1: unchanged, 0: disabled, 1: enabled)
+-- ARGV[5]: store_capacity - INTEGER (< -1: unchanged, -1: unlimited, >= 0: sized)
+-- ARGV[6]: time - INTEGER
+--
+-- Returns 1 if the step was applied, otherwise 0
+-----------------------------------------------------------------------------------------------------------------------
+
+local schedules_key = ARGV[1] .. ':schedules'
+local schedules_applied_key = ARGV[1] .. ':schedules_applied'
+local queue_enabled_key = ARGV[1] .. ':queue_enabled'
+local store_capacity_key = ARGV[1] .. ':store_capacity'
+local queue_sync_timestamp_key = ARGV[1] .. ':queue_sync_timestamp'
+
+local schedule = ARGV[2]
+local step = ARGV[3]
+local queue_enabled = tonumber(ARGV[4])
+local store_capacity = tonumber(ARGV[5])
+local time = tonumber(ARGV[6])
+
+if redis.call('HEXISTS', schedules_key, schedule) == 0 then
+    return 0
+end
+if redis.call('HGET', schedules_applied_key, schedule) == step then
+    return 0
+end
+
+if queue_enabled >= 0 then
+    redis.call('SET', queue_enabled_key, queue_enabled)
+end
+if store_capacity >= -1 then
+    redis.call('SET', store_capacity_key, store_capacity)
+end
+redis.call('SET', queue_sync_timestamp_key, time)
+redis.call('HSET', schedules_applied_key, schedule, step)
+
+return 1
@@ -0,0 +1,36 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- STICKY CLAIM
+--
+-- Stick an ID to an upstream server, shared by every server using the same prefix.  An ID that is already stuck to an
+-- upstream keeps it (another server may have claimed it first), otherwise the ID is only stuck to the upstream while
+-- it has fewer sticky sessions than its maximum.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: id - STRING
+-- ARGV[3]: upstream - STRING (URI of the upstream server)
+-- ARGV[4]: max_sticky_sessions - INTEGER
+-- ARGV[5]: time - INTEGER
+--
+-- Returns: URI of the upstream the ID is stuck to, or nil if the upstream is full
+-----------------------------------------------------------------------------------------------------------------------
+
+local sticky_upstreams_key = ARGV[1] .. ':sticky_upstreams'
+local sticky_seen_key = ARGV[1] .. ':sticky_seen'
+local sticky_counts_key = ARGV[1] .. ':sticky_counts'
+
+local existing = redis.call('HGET', sticky_upstreams_key, ARGV[2])
+if existing then
+    redis.call('ZADD', sticky_seen_key, ARGV[5], ARGV[2])
+    return existing
+end
+
+local count = tonumber(redis.call('HGET', sticky_counts_key, ARGV[3])) or 0
+if count >= tonumber(ARGV[4]) then
+    return false
+end
+
+redis.call('HSET', sticky_upstreams_key, ARGV[2], ARGV[3])
+redis.call('ZADD', sticky_seen_key, ARGV[5], ARGV[2])
+redis.call('HINCRBY', sticky_counts_key, ARGV[3], 1)
+
+return ARGV[3]
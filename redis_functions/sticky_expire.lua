@@ -0,0 +1,31 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- STICKY EXPIRE
+--
+-- Remove all sticky sessions that have not been seen within the expiry.  Expired IDs are only returned once, even
+-- when several servers expire sticky sessions at the same time.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: time - INTEGER
+-- ARGV[3]: expiry - INTEGER (seconds)
+--
+-- Returns: IDs of the expired sticky sessions
+-----------------------------------------------------------------------------------------------------------------------
+
+local sticky_upstreams_key = ARGV[1] .. ':sticky_upstreams'
+local sticky_seen_key = ARGV[1] .. ':sticky_seen'
+local sticky_counts_key = ARGV[1] .. ':sticky_counts'
+
+local expired = redis.call('ZRANGEBYSCORE', sticky_seen_key, '-inf', '(' .. (ARGV[2] - ARGV[3]))
+
+for _, id in ipairs(expired) do
+    local upstream = redis.call('HGET', sticky_upstreams_key, id)
+    if upstream then
+        redis.call('HDEL', sticky_upstreams_key, id)
+        if redis.call('HINCRBY', sticky_counts_key, upstream, -1) <= 0 then
+            redis.call('HDEL', sticky_counts_key, upstream)
+        end
+    end
+    redis.call('ZREM', sticky_seen_key, id)
+end
+
+return expired
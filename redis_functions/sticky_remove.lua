@@ -0,0 +1,27 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- STICKY REMOVE
+--
+-- Remove the sticky session of an ID, freeing up a sticky session on its upstream server.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: id - STRING
+--
+-- Returns: 1 if the ID had a sticky session, otherwise 0
+-----------------------------------------------------------------------------------------------------------------------
+
+local sticky_upstreams_key = ARGV[1] .. ':sticky_upstreams'
+local sticky_seen_key = ARGV[1] .. ':sticky_seen'
+local sticky_counts_key = ARGV[1] .. ':sticky_counts'
+
+local upstream = redis.call('HGET', sticky_upstreams_key, ARGV[2])
+if not upstream then
+    return 0
+end
+
+redis.call('HDEL', sticky_upstreams_key, ARGV[2])
+redis.call('ZREM', sticky_seen_key, ARGV[2])
+if redis.call('HINCRBY', sticky_counts_key, upstream, -1) <= 0 then
+    redis.call('HDEL', sticky_counts_key, upstream)
+end
+
+return 1
@@ -0,0 +1,23 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- STICKY TOUCH
+--
+-- Find the upstream server an ID is stuck to, and mark the sticky session as seen so that it doesn't expire.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: id - STRING
+-- ARGV[3]: time - INTEGER
+--
+-- Returns: URI of the upstream the ID is stuck to, or nil if the ID has no sticky session
+-----------------------------------------------------------------------------------------------------------------------
+
+local sticky_upstreams_key = ARGV[1] .. ':sticky_upstreams'
+local sticky_seen_key = ARGV[1] .. ':sticky_seen'
+
+local upstream = redis.call('HGET', sticky_upstreams_key, ARGV[2])
+if not upstream then
+    return false
+end
+
+redis.call('ZADD', sticky_seen_key, ARGV[3], ARGV[2])
+
+return upstream
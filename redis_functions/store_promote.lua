@@ -1,17 +1,19 @@
 -----------------------------------------------------------------------------------------------------------------------
 -- STORE PROMOTE
 --
--- Promote any Queue IDs into the Store if the Store has some available capacity
+-- Promote any Queue IDs into the Store if the Store has some available capacity.  If the Queue is disabled and the
+-- disabled policy is drain, then all Queue IDs are promoted regardless of capacity.
 --
 -- ARGV[1]: prefix - STRING
+-- ARGV[2]: disabled_policy - INTEGER (0 = keep, 1 = drain)
 -----------------------------------------------------------------------------------------------------------------------
 
-local queue_ids_key = ARGV[1] .. ':queue_ids'
-local queue_expiry_secs_key = ARGV[1] .. ':queue_expiry_secs'
-local queue_position_cache_key = ARGV[1] .. ':queue_position_cache'
+local queue_order_key = ARGV[1] .. ':queue_order'
+local queue_expiry_key = ARGV[1] .. ':queue_expiry'
 local store_capacity_key = ARGV[1] .. ':store_capacity'
 local store_ids_key = ARGV[1] .. ':store_ids'
 local store_expiry_secs_key = ARGV[1] .. ':store_expiry_secs'
+local queue_enabled_key = ARGV[1] .. ':queue_enabled'
 
 -- Determine store capacity
 local store_capacity = redis.call('GET', store_capacity_key)
@@ -26,37 +28,31 @@ local store_size = redis.call('SCARD', store_ids_key);
 
 -- Determine number of IDs to transfer from Queue to Store
 local transfer_size = 0;
-if store_size < store_capacity then
+if redis.call('GET', queue_enabled_key) ~= '1' and tonumber(ARGV[2]) == 1 then
+    transfer_size = redis.call('ZCARD', queue_order_key);
+elseif store_size < store_capacity then
     if store_capacity > 0 then
         transfer_size = store_capacity - store_size
     else
-        transfer_size = redis.call('LLEN', queue_ids_key);
+        transfer_size = redis.call('ZCARD', queue_order_key);
     end
 end
 
--- Pop all IDs from Queue for transfer
-local transfer_ids = {}
-local transfer_count = 0
-for i=0, transfer_size - 1 do
-    local uuid_id = redis.call('LPOP', queue_ids_key)
-    if uuid_id then
-        redis.call('HDEL', queue_position_cache_key, uuid_id)
-        table.insert(transfer_ids, uuid_id)
-        transfer_count = transfer_count + 1
-    end
+if transfer_size <= 0 then
+    return 0
 end
 
--- Add popped IDs into the store, transferring expiry from queue to store
+-- Pop IDs from the front of the Queue, and add them into the store, transferring expiry from queue to store
+local popped = redis.call('ZPOPMIN', queue_order_key, transfer_size)
 local moved = 0
-if transfer_count > 0 then
-    redis.call('SADD', store_ids_key, unpack(transfer_ids))
-    for index, uuid_id in pairs(transfer_ids) do
-        local expiry = redis.call('HGET', queue_expiry_secs_key, uuid_id)
-        if expiry ~= nil then
-            redis.call('HSET', store_expiry_secs_key, uuid_id, expiry)
-        end
-        redis.call('HDEL', queue_expiry_secs_key, uuid_id)
-        moved = moved + 1
+for i = 1, #popped, 2 do
+    local uuid_id = popped[i]
+    local expiry = redis.call('ZSCORE', queue_expiry_key, uuid_id)
+    redis.call('SADD', store_ids_key, uuid_id)
+    if expiry then
+        redis.call('HSET', store_expiry_secs_key, uuid_id, expiry)
     end
+    redis.call('ZREM', queue_expiry_key, uuid_id)
+    moved = moved + 1
 end
-return moved
\ No newline at end of file
+return moved
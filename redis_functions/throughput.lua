@@ -0,0 +1,45 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- THROUGHPUT
+--
+-- Sum the IDs promoted into the store and expired from the store over a sliding window, along with how many seconds
+-- of that window have been recorded (less than the window if rotation has not been running for that long).
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: time - INTEGER
+-- ARGV[3]: window_secs - INTEGER
+--
+-- RETURN: {promoted, store_expired, observed_secs} - {INTEGER, INTEGER, INTEGER}
+-----------------------------------------------------------------------------------------------------------------------
+
+local throughput_promoted_key = ARGV[1] .. ':throughput_promoted'
+local throughput_store_expired_key = ARGV[1] .. ':throughput_store_expired'
+
+local time = tonumber(ARGV[2])
+local window = tonumber(ARGV[3])
+local oldest_bucket = math.floor((time - window) / 60)
+
+local first_bucket = nil
+local function sum_window(key)
+    local total = 0
+    local values = redis.call('HGETALL', key)
+    for i = 1, #values, 2 do
+        local bucket = tonumber(values[i])
+        if bucket >= oldest_bucket then
+            total = total + tonumber(values[i + 1])
+            if first_bucket == nil or bucket < first_bucket then
+                first_bucket = bucket
+            end
+        end
+    end
+    return total
+end
+
+local promoted = sum_window(throughput_promoted_key)
+local store_expired = sum_window(throughput_store_expired_key)
+
+local observed = 0
+if first_bucket ~= nil then
+    observed = math.max(0, math.min(window, time - first_bucket * 60))
+end
+
+return { promoted, store_expired, observed }
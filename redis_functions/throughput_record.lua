@@ -0,0 +1,32 @@
+-----------------------------------------------------------------------------------------------------------------------
+-- THROUGHPUT RECORD
+--
+-- Record the result of a queue rotation into per-minute buckets, so that the rate IDs move through the store can be
+-- measured over a sliding window.  Buckets are written even when nothing moved, so quiet minutes count towards the
+-- window.  Buckets older than the window are removed.
+--
+-- ARGV[1]: prefix - STRING
+-- ARGV[2]: time - INTEGER
+-- ARGV[3]: promoted - INTEGER
+-- ARGV[4]: store_expired - INTEGER
+-- ARGV[5]: window_secs - INTEGER
+-----------------------------------------------------------------------------------------------------------------------
+
+local throughput_promoted_key = ARGV[1] .. ':throughput_promoted'
+local throughput_store_expired_key = ARGV[1] .. ':throughput_store_expired'
+
+local time = tonumber(ARGV[2])
+local bucket = math.floor(time / 60)
+local oldest_bucket = math.floor((time - tonumber(ARGV[5])) / 60)
+
+redis.call('HINCRBY', throughput_promoted_key, bucket, tonumber(ARGV[3]))
+redis.call('HINCRBY', throughput_store_expired_key, bucket, tonumber(ARGV[4]))
+
+-- Remove buckets that have fallen out of the window
+for _, key in ipairs({ throughput_promoted_key, throughput_store_expired_key }) do
+    for _, field in ipairs(redis.call('HKEYS', key)) do
+        if tonumber(field) < oldest_bucket then
+            redis.call('HDEL', key, field)
+        end
+    end
+end
@@ -1,15 +1,21 @@
 use axum_server::{Handle, tls_rustls::RustlsConfig};
-use reqwest::Client;
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    io::{self, IsTerminal},
+    net::SocketAddr,
+    sync::Arc,
+};
 use tokio::{join, sync::Notify};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
+use crate::auth::{ApiToken, Authenticators};
 use crate::background::run as background_run;
+use crate::clients::HttpClients;
 use crate::config::Config;
 use crate::database::{create_redis_client, create_redis_pool};
-use crate::queue::{QueueControl, QueueEvents};
+use crate::queue::{QueueControl, QueueEvents, StickySessions};
 use crate::servers::{redirect_http_to_https, secure_server};
 use crate::signals::shutdown_signal;
+use crate::sites::{Site, Sites};
 use crate::state::AppState;
 use crate::upstream::UpstreamPool;
 use crate::{control, omnis};
@@ -41,10 +47,12 @@ pub async fn run(
 
     // Create queue control and initialize functions
     let queue = match QueueControl::new(
-        redis_pool,
+        redis_pool.clone(),
         config.quarantine_expiry,
         config.validated_expiry,
         config.publish_throttle,
+        config.queue_disabled_policy,
+        config.throughput_window,
     ) {
         Ok(q) => q,
         Err(e) => {
@@ -53,24 +61,33 @@ pub async fn run(
         }
     };
 
-    // Initialize queue functions
-    if let Err(e) = queue
-        .init(
-            &config.queue_prefix,
-            config.queue_enabled,
-            config.store_capacity,
-            &config.locales,
+    // Initialize every site, each with its own queue, queue subscriber and upstream pool
+    let mut sites = Vec::new();
+    for site_config in config.all_sites() {
+        // Initialize queue functions
+        if let Err(e) = queue
+            .init(
+                &site_config.queue_prefix,
+                site_config.queue_enabled,
+                site_config.store_capacity,
+                &config.locales,
+            )
+            .await
+        {
+            error!(
+                "Failed to initialize queue for {}: {:?}",
+                site_config.name, e
+            );
+            return;
+        };
+
+        // Create queue subscriber, for emitted events
+        let queue_subscriber = match QueueEvents::from_client(
+            redis_client.clone(),
+            &site_config.queue_prefix,
+            stream_notify.clone(),
         )
         .await
-    {
-        error!("Failed to initialize queue: {:?}", e);
-        return;
-    };
-
-    // Create queue subscriber, for emitted events
-    let queue_subscriber =
-        match QueueEvents::from_client(redis_client, &config.queue_prefix, stream_notify.clone())
-            .await
         {
             Ok(s) => s,
             Err(error) => {
@@ -79,16 +96,54 @@ pub async fn run(
             }
         };
 
-    // Create a new http client pool
-    let http_client = Client::builder()
-        .connect_timeout(config.connect_timeout)
-        .redirect(reqwest::redirect::Policy::none())
-        .referer(false)
-        .build()
-        .expect("Failed to build HTTP client");
+        let mut upstream_pool =
+            UpstreamPool::new(config.sticky_session_timeout, config.circuit_breaker())
+                .with_load_balancing(config.load_balancing())
+                .with_slow_start(config.slow_start);
+        if config.shared_sticky_sessions {
+            let shared_sticky =
+                match StickySessions::new(redis_pool.clone(), &site_config.queue_prefix) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("Failed to initialize shared sticky sessions: {:?}", e);
+                        return;
+                    }
+                };
+            upstream_pool = upstream_pool.with_shared_sticky_sessions(shared_sticky);
+        }
+
+        // Upstreams are stored in Redis, and only seeded from the config on the first start
+        if let Err(e) = queue
+            .verify_upstreams(&site_config.queue_prefix, &site_config.initial_upstream)
+            .await
+        {
+            error!(
+                "Failed to initialize upstreams for {}: {:?}",
+                site_config.name, e
+            );
+            return;
+        }
+
+        info!(
+            "Site {} using Redis prefix {}",
+            site_config.name, site_config.queue_prefix
+        );
+        let site = Site::new(site_config, upstream_pool, queue_subscriber);
+        if let Err(e) = site.sync_upstreams(&queue).await {
+            error!("Failed to load upstreams for {}: {:?}", site.name, e);
+            return;
+        }
+        sites.push(site);
+    }
 
-    let upstream_pool = UpstreamPool::new(config.sticky_session_timeout);
-    upstream_pool.add_upstreams(&config.initial_upstream).await;
+    // Create the http client pools for the upstream servers
+    let http_clients = match HttpClients::new(config.connect_timeout, config.upstream_tls.clone()) {
+        Ok(clients) => clients,
+        Err(e) => {
+            error!("Failed to build HTTP client: {:?}", e);
+            return;
+        }
+    };
 
     let public_tls_pair = config.public_tls_pair.clone();
     let public_tls = RustlsConfig::from_pem(public_tls_pair.0, public_tls_pair.1)
@@ -100,14 +155,30 @@ pub async fn run(
         .await
         .expect("Failed to read monitor TLS certificate and key");
 
+    // Build control server authentication.  Without any configured credentials the control
+    // server would be unreachable, so generate a one-off token for this run instead.  The token
+    // is only shown on an interactive terminal, never in the logs
+    let authenticators = if config.api_tokens.is_empty() && config.control_users.is_empty() {
+        if !io::stderr().is_terminal() {
+            error!("No control credentials configured, set api_tokens or control_users");
+            return;
+        }
+        let generated = ApiToken::generate("generated");
+        warn!("No control credentials configured, generated an API token for this run");
+        eprintln!("Generated control API token: {}", generated.token);
+        Authenticators::from_config(&[generated], &[])
+    } else {
+        Authenticators::from_config(&config.api_tokens, &config.control_users)
+    };
+
     // Create our app state
     let state = AppState::new(
         config,
         stream_notify.clone(),
         queue,
-        queue_subscriber,
-        upstream_pool,
-        http_client,
+        Sites::new(sites),
+        http_clients,
+        authenticators,
     );
 
     // Create apps
@@ -0,0 +1,412 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http::{HeaderMap, header::AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display, Formatter};
+use uuid::Uuid;
+
+/// Level of access granted to an identity on the control API.  Each role includes everything
+/// granted to the roles before it.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// View status, upstreams and event streams (dashboards)
+    #[default]
+    Read,
+    /// Adjust queue settings and individual queue entries (on-call staff)
+    Operator,
+    /// Change upstreams, waiting pages and secrets
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Static bearer token that grants access to the control API
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl ApiToken {
+    pub fn new(name: impl Into<String>, token: impl Into<String>, role: Role) -> Self {
+        Self {
+            name: name.into(),
+            token: token.into(),
+            role,
+        }
+    }
+
+    /// Generate a random admin token, for use when no credentials have been configured
+    pub fn generate(name: impl Into<String>) -> Self {
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        Self::new(name, token, Role::Admin)
+    }
+}
+
+// Never leak the token into logs
+impl Debug for ApiToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiToken")
+            .field("name", &self.name)
+            .field("token", &"<redacted>")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+/// User that can sign in to the control API with a username and password
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+// Never leak the password into logs
+impl Debug for ControlUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ControlUser")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+/// How an identity proved who they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Bearer,
+    Basic,
+}
+
+/// Authenticated caller of the control API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub method: AuthMethod,
+    pub role: Role,
+}
+
+impl Identity {
+    pub fn new(name: impl Into<String>, method: AuthMethod, role: Role) -> Self {
+        Self {
+            name: name.into(),
+            method,
+            role,
+        }
+    }
+
+    /// Check if this identity is allowed to use routes that require `role`
+    pub fn has_role(&self, role: Role) -> bool {
+        self.role >= role
+    }
+}
+
+/// Credentials presented by a caller, before they have been verified
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Bearer(String),
+    Basic(String, String),
+}
+
+impl Credentials {
+    /// Parse credentials from the `Authorization` header, if any are present
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(AUTHORIZATION)?.to_str().ok()?.trim();
+        let (scheme, payload) = value.split_once(' ')?;
+        let payload = payload.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return Some(Self::Bearer(String::from(payload)));
+        }
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = STANDARD.decode(payload).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            return Some(Self::Basic(String::from(username), String::from(password)));
+        }
+
+        None
+    }
+}
+
+/// Source of identities for the control API.  Each implementation handles one kind of
+/// credential and ignores the rest.
+pub trait Authenticator: Send + Sync {
+    /// Verify credentials, returning the matching identity
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity>;
+
+    /// Look up a previously authenticated identity by name, along with its current secret (used to
+    /// re-validate sessions)
+    fn lookup(&self, method: AuthMethod, name: &str) -> Option<(Identity, &str)>;
+}
+
+/// Static bearer tokens from the configuration
+pub struct TokenAuthenticator {
+    tokens: Vec<ApiToken>,
+}
+
+impl TokenAuthenticator {
+    pub fn new(tokens: &[ApiToken]) -> Self {
+        Self {
+            tokens: tokens.to_vec(),
+        }
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        let Credentials::Bearer(token) = credentials else {
+            return None;
+        };
+
+        self.tokens
+            .iter()
+            .find(|t| constant_time_eq(t.token.as_bytes(), token.as_bytes()))
+            .map(|t| Identity::new(&t.name, AuthMethod::Bearer, t.role))
+    }
+
+    fn lookup(&self, method: AuthMethod, name: &str) -> Option<(Identity, &str)> {
+        if method != AuthMethod::Bearer {
+            return None;
+        }
+
+        self.tokens.iter().find(|t| t.name == name).map(|t| {
+            (
+                Identity::new(&t.name, AuthMethod::Bearer, t.role),
+                t.token.as_str(),
+            )
+        })
+    }
+}
+
+/// HTTP Basic users from the configuration
+pub struct BasicAuthenticator {
+    users: Vec<ControlUser>,
+}
+
+impl BasicAuthenticator {
+    pub fn new(users: &[ControlUser]) -> Self {
+        Self {
+            users: users.to_vec(),
+        }
+    }
+}
+
+impl Authenticator for BasicAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        let Credentials::Basic(username, password) = credentials else {
+            return None;
+        };
+
+        self.users
+            .iter()
+            .find(|u| {
+                u.username == *username
+                    && constant_time_eq(u.password.as_bytes(), password.as_bytes())
+            })
+            .map(|u| Identity::new(&u.username, AuthMethod::Basic, u.role))
+    }
+
+    fn lookup(&self, method: AuthMethod, name: &str) -> Option<(Identity, &str)> {
+        if method != AuthMethod::Basic {
+            return None;
+        }
+
+        self.users.iter().find(|u| u.username == name).map(|u| {
+            (
+                Identity::new(&u.username, AuthMethod::Basic, u.role),
+                u.password.as_str(),
+            )
+        })
+    }
+}
+
+/// Ordered chain of authenticators, where the first match wins
+pub struct Authenticators {
+    authenticators: Vec<Box<dyn Authenticator>>,
+}
+
+impl Authenticators {
+    pub fn new() -> Self {
+        Self {
+            authenticators: Vec::new(),
+        }
+    }
+
+    /// Build the standard chain of authenticators from configured tokens and users
+    pub fn from_config(tokens: &[ApiToken], users: &[ControlUser]) -> Self {
+        Self::new()
+            .with(TokenAuthenticator::new(tokens))
+            .with(BasicAuthenticator::new(users))
+    }
+
+    /// Append an authenticator to the end of the chain
+    pub fn with(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticators.push(Box::new(authenticator));
+        self
+    }
+
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        self.authenticators
+            .iter()
+            .find_map(|a| a.authenticate(credentials))
+    }
+
+    pub fn lookup(&self, method: AuthMethod, name: &str) -> Option<(Identity, &str)> {
+        self.authenticators
+            .iter()
+            .find_map(|a| a.lookup(method, name))
+    }
+}
+
+impl Default for Authenticators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compare two byte strings without exiting early, so that comparison time does not leak how
+/// much of a secret matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::HeaderValue;
+
+    fn authenticators() -> Authenticators {
+        Authenticators::from_config(
+            &[ApiToken::new("grafana", "secret-token", Role::Read)],
+            &[ControlUser {
+                username: String::from("admin"),
+                password: String::from("hunter2"),
+                role: Role::Admin,
+            }],
+        )
+    }
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(authorization).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_credentials_bearer() {
+        let credentials = Credentials::from_headers(&headers("Bearer abc"));
+        assert!(credentials == Some(Credentials::Bearer(String::from("abc"))));
+    }
+
+    #[test]
+    fn test_credentials_basic() {
+        let encoded = STANDARD.encode("admin:hunter2");
+        let credentials = Credentials::from_headers(&headers(&format!("Basic {}", encoded)));
+        assert!(
+            credentials
+                == Some(Credentials::Basic(
+                    String::from("admin"),
+                    String::from("hunter2")
+                ))
+        );
+    }
+
+    #[test]
+    fn test_credentials_missing() {
+        assert!(Credentials::from_headers(&HeaderMap::new()).is_none());
+        assert!(Credentials::from_headers(&headers("Digest abc")).is_none());
+    }
+
+    #[test]
+    fn test_authenticate_token() {
+        let identity = authenticators()
+            .authenticate(&Credentials::Bearer(String::from("secret-token")))
+            .expect("token should authenticate");
+        assert_eq!(
+            identity,
+            Identity::new("grafana", AuthMethod::Bearer, Role::Read)
+        );
+
+        let identity = authenticators().authenticate(&Credentials::Bearer(String::from("nope")));
+        assert_eq!(identity, None);
+    }
+
+    #[test]
+    fn test_authenticate_basic() {
+        let credentials = Credentials::Basic(String::from("admin"), String::from("hunter2"));
+        let identity = authenticators()
+            .authenticate(&credentials)
+            .expect("user should authenticate");
+        assert_eq!(
+            identity,
+            Identity::new("admin", AuthMethod::Basic, Role::Admin)
+        );
+
+        let credentials = Credentials::Basic(String::from("admin"), String::from("hunter3"));
+        assert_eq!(authenticators().authenticate(&credentials), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let auth = authenticators();
+        assert!(auth.lookup(AuthMethod::Basic, "admin").is_some());
+        assert!(auth.lookup(AuthMethod::Bearer, "admin").is_none());
+        assert!(auth.lookup(AuthMethod::Bearer, "grafana").is_some());
+    }
+
+    #[test]
+    fn test_has_role() {
+        let operator = Identity::new("oncall", AuthMethod::Basic, Role::Operator);
+        assert!(operator.has_role(Role::Read));
+        assert!(operator.has_role(Role::Operator));
+        assert!(!operator.has_role(Role::Admin));
+    }
+
+    #[test]
+    fn test_role_defaults_to_read() {
+        let token: ApiToken = toml::from_str("name = \"a\"\ntoken = \"b\"").unwrap();
+        assert_eq!(token.role, Role::Read);
+
+        let user: ControlUser = toml::from_str("username = \"a\"\npassword = \"b\"").unwrap();
+        assert_eq!(user.role, Role::Read);
+
+        let user: ControlUser =
+            toml::from_str("username = \"a\"\npassword = \"b\"\nrole = \"admin\"").unwrap();
+        assert_eq!(user.role, Role::Admin);
+    }
+
+    #[test]
+    fn test_generated_token_is_random() {
+        assert_ne!(ApiToken::generate("a").token, ApiToken::generate("a").token);
+    }
+}
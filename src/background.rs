@@ -1,12 +1,29 @@
+use futures_util::future::join_all;
 use std::sync::Arc;
-use tokio::{join, select, sync::Notify, time::sleep};
+use tokio::{
+    join, select,
+    sync::{Notify, broadcast::error::RecvError},
+    time::sleep,
+};
 use tracing::{error, info};
 
 use crate::constants::BACKGROUND_SLEEP_TIME;
+use crate::queue::QueueEvent;
+use crate::sites::Site;
 use crate::state::AppState;
+use crate::upstream::Upstream;
 
 pub async fn run(state: AppState, shutdown_notifier: Arc<Notify>) {
     info!("Starting background tasks");
+    join!(
+        run_background_tasks(state.clone(), shutdown_notifier.clone()),
+        run_health_checks(state.clone(), shutdown_notifier.clone()),
+        run_upstream_sync(state.clone(), shutdown_notifier.clone())
+    );
+    info!("Shutdown background tasks");
+}
+
+async fn run_background_tasks(state: AppState, shutdown_notifier: Arc<Notify>) {
     loop {
         background_tasks(state.clone()).await;
 
@@ -16,7 +33,54 @@ pub async fn run(state: AppState, shutdown_notifier: Arc<Notify>) {
             _ = sleep(BACKGROUND_SLEEP_TIME) => {}
         }
     }
-    info!("Shutdown background tasks");
+}
+
+/// Health checks run on their own interval, so a slow upstream doesn't hold up the queue
+async fn run_health_checks(state: AppState, shutdown_notifier: Arc<Notify>) {
+    if !state.config.health_check_enabled {
+        return;
+    }
+    loop {
+        health_checks(state.clone()).await;
+
+        select! {
+            _ = shutdown_notifier.notified() => break,
+            _ = sleep(state.config.health_check_interval) => {}
+        }
+    }
+}
+
+/// Sync the upstream pools as soon as any server changes the stored upstreams
+async fn run_upstream_sync(state: AppState, shutdown_notifier: Arc<Notify>) {
+    let syncs = state
+        .sites
+        .iter()
+        .map(|site| sync_upstreams_on_change(&state, site, shutdown_notifier.clone()));
+    join_all(syncs).await;
+}
+
+async fn sync_upstreams_on_change(state: &AppState, site: &Site, shutdown_notifier: Arc<Notify>) {
+    let mut receiver = site.events.clone().receiver();
+    loop {
+        select! {
+            _ = shutdown_notifier.notified() => break,
+            event = receiver.recv() => match event {
+                // Missed events may have included an upstream change
+                Ok(QueueEvent::UpstreamsChanged) | Err(RecvError::Lagged(_)) => {
+                    sync_upstreams(state, site).await
+                }
+                Ok(_) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Sync the upstream pool of a site with the upstreams stored in Redis
+async fn sync_upstreams(state: &AppState, site: &Site) {
+    if let Err(e) = site.sync_upstreams(&state.queue).await {
+        error!("Failed to sync upstreams ({}): {:?}", site.name, e);
+    }
 }
 
 /// Tasks that run periodically in the background
@@ -26,9 +90,81 @@ async fn background_tasks(state: AppState) {
 
 /// Web
 async fn web_tasks(state: AppState) {
-    let ids = state.upstream_pool.expire_sticky_sessions().await;
-    if !ids.is_empty() {
-        info!("Expired {} sticky sessions", ids.len());
+    for site in state.sites.iter() {
+        // Catch up with any upstream changes published while the subscriber was reconnecting
+        sync_upstreams(&state, site).await;
+
+        let ids = site.upstream_pool.expire_sticky_sessions().await;
+        if !ids.is_empty() {
+            info!("Expired {} sticky sessions ({})", ids.len(), site.name);
+        }
+
+        for uri in site.upstream_pool.remove_drained().await {
+            info!("Upstream {} drained and removed ({})", uri, site.name);
+        }
+
+        if let Err(e) = site.check_failover(&state.queue).await {
+            error!(
+                "Failed to publish upstream failover ({}): {:?}",
+                site.name, e
+            );
+        }
+    }
+}
+
+/// Probe every upstream server of every site at once, and record the results in their pools
+async fn health_checks(state: AppState) {
+    let config = &state.config;
+    let rise = config.health_check_rise.max(1);
+    let fall = config.health_check_fall.max(1);
+
+    for site in state.sites.iter() {
+        let upstreams = site.upstream_pool.upstreams().await;
+        let checks = upstreams
+            .iter()
+            .map(|upstream| health_check(&state, upstream));
+        let results = join_all(checks).await;
+
+        for (upstream, result) in upstreams.iter().zip(results) {
+            site.upstream_pool
+                .record_health(&upstream.uri, result, rise, fall)
+                .await;
+        }
+
+        if let Err(e) = site.check_failover(&state.queue).await {
+            error!(
+                "Failed to publish upstream failover ({}): {:?}",
+                site.name, e
+            );
+        }
+    }
+}
+
+/// Single health check against an upstream server.  Any response other than a server error
+/// shows the server is up and handling requests
+async fn health_check(state: &AppState, upstream: &Upstream) -> Result<(), String> {
+    let config = &state.config;
+    let upstream_client = state
+        .http_clients
+        .upstream(&upstream.uri, &upstream.tls)
+        .map_err(|e| format!("{:?}", e))?;
+    let url = format!(
+        "{}/{}",
+        upstream_client.uri.trim_end_matches('/'),
+        config.health_check_path.trim_start_matches('/')
+    );
+
+    let response = upstream_client
+        .client
+        .get(url)
+        .timeout(config.health_check_timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match response.status().is_server_error() {
+        true => Err(format!("status {}", response.status())),
+        false => Ok(()),
     }
 }
 
@@ -37,26 +173,38 @@ async fn queue_tasks(state: AppState) {
     // Flush all emit buffer entries
     state.queue.flush_event_throttle_buffer(None).await;
 
-    // Verify waiting page
-    let queue_prefix = state.config.queue_prefix.clone();
-    for locale in state.config.locales.iter() {
-        state.queue.verify_waiting_page(&queue_prefix, locale).await;
-    }
+    for site in state.sites.iter() {
+        // Verify waiting page
+        let queue_prefix = site.queue_prefix.clone();
+        for locale in state.config.locales.iter() {
+            state.queue.verify_waiting_page(&queue_prefix, locale).await;
+        }
+
+        if state.config.queue_rotation_enabled {
+            // Scheduled settings, applied before rotating so the rotation uses them
+            match state.queue.apply_schedules(&queue_prefix, None).await {
+                Ok(applied) => {
+                    if applied > 0 {
+                        info!("Applied {} scheduled settings ({})", applied, site.name)
+                    }
+                }
+                Err(e) => error!("Failed to apply schedules ({}): {:?}", site.name, e),
+            }
 
-    if state.config.queue_rotation_enabled {
-        // Queue rotation
-        let result = state.queue.rotate_full(&queue_prefix, None).await;
+            // Queue rotation
+            let result = state.queue.rotate_full(&queue_prefix, None).await;
 
-        match result {
-            Ok(rotate) => {
-                if rotate.has_changes() {
-                    info!(
-                        "Queue rotation -- queue expired: {}  store expired: {}  promoted: {}",
-                        rotate.queue_expired, rotate.store_expired, rotate.promoted
-                    )
+            match result {
+                Ok(rotate) => {
+                    if rotate.has_changes() {
+                        info!(
+                            "Queue rotation ({}) -- queue expired: {}  store expired: {}  promoted: {}",
+                            site.name, rotate.queue_expired, rotate.store_expired, rotate.promoted
+                        )
+                    }
                 }
+                Err(e) => error!("Failed to rotate queue ({}): {:?}", site.name, e),
             }
-            Err(e) => error!("Failed to rotate queue: {:?}", e),
         }
     }
 }
@@ -2,11 +2,12 @@ use clap::{ArgAction, Args, Parser, Subcommand};
 use std::collections::HashSet;
 use std::time::Duration;
 
+use crate::auth::{ApiToken, Role};
 use crate::config::{Config, build_tls_pair};
 use crate::errors::{Error, Result};
-use crate::queue::StoreCapacity;
+use crate::queue::{QueueDisabledPolicy, StoreCapacity};
 use crate::secrets::decode_master_key;
-use crate::upstream::Upstream;
+use crate::upstream::{LoadBalancing, Upstream, UpstreamTls};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -74,7 +75,8 @@ pub struct RunArgs {
     )]
     pub redis_uri: String,
 
-    /// Initial upstream servers, comma-delimited
+    /// Initial upstream servers, comma-delimited.  Only stored in Redis the first time any server
+    /// starts with the queue prefix
     #[arg(
         long,
         conflicts_with = "config_file",
@@ -102,6 +104,64 @@ pub struct RunArgs {
     )]
     pub upstream_sessions: usize,
 
+    /// Weights of the initial upstream servers, comma-delimited in the same order as the upstream
+    /// servers.  Upstreams without a weight have a weight of 1
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_UPSTREAM_WEIGHTS"
+    )]
+    pub upstream_weights: Vec<usize>,
+
+    /// Groups of the initial upstream servers, comma-delimited in the same order as the upstream
+    /// servers.  Upstreams without a group (or with an empty group) aren't in a group
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_UPSTREAM_GROUPS"
+    )]
+    pub upstream_groups: Vec<String>,
+
+    /// Priorities of the initial upstream servers, comma-delimited in the same order as the
+    /// upstream servers.  Upstreams with a higher priority (backups) only get traffic when every
+    /// upstream with a lower priority is unhealthy, removed or full.  Upstreams without a
+    /// priority have a priority of 0
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_UPSTREAM_PRIORITIES"
+    )]
+    pub upstream_priorities: Vec<usize>,
+
+    /// Canary labels of the initial upstream servers, comma-delimited in the same order as the
+    /// upstream servers.  Upstreams without a label (or with an empty label) aren't canaries
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_UPSTREAM_CANARIES"
+    )]
+    pub upstream_canaries: Vec<String>,
+
+    /// Canary weights of the initial upstream servers, comma-delimited in the same order as the
+    /// upstream servers.  Each is the percentage (0 to 100) of new sticky sessions and regular
+    /// requests sent to the upstreams with the same canary label
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_UPSTREAM_CANARY_WEIGHTS"
+    )]
+    pub upstream_canary_weights: Vec<usize>,
+
     /// TLS Private Key to use for the publicly accessible server
     #[arg(
         long,
@@ -182,6 +242,72 @@ pub struct RunArgs {
     )]
     pub monitor_tls_certificate_path: Option<String>,
 
+    /// Path to a PEM bundle of CA certificates to trust for HTTPS upstream servers, on top of the
+    /// built-in roots
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        env = "OMNIS_BOUNCER_UPSTREAM_TLS_CA_PATH"
+    )]
+    pub upstream_tls_ca_path: Option<String>,
+
+    /// Path to the TLS client certificate to present to HTTPS upstream servers (mutual TLS)
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        requires = "upstream_tls_key_path",
+        env = "OMNIS_BOUNCER_UPSTREAM_TLS_CERTIFICATE_PATH"
+    )]
+    pub upstream_tls_certificate_path: Option<String>,
+
+    /// Path to the private key of the TLS client certificate for HTTPS upstream servers
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        requires = "upstream_tls_certificate_path",
+        env = "OMNIS_BOUNCER_UPSTREAM_TLS_KEY_PATH"
+    )]
+    pub upstream_tls_key_path: Option<String>,
+
+    /// Hostname to send in SNI and check the certificates of HTTPS upstream servers against,
+    /// rather than the host of each upstream URI
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        env = "OMNIS_BOUNCER_UPSTREAM_TLS_SERVER_NAME"
+    )]
+    pub upstream_tls_server_name: Option<String>,
+
+    /// Accept any certificate from HTTPS upstream servers.  Only for testing, as this allows the
+    /// connections to be intercepted
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        action = ArgAction::Set,
+        default_value = "false",
+        env = "OMNIS_BOUNCER_UPSTREAM_TLS_INSECURE_SKIP_VERIFY"
+    )]
+    pub upstream_tls_insecure_skip_verify: bool,
+
+    /// Bearer tokens that grant admin access to the monitor and control server, comma-delimited
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        num_args = 0..,
+        value_delimiter = ',',
+        env = "OMNIS_BOUNCER_API_TOKENS"
+    )]
+    pub api_tokens: Vec<String>,
+
+    /// Expiration (in seconds) for a login session on the monitor and control server
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "28800",
+        env = "OMNIS_BOUNCER_CONTROL_SESSION_EXPIRATION_SECS"
+    )]
+    pub control_session_expiration: u64,
+
     /// Name to use for the cookie that stores the queue unique identifier
     #[arg(
         long,
@@ -209,6 +335,25 @@ pub struct RunArgs {
     )]
     pub queue_size_cookie_name: String,
 
+    /// Name to use for the cookie that stores the estimated wait (in seconds) for the queue
+    /// position
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "omnis-bouncer-queue-eta",
+        env = "OMNIS_BOUNCER_COOKIE_ETA_NAME"
+    )]
+    pub eta_cookie_name: String,
+
+    /// Name to use for the cookie that stores a signed priority tier token
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "omnis-bouncer-priority",
+        env = "OMNIS_BOUNCER_COOKIE_PRIORITY_NAME"
+    )]
+    pub priority_cookie_name: String,
+
     /// Name to use for the header that indicates if the ID for the request should be evicted
     /// from the store
     #[arg(
@@ -246,6 +391,43 @@ pub struct RunArgs {
     )]
     pub queue_size_http_header: String,
 
+    /// Name to use for the header that stores the estimated wait (in seconds) for the queue
+    /// position
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "x-omnis-bouncer-queue-eta",
+        env = "OMNIS_BOUNCER_HTTP_HEADER_ETA_NAME"
+    )]
+    pub eta_http_header: String,
+
+    /// Name to use for the header that carries a signed priority tier token
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "x-omnis-bouncer-priority",
+        env = "OMNIS_BOUNCER_HTTP_HEADER_PRIORITY_NAME"
+    )]
+    pub priority_http_header: String,
+
+    /// Name to use for the query parameter that carries a signed priority tier token
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "omnis-bouncer-priority",
+        env = "OMNIS_BOUNCER_QUERY_PARAM_PRIORITY_NAME"
+    )]
+    pub priority_query_param: String,
+
+    /// Name to use for the query parameter that carries a signed invite
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "omnis-bouncer-invite",
+        env = "OMNIS_BOUNCER_QUERY_PARAM_INVITE_NAME"
+    )]
+    pub invite_query_param: String,
+
     /// Timeout (in seconds) when acquiring a connection from the pool
     #[arg(
         long,
@@ -264,6 +446,125 @@ pub struct RunArgs {
     )]
     pub connect_timeout: u64,
 
+    /// Timeout (in seconds) for an upstream server to finish responding to static asset
+    /// requests loading the cache, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "30",
+        env = "OMNIS_BOUNCER_RESPONSE_TIMEOUT_CACHE_LOAD_SECS"
+    )]
+    pub response_timeout_cache_load: u64,
+
+    /// Timeout (in seconds) for an upstream server to finish responding to Javascript
+    /// Client requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "60",
+        env = "OMNIS_BOUNCER_RESPONSE_TIMEOUT_JS_CLIENT_SECS"
+    )]
+    pub response_timeout_js_client: u64,
+
+    /// Timeout (in seconds) for an upstream server to finish responding to Javascript
+    /// Client push long-polls, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "300",
+        env = "OMNIS_BOUNCER_RESPONSE_TIMEOUT_PUSH_SECS"
+    )]
+    pub response_timeout_push: u64,
+
+    /// Timeout (in seconds) for an upstream server to finish responding to REST API
+    /// requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "60",
+        env = "OMNIS_BOUNCER_RESPONSE_TIMEOUT_REST_API_SECS"
+    )]
+    pub response_timeout_rest_api: u64,
+
+    /// Timeout (in seconds) for an upstream server to finish responding to Ultra-Thin
+    /// requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "120",
+        env = "OMNIS_BOUNCER_RESPONSE_TIMEOUT_ULTRA_THIN_SECS"
+    )]
+    pub response_timeout_ultra_thin: u64,
+
+    /// Timeout (in seconds) between parts of an upstream response body for static asset
+    /// requests loading the cache, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "10",
+        env = "OMNIS_BOUNCER_IDLE_TIMEOUT_CACHE_LOAD_SECS"
+    )]
+    pub idle_timeout_cache_load: u64,
+
+    /// Timeout (in seconds) between parts of an upstream response body for Javascript
+    /// Client requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "30",
+        env = "OMNIS_BOUNCER_IDLE_TIMEOUT_JS_CLIENT_SECS"
+    )]
+    pub idle_timeout_js_client: u64,
+
+    /// Timeout (in seconds) between parts of an upstream response body for Javascript
+    /// Client push long-polls, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "300",
+        env = "OMNIS_BOUNCER_IDLE_TIMEOUT_PUSH_SECS"
+    )]
+    pub idle_timeout_push: u64,
+
+    /// Timeout (in seconds) between parts of an upstream response body for REST API
+    /// requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "30",
+        env = "OMNIS_BOUNCER_IDLE_TIMEOUT_REST_API_SECS"
+    )]
+    pub idle_timeout_rest_api: u64,
+
+    /// Timeout (in seconds) between parts of an upstream response body for Ultra-Thin
+    /// requests, 0 for none
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "60",
+        env = "OMNIS_BOUNCER_IDLE_TIMEOUT_ULTRA_THIN_SECS"
+    )]
+    pub idle_timeout_ultra_thin: u64,
+
+    /// Number of times to retry a request on another upstream server, after failing to connect.
+    /// Only GET and HEAD requests, and requests with a replayable body, are retried
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "2",
+        env = "OMNIS_BOUNCER_RETRY_ATTEMPTS"
+    )]
+    pub retry_attempts: usize,
+
+    /// Time (in seconds) from the first attempt of a request that it can still be retried in
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "10",
+        env = "OMNIS_BOUNCER_RETRY_BUDGET_SECS"
+    )]
+    pub retry_budget: u64,
+
     /// Expiration (in seconds) for the cookie that stores the queue identifier
     #[arg(
         long,
@@ -282,6 +583,17 @@ pub struct RunArgs {
     )]
     pub sticky_session_timeout: u64,
 
+    /// Keep sticky sessions in Redis, so that every server sharing the queue sends the same
+    /// Javascript Client to the same upstream server
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        action = ArgAction::Set,
+        default_value = "false",
+        env = "OMNIS_BOUNCER_SHARED_STICKY_SESSIONS"
+    )]
+    pub shared_sticky_sessions: bool,
+
     /// Timeout (in seconds) for caching assets from upstream servers
     #[arg(
         long,
@@ -375,6 +687,16 @@ pub struct RunArgs {
     )]
     pub queue_rotation_enabled: bool,
 
+    /// Handling of IDs already waiting in the queue while the queue is disabled: "drain" moves
+    /// them into the store, "keep" lets them through but keeps their position in the queue
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "drain",
+        env = "OMNIS_BOUNCER_QUEUE_DISABLED_POLICY"
+    )]
+    pub queue_disabled_policy: String,
+
     /// Set the store capacity to this value if starting up and no values are stored in Redis
     #[arg(
         long,
@@ -423,6 +745,145 @@ pub struct RunArgs {
     )]
     pub publish_throttle: u64,
 
+    /// Sliding window (in seconds) of queue rotations used to measure store throughput, when
+    /// estimating the wait for a position in the queue
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "900",
+        env = "OMNIS_BOUNCER_THROUGHPUT_WINDOW_SECS"
+    )]
+    pub throughput_window: u64,
+
+    /// Actively check the health of upstream servers, taking unhealthy servers out of rotation
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        action = ArgAction::Set,
+        default_value = "true",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_ENABLED"
+    )]
+    pub health_check_enabled: bool,
+
+    /// Path requested from each upstream server by the health checks.  Any response other than
+    /// a server error (5xx) is a successful check
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "/",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_PATH"
+    )]
+    pub health_check_path: String,
+
+    /// Interval (in seconds) between health checks of each upstream server, at least 1
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "10",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_INTERVAL_SECS"
+    )]
+    pub health_check_interval: u64,
+
+    /// Timeout (in seconds) for a single health check
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "5",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_TIMEOUT_SECS"
+    )]
+    pub health_check_timeout: u64,
+
+    /// Number of successful health checks in a row before an unhealthy upstream server is put
+    /// back into rotation
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "2",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_RISE"
+    )]
+    pub health_check_rise: usize,
+
+    /// Number of failed health checks in a row before an upstream server is taken out of
+    /// rotation
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "3",
+        env = "OMNIS_BOUNCER_HEALTH_CHECK_FALL"
+    )]
+    pub health_check_fall: usize,
+
+    /// Number of failed requests in a row (connection errors, timeouts or server errors) that
+    /// open the circuit breaker of an upstream server, taking it out of rotation.  0 disables the
+    /// circuit breaker
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "5",
+        env = "OMNIS_BOUNCER_CIRCUIT_BREAKER_FAILURES"
+    )]
+    pub circuit_breaker_failures: usize,
+
+    /// Period (in seconds) that the failed requests must fall within to open the circuit breaker
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "60",
+        env = "OMNIS_BOUNCER_CIRCUIT_BREAKER_WINDOW_SECS"
+    )]
+    pub circuit_breaker_window: u64,
+
+    /// Time (in seconds) an open circuit breaker waits before letting a trial request through to
+    /// the upstream server
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "30",
+        env = "OMNIS_BOUNCER_CIRCUIT_BREAKER_COOLDOWN_SECS"
+    )]
+    pub circuit_breaker_cooldown: u64,
+
+    /// Load balancing strategy for regular requests: "least_connections",
+    /// "weighted_least_connections", "least_sticky_sessions", "round_robin",
+    /// "power_of_two_choices" or "least_latency"
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "least_connections",
+        env = "OMNIS_BOUNCER_LOAD_BALANCING_REGULAR"
+    )]
+    pub load_balancing_regular: String,
+
+    /// Load balancing strategy for new Javascript Client sticky sessions
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "least_sticky_sessions",
+        env = "OMNIS_BOUNCER_LOAD_BALANCING_STICKY"
+    )]
+    pub load_balancing_sticky: String,
+
+    /// Load balancing strategy for loading static assets into the cache
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "least_connections",
+        env = "OMNIS_BOUNCER_LOAD_BALANCING_CACHE_LOAD"
+    )]
+    pub load_balancing_cache_load: String,
+
+    /// Time (in seconds) an upstream server warms up for after it is added through the control API
+    /// or becomes healthy again.  Its connection and sticky session limits ramp up linearly over
+    /// this window, from a small share of the limits.  Upstreams loaded when the server starts are
+    /// used at their full limits.  0 disables slow start
+    #[arg(
+        long,
+        conflicts_with = "config_file",
+        default_value = "0",
+        env = "OMNIS_BOUNCER_SLOW_START_SECS"
+    )]
+    pub slow_start: u64,
+
     /// Convert headers into arguments for Ultra-Thin requests
     #[arg(
         long,
@@ -458,7 +919,34 @@ pub struct RunArgs {
 fn build_upstream(args: &RunArgs) -> Vec<Upstream> {
     args.upstream
         .iter()
-        .map(|u| Upstream::new(u, args.upstream_connections, args.upstream_sessions))
+        .enumerate()
+        .map(|(i, u)| {
+            Upstream::new(u, args.upstream_connections, args.upstream_sessions)
+                .with_weight(args.upstream_weights.get(i).copied().unwrap_or(1))
+                .with_group(
+                    args.upstream_groups
+                        .get(i)
+                        .filter(|group| !group.is_empty())
+                        .cloned(),
+                    args.upstream_priorities.get(i).copied().unwrap_or(0),
+                )
+                .with_canary(
+                    args.upstream_canaries
+                        .get(i)
+                        .filter(|canary| !canary.is_empty())
+                        .cloned(),
+                    args.upstream_canary_weights.get(i).copied().unwrap_or(0),
+                )
+        })
+        .collect()
+}
+
+// Build API tokens from args, named by their position in the list
+fn build_api_tokens(args: &RunArgs) -> Vec<ApiToken> {
+    args.api_tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| ApiToken::new(format!("token-{}", i + 1), token, Role::Admin))
         .collect()
 }
 
@@ -491,17 +979,46 @@ impl TryFrom<&RunArgs> for Config {
                 args.monitor_tls_certificate.clone(),
                 args.monitor_tls_key.clone(),
             )?,
+            upstream_tls: UpstreamTls {
+                ca_path: args.upstream_tls_ca_path.clone(),
+                certificate_path: args.upstream_tls_certificate_path.clone(),
+                key_path: args.upstream_tls_key_path.clone(),
+                server_name: args.upstream_tls_server_name.clone(),
+                insecure_skip_verify: args.upstream_tls_insecure_skip_verify.then_some(true),
+            },
+            api_tokens: build_api_tokens(args),
+            control_users: Vec::new(),
+            control_session_expiration: Duration::from_secs(args.control_session_expiration),
             id_cookie_name: args.id_cookie_name.clone(),
             position_cookie_name: args.position_cookie_name.clone(),
             queue_size_cookie_name: args.queue_size_cookie_name.clone(),
+            eta_cookie_name: args.eta_cookie_name.clone(),
+            priority_cookie_name: args.priority_cookie_name.clone(),
             id_upstream_http_header: args.id_upstream_http_header.to_lowercase(), // Must be lowercase
             id_evict_upstream_http_header: args.id_evict_upstream_http_header.to_lowercase(), // Must be lowercase
             position_http_header: args.position_http_header.to_lowercase(), // Must be lowercase
             queue_size_http_header: args.queue_size_http_header.to_lowercase(), // Must be lowercase
+            eta_http_header: args.eta_http_header.to_lowercase(),           // Must be lowercase
+            priority_http_header: args.priority_http_header.to_lowercase(), // Must be lowercase
+            priority_query_param: args.priority_query_param.clone(),
+            invite_query_param: args.invite_query_param.clone(),
             acquire_timeout: Duration::from_secs(args.acquire_timeout),
             connect_timeout: Duration::from_secs(args.connect_timeout),
+            response_timeout_cache_load: Duration::from_secs(args.response_timeout_cache_load),
+            response_timeout_js_client: Duration::from_secs(args.response_timeout_js_client),
+            response_timeout_push: Duration::from_secs(args.response_timeout_push),
+            response_timeout_rest_api: Duration::from_secs(args.response_timeout_rest_api),
+            response_timeout_ultra_thin: Duration::from_secs(args.response_timeout_ultra_thin),
+            idle_timeout_cache_load: Duration::from_secs(args.idle_timeout_cache_load),
+            idle_timeout_js_client: Duration::from_secs(args.idle_timeout_js_client),
+            idle_timeout_push: Duration::from_secs(args.idle_timeout_push),
+            idle_timeout_rest_api: Duration::from_secs(args.idle_timeout_rest_api),
+            idle_timeout_ultra_thin: Duration::from_secs(args.idle_timeout_ultra_thin),
+            retry_attempts: args.retry_attempts,
+            retry_budget: Duration::from_secs(args.retry_budget),
             cookie_id_expiration: Duration::from_secs(args.cookie_id_expiration),
             sticky_session_timeout: Duration::from_secs(args.sticky_session_timeout),
+            shared_sticky_sessions: args.shared_sticky_sessions,
             asset_cache_secs: Duration::from_secs(args.asset_cache_secs),
             buffer_connections: args.buffer_connections,
             js_client_rate_limit_per_sec: args.js_client_rate_limit_per_sec,
@@ -512,14 +1029,34 @@ impl TryFrom<&RunArgs> for Config {
             control_port: args.monitor_https_port,
             queue_enabled: args.queue_enabled,
             queue_rotation_enabled: args.queue_rotation_enabled,
+            queue_disabled_policy: QueueDisabledPolicy::try_from(
+                args.queue_disabled_policy.as_str(),
+            )?,
             store_capacity: StoreCapacity::try_from(args.store_capacity)?,
             queue_prefix: args.redis_prefix.clone(),
             quarantine_expiry: Duration::from_secs(args.quarantine_expiry),
             validated_expiry: Duration::from_secs(args.validated_expiry),
             publish_throttle: Duration::from_millis(args.publish_throttle),
+            throughput_window: Duration::from_secs(args.throughput_window),
+            health_check_enabled: args.health_check_enabled,
+            health_check_path: args.health_check_path.clone(),
+            health_check_interval: Duration::from_secs(args.health_check_interval.max(1)),
+            health_check_timeout: Duration::from_secs(args.health_check_timeout),
+            health_check_rise: args.health_check_rise,
+            health_check_fall: args.health_check_fall,
+            circuit_breaker_failures: args.circuit_breaker_failures,
+            circuit_breaker_window: Duration::from_secs(args.circuit_breaker_window),
+            circuit_breaker_cooldown: Duration::from_secs(args.circuit_breaker_cooldown),
+            load_balancing_regular: LoadBalancing::try_from(args.load_balancing_regular.as_str())?,
+            load_balancing_sticky: LoadBalancing::try_from(args.load_balancing_sticky.as_str())?,
+            load_balancing_cache_load: LoadBalancing::try_from(
+                args.load_balancing_cache_load.as_str(),
+            )?,
+            slow_start: Duration::from_secs(args.slow_start),
             ultra_thin_inject_headers: args.ultra_thin_inject_headers,
             fallback_ultra_thin_library: args.fallback_ultra_thin_library.clone(),
             fallback_ultra_thin_class: args.fallback_ultra_thin_class.clone(),
+            sites: Vec::new(),
         };
 
         Ok(config)
@@ -0,0 +1,256 @@
+use http::Uri;
+use reqwest::{
+    Certificate, Client, Identity,
+    dns::{Addrs, Name, Resolve, Resolving},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::config::read_file;
+use crate::errors::{Error, Result};
+use crate::upstream::UpstreamTls;
+
+/// Client for requests to an upstream server, along with the URI to send them to (which has the
+/// TLS server name as its host, if the upstream overrides it)
+pub struct UpstreamClient {
+    pub client: Client,
+    pub uri: String,
+}
+
+/// HTTP clients for the upstream servers.  Upstreams using the global TLS options share a single
+/// client, and any other upstream gets its own client the first time it is used
+pub struct HttpClients {
+    connect_timeout: Duration,
+    tls: UpstreamTls,
+    shared: Client,
+    upstreams: Mutex<HashMap<String, (UpstreamTls, Client)>>,
+}
+
+impl HttpClients {
+    /// Build the shared client, failing if the global TLS options can't be used
+    pub fn new(connect_timeout: Duration, tls: UpstreamTls) -> Result<Self> {
+        let shared = build_client(connect_timeout, &tls, None)
+            .map_err(|e| Error::UpstreamTlsInvalid(format!("global options: {}", e)))?;
+        Ok(Self {
+            connect_timeout,
+            tls,
+            shared,
+            upstreams: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Client for an upstream server with the given TLS options, failing if they can't be used
+    pub fn upstream(&self, uri: &str, tls: &UpstreamTls) -> Result<UpstreamClient> {
+        let tls = tls.or(&self.tls);
+        let Some(server_name) = &tls.server_name else {
+            if tls == self.tls {
+                return Ok(UpstreamClient {
+                    client: self.shared.clone(),
+                    uri: String::from(uri),
+                });
+            }
+            return Ok(UpstreamClient {
+                client: self.client(uri, &tls)?,
+                uri: String::from(uri),
+            });
+        };
+
+        let server_uri = with_host(uri, server_name)
+            .map_err(|e| Error::UpstreamTlsInvalid(format!("{}: {}", uri, e)))?;
+        Ok(UpstreamClient {
+            client: self.client(uri, &tls)?,
+            uri: server_uri,
+        })
+    }
+
+    /// Client of a single upstream server, built again if its TLS options have changed
+    fn client(&self, uri: &str, tls: &UpstreamTls) -> Result<Client> {
+        let mut upstreams = match self.upstreams.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some((built, client)) = upstreams.get(uri)
+            && built == tls
+        {
+            return Ok(client.clone());
+        }
+
+        let client = build_client(self.connect_timeout, tls, Some(uri))
+            .map_err(|e| Error::UpstreamTlsInvalid(format!("{}: {}", uri, e)))?;
+        upstreams.insert(String::from(uri), (tls.clone(), client.clone()));
+        Ok(client)
+    }
+}
+
+/// Build a client with the given TLS options.  With a server name, the client also needs the URI
+/// of the upstream, so that it can resolve the server name to the upstream's host
+fn build_client(
+    connect_timeout: Duration,
+    tls: &UpstreamTls,
+    uri: Option<&str>,
+) -> core::result::Result<Client, String> {
+    let mut builder = Client::builder()
+        .connect_timeout(connect_timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .referer(false);
+
+    if let Some(path) = &tls.ca_path {
+        let pem = read_file(path).map_err(|e| format!("CA bundle \"{}\": {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("CA bundle \"{}\": {}", path, e))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&tls.certificate_path, &tls.key_path) {
+        (Some(certificate_path), Some(key_path)) => {
+            let mut pem = read_file(certificate_path)
+                .map_err(|e| format!("client certificate \"{}\": {}", certificate_path, e))?;
+            pem.push(b'\n');
+            pem.extend(
+                read_file(key_path).map_err(|e| format!("client key \"{}\": {}", key_path, e))?,
+            );
+            let identity =
+                Identity::from_pem(&pem).map_err(|e| format!("client certificate: {}", e))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(String::from(
+                "client certificate and key must be set together",
+            ));
+        }
+    }
+
+    if tls.insecure_skip_verify == Some(true) {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let (Some(server_name), Some(uri)) = (&tls.server_name, uri) {
+        let parsed: Uri = uri.parse().map_err(|e| format!("{}", e))?;
+        let host = parsed.host().ok_or("missing host")?;
+        builder = builder.dns_resolver(Arc::new(ServerNameResolver {
+            server_name: server_name.to_lowercase(),
+            host: String::from(host.trim_start_matches('[').trim_end_matches(']')),
+        }));
+    }
+
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Replace the host of a URI, keeping its scheme, port and path
+fn with_host(uri: &str, host: &str) -> core::result::Result<String, String> {
+    let parsed: Uri = uri.parse().map_err(|e| format!("{}", e))?;
+    let authority = parsed.authority().ok_or("missing host")?;
+    let start = uri.find(authority.as_str()).ok_or("missing host")?;
+    let end = start + authority.as_str().len();
+    let authority = match authority.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => String::from(host),
+    };
+    Ok(format!("{}{}{}", &uri[..start], authority, &uri[end..]))
+}
+
+/// Resolves the TLS server name of an upstream server to the upstream's own host, so that
+/// requests sent to the server name (for SNI and certificate checks) still reach the upstream
+struct ServerNameResolver {
+    server_name: String,
+    host: String,
+}
+
+impl Resolve for ServerNameResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = match name.as_str().eq_ignore_ascii_case(&self.server_name) {
+            true => self.host.clone(),
+            false => String::from(name.as_str()),
+        };
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?;
+            let addrs: Addrs = Box::new(addrs.collect::<Vec<_>>().into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_with_host() {
+        assert_eq!(
+            with_host("https://10.0.0.5:8443", "omnis.internal"),
+            Ok(String::from("https://omnis.internal:8443"))
+        );
+        assert_eq!(
+            with_host("https://10.0.0.5/omnis", "omnis.internal"),
+            Ok(String::from("https://omnis.internal/omnis"))
+        );
+        assert_eq!(
+            with_host("https://[::1]:8443", "omnis.internal"),
+            Ok(String::from("https://omnis.internal:8443"))
+        );
+        assert!(with_host("/omnis", "omnis.internal").is_err());
+    }
+
+    #[test]
+    fn test_tls_overrides() {
+        let global = UpstreamTls {
+            ca_path: Some(String::from("/certs/ca.pem")),
+            certificate_path: Some(String::from("/certs/client.pem")),
+            key_path: Some(String::from("/certs/client.key")),
+            server_name: None,
+            insecure_skip_verify: None,
+        };
+        let upstream = UpstreamTls {
+            key_path: Some(String::from("/certs/other.key")),
+            server_name: Some(String::from("omnis.internal")),
+            ..UpstreamTls::default()
+        };
+        assert_eq!(
+            upstream.or(&global),
+            UpstreamTls {
+                ca_path: Some(String::from("/certs/ca.pem")),
+                certificate_path: None,
+                key_path: Some(String::from("/certs/other.key")),
+                server_name: Some(String::from("omnis.internal")),
+                insecure_skip_verify: None,
+            }
+        );
+        assert_eq!(UpstreamTls::default().or(&global), global);
+    }
+
+    #[test]
+    fn test_upstream_clients() {
+        let clients = HttpClients::new(Duration::from_secs(1), UpstreamTls::default()).unwrap();
+        let client = clients
+            .upstream("https://10.0.0.5:8443", &UpstreamTls::default())
+            .unwrap();
+        assert_eq!(client.uri, "https://10.0.0.5:8443");
+
+        let tls = UpstreamTls {
+            server_name: Some(String::from("omnis.internal")),
+            insecure_skip_verify: Some(true),
+            ..UpstreamTls::default()
+        };
+        let client = clients.upstream("https://10.0.0.5:8443", &tls).unwrap();
+        assert_eq!(client.uri, "https://omnis.internal:8443");
+
+        // Client certificate without a key, and a missing CA bundle
+        let tls = UpstreamTls {
+            certificate_path: Some(String::from("/certs/client.pem")),
+            ..UpstreamTls::default()
+        };
+        assert!(clients.upstream("https://10.0.0.5:8443", &tls).is_err());
+        let tls = UpstreamTls {
+            ca_path: Some(String::from("/missing/ca.pem")),
+            ..UpstreamTls::default()
+        };
+        assert!(clients.upstream("https://10.0.0.5:8443", &tls).is_err());
+        assert!(HttpClients::new(Duration::from_secs(1), tls).is_err());
+    }
+}
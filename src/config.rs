@@ -11,11 +11,16 @@ use std::{
 };
 use toml::de;
 
+use crate::auth::{ApiToken, ControlUser};
 use crate::constants::{SELF_SIGNED_CERT, SELF_SIGNED_KEY};
 use crate::errors::Error;
-use crate::queue::StoreCapacity;
+use crate::omnis::{ResponseTimeouts, TrafficClass};
+use crate::queue::{QueueDisabledPolicy, StoreCapacity};
 use crate::secrets::decode_master_key;
-use crate::upstream::Upstream;
+use crate::sites::SiteConfig;
+use crate::upstream::{
+    CircuitBreakerSettings, LoadBalancing, LoadBalancingSettings, Upstream, UpstreamTls,
+};
 
 #[derive(Debug)]
 pub struct Config {
@@ -27,17 +32,40 @@ pub struct Config {
     pub initial_upstream: Vec<Upstream>,
     pub public_tls_pair: (Vec<u8>, Vec<u8>),
     pub monitor_tls_pair: (Vec<u8>, Vec<u8>),
+    pub upstream_tls: UpstreamTls,
+    pub api_tokens: Vec<ApiToken>,
+    pub control_users: Vec<ControlUser>,
+    pub control_session_expiration: Duration,
     pub id_cookie_name: String,
     pub position_cookie_name: String,
     pub queue_size_cookie_name: String,
+    pub eta_cookie_name: String,
+    pub priority_cookie_name: String,
     pub id_upstream_http_header: String,
     pub id_evict_upstream_http_header: String,
     pub position_http_header: String,
     pub queue_size_http_header: String,
+    pub eta_http_header: String,
+    pub priority_http_header: String,
+    pub priority_query_param: String,
+    pub invite_query_param: String,
     pub acquire_timeout: Duration,
     pub connect_timeout: Duration,
+    pub response_timeout_cache_load: Duration,
+    pub response_timeout_js_client: Duration,
+    pub response_timeout_push: Duration,
+    pub response_timeout_rest_api: Duration,
+    pub response_timeout_ultra_thin: Duration,
+    pub idle_timeout_cache_load: Duration,
+    pub idle_timeout_js_client: Duration,
+    pub idle_timeout_push: Duration,
+    pub idle_timeout_rest_api: Duration,
+    pub idle_timeout_ultra_thin: Duration,
+    pub retry_attempts: usize,
+    pub retry_budget: Duration,
     pub cookie_id_expiration: Duration,
     pub sticky_session_timeout: Duration,
+    pub shared_sticky_sessions: bool,
     pub asset_cache_secs: Duration,
     pub buffer_connections: usize,
     pub js_client_rate_limit_per_sec: u64,
@@ -48,24 +76,87 @@ pub struct Config {
     pub control_port: u16,
     pub queue_enabled: bool,
     pub queue_rotation_enabled: bool,
+    pub queue_disabled_policy: QueueDisabledPolicy,
     pub store_capacity: StoreCapacity,
     pub queue_prefix: String,
     pub quarantine_expiry: Duration,
     pub validated_expiry: Duration,
     pub publish_throttle: Duration,
+    pub throughput_window: Duration,
+    pub health_check_enabled: bool,
+    pub health_check_path: String,
+    pub health_check_interval: Duration,
+    pub health_check_timeout: Duration,
+    pub health_check_rise: usize,
+    pub health_check_fall: usize,
+    pub circuit_breaker_failures: usize,
+    pub circuit_breaker_window: Duration,
+    pub circuit_breaker_cooldown: Duration,
+    pub load_balancing_regular: LoadBalancing,
+    pub load_balancing_sticky: LoadBalancing,
+    pub load_balancing_cache_load: LoadBalancing,
+    pub slow_start: Duration,
     pub ultra_thin_inject_headers: bool,
     pub fallback_ultra_thin_library: Option<String>,
     pub fallback_ultra_thin_class: Option<String>,
+    pub sites: Vec<SiteConfig>,
 }
 
 impl Config {
     pub fn fallback_enabled(&self) -> bool {
         self.fallback_ultra_thin_library.is_some() && self.fallback_ultra_thin_class.is_some()
     }
+
+    pub fn circuit_breaker(&self) -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failures: self.circuit_breaker_failures,
+            window: self.circuit_breaker_window,
+            cooldown: self.circuit_breaker_cooldown,
+        }
+    }
+
+    pub fn load_balancing(&self) -> LoadBalancingSettings {
+        LoadBalancingSettings {
+            regular: self.load_balancing_regular,
+            sticky: self.load_balancing_sticky,
+            cache_load: self.load_balancing_cache_load,
+        }
+    }
+
+    pub fn response_timeouts(&self, class: TrafficClass) -> ResponseTimeouts {
+        let (response, idle) = match class {
+            TrafficClass::CacheLoad => (
+                self.response_timeout_cache_load,
+                self.idle_timeout_cache_load,
+            ),
+            TrafficClass::JavascriptClient => {
+                (self.response_timeout_js_client, self.idle_timeout_js_client)
+            }
+            TrafficClass::Push => (self.response_timeout_push, self.idle_timeout_push),
+            TrafficClass::RestApi => (self.response_timeout_rest_api, self.idle_timeout_rest_api),
+            TrafficClass::UltraThin => (
+                self.response_timeout_ultra_thin,
+                self.idle_timeout_ultra_thin,
+            ),
+        };
+
+        // Zero disables the timeout
+        ResponseTimeouts {
+            response: (!response.is_zero()).then_some(response),
+            idle: (!idle.is_zero()).then_some(idle),
+        }
+    }
+
+    /// The default site, followed by all additional sites
+    pub fn all_sites(&self) -> Vec<SiteConfig> {
+        let mut sites = vec![SiteConfig::from_config(self)];
+        sites.extend(self.sites.iter().cloned());
+        sites
+    }
 }
 
 // Read a single file from a string path
-fn read_file(path: impl Into<String>) -> Result<Vec<u8>, io::Error> {
+pub fn read_file(path: impl Into<String>) -> Result<Vec<u8>, io::Error> {
     let path = path.into();
     let path = path.resolve();
     let mut contents: Vec<u8> = Vec::new();
@@ -93,6 +184,13 @@ pub struct ConfigFileUpstream {
     pub uri: String,
     pub connections: Option<usize>,
     pub sticky_sessions: Option<usize>,
+    pub weight: Option<usize>,
+    pub group: Option<String>,
+    pub priority: Option<usize>,
+    pub canary: Option<String>,
+    pub canary_weight: Option<usize>,
+    #[serde(default, flatten)]
+    pub tls: UpstreamTls,
 }
 
 impl From<&ConfigFileUpstream> for Upstream {
@@ -102,16 +200,108 @@ impl From<&ConfigFileUpstream> for Upstream {
             uri: config.uri.clone(),
             connections: config.connections.unwrap_or(defaults.connections),
             sticky_sessions: config.sticky_sessions.unwrap_or(defaults.sticky_sessions),
+            weight: config.weight.unwrap_or(defaults.weight).max(1),
+            group: config.group.clone(),
+            priority: config.priority.unwrap_or(defaults.priority),
+            canary: config.canary.clone(),
+            canary_weight: config
+                .canary_weight
+                .unwrap_or(defaults.canary_weight)
+                .min(100),
+            tls: config.tls.clone(),
         }
     }
 }
 
+/// Additional site, selected by host or path prefix.  Anything not set is inherited from the top
+/// level configuration, except upstreams, and the Redis prefix which defaults to
+/// `<redis_prefix>:<name>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigFileSite {
+    pub name: String,
+    pub hosts: Option<Vec<String>>,
+    pub path_prefix: Option<String>,
+    pub redis_prefix: Option<String>,
+    pub queue_enabled: Option<bool>,
+    pub store_capacity: Option<isize>,
+    pub initial_upstream: Option<Vec<ConfigFileUpstream>>,
+    pub id_cookie_name: Option<String>,
+    pub position_cookie_name: Option<String>,
+    pub queue_size_cookie_name: Option<String>,
+    pub eta_cookie_name: Option<String>,
+    pub priority_cookie_name: Option<String>,
+}
+
+/// Resolve an additional site against the (already merged) top level configuration
+fn build_site(config: &Config, site: ConfigFileSite) -> Result<SiteConfig, ConfigFileError> {
+    if !SiteConfig::valid_name(&site.name) {
+        return Err(ConfigFileError::SiteInvalid(site.name));
+    }
+
+    let hosts: Vec<String> = site
+        .hosts
+        .unwrap_or_default()
+        .iter()
+        .map(|host| host.to_lowercase())
+        .collect();
+    let path_prefix = match site.path_prefix {
+        Some(prefix) if prefix.starts_with('/') && prefix.trim_end_matches('/').len() > 1 => {
+            Some(String::from(prefix.trim_end_matches('/')))
+        }
+        Some(_) => return Err(ConfigFileError::SiteInvalid(site.name)),
+        None => None,
+    };
+
+    // Only the default site can match every request
+    if hosts.is_empty() && path_prefix.is_none() {
+        return Err(ConfigFileError::SiteInvalid(site.name));
+    }
+
+    Ok(SiteConfig {
+        hosts,
+        path_prefix,
+        queue_prefix: site
+            .redis_prefix
+            .unwrap_or(format!("{}:{}", config.queue_prefix, site.name)),
+        queue_enabled: site.queue_enabled.unwrap_or(config.queue_enabled),
+        store_capacity: match site.store_capacity {
+            Some(c) => match StoreCapacity::try_from(c) {
+                Ok(capacity) => capacity,
+                Err(_) => return Err(ConfigFileError::StoreCapacityOutOfRange(c)),
+            },
+            None => config.store_capacity,
+        },
+        initial_upstream: match &site.initial_upstream {
+            Some(u) => u.iter().map(Upstream::from).collect(),
+            None => Vec::new(),
+        },
+        id_cookie_name: site.id_cookie_name.unwrap_or(config.id_cookie_name.clone()),
+        position_cookie_name: site
+            .position_cookie_name
+            .unwrap_or(config.position_cookie_name.clone()),
+        queue_size_cookie_name: site
+            .queue_size_cookie_name
+            .unwrap_or(config.queue_size_cookie_name.clone()),
+        eta_cookie_name: site
+            .eta_cookie_name
+            .unwrap_or(config.eta_cookie_name.clone()),
+        priority_cookie_name: site
+            .priority_cookie_name
+            .unwrap_or(config.priority_cookie_name.clone()),
+        name: site.name,
+    })
+}
+
 pub enum ConfigFileError {
     IOError(io::Error),
     ContentsUnreadable(de::Error),
     InvalidCookieKey(DecodeError),
     StoreCapacityOutOfRange(isize),
+    QueueDisabledPolicyInvalid(String),
+    LoadBalancingInvalid(String),
     TLSCertificateError(io::Error),
+    SiteInvalid(String),
+    SiteDuplicate(String),
 }
 
 impl Display for ConfigFileError {
@@ -127,9 +317,27 @@ impl Display for ConfigFileError {
                 "Store capacity should be -1 (infinite) or greater than 0: {}",
                 e
             ),
+            ConfigFileError::QueueDisabledPolicyInvalid(e) => write!(
+                f,
+                "Queue disabled policy should be \"drain\" or \"keep\": {}",
+                e
+            ),
+            ConfigFileError::LoadBalancingInvalid(e) => write!(
+                f,
+                "Load balancing should be \"least_connections\", \"weighted_least_connections\", \"least_sticky_sessions\", \"round_robin\", \"power_of_two_choices\" or \"least_latency\": {}",
+                e
+            ),
             ConfigFileError::TLSCertificateError(e) => {
                 write!(f, "Unable to read TLS Certificate: {}", e)
             }
+            ConfigFileError::SiteInvalid(e) => write!(
+                f,
+                "Sites should have a name of letters, numbers, \"-\" or \"_\", and at least one host or a path prefix: {}",
+                e
+            ),
+            ConfigFileError::SiteDuplicate(e) => {
+                write!(f, "Site names and Redis prefixes should be unique: {}", e)
+            }
         }
     }
 }
@@ -146,17 +354,44 @@ pub struct ConfigFile {
     pub public_tls_certificate_path: Option<String>,
     pub monitor_tls_key_path: Option<String>,
     pub monitor_tls_certificate_path: Option<String>,
+    pub upstream_tls_ca_path: Option<String>,
+    pub upstream_tls_certificate_path: Option<String>,
+    pub upstream_tls_key_path: Option<String>,
+    pub upstream_tls_server_name: Option<String>,
+    pub upstream_tls_insecure_skip_verify: Option<bool>,
+    pub api_tokens: Option<Vec<ApiToken>>,
+    pub control_users: Option<Vec<ControlUser>>,
+    pub control_session_expiration: Option<u64>,
     pub id_cookie_name: Option<String>,
     pub position_cookie_name: Option<String>,
     pub queue_size_cookie_name: Option<String>,
+    pub eta_cookie_name: Option<String>,
+    pub priority_cookie_name: Option<String>,
     pub id_upstream_http_header: Option<String>,
     pub id_evict_upstream_http_header: Option<String>,
     pub position_http_header: Option<String>,
     pub queue_size_http_header: Option<String>,
+    pub eta_http_header: Option<String>,
+    pub priority_http_header: Option<String>,
+    pub priority_query_param: Option<String>,
+    pub invite_query_param: Option<String>,
     pub acquire_timeout: Option<u64>,
     pub connect_timeout: Option<u64>,
+    pub response_timeout_cache_load: Option<u64>,
+    pub response_timeout_js_client: Option<u64>,
+    pub response_timeout_push: Option<u64>,
+    pub response_timeout_rest_api: Option<u64>,
+    pub response_timeout_ultra_thin: Option<u64>,
+    pub idle_timeout_cache_load: Option<u64>,
+    pub idle_timeout_js_client: Option<u64>,
+    pub idle_timeout_push: Option<u64>,
+    pub idle_timeout_rest_api: Option<u64>,
+    pub idle_timeout_ultra_thin: Option<u64>,
+    pub retry_attempts: Option<usize>,
+    pub retry_budget: Option<u64>,
     pub cookie_id_expiration: Option<u64>,
     pub sticky_session_timeout: Option<u64>,
+    pub shared_sticky_sessions: Option<bool>,
     pub asset_cache_secs: Option<u64>,
     pub buffer_connections: Option<usize>,
     pub js_client_rate_limit_per_sec: Option<u64>,
@@ -167,14 +402,42 @@ pub struct ConfigFile {
     pub monitor_https_port: Option<u16>,
     pub queue_enabled: Option<bool>,
     pub queue_rotation_enabled: Option<bool>,
+    pub queue_disabled_policy: Option<String>,
     pub store_capacity: Option<isize>,
     pub redis_prefix: Option<String>,
     pub quarantine_expiry: Option<u64>,
     pub validated_expiry: Option<u64>,
     pub publish_throttle: Option<u64>,
+    pub throughput_window: Option<u64>,
+    pub health_check_enabled: Option<bool>,
+    pub health_check_path: Option<String>,
+    pub health_check_interval: Option<u64>,
+    pub health_check_timeout: Option<u64>,
+    pub health_check_rise: Option<usize>,
+    pub health_check_fall: Option<usize>,
+    pub circuit_breaker_failures: Option<usize>,
+    pub circuit_breaker_window: Option<u64>,
+    pub circuit_breaker_cooldown: Option<u64>,
+    pub load_balancing_regular: Option<String>,
+    pub load_balancing_sticky: Option<String>,
+    pub load_balancing_cache_load: Option<String>,
+    pub slow_start: Option<u64>,
     pub ultra_thin_inject_headers: Option<bool>,
     pub fallback_ultra_thin_library: Option<String>,
     pub fallback_ultra_thin_class: Option<String>,
+    pub sites: Option<Vec<ConfigFileSite>>,
+}
+
+/// Load balancing strategy from the configuration file, or the default if not set
+fn merge_load_balancing(
+    strategy: Option<String>,
+    default: LoadBalancing,
+) -> Result<LoadBalancing, ConfigFileError> {
+    match strategy {
+        Some(s) => LoadBalancing::try_from(s.as_str())
+            .map_err(|_| ConfigFileError::LoadBalancingInvalid(s)),
+        None => Ok(default),
+    }
 }
 
 /// Read all values set by the configuration file and merge in defaults values, sourced from the CLI
@@ -213,7 +476,7 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
         config.monitor_tls_pair
     };
 
-    Ok(Config {
+    let mut config = Config {
         app_name: config_file.name.unwrap_or(config.app_name),
         default_locale: config_file.default_locale.unwrap_or(config.default_locale),
         locales: match config_file.locales {
@@ -237,6 +500,20 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
         },
         public_tls_pair,
         monitor_tls_pair,
+        upstream_tls: UpstreamTls {
+            ca_path: config_file.upstream_tls_ca_path,
+            certificate_path: config_file.upstream_tls_certificate_path,
+            key_path: config_file.upstream_tls_key_path,
+            server_name: config_file.upstream_tls_server_name,
+            insecure_skip_verify: config_file.upstream_tls_insecure_skip_verify,
+        }
+        .or(&config.upstream_tls),
+        api_tokens: config_file.api_tokens.unwrap_or(config.api_tokens),
+        control_users: config_file.control_users.unwrap_or(config.control_users),
+        control_session_expiration: match config_file.control_session_expiration {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.control_session_expiration,
+        },
         id_cookie_name: config_file.id_cookie_name.unwrap_or(config.id_cookie_name),
         position_cookie_name: config_file
             .position_cookie_name
@@ -244,6 +521,12 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
         queue_size_cookie_name: config_file
             .queue_size_cookie_name
             .unwrap_or(config.queue_size_cookie_name),
+        eta_cookie_name: config_file
+            .eta_cookie_name
+            .unwrap_or(config.eta_cookie_name),
+        priority_cookie_name: config_file
+            .priority_cookie_name
+            .unwrap_or(config.priority_cookie_name),
         id_upstream_http_header: config_file
             .id_upstream_http_header
             .unwrap_or(config.id_upstream_http_header),
@@ -256,6 +539,18 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
         queue_size_http_header: config_file
             .queue_size_http_header
             .unwrap_or(config.queue_size_http_header),
+        eta_http_header: config_file
+            .eta_http_header
+            .unwrap_or(config.eta_http_header),
+        priority_http_header: config_file
+            .priority_http_header
+            .unwrap_or(config.priority_http_header),
+        priority_query_param: config_file
+            .priority_query_param
+            .unwrap_or(config.priority_query_param),
+        invite_query_param: config_file
+            .invite_query_param
+            .unwrap_or(config.invite_query_param),
         acquire_timeout: match config_file.acquire_timeout {
             Some(secs) => Duration::from_secs(secs),
             None => config.acquire_timeout,
@@ -264,6 +559,51 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
             Some(secs) => Duration::from_secs(secs),
             None => config.connect_timeout,
         },
+        response_timeout_cache_load: match config_file.response_timeout_cache_load {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.response_timeout_cache_load,
+        },
+        response_timeout_js_client: match config_file.response_timeout_js_client {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.response_timeout_js_client,
+        },
+        response_timeout_push: match config_file.response_timeout_push {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.response_timeout_push,
+        },
+        response_timeout_rest_api: match config_file.response_timeout_rest_api {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.response_timeout_rest_api,
+        },
+        response_timeout_ultra_thin: match config_file.response_timeout_ultra_thin {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.response_timeout_ultra_thin,
+        },
+        idle_timeout_cache_load: match config_file.idle_timeout_cache_load {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.idle_timeout_cache_load,
+        },
+        idle_timeout_js_client: match config_file.idle_timeout_js_client {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.idle_timeout_js_client,
+        },
+        idle_timeout_push: match config_file.idle_timeout_push {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.idle_timeout_push,
+        },
+        idle_timeout_rest_api: match config_file.idle_timeout_rest_api {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.idle_timeout_rest_api,
+        },
+        idle_timeout_ultra_thin: match config_file.idle_timeout_ultra_thin {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.idle_timeout_ultra_thin,
+        },
+        retry_attempts: config_file.retry_attempts.unwrap_or(config.retry_attempts),
+        retry_budget: match config_file.retry_budget {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.retry_budget,
+        },
         cookie_id_expiration: match config_file.cookie_id_expiration {
             Some(secs) => Duration::from_secs(secs),
             None => config.cookie_id_expiration,
@@ -272,6 +612,9 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
             Some(secs) => Duration::from_secs(secs),
             None => config.sticky_session_timeout,
         },
+        shared_sticky_sessions: config_file
+            .shared_sticky_sessions
+            .unwrap_or(config.shared_sticky_sessions),
         asset_cache_secs: match config_file.asset_cache_secs {
             Some(secs) => Duration::from_secs(secs),
             None => config.asset_cache_secs,
@@ -297,6 +640,13 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
         queue_rotation_enabled: config_file
             .queue_rotation_enabled
             .unwrap_or(config.queue_rotation_enabled),
+        queue_disabled_policy: match config_file.queue_disabled_policy {
+            Some(p) => match QueueDisabledPolicy::try_from(p.as_str()) {
+                Ok(policy) => policy,
+                Err(_) => return Err(ConfigFileError::QueueDisabledPolicyInvalid(p)),
+            },
+            None => config.queue_disabled_policy,
+        },
         store_capacity: match config_file.store_capacity {
             Some(c) => match StoreCapacity::try_from(c) {
                 Ok(capacity) => capacity,
@@ -320,6 +670,57 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
             Some(secs) => Duration::from_secs(secs),
             None => config.publish_throttle,
         },
+        throughput_window: match config_file.throughput_window {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.throughput_window,
+        },
+        health_check_enabled: config_file
+            .health_check_enabled
+            .unwrap_or(config.health_check_enabled),
+        health_check_path: config_file
+            .health_check_path
+            .unwrap_or(config.health_check_path),
+        health_check_interval: match config_file.health_check_interval {
+            Some(secs) => Duration::from_secs(secs.max(1)),
+            None => config.health_check_interval,
+        },
+        health_check_timeout: match config_file.health_check_timeout {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.health_check_timeout,
+        },
+        health_check_rise: config_file
+            .health_check_rise
+            .unwrap_or(config.health_check_rise),
+        health_check_fall: config_file
+            .health_check_fall
+            .unwrap_or(config.health_check_fall),
+        circuit_breaker_failures: config_file
+            .circuit_breaker_failures
+            .unwrap_or(config.circuit_breaker_failures),
+        circuit_breaker_window: match config_file.circuit_breaker_window {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.circuit_breaker_window,
+        },
+        circuit_breaker_cooldown: match config_file.circuit_breaker_cooldown {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.circuit_breaker_cooldown,
+        },
+        load_balancing_regular: merge_load_balancing(
+            config_file.load_balancing_regular,
+            config.load_balancing_regular,
+        )?,
+        load_balancing_sticky: merge_load_balancing(
+            config_file.load_balancing_sticky,
+            config.load_balancing_sticky,
+        )?,
+        load_balancing_cache_load: merge_load_balancing(
+            config_file.load_balancing_cache_load,
+            config.load_balancing_cache_load,
+        )?,
+        slow_start: match config_file.slow_start {
+            Some(secs) => Duration::from_secs(secs),
+            None => config.slow_start,
+        },
         ultra_thin_inject_headers: config_file
             .ultra_thin_inject_headers
             .unwrap_or(config.ultra_thin_inject_headers),
@@ -331,7 +732,29 @@ fn merge_config(config: Config, config_file: ConfigFile) -> Result<Config, Confi
             Some(library) => Some(library),
             None => config.fallback_ultra_thin_class,
         },
-    })
+        sites: config.sites,
+    };
+
+    // Sites inherit from the merged configuration, so are built last
+    if let Some(file_sites) = config_file.sites {
+        let mut sites = Vec::new();
+        for site in file_sites {
+            sites.push(build_site(&config, site)?);
+        }
+        config.sites = sites;
+    }
+
+    let all_sites = config.all_sites();
+    for (index, site) in all_sites.iter().enumerate() {
+        let duplicate = all_sites[..index]
+            .iter()
+            .any(|other| other.name == site.name || other.queue_prefix == site.queue_prefix);
+        if duplicate {
+            return Err(ConfigFileError::SiteDuplicate(site.name.clone()));
+        }
+    }
+
+    Ok(config)
 }
 
 pub fn read_config_file(
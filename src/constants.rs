@@ -47,6 +47,31 @@ pub static BACKGROUND_SLEEP_TIME: Duration = Duration::from_secs(10);
 pub static DEBOUNCE_INTERVAL: Duration = Duration::from_secs(2);
 pub static SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
 
+// Upstream Servers
+/// Weight of each new response time in the average response time of an upstream (0 to 1)
+pub static LATENCY_SMOOTHING: f64 = 0.2;
+/// Number of the most recent response times of an upstream kept for its latency percentiles
+pub static LATENCY_SAMPLES: usize = 500;
+/// Interval to push the statistics of the upstreams to the control UI
+pub static UPSTREAM_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval to look for a free shared sticky session while waiting, as sticky sessions ended by
+/// other servers don't wake this server's waiters
+pub static SHARED_STICKY_RECHECK: Duration = Duration::from_secs(1);
+/// Share of its connection and sticky session limits an upstream starts at during slow start
+pub static SLOW_START_SHARE: f64 = 0.1;
+/// Interval to look for a permit while waiting on upstreams that are warming up, as their limits
+/// ramp up without waking the waiters
+pub static SLOW_START_RECHECK: Duration = Duration::from_millis(500);
+
+// Sites
+pub static DEFAULT_SITE_NAME: &str = "default";
+
+// Control Server
+pub static CONTROL_SESSION_COOKIE_NAME: &str = "omnis-bouncer-control-session";
+pub static DEFAULT_PRIORITY_TOKEN_EXPIRY: Duration = Duration::from_secs(86400);
+pub static DEFAULT_INVITE_EXPIRY: Duration = Duration::from_secs(7 * 86400);
+pub static DEFAULT_INVITE_USES: u32 = 1;
+
 // Web Server Debug
 #[cfg(debug_assertions)]
 pub static LOCALHOST_CORS_DEBUG_URI: &str = "http://localhost:5173";
@@ -0,0 +1,111 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tower_cookies::{Cookie, Cookies};
+use tracing::error;
+
+use crate::auth::{AuthMethod, Credentials, Identity, Role};
+use crate::constants::CONTROL_SESSION_COOKIE_NAME;
+use crate::cookies::add_private_server_cookie;
+use crate::errors::{Error, Result};
+use crate::state::AppState;
+use crate::tokens::{fingerprint, verify_fingerprint};
+
+/// Login session for the control UI, stored in an encrypted cookie.  The fingerprint of the
+/// credential ends the session once the password or token is changed
+#[derive(Debug, Serialize, Deserialize)]
+struct Session {
+    method: AuthMethod,
+    name: String,
+    fingerprint: String,
+    expires: i64,
+}
+
+/// Start a login session for an identity, so that the browser can use the control UI (including
+/// SSE and WebSockets, which can not send an `Authorization` header)
+pub fn start_session(state: &AppState, cookies: &Cookies, identity: &Identity) -> Result<()> {
+    let config = &state.config;
+    let expiry = config.control_session_expiration;
+    let Some((_, secret)) = state.authenticators.lookup(identity.method, &identity.name) else {
+        return Err(Error::Unauthorized);
+    };
+
+    let session = Session {
+        method: identity.method,
+        name: identity.name.clone(),
+        fingerprint: fingerprint(config.cookie_secret_key.signing(), secret),
+        expires: Utc::now().timestamp() + expiry.as_secs() as i64,
+    };
+
+    add_private_server_cookie(
+        &cookies.private(&config.cookie_secret_key),
+        CONTROL_SESSION_COOKIE_NAME,
+        serde_json::to_string(&session)?,
+        Some(expiry),
+    );
+
+    Ok(())
+}
+
+/// End the login session for the current browser
+pub fn end_session(state: &AppState, cookies: &Cookies) {
+    cookies
+        .private(&state.config.cookie_secret_key)
+        .remove(Cookie::from(CONTROL_SESSION_COOKIE_NAME));
+}
+
+/// Identity from an unexpired session cookie, re-validated against the current credentials
+fn session_identity(state: &AppState, cookies: &Cookies) -> Option<Identity> {
+    let cookie = cookies
+        .private(&state.config.cookie_secret_key)
+        .get(CONTROL_SESSION_COOKIE_NAME)?;
+
+    let session: Session = match serde_json::from_str(cookie.value()) {
+        Ok(session) => session,
+        Err(error) => {
+            error!("Failed to parse control session cookie: {}", error);
+            return None;
+        }
+    };
+
+    if session.expires < Utc::now().timestamp() {
+        return None;
+    }
+
+    let (identity, secret) = state.authenticators.lookup(session.method, &session.name)?;
+    let key = state.config.cookie_secret_key.signing();
+    verify_fingerprint(key, secret, &session.fingerprint).then_some(identity)
+}
+
+/// Determine who is making a request, from the `Authorization` header or a session cookie
+pub fn authenticate(state: &AppState, cookies: &Cookies, request: &Request) -> Option<Identity> {
+    match Credentials::from_headers(request.headers()) {
+        Some(credentials) => state.authenticators.authenticate(&credentials),
+        None => session_identity(state, cookies),
+    }
+}
+
+/// Middleware that rejects any request without valid credentials (401), or whose identity does
+/// not hold at least `role` (403), and makes the `Identity` available to handlers through request
+/// extensions
+pub async fn require_role(
+    State((state, role)): State<(AppState, Role)>,
+    cookies: Cookies,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let Some(identity) = authenticate(&state, &cookies, &request) else {
+        return Err(Error::Unauthorized);
+    };
+
+    if !identity.has_role(role) {
+        return Err(Error::Forbidden(role));
+    }
+
+    request.extensions_mut().insert(identity);
+    Ok(next.run(request).await)
+}
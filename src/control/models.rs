@@ -1,22 +1,96 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::queue::{QueueEvent, QueueSettings, QueueStatus};
+use crate::auth::{AuthMethod, Identity};
+use crate::queue::{QueueEvent, QueueSettings, QueueStatus, QueueThroughput};
+use crate::sites::SiteConfig;
 use crate::upstream;
 use crate::{config, queue};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[schema(
     examples(
-        json!({"uri": "http://127.0.0.1:63111", "connections": 100, "sticky_sessions": 10})
+        json!({"uri": "http://127.0.0.1:63111", "connections": 100, "sticky_sessions": 10, "weight": 2}),
+        json!({"uri": "http://10.1.0.5:63111", "connections": 20, "sticky_sessions": 5, "weight": 1, "group": "dr", "priority": 1}),
+        json!({"uri": "http://127.0.0.1:63113", "connections": 100, "sticky_sessions": 10, "weight": 1, "canary": "studio-11.1", "canary_weight": 10}),
+        json!({"uri": "https://10.0.0.5:63111", "connections": 100, "sticky_sessions": 10, "weight": 1, "tls_ca_path": "/certs/lab-ca.pem", "tls_server_name": "omnis.internal"}),
+        json!({"uri": "http://127.0.0.1:63111", "connections": 100, "sticky_sessions": 10, "weight": 1, "latency": 120, "current_connections": 12, "current_sticky_sessions": 8, "requests": 5120, "errors": {"connect": 3, "timeout": 1, "server_error": 12, "other": 0}, "latency_p50": 95, "latency_p95": 410, "bytes_received": 73400320, "last_success": "2025-09-23T10:43:58Z", "health": {"healthy": false, "checked": "2025-09-23T10:44:00Z", "error": "error sending request for url (http://127.0.0.1:63111/)"}, "circuit": {"state": "open", "trips": 2, "opened": "2025-09-23T10:44:00Z"}})
     )
 )]
 pub struct Upstream {
     uri: String,
     connections: usize,
     sticky_sessions: usize,
+    /// Share of the traffic for weighted load balancing, relative to the other upstreams
+    #[serde(default = "default_weight")]
+    weight: usize,
+    /// Name of the group the upstream belongs to, such as a primary or backup farm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Upstreams with a lower priority are preferred.  Upstreams with a higher priority (backups)
+    /// only get traffic when every upstream with a lower priority is unhealthy, removed or full
+    #[serde(default)]
+    priority: usize,
+    /// Label of the canary release the upstream runs, such as a new Omnis library version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    canary: Option<String>,
+    /// Percentage (0 to 100) of new sticky sessions and regular requests sent to the upstreams with
+    /// the same canary label.  Sessions already stuck to an upstream stay where they are
+    #[serde(default)]
+    canary_weight: usize,
+    /// Options for connecting over HTTPS, on top of the global options
+    #[serde(default, flatten)]
+    tls: UpstreamTls,
+    /// Average response time (in milliseconds), only reported for upstreams in the pool that have
+    /// served a request
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    latency: Option<u64>,
+    /// Requests in progress, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    current_connections: Option<usize>,
+    /// Sticky sessions pinned to the upstream, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    current_sticky_sessions: Option<usize>,
+    /// Responses received (including those with a 5xx status), only reported for upstreams in the
+    /// pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    requests: Option<u64>,
+    /// Number of requests that ran into each kind of error, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    errors: Option<UpstreamErrors>,
+    /// Median of the recent response times (in milliseconds), only reported for upstreams in the
+    /// pool that have served a request
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    latency_p50: Option<u64>,
+    /// 95th percentile of the recent response times (in milliseconds), only reported for upstreams
+    /// in the pool that have served a request
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    latency_p95: Option<u64>,
+    /// Bytes of response bodies received, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    bytes_received: Option<u64>,
+    /// Time of the last successful response, only reported for upstreams in the pool that have
+    /// served one
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    last_success: Option<DateTime<Utc>>,
+    /// Progress of the warm-up, only reported for upstreams in the pool that are warming up
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    slow_start: Option<UpstreamSlowStart>,
+    /// Result of the health checks, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    health: Option<UpstreamHealth>,
+    /// State of the circuit breaker, only reported for upstreams in the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    circuit: Option<UpstreamCircuit>,
+    /// Progress of the drain, only reported for upstreams being drained out of the pool
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    drain: Option<UpstreamDraining>,
+}
+
+fn default_weight() -> usize {
+    1
 }
 
 impl From<&upstream::Upstream> for Upstream {
@@ -25,6 +99,192 @@ impl From<&upstream::Upstream> for Upstream {
             uri: upstream.uri.clone(),
             connections: upstream.connections,
             sticky_sessions: upstream.sticky_sessions,
+            weight: upstream.weight,
+            group: upstream.group.clone(),
+            priority: upstream.priority,
+            canary: upstream.canary.clone(),
+            canary_weight: upstream.canary_weight,
+            tls: UpstreamTls::from(&upstream.tls),
+            latency: None,
+            current_connections: None,
+            current_sticky_sessions: None,
+            requests: None,
+            errors: None,
+            latency_p50: None,
+            latency_p95: None,
+            bytes_received: None,
+            last_success: None,
+            slow_start: None,
+            health: None,
+            circuit: None,
+            drain: None,
+        }
+    }
+}
+
+impl From<&upstream::UpstreamStatus> for Upstream {
+    fn from(status: &upstream::UpstreamStatus) -> Self {
+        let statistics = &status.statistics;
+        Self {
+            latency: status.latency.map(|latency| latency.as_millis() as u64),
+            current_connections: Some(statistics.current_connections),
+            current_sticky_sessions: Some(statistics.current_sticky_sessions),
+            requests: Some(statistics.requests),
+            errors: Some(UpstreamErrors::from(&statistics.errors)),
+            latency_p50: statistics.latency_p50.map(|p50| p50.as_millis() as u64),
+            latency_p95: statistics.latency_p95.map(|p95| p95.as_millis() as u64),
+            bytes_received: Some(statistics.bytes_received),
+            last_success: statistics.last_success,
+            slow_start: status.slow_start.as_ref().map(UpstreamSlowStart::from),
+            health: Some(UpstreamHealth::from(&status.health)),
+            circuit: Some(UpstreamCircuit::from(&status.circuit)),
+            drain: status.drain.as_ref().map(UpstreamDraining::from),
+            ..Self::from(&status.upstream)
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamTls {
+    /// Path to a PEM bundle of CA certificates to trust, on top of the built-in roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_ca_path: Option<String>,
+    /// Path to a PEM client certificate for mutual TLS, set together with the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_certificate_path: Option<String>,
+    /// Path to the PEM private key of the client certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_key_path: Option<String>,
+    /// Hostname sent in SNI and checked against the certificate, rather than the URI host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_server_name: Option<String>,
+    /// Accept any certificate, only for testing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls_insecure_skip_verify: Option<bool>,
+}
+
+impl From<&upstream::UpstreamTls> for UpstreamTls {
+    fn from(tls: &upstream::UpstreamTls) -> Self {
+        Self {
+            tls_ca_path: tls.ca_path.clone(),
+            tls_certificate_path: tls.certificate_path.clone(),
+            tls_key_path: tls.key_path.clone(),
+            tls_server_name: tls.server_name.clone(),
+            tls_insecure_skip_verify: tls.insecure_skip_verify,
+        }
+    }
+}
+
+impl From<&UpstreamTls> for upstream::UpstreamTls {
+    fn from(tls: &UpstreamTls) -> Self {
+        Self {
+            ca_path: tls.tls_ca_path.clone(),
+            certificate_path: tls.tls_certificate_path.clone(),
+            key_path: tls.tls_key_path.clone(),
+            server_name: tls.tls_server_name.clone(),
+            insecure_skip_verify: tls.tls_insecure_skip_verify,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamErrors {
+    /// Couldn't connect to the upstream
+    connect: u64,
+    /// Upstream took too long to respond
+    timeout: u64,
+    /// Upstream responded with a 5xx status
+    server_error: u64,
+    other: u64,
+}
+
+impl From<&upstream::RequestErrors> for UpstreamErrors {
+    fn from(errors: &upstream::RequestErrors) -> Self {
+        Self {
+            connect: errors.connect,
+            timeout: errors.timeout,
+            server_error: errors.server_error,
+            other: errors.other,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamSlowStart {
+    /// Time the upstream has warmed up and takes its full limits
+    ends: DateTime<Utc>,
+    /// Connection limit while warming up
+    connections: usize,
+    /// Sticky session limit while warming up
+    sticky_sessions: usize,
+}
+
+impl From<&upstream::SlowStartStatus> for UpstreamSlowStart {
+    fn from(status: &upstream::SlowStartStatus) -> Self {
+        Self {
+            ends: status.ends,
+            connections: status.connections,
+            sticky_sessions: status.sticky_sessions,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamHealth {
+    /// Unhealthy upstreams are taken out of rotation until they pass their health checks again
+    healthy: bool,
+    checked: Option<DateTime<Utc>>,
+    /// Reason the last health check failed
+    error: Option<String>,
+}
+
+impl From<&upstream::UpstreamHealth> for UpstreamHealth {
+    fn from(health: &upstream::UpstreamHealth) -> Self {
+        Self {
+            healthy: health.healthy,
+            checked: health.checked,
+            error: health.error.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamCircuit {
+    /// "closed", "open" (no new traffic) or "half_open" (waiting on a trial request)
+    state: String,
+    /// Number of times the circuit has opened
+    trips: usize,
+    opened: Option<DateTime<Utc>>,
+}
+
+impl From<&upstream::CircuitBreaker> for UpstreamCircuit {
+    fn from(circuit: &upstream::CircuitBreaker) -> Self {
+        Self {
+            state: String::from(circuit.state),
+            trips: circuit.trips,
+            opened: circuit.opened,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamDraining {
+    started: DateTime<Utc>,
+    /// Time the upstream is removed, even if sticky sessions remain
+    deadline: DateTime<Utc>,
+    /// Sticky sessions still pinned to the upstream
+    sticky_sessions: usize,
+    /// Requests still in progress against the upstream
+    connections: usize,
+}
+
+impl From<&upstream::DrainStatus> for UpstreamDraining {
+    fn from(status: &upstream::DrainStatus) -> Self {
+        Self {
+            started: status.drain.started,
+            deadline: status.drain.deadline,
+            sticky_sessions: status.sticky_sessions,
+            connections: status.connections,
         }
     }
 }
@@ -35,10 +295,79 @@ impl From<&Upstream> for upstream::Upstream {
             uri: upstream.uri.clone(),
             connections: upstream.connections,
             sticky_sessions: upstream.sticky_sessions,
+            weight: upstream.weight.max(1),
+            group: upstream.group.clone(),
+            priority: upstream.priority,
+            canary: upstream.canary.clone(),
+            canary_weight: upstream.canary_weight.min(100),
+            tls: upstream::UpstreamTls::from(&upstream.tls),
         }
     }
 }
 
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"connections": 50}),
+        json!({"connections": 200, "sticky_sessions": 50})
+    )
+)]
+pub struct UpstreamLimits {
+    /// Maximum connections in progress.  Lowering it below the connections in progress lets them
+    /// finish, without starting new ones until the upstream is back under the limit
+    #[serde(default)]
+    pub connections: Option<usize>,
+    /// Maximum sticky sessions.  Lowering it below the current sticky sessions keeps them until
+    /// they expire
+    #[serde(default)]
+    pub sticky_sessions: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"label": null, "weight": 90, "upstreams": ["http://127.0.0.1:63111", "http://127.0.0.1:63112"], "sticky_sessions": 182, "connections": 40}),
+        json!({"label": "studio-11.1", "weight": 10, "upstreams": ["http://127.0.0.1:63113"], "sticky_sessions": 21, "connections": 5})
+    )
+)]
+pub struct Canary {
+    /// Canary label shared by the upstreams, or null for the upstreams without one
+    label: Option<String>,
+    /// Percentage of new sticky sessions and regular requests sent to the upstreams.  The upstreams
+    /// without a label get whatever the canaries don't take
+    weight: usize,
+    /// URIs of the upstreams with the label
+    upstreams: Vec<String>,
+    /// Sticky sessions pinned to the upstreams (across every server, when they are shared)
+    sticky_sessions: usize,
+    /// Requests in progress on the upstreams, from this server
+    connections: usize,
+}
+
+impl From<&upstream::CanaryStatus> for Canary {
+    fn from(status: &upstream::CanaryStatus) -> Self {
+        Self {
+            label: status.label.clone(),
+            weight: status.weight,
+            upstreams: status.upstreams.clone(),
+            sticky_sessions: status.sticky_sessions,
+            connections: status.connections,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"weight": 25})
+    )
+)]
+pub struct CanaryWeight {
+    /// Percentage (0 to 100) of new sticky sessions and regular requests to send to the canary.
+    /// `0` stops new sessions going to the canary, without moving the sessions already there
+    pub weight: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[schema(
     examples(
@@ -52,35 +381,55 @@ pub struct UpstreamRemove {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[schema(
     examples(
-        json!({"id": "ac42170a-a24b-4c9b-8eba-77204c05173d", "location": "queue", "position": 10}),
-        json!({"id": "1d42fe6c-baea-4645-a615-de9540764ea0", "location": "store", "position": null}),
-        json!({"id": "5bd3d674-8029-4526-b063-0c780e75ec50", "location": "missing", "position": null})
+        json!({"uri": "http://127.0.0.1:63111"}),
+        json!({"uri": "http://127.0.0.1:63112", "timeout": 600})
+    )
+)]
+pub struct UpstreamDrain {
+    pub(crate) uri: String,
+    /// Seconds to wait for sticky sessions to end before the upstream is removed, defaults to the
+    /// sticky session timeout
+    #[serde(default)]
+    pub(crate) timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"id": "ac42170a-a24b-4c9b-8eba-77204c05173d", "location": "queue", "position": 10, "eta": 360}),
+        json!({"id": "1d42fe6c-baea-4645-a615-de9540764ea0", "location": "store", "position": null, "eta": null}),
+        json!({"id": "5bd3d674-8029-4526-b063-0c780e75ec50", "location": "missing", "position": null, "eta": null})
     )
 )]
 pub struct QueuePosition {
     id: String,
     location: String,
     position: Option<usize>,
+    /// Estimated wait (in seconds) until the ID is promoted into the store, if known
+    eta: Option<u64>,
 }
 
 impl QueuePosition {
-    pub fn new(id: Uuid, position: queue::QueuePosition) -> Self {
+    pub fn new(id: Uuid, position: queue::QueuePosition, throughput: QueueThroughput) -> Self {
         let id = String::from(id);
         match position {
             queue::QueuePosition::NotPresent => Self {
                 id,
                 location: String::from("missing"),
                 position: None,
+                eta: None,
             },
             queue::QueuePosition::Store => Self {
                 id,
                 location: String::from("store"),
                 position: None,
+                eta: None,
             },
             queue::QueuePosition::Queue(position) => Self {
                 id,
                 location: String::from("queue"),
                 position: Some(position),
+                eta: throughput.eta(position).map(|eta| eta.as_secs()),
             },
         }
     }
@@ -89,11 +438,12 @@ impl QueuePosition {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[schema(
     examples(
-        json!({"name":"Omnis Bouncer","redis_uri":"redis://127.0.0.1","config_upstream":[{"uri":"http://127.0.0.1:63111","connections":100,"sticky_sessions":100}],"id_cookie_name":"omnis-bouncer-id","position_cookie_name":"omnis-bouncer-queue-position","queue_size_cookie_name":"omnis-bouncer-queue-size","position_http_header":"x-omnis-bouncer-queue-position","queue_size_http_header":"x-omnis-bouncer-queue-size","acquire_timeout":10,"connect_timeout":10,"cookie_id_expiration":86400,"sticky_session_timeout":600,"asset_cache_secs":60,"buffer_connections":1000,"js_client_rate_limit_per_sec":0,"api_rate_limit_per_sec":10,"ultra_rate_limit_per_sec":10,"public_http_port":3000,"public_https_port":3001,"monitor_https_port":2999,"queue_enabled":true,"queue_rotation_enabled":true,"store_capacity":5,"redis_prefix":"omnis_bouncer","quarantine_expiry":45,"validated_expiry":600,"publish_throttle":0})
+        json!({"name":"Omnis Bouncer","site":"default","redis_uri":"redis://127.0.0.1","config_upstream":[{"uri":"http://127.0.0.1:63111","connections":100,"sticky_sessions":100,"weight":1}],"id_cookie_name":"omnis-bouncer-id","position_cookie_name":"omnis-bouncer-queue-position","queue_size_cookie_name":"omnis-bouncer-queue-size","eta_cookie_name":"omnis-bouncer-queue-eta","priority_cookie_name":"omnis-bouncer-priority","position_http_header":"x-omnis-bouncer-queue-position","queue_size_http_header":"x-omnis-bouncer-queue-size","eta_http_header":"x-omnis-bouncer-queue-eta","priority_http_header":"x-omnis-bouncer-priority","priority_query_param":"omnis-bouncer-priority","invite_query_param":"omnis-bouncer-invite","acquire_timeout":10,"connect_timeout":10,"response_timeout_cache_load":30,"response_timeout_js_client":60,"response_timeout_push":300,"response_timeout_rest_api":60,"response_timeout_ultra_thin":120,"idle_timeout_cache_load":10,"idle_timeout_js_client":30,"idle_timeout_push":300,"idle_timeout_rest_api":30,"idle_timeout_ultra_thin":60,"retry_attempts":2,"retry_budget":10,"cookie_id_expiration":86400,"sticky_session_timeout":600,"shared_sticky_sessions":false,"asset_cache_secs":60,"buffer_connections":1000,"js_client_rate_limit_per_sec":0,"api_rate_limit_per_sec":10,"ultra_rate_limit_per_sec":10,"public_http_port":3000,"public_https_port":3001,"monitor_https_port":2999,"control_session_expiration":28800,"queue_enabled":true,"queue_rotation_enabled":true,"queue_disabled_policy":"drain","store_capacity":5,"redis_prefix":"omnis_bouncer","quarantine_expiry":45,"validated_expiry":600,"publish_throttle":0,"throughput_window":900,"health_check_enabled":true,"health_check_path":"/","health_check_interval":10,"health_check_timeout":5,"health_check_rise":2,"health_check_fall":3,"circuit_breaker_failures":5,"circuit_breaker_window":60,"circuit_breaker_cooldown":30,"load_balancing_regular":"least_connections","load_balancing_sticky":"least_sticky_sessions","load_balancing_cache_load":"least_connections","slow_start":0})
     )
 )]
 pub struct Config {
     pub name: String,
+    pub site: String,
     pub default_locale: String,
     pub locales: Vec<String>,
     pub redis_uri: String,
@@ -103,12 +453,31 @@ pub struct Config {
     pub id_upstream_http_header: String,
     pub id_evict_upstream_http_header: String,
     pub queue_size_cookie_name: String,
+    pub eta_cookie_name: String,
+    pub priority_cookie_name: String,
     pub position_http_header: String,
     pub queue_size_http_header: String,
+    pub eta_http_header: String,
+    pub priority_http_header: String,
+    pub priority_query_param: String,
+    pub invite_query_param: String,
     pub acquire_timeout: u64,
     pub connect_timeout: u64,
+    pub response_timeout_cache_load: u64,
+    pub response_timeout_js_client: u64,
+    pub response_timeout_push: u64,
+    pub response_timeout_rest_api: u64,
+    pub response_timeout_ultra_thin: u64,
+    pub idle_timeout_cache_load: u64,
+    pub idle_timeout_js_client: u64,
+    pub idle_timeout_push: u64,
+    pub idle_timeout_rest_api: u64,
+    pub idle_timeout_ultra_thin: u64,
+    pub retry_attempts: usize,
+    pub retry_budget: u64,
     pub cookie_id_expiration: u64,
     pub sticky_session_timeout: u64,
+    pub shared_sticky_sessions: bool,
     pub asset_cache_secs: u64,
     pub buffer_connections: usize,
     pub js_client_rate_limit_per_sec: u64,
@@ -117,37 +486,74 @@ pub struct Config {
     pub public_http_port: u16,
     pub public_https_port: u16,
     pub monitor_https_port: u16,
+    pub control_session_expiration: u64,
     pub queue_enabled: bool,
     pub queue_rotation_enabled: bool,
+    pub queue_disabled_policy: String,
     pub store_capacity: isize,
     pub redis_prefix: String,
     pub quarantine_expiry: u64,
     pub validated_expiry: u64,
     pub publish_throttle: u64,
+    pub throughput_window: u64,
+    pub health_check_enabled: bool,
+    pub health_check_path: String,
+    pub health_check_interval: u64,
+    pub health_check_timeout: u64,
+    pub health_check_rise: usize,
+    pub health_check_fall: usize,
+    pub circuit_breaker_failures: usize,
+    pub circuit_breaker_window: u64,
+    pub circuit_breaker_cooldown: u64,
+    pub load_balancing_regular: String,
+    pub load_balancing_sticky: String,
+    pub load_balancing_cache_load: String,
+    pub slow_start: u64,
     pub ultra_thin_inject_headers: bool,
     pub fallback_ultra_thin_library: Option<String>,
     pub fallback_ultra_thin_class: Option<String>,
 }
 
-impl From<&config::Config> for Config {
-    fn from(config: &config::Config) -> Self {
+impl Config {
+    /// Configuration as seen by a single site, which has its own queue, upstreams and cookies
+    pub fn new(config: &config::Config, site: &SiteConfig) -> Self {
         Self {
             name: config.app_name.clone(),
+            site: site.name.clone(),
             default_locale: config.default_locale.clone(),
             locales: config.locales.iter().cloned().collect(),
             redis_uri: config.redis_uri.clone(),
-            config_upstream: config.initial_upstream.iter().map(Upstream::from).collect(),
-            id_cookie_name: config.id_cookie_name.clone(),
-            position_cookie_name: config.position_cookie_name.clone(),
-            queue_size_cookie_name: config.queue_size_cookie_name.clone(),
+            config_upstream: site.initial_upstream.iter().map(Upstream::from).collect(),
+            id_cookie_name: site.id_cookie_name.clone(),
+            position_cookie_name: site.position_cookie_name.clone(),
+            queue_size_cookie_name: site.queue_size_cookie_name.clone(),
+            eta_cookie_name: site.eta_cookie_name.clone(),
+            priority_cookie_name: site.priority_cookie_name.clone(),
             id_upstream_http_header: config.id_upstream_http_header.clone(),
             id_evict_upstream_http_header: config.id_evict_upstream_http_header.clone(),
             position_http_header: config.position_http_header.clone(),
             queue_size_http_header: config.queue_size_http_header.clone(),
+            eta_http_header: config.eta_http_header.clone(),
+            priority_http_header: config.priority_http_header.clone(),
+            priority_query_param: config.priority_query_param.clone(),
+            invite_query_param: config.invite_query_param.clone(),
             acquire_timeout: config.acquire_timeout.as_secs(),
             connect_timeout: config.connect_timeout.as_secs(),
+            response_timeout_cache_load: config.response_timeout_cache_load.as_secs(),
+            response_timeout_js_client: config.response_timeout_js_client.as_secs(),
+            response_timeout_push: config.response_timeout_push.as_secs(),
+            response_timeout_rest_api: config.response_timeout_rest_api.as_secs(),
+            response_timeout_ultra_thin: config.response_timeout_ultra_thin.as_secs(),
+            idle_timeout_cache_load: config.idle_timeout_cache_load.as_secs(),
+            idle_timeout_js_client: config.idle_timeout_js_client.as_secs(),
+            idle_timeout_push: config.idle_timeout_push.as_secs(),
+            idle_timeout_rest_api: config.idle_timeout_rest_api.as_secs(),
+            idle_timeout_ultra_thin: config.idle_timeout_ultra_thin.as_secs(),
+            retry_attempts: config.retry_attempts,
+            retry_budget: config.retry_budget.as_secs(),
             cookie_id_expiration: config.cookie_id_expiration.as_secs(),
             sticky_session_timeout: config.sticky_session_timeout.as_secs(),
+            shared_sticky_sessions: config.shared_sticky_sessions,
             asset_cache_secs: config.asset_cache_secs.as_secs(),
             buffer_connections: config.buffer_connections,
             js_client_rate_limit_per_sec: config.js_client_rate_limit_per_sec,
@@ -156,13 +562,29 @@ impl From<&config::Config> for Config {
             public_http_port: config.http_port,
             public_https_port: config.https_port,
             monitor_https_port: config.control_port,
-            queue_enabled: config.queue_enabled,
+            control_session_expiration: config.control_session_expiration.as_secs(),
+            queue_enabled: site.queue_enabled,
             queue_rotation_enabled: config.queue_rotation_enabled,
-            store_capacity: isize::from(config.store_capacity),
-            redis_prefix: config.queue_prefix.clone(),
+            queue_disabled_policy: String::from(config.queue_disabled_policy),
+            store_capacity: isize::from(site.store_capacity),
+            redis_prefix: site.queue_prefix.clone(),
             quarantine_expiry: config.quarantine_expiry.as_secs(),
             validated_expiry: config.validated_expiry.as_secs(),
             publish_throttle: config.publish_throttle.as_secs(),
+            throughput_window: config.throughput_window.as_secs(),
+            health_check_enabled: config.health_check_enabled,
+            health_check_path: config.health_check_path.clone(),
+            health_check_interval: config.health_check_interval.as_secs(),
+            health_check_timeout: config.health_check_timeout.as_secs(),
+            health_check_rise: config.health_check_rise,
+            health_check_fall: config.health_check_fall,
+            circuit_breaker_failures: config.circuit_breaker_failures,
+            circuit_breaker_window: config.circuit_breaker_window.as_secs(),
+            circuit_breaker_cooldown: config.circuit_breaker_cooldown.as_secs(),
+            load_balancing_regular: String::from(config.load_balancing_regular),
+            load_balancing_sticky: String::from(config.load_balancing_sticky),
+            load_balancing_cache_load: String::from(config.load_balancing_cache_load),
+            slow_start: config.slow_start.as_secs(),
             ultra_thin_inject_headers: config.ultra_thin_inject_headers,
             fallback_ultra_thin_library: config.fallback_ultra_thin_library.clone(),
             fallback_ultra_thin_class: config.fallback_ultra_thin_class.clone(),
@@ -170,6 +592,80 @@ impl From<&config::Config> for Config {
     }
 }
 
+/// Site that a control API request applies to
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SiteQuery {
+    /// Name of the site (default: "default")
+    pub site: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"name": "default", "hosts": [], "path_prefix": null, "redis_prefix": "omnis_bouncer"}),
+        json!({"name": "tickets", "hosts": ["tickets.example.com"], "path_prefix": null, "redis_prefix": "omnis_bouncer:tickets"})
+    )
+)]
+pub struct Site {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub path_prefix: Option<String>,
+    pub redis_prefix: String,
+}
+
+impl From<&SiteConfig> for Site {
+    fn from(site: &SiteConfig) -> Self {
+        Self {
+            name: site.name.clone(),
+            hosts: site.hosts.clone(),
+            path_prefix: site.path_prefix.clone(),
+            redis_prefix: site.queue_prefix.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"username": "admin", "password": "hunter2"}),
+        json!({"token": "1b0e5e3d4c5a4b9c8d7e6f5a4b3c2d1e"})
+    )
+)]
+pub struct Login {
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"name": "admin", "method": "basic", "role": "admin"})
+    )
+)]
+pub struct Whoami {
+    pub name: String,
+    pub method: String,
+    pub role: String,
+}
+
+impl From<&Identity> for Whoami {
+    fn from(identity: &Identity) -> Self {
+        Self {
+            name: identity.name.clone(),
+            method: match identity.method {
+                AuthMethod::Bearer => String::from("bearer"),
+                AuthMethod::Basic => String::from("basic"),
+            },
+            role: identity.role.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 #[schema(
     examples(
@@ -208,7 +704,192 @@ pub struct SettingsPatch {
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[schema(
     examples(
-        json!({"queue_enabled": true, "store_capacity": 10, "queue_size": 100, "store_size": 10, "updated": "2025-09-23T10:44"})
+        json!({"name": "staff", "priority": 100, "bypass": true}),
+        json!({"name": "members", "priority": 10, "bypass": false})
+    )
+)]
+pub struct PriorityTier {
+    pub name: String,
+    /// Higher priorities are promoted into the store first (1 to 1000)
+    pub priority: u16,
+    /// Skip the queue entirely, straight into the store
+    pub bypass: bool,
+}
+
+impl From<queue::PriorityTier> for PriorityTier {
+    fn from(tier: queue::PriorityTier) -> Self {
+        Self {
+            name: tier.name,
+            priority: tier.priority,
+            bypass: tier.bypass,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"priority": 100, "bypass": true})
+    )
+)]
+pub struct PriorityTierUpdate {
+    pub priority: u16,
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"expires_in": 86400})
+    )
+)]
+pub struct PriorityTokenRequest {
+    /// Seconds until the token expires (default: 1 day)
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"tier": "staff", "token": "eyJwdXJwb3NlIjoicHJpb3JpdHkiLCJleHBpcmVzIjoxNzU4MDQwODAwLCJjbGFpbXMiOnsidGllciI6InN0YWZmIn19.2Vv1m6TqCfKpQJ3cX0k5Yy5b3Jp1W6Jd4d7Xr9m0a1Q", "expires": "2025-09-23T10:44:00Z"})
+    )
+)]
+pub struct PriorityToken {
+    pub tier: String,
+    pub token: String,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"uses": 100, "expires_in": 604800})
+    )
+)]
+pub struct InviteRequest {
+    /// Number of visitors that can redeem the invite (default: 1)
+    #[serde(default)]
+    pub uses: Option<u32>,
+    /// Seconds until the invite expires (default: 7 days)
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"invite": "bdc2ddc5-6e30-4c3c-b5a3-05d1a3c1f9a4", "uses": 100, "token": "eyJwdXJwb3NlIjoiaW52aXRlIiwiZXhwaXJlcyI6MTc1ODY0NTg0MCwiY2xhaW1zIjp7Imludml0ZSI6ImJkYzJkZGM1LTZlMzAtNGMzYy1iNWEzLTA1ZDFhM2MxZjlhNCIsInVzZXMiOjEwMH19.kV9p0m3Qd2Yx1Jt8cR5nW7bL4sE6hF0aG2iK9uZ3oXc", "expires": "2025-09-30T10:44:00Z"})
+    )
+)]
+pub struct Invite {
+    pub invite: String,
+    pub uses: u32,
+    pub token: String,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"invite": "bdc2ddc5-6e30-4c3c-b5a3-05d1a3c1f9a4", "redeemed": 42})
+    )
+)]
+pub struct InviteRedemptions {
+    pub invite: String,
+    pub redeemed: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"from": 100, "to": 1000, "minutes": 30})
+    )
+)]
+pub struct CapacityRamp {
+    /// Store capacity when the schedule starts
+    pub from: usize,
+    /// Store capacity once the ramp has finished
+    pub to: usize,
+    /// Minutes taken to move from one capacity to the other
+    pub minutes: u32,
+}
+
+impl From<queue::CapacityRamp> for CapacityRamp {
+    fn from(ramp: queue::CapacityRamp) -> Self {
+        Self {
+            from: ramp.from,
+            to: ramp.to,
+            minutes: ramp.minutes,
+        }
+    }
+}
+
+impl From<CapacityRamp> for queue::CapacityRamp {
+    fn from(ramp: CapacityRamp) -> Self {
+        Self {
+            from: ramp.from,
+            to: ramp.to,
+            minutes: ramp.minutes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"id": "0f7c1a8e-5d0b-4f2e-9a43-6b8d2e1c7f90", "start": "2025-09-23T09:00:00Z", "ends": "2025-09-23T09:00:00Z", "queue_enabled": true, "store_capacity": 100, "ramp": null}),
+        json!({"id": "6a2d9b44-17c3-4e85-8f0a-3c5e7d9b1a26", "start": "2025-09-23T09:00:00Z", "ends": "2025-09-23T09:30:00Z", "queue_enabled": null, "store_capacity": null, "ramp": {"from": 100, "to": 1000, "minutes": 30}})
+    )
+)]
+pub struct Schedule {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    /// Time the schedule has been fully applied, after any ramp has finished
+    pub ends: DateTime<Utc>,
+    pub queue_enabled: Option<bool>,
+    pub store_capacity: Option<isize>,
+    pub ramp: Option<CapacityRamp>,
+}
+
+impl From<queue::Schedule> for Schedule {
+    fn from(schedule: queue::Schedule) -> Self {
+        Self {
+            ends: schedule.ends(),
+            id: schedule.id,
+            start: schedule.start,
+            queue_enabled: schedule.queue_enabled,
+            store_capacity: schedule.store_capacity,
+            ramp: schedule.ramp.map(CapacityRamp::from),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"start": "2025-09-23T09:00:00Z", "queue_enabled": true, "store_capacity": 100}),
+        json!({"start": "2025-09-23T09:00:00Z", "ramp": {"from": 100, "to": 1000, "minutes": 30}})
+    )
+)]
+pub struct ScheduleUpdate {
+    /// Time (UTC) the settings are applied
+    pub start: DateTime<Utc>,
+    #[serde(default)]
+    pub queue_enabled: Option<bool>,
+    /// Store capacity to set at the start time (`-1` for unlimited)
+    #[serde(default)]
+    pub store_capacity: Option<isize>,
+    /// Ramp the store capacity from the start time, instead of setting it outright
+    #[serde(default)]
+    pub ramp: Option<CapacityRamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[schema(
+    examples(
+        json!({"queue_enabled": true, "store_capacity": 10, "queue_size": 100, "store_size": 10, "throughput_per_minute": 16.5, "queue_eta": 364, "updated": "2025-09-23T10:44"})
     )
 )]
 pub struct Status {
@@ -216,6 +897,10 @@ pub struct Status {
     pub store_capacity: isize,
     pub queue_size: usize,
     pub store_size: usize,
+    /// IDs leaving the queue per minute, over the throughput window
+    pub throughput_per_minute: f64,
+    /// Estimated wait (in seconds) for an ID joining the back of the queue, if known
+    pub queue_eta: Option<u64>,
     pub updated: Option<DateTime<Utc>>,
 }
 
@@ -226,6 +911,11 @@ impl From<QueueStatus> for Status {
             store_capacity: status.capacity.into(),
             queue_size: status.queue_size,
             store_size: status.store_size,
+            throughput_per_minute: status.throughput.per_minute(),
+            queue_eta: status
+                .throughput
+                .eta(status.queue_size + 1)
+                .map(|eta| eta.as_secs()),
             updated: status.updated,
         }
     }
@@ -240,6 +930,11 @@ pub enum Event {
     QueueRemoved,
     StoreAdded,
     StoreExpired,
+    UpstreamCircuitOpened,
+    UpstreamCircuitClosed,
+    UpstreamsChanged,
+    UpstreamFailover,
+    UpstreamFailback,
 }
 
 impl From<QueueEvent> for Event {
@@ -252,6 +947,11 @@ impl From<QueueEvent> for Event {
             QueueEvent::StoreAdded => Self::StoreAdded,
             QueueEvent::StoreExpired => Self::StoreExpired,
             QueueEvent::QueueRemoved => Self::QueueRemoved,
+            QueueEvent::UpstreamCircuitOpened => Self::UpstreamCircuitOpened,
+            QueueEvent::UpstreamCircuitClosed => Self::UpstreamCircuitClosed,
+            QueueEvent::UpstreamsChanged => Self::UpstreamsChanged,
+            QueueEvent::UpstreamFailover => Self::UpstreamFailover,
+            QueueEvent::UpstreamFailback => Self::UpstreamFailback,
         }
     }
 }
@@ -266,6 +966,11 @@ impl From<Event> for String {
             Event::StoreAdded => String::from("store:added"),
             Event::StoreExpired => String::from("store:expired"),
             Event::QueueRemoved => String::from("queue:removed"),
+            Event::UpstreamCircuitOpened => String::from("upstream:circuit_opened"),
+            Event::UpstreamCircuitClosed => String::from("upstream:circuit_closed"),
+            Event::UpstreamsChanged => String::from("upstream:updated"),
+            Event::UpstreamFailover => String::from("upstream:failover"),
+            Event::UpstreamFailback => String::from("upstream:failback"),
         }
     }
 }
@@ -1,47 +1,65 @@
+use async_stream::stream;
 use axum::extract::Query;
 use axum::response::Html;
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     extract::{
         Path, State,
         ws::{self, WebSocketUpgrade},
     },
+    middleware,
     response::{
         Response,
         sse::{Event as SSEvent, KeepAlive, Sse},
     },
     routing::any,
 };
+use chrono::Utc;
 use futures_util::stream::Stream;
+#[cfg(debug_assertions)]
+use http::header::AUTHORIZATION;
 use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header::CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::time::Duration;
 use tokio_stream::StreamExt;
 use tower_cookies::{CookieManagerLayer, Cookies};
 use tower_http::{compression::CompressionLayer, decompression::RequestDecompressionLayer};
 use tower_serve_static::{File, ServeDir, ServeFile};
 use tracing::{debug, error};
 use utoipa::{
-    OpenApi, openapi::Tag, openapi::extensions::ExtensionsBuilder, openapi::tag::TagBuilder,
+    OpenApi,
+    openapi::Tag,
+    openapi::extensions::{Extensions, ExtensionsBuilder},
+    openapi::security::{Http, HttpAuthScheme, SecurityRequirement, SecurityScheme},
+    openapi::tag::TagBuilder,
 };
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 
+use crate::auth::{Credentials, Identity, Role};
 use crate::constants::{
-    AUTHORITY_CERT, AUTHORITY_PFX, STATIC_ASSETS_DIR, UI_ASSET_DIR, UI_FAVICON, UI_INDEX,
+    AUTHORITY_CERT, AUTHORITY_PFX, DEFAULT_INVITE_EXPIRY, DEFAULT_INVITE_USES,
+    DEFAULT_PRIORITY_TOKEN_EXPIRY, STATIC_ASSETS_DIR, UI_ASSET_DIR, UI_FAVICON, UI_INDEX,
+    UPSTREAM_STATS_INTERVAL,
 };
+use crate::control::auth::{end_session, require_role, start_session};
 use crate::control::models::{
-    Config, Event, QueuePosition, Settings, SettingsPatch, Status, Upstream, UpstreamRemove,
+    Canary, CanaryWeight, Config, Event, Invite, InviteRedemptions, InviteRequest, Login,
+    PriorityTier, PriorityTierUpdate, PriorityToken, PriorityTokenRequest, QueuePosition, Schedule,
+    ScheduleUpdate, Settings, SettingsPatch, Site, SiteQuery, Status, Upstream, UpstreamDrain,
+    UpstreamLimits, UpstreamRemove, Whoami,
 };
 use crate::errors::{Error, Result};
-use crate::queue::StoreCapacity;
+use crate::queue::{self, QueueThroughput, StoreCapacity};
 use crate::secrets::encode_master_key;
 use crate::signals::cancellable;
 use crate::state::AppState;
-use crate::{cookies, upstream};
+use crate::waiting_room::{sign_invite_token, sign_priority_token};
+use crate::{cookies, tokens, upstream};
 
 #[cfg(debug_assertions)]
 use http::Method;
@@ -97,6 +115,42 @@ fn build_tag(
     tag
 }
 
+/// Security requirements for either a bearer token or a username/password, holding `role`
+fn role_security(role: Role) -> Vec<SecurityRequirement> {
+    vec![
+        SecurityRequirement::new("bearer", [role.as_str()]),
+        SecurityRequirement::new("basic", [role.as_str()]),
+    ]
+}
+
+fn role_extensions(role: Role) -> Extensions {
+    ExtensionsBuilder::new()
+        .add("x-required-role", role.as_str())
+        .build()
+}
+
+/// Document the role required for every operation in a router, as both security requirement
+/// scopes (which Swagger and Redoc display) and an `x-required-role` extension
+fn with_required_role(mut router: OpenApiRouter<AppState>, role: Role) -> OpenApiRouter<AppState> {
+    for item in router.get_openapi_mut().paths.paths.values_mut() {
+        let operations = [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.patch,
+        ];
+        for operation in operations.into_iter().flatten() {
+            operation.security = Some(role_security(role));
+            operation
+                .extensions
+                .get_or_insert_default()
+                .merge(role_extensions(role));
+        }
+    }
+    router
+}
+
 pub fn router(state: AppState) -> Router {
     // Support static file handling from /static directory that is embedded in the final binary
     let static_service = ServeDir::new(&STATIC_ASSETS_DIR);
@@ -111,23 +165,67 @@ pub fn router(state: AppState) -> Router {
     // Create OpenAPI instance
     let openapi = ControlAPI::openapi();
 
-    // Create OpenAPI router with catalogued routes
-    #[allow(unused_mut)]
-    let openapi_router = OpenApiRouter::with_openapi(openapi)
-        .routes(routes!(get_health))
+    // Middleware that requires routes to be authenticated with at least the given role
+    let role_layer =
+        |role: Role| middleware::from_fn_with_state((state.clone(), role), require_role);
+
+    // Read-only routes, for dashboards and monitoring
+    let read_router = OpenApiRouter::new()
+        .routes(routes!(get_whoami))
         .routes(routes!(get_config))
-        .routes(routes!(get_cookie_key))
+        .routes(routes!(get_sites))
         .routes(routes!(get_authority_pfx))
         .routes(routes!(get_authority_pem))
-        .routes(routes!(get_upstreams, add_upstreams, remove_upstreams))
+        .routes(routes!(get_upstreams))
         .routes(routes!(get_status))
-        .routes(routes!(get_settings, patch_settings))
+        .routes(routes!(get_settings))
         .routes(routes!(get_waiting_page_accept_language))
-        .routes(routes!(get_waiting_page, set_waiting_page))
-        .routes(routes!(add_store_id))
-        .routes(routes!(get_queue_id, add_queue_id, delete_queue_id))
+        .routes(routes!(get_waiting_page))
+        .routes(routes!(get_queue_id))
+        .routes(routes!(get_priority_tiers))
+        .routes(routes!(get_invite))
+        .routes(routes!(get_schedules))
+        .routes(routes!(get_schedule))
         .routes(routes!(get_server_sent_events))
+        .routes(routes!(get_upstream_events))
+        .routes(routes!(get_canaries))
         .route("/api/ws", any(get_web_socket))
+        .route_layer(role_layer(Role::Read));
+
+    // Operator routes, for on-call staff adjusting the queue
+    let operator_router = OpenApiRouter::new()
+        .routes(routes!(patch_settings))
+        .routes(routes!(add_store_id))
+        .routes(routes!(add_queue_id))
+        .routes(routes!(delete_queue_id))
+        .routes(routes!(set_priority_tier, remove_priority_tier))
+        .routes(routes!(create_priority_token))
+        .routes(routes!(create_invite))
+        .routes(routes!(create_schedule))
+        .routes(routes!(set_schedule, remove_schedule))
+        .route_layer(role_layer(Role::Operator));
+
+    // Admin routes, for changing the shape of the deployment and reading secrets
+    let admin_router = OpenApiRouter::new()
+        .routes(routes!(get_cookie_key))
+        .routes(routes!(add_upstreams))
+        .routes(routes!(remove_upstreams))
+        .routes(routes!(drain_upstreams))
+        .routes(routes!(patch_upstream))
+        .routes(routes!(put_canary_weight))
+        .routes(routes!(set_waiting_page))
+        .route_layer(role_layer(Role::Admin));
+
+    // Create OpenAPI router with catalogued routes.  Only health checks, login, and the static UI
+    // shell that presents the login form are open, everything else requires a role
+    #[allow(unused_mut)]
+    let openapi_router = OpenApiRouter::with_openapi(openapi)
+        .merge(with_required_role(read_router, Role::Read))
+        .merge(with_required_role(operator_router, Role::Operator))
+        .merge(with_required_role(admin_router, Role::Admin))
+        .routes(routes!(get_health))
+        .routes(routes!(login))
+        .routes(routes!(logout))
         .nest_service("/favicon.ico", favicon_service)
         .nest_service("/static", static_service)
         .nest_service("/assets", asset_service);
@@ -149,6 +247,27 @@ pub fn router(state: AppState) -> Router {
             "Routes for streaming events from the server",
         ),
         build_tag("ops", "Operations", "Routes for simpler DevOps"),
+        build_tag(
+            "auth",
+            "Authentication",
+            "Routes for signing in to the control server",
+        ),
+    ]);
+
+    // All routes require either a bearer token, or a username/password, unless specifically
+    // marked as open
+    let components = api.components.get_or_insert_default();
+    components.add_security_scheme(
+        "bearer",
+        SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+    );
+    components.add_security_scheme(
+        "basic",
+        SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+    );
+    api.security = Some(vec![
+        SecurityRequirement::new("bearer", Vec::<String>::new()),
+        SecurityRequirement::new("basic", Vec::<String>::new()),
     ]);
 
     let extensions = api
@@ -160,6 +279,7 @@ pub fn router(state: AppState) -> Router {
         TagGroup::new("queue", ["queue"].to_vec()),
         TagGroup::new("stream", ["stream"].to_vec()),
         TagGroup::new("ops", ["ops"].to_vec()),
+        TagGroup::new("auth", ["auth"].to_vec()),
     ];
     extensions.insert(
         String::from("x-tagGroup"),
@@ -180,28 +300,40 @@ pub fn router(state: AppState) -> Router {
 * `store:added`
 * `store:expired`
 * `queue:removed`
+* `upstream:circuit_opened`
+* `upstream:circuit_closed`
+* `upstream:updated`
+* `upstream:failover`
+* `upstream:failback`
 
 See [MDN - Writing Web Socket Client Applications](https://developer.mozilla.org/en-US/docs/Web/API/WebSockets_API/Writing_WebSocket_client_applications) for more details",
             ))
-            .tag("stream"),
+            .tag("stream")
+            .securities(Some(role_security(Role::Read)))
+            .extensions(Some(role_extensions(Role::Read))),
     );
     api.paths.paths.insert(String::from("/api/ws"), path);
     // END WORKAROUND
 
-    // Merge OpenAPI spec into routing
-    router = router
+    // Merge OpenAPI spec into routing (documentation also requires the read role)
+    let docs_router: Router<AppState> = Router::new()
         .merge(SwaggerUi::new("/swagger").url("/openapi.json", api.clone()))
         .merge(Redoc::with_url("/docs", api))
-        .fallback(control_ui_handler);
+        .route_layer(role_layer(Role::Read));
+
+    router = router.merge(docs_router).fallback(control_ui_handler);
 
     #[cfg(debug_assertions)]
     {
         let origins = [LOCALHOST_CORS_DEBUG_URI.parse().unwrap()];
         let cors_layer = CorsLayer::new()
             // allow `GET` and `POST` when accessing the resource
-            .allow_methods([Method::GET, Method::PATCH])
+            .allow_methods([Method::GET, Method::PATCH, Method::POST])
             // allow requests from any origin
-            .allow_origin(origins);
+            .allow_origin(origins)
+            // allow the session cookie and credentials to be sent from the UI dev server
+            .allow_credentials(true)
+            .allow_headers([AUTHORIZATION, CONTENT_TYPE]);
 
         router = router.layer(cors_layer);
     }
@@ -219,6 +351,7 @@ See [MDN - Writing Web Socket Client Applications](https://developer.mozilla.org
     tag = "ops",
     summary = "Health",
     description = "Health check for use with telemetry and containers",
+    security(()),
     responses(
         (status = 200, description = "OK", body = String, example = "ok")
     )
@@ -227,6 +360,70 @@ async fn get_health() -> String {
     String::from("ok")
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    tag = "auth",
+    summary = "Login",
+    description = "Sign in with a username and password, or an API token, and start a session for the control UI",
+    request_body = Login,
+    security(()),
+    responses(
+        (status = 200, description = "OK", body = Whoami),
+        (status = 401, description = "Unauthorized", body = String, example = "unauthorized"),
+    )
+)]
+async fn login(
+    State(state): State<AppState>,
+    cookies: Cookies,
+    Json(login): Json<Login>,
+) -> Result<Json<Whoami>> {
+    let credentials = match (login.username, login.password, login.token) {
+        (Some(username), Some(password), _) => Credentials::Basic(username, password),
+        (_, _, Some(token)) => Credentials::Bearer(token),
+        _ => return Err(Error::Unauthorized),
+    };
+
+    let Some(identity) = state.authenticators.authenticate(&credentials) else {
+        return Err(Error::Unauthorized);
+    };
+
+    start_session(&state, &cookies, &identity)?;
+
+    Ok(Json(Whoami::from(&identity)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    tag = "auth",
+    summary = "Logout",
+    description = "End the current control UI session",
+    security(()),
+    responses(
+        (status = 204, description = "No Content"),
+    )
+)]
+async fn logout(State(state): State<AppState>, cookies: Cookies) -> StatusCode {
+    end_session(&state, &cookies);
+    StatusCode::NO_CONTENT
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/whoami",
+    tag = "auth",
+    summary = "Current Identity",
+    description = "Identity that the current request is authenticated as",
+    responses(
+        (status = 200, description = "OK", body = Whoami),
+        (status = 401, description = "Unauthorized", body = String, example = "unauthorized"),
+    )
+)]
+async fn get_whoami(Extension(identity): Extension<Identity>) -> Json<Whoami> {
+    Json(Whoami::from(&identity))
+}
+
 #[utoipa::path(
     get,
     path = "/api/config",
@@ -235,15 +432,41 @@ async fn get_health() -> String {
     description = "General configuration of the server",
     responses(
         (status = 200, description = "OK", body = Config)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn get_config(State(state): State<AppState>) -> Result<Json<Config>> {
+async fn get_config(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Config>> {
     let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
     let config = &state.config;
-    let config = Config::from(config);
+    let config = Config::new(config, site);
     Ok(Json(config))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/sites",
+    tag = "server",
+    summary = "Sites",
+    description = "All sites served by the bouncer, in the order they are matched against requests.  Every other route applies to the site named by its `site` query parameter, or the default site",
+    responses(
+        (status = 200, description = "OK", body = Vec<Site>)
+    )
+)]
+async fn get_sites(State(state): State<AppState>) -> Json<Vec<Site>> {
+    let state = state.clone();
+    Json(
+        state
+            .sites
+            .iter()
+            .map(|site| Site::from(&site.config))
+            .collect(),
+    )
+}
+
 #[utoipa::path(
     get,
     path = "/api/generate_key",
@@ -296,22 +519,27 @@ async fn get_authority_pem() -> Result<String> {
     path = "/api/upstreams",
     tag = "server",
     summary = "Upstream Servers",
-    description = "List of all currently active upstream servers",
+    description = "List of all currently active upstream servers, along with their health, circuit breaker state and live statistics",
     responses(
         (status = 200, description = "OK", body = Vec<Upstream>)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn get_upstreams(State(state): State<AppState>) -> Json<Vec<Upstream>> {
+async fn get_upstreams(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Vec<Upstream>>> {
     let state = state.clone();
-    let upstream_pool = &state.upstream_pool;
-    Json(
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
+    Ok(Json(
         upstream_pool
-            .upstreams()
+            .statuses()
             .await
             .iter()
             .map(Upstream::from)
             .collect(),
-    )
+    ))
 }
 
 #[utoipa::path(
@@ -319,25 +547,40 @@ async fn get_upstreams(State(state): State<AppState>) -> Json<Vec<Upstream>> {
     path = "/api/upstreams",
     tag = "server",
     summary = "Add Upstream Servers",
-    description = "Add one or more upstream Omnis Studio servers",
+    description = "Add one or more upstream Omnis Studio servers.  Upstreams are stored in Redis, and shared with every server using the same queue.  TLS options not set on an upstream are taken from the global options",
     request_body = [Upstream],
     responses(
         (status = 201, description = "Created"),
+        (status = 400, description = "Bad Request", body = String, example = "upstream TLS options invalid: https://10.0.0.5:63111: CA bundle \"/certs/lab-ca.pem\": No such file or directory (os error 2)"),
         (status = 422, description = "Unprocessable Entity", body = String, example = "Failed to deserialize the JSON body into the target type: [0].connections: invalid type: string \"whoopsie\", expected usize at line 2 column 27"),
-    )
+    ),
+    params(SiteQuery)
 )]
 async fn add_upstreams(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Json(upstreams): Json<Vec<Upstream>>,
-) -> StatusCode {
+) -> Result<StatusCode> {
     let state = state.clone();
-    let upstream_pool = &state.upstream_pool;
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
 
     let upstreams: Vec<upstream::Upstream> =
         upstreams.iter().map(upstream::Upstream::from).collect();
+    for upstream in upstreams.iter() {
+        state.http_clients.upstream(&upstream.uri, &upstream.tls)?;
+    }
+    site.sync_upstreams(&state.queue).await?;
     upstream_pool.add_upstreams(&upstreams).await;
 
-    StatusCode::CREATED
+    let uris: Vec<String> = upstreams.iter().map(|u| u.uri.clone()).collect();
+    let records = upstream_pool.records(&uris).await;
+    state
+        .queue
+        .set_upstreams(&site.queue_prefix, &records)
+        .await?;
+
+    Ok(StatusCode::CREATED)
 }
 
 #[utoipa::path(
@@ -350,19 +593,200 @@ async fn add_upstreams(
     responses(
         (status = 200, description = "OK"),
         (status = 422, description = "Unprocessable Entity", body = String, example = "Failed to deserialize the JSON body into the target type: [0].connections: invalid type: string \"whoopsie\", expected usize at line 2 column 27"),
-    )
+    ),
+    params(SiteQuery)
 )]
 async fn remove_upstreams(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Json(upstreams): Json<Vec<UpstreamRemove>>,
-) -> StatusCode {
+) -> Result<StatusCode> {
     let state = state.clone();
-    let upstream_pool = &state.upstream_pool;
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
 
     let upstreams: Vec<String> = upstreams.iter().map(|u| u.uri.clone()).collect();
+    site.sync_upstreams(&state.queue).await?;
     upstream_pool.remove_uris(&upstreams).await;
+    state
+        .queue
+        .remove_upstreams(&site.queue_prefix, &upstreams)
+        .await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/upstreams/drain",
+    tag = "server",
+    summary = "Drain Upstream Servers",
+    description = "Drain one or more upstream Omnis Studio servers out of the pool.  Draining servers get no new connections or sticky sessions, and are removed once their sticky sessions have ended or the timeout has passed",
+    request_body = [UpstreamDrain],
+    responses(
+        (status = 200, description = "OK"),
+        (status = 404, description = "Not Found", body = String, example = "upstream not found: http://127.0.0.1:63111"),
+        (status = 422, description = "Unprocessable Entity", body = String, example = "Failed to deserialize the JSON body into the target type: [0].timeout: invalid type: string \"whoopsie\", expected u64 at line 2 column 23"),
+    ),
+    params(SiteQuery)
+)]
+async fn drain_upstreams(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Json(upstreams): Json<Vec<UpstreamDrain>>,
+) -> Result<StatusCode> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
+
+    let upstreams: Vec<(String, Option<Duration>)> = upstreams
+        .iter()
+        .map(|u| (u.uri.clone(), u.timeout.map(Duration::from_secs)))
+        .collect();
+    site.sync_upstreams(&state.queue).await?;
+    upstream_pool
+        .drain_uris(&upstreams)
+        .await
+        .map_err(Error::UpstreamMissing)?;
+
+    let uris: Vec<String> = upstreams.into_iter().map(|(uri, _)| uri).collect();
+    let records = upstream_pool.records(&uris).await;
+    state
+        .queue
+        .set_upstreams(&site.queue_prefix, &records)
+        .await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/upstreams/{uri}",
+    tag = "server",
+    summary = "Upstream Server Limits",
+    description = "Change the connection and sticky session limits of an upstream Omnis Studio server in place, without dropping its connections or sticky sessions",
+    request_body = UpstreamLimits,
+    responses(
+        (status = 200, description = "OK", body = Upstream),
+        (status = 404, description = "Not Found", body = String, example = "upstream not found: http://127.0.0.1:63111"),
+        (status = 422, description = "Unprocessable Entity", body = String, example = "Failed to deserialize the JSON body into the target type: connections: invalid type: string \"whoopsie\", expected usize at line 2 column 27"),
+    ),
+    params(
+        SiteQuery,
+        ("uri" = String, Path, description = "URI of the upstream server (URL encoded)")
+    )
+)]
+async fn patch_upstream(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(uri): Path<String>,
+    Json(limits): Json<UpstreamLimits>,
+) -> Result<Json<Upstream>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
+
+    site.sync_upstreams(&state.queue).await?;
+    upstream_pool
+        .update_limits(&uri, limits.connections, limits.sticky_sessions)
+        .await
+        .map_err(Error::UpstreamMissing)?;
+
+    let records = upstream_pool.records(std::slice::from_ref(&uri)).await;
+    state
+        .queue
+        .set_upstreams(&site.queue_prefix, &records)
+        .await?;
 
-    StatusCode::OK
+    match upstream_pool
+        .statuses()
+        .await
+        .iter()
+        .find(|status| status.upstream.uri == uri)
+    {
+        Some(status) => Ok(Json(Upstream::from(status))),
+        // Removed straight after the update
+        None => Err(Error::UpstreamMissing(uri)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/upstreams/canaries",
+    tag = "server",
+    summary = "Upstream Canaries",
+    description = "Upstream servers grouped by canary label, with the share of new sticky sessions and regular requests each label gets and the sessions stuck to it.  Upstreams without a label are listed with a null label",
+    responses(
+        (status = 200, description = "OK", body = Vec<Canary>)
+    ),
+    params(SiteQuery)
+)]
+async fn get_canaries(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Vec<Canary>>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    Ok(Json(
+        site.upstream_pool
+            .canaries()
+            .await
+            .iter()
+            .map(Canary::from)
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/upstreams/canaries/{label}",
+    tag = "server",
+    summary = "Upstream Canary Weight",
+    description = "Change the percentage of new sticky sessions and regular requests sent to the upstream servers with a canary label.  Sessions already stuck to an upstream stay where they are",
+    request_body = CanaryWeight,
+    responses(
+        (status = 200, description = "OK", body = Vec<Canary>),
+        (status = 400, description = "Bad Request", body = String, example = "canary weight should be a percentage from 0 to 100"),
+        (status = 404, description = "Not Found", body = String, example = "canary not found: studio-11.1"),
+    ),
+    params(
+        SiteQuery,
+        ("label" = String, Path, description = "Canary label of the upstream servers")
+    )
+)]
+async fn put_canary_weight(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(label): Path<String>,
+    Json(canary): Json<CanaryWeight>,
+) -> Result<Json<Vec<Canary>>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let upstream_pool = &site.upstream_pool;
+    if canary.weight > 100 {
+        return Err(Error::CanaryWeightOutOfRange(canary.weight));
+    }
+
+    site.sync_upstreams(&state.queue).await?;
+    let uris = upstream_pool
+        .set_canary_weight(&label, canary.weight)
+        .await
+        .map_err(Error::CanaryMissing)?;
+
+    let records = upstream_pool.records(&uris).await;
+    state
+        .queue
+        .set_upstreams(&site.queue_prefix, &records)
+        .await?;
+
+    Ok(Json(
+        upstream_pool
+            .canaries()
+            .await
+            .iter()
+            .map(Canary::from)
+            .collect(),
+    ))
 }
 
 #[utoipa::path(
@@ -373,14 +797,18 @@ async fn remove_upstreams(
     description = "Status of the queue, combined with settings for easy access",
     responses(
         (status = 200, description = "OK", body = Status)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn get_status(State(state): State<AppState>) -> Result<Json<Status>> {
+async fn get_status(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Status>> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
-    let queue_status = queue.queue_status(config.queue_prefix.clone()).await?;
+    let queue_status = queue.queue_status(site.queue_prefix.clone()).await?;
     Ok(Json(Status::from(queue_status)))
 }
 
@@ -392,14 +820,18 @@ async fn get_status(State(state): State<AppState>) -> Result<Json<Status>> {
     description = "Queue settings currently in use",
     responses(
         (status = 200, description = "OK", body = Settings)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn get_settings(State(state): State<AppState>) -> Result<Json<Settings>> {
+async fn get_settings(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Settings>> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
-    let queue_settings = queue.queue_settings(config.queue_prefix.clone()).await?;
+    let queue_settings = queue.queue_settings(site.queue_prefix.clone()).await?;
     Ok(Json(Settings::from(queue_settings)))
 }
 
@@ -414,14 +846,16 @@ async fn get_settings(State(state): State<AppState>) -> Result<Json<Settings>> {
         (status = 200, description = "OK", body = Settings),
         (status = 400, description = "Bad Request", body = String, example = "store capacity out of range"),
         (status = 422, description = "Unprocessable Entity", body = String, example = "Failed to deserialize the JSON body into the target type: queue_enabled: invalid type: integer `10`, expected a boolean at line 1 column 20"),
-    )
+    ),
+    params(SiteQuery)
 )]
 async fn patch_settings(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Json(changes): Json<SettingsPatch>,
 ) -> Result<Json<Settings>> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     match (changes.queue_enabled, changes.store_capacity) {
@@ -429,7 +863,7 @@ async fn patch_settings(
             // Setting both queue enabled and store capacity
             queue
                 .set_queue_settings(
-                    config.queue_prefix.clone(),
+                    site.queue_prefix.clone(),
                     enabled,
                     StoreCapacity::try_from(capacity)?,
                 )
@@ -438,14 +872,14 @@ async fn patch_settings(
         (Some(enabled), None) => {
             // Only setting queue enabled
             queue
-                .set_queue_enabled(config.queue_prefix.clone(), enabled)
+                .set_queue_enabled(site.queue_prefix.clone(), enabled)
                 .await?
         }
         (None, Some(capacity)) => {
             // Only setting store capacity
             queue
                 .set_store_capacity(
-                    config.queue_prefix.clone(),
+                    site.queue_prefix.clone(),
                     StoreCapacity::try_from(capacity)?,
                 )
                 .await?
@@ -455,7 +889,7 @@ async fn patch_settings(
         }
     }
 
-    let queue_settings = queue.queue_settings(config.queue_prefix.clone()).await?;
+    let queue_settings = queue.queue_settings(site.queue_prefix.clone()).await?;
     Ok(Json(Settings::from(queue_settings)))
 }
 
@@ -469,6 +903,7 @@ async fn waiting_page_response(
     let state = state.clone();
     let config = &state.config;
     let queue = &state.queue;
+    let site = state.sites.scoped(params.get("site").map(String::as_str))?;
 
     let test_position = match params.get("position") {
         Some(position) => position.clone(),
@@ -478,10 +913,15 @@ async fn waiting_page_response(
         Some(size) => size.clone(),
         None => String::from("100"),
     };
+    let test_eta = match params.get("eta") {
+        Some(eta) => eta.clone(),
+        None => String::from("360"),
+    };
 
     // Add fake cookies
-    cookies::add_browser_cookie(cookies, config.position_cookie_name.clone(), &test_position);
-    cookies::add_browser_cookie(cookies, config.queue_size_cookie_name.clone(), &test_size);
+    cookies::add_browser_cookie(cookies, site.position_cookie_name.clone(), &test_position);
+    cookies::add_browser_cookie(cookies, site.queue_size_cookie_name.clone(), &test_size);
+    cookies::add_browser_cookie(cookies, site.eta_cookie_name.clone(), &test_eta);
 
     // Add fakes headers
     let mut headers = HeaderMap::new();
@@ -493,12 +933,16 @@ async fn waiting_page_response(
         HeaderName::from_lowercase(config.queue_size_http_header.as_bytes())?,
         test_size.clone().parse()?,
     );
+    headers.insert(
+        HeaderName::from_lowercase(config.eta_http_header.as_bytes())?,
+        test_eta.clone().parse()?,
+    );
 
     Ok((
         headers,
         Html(
             queue
-                .waiting_page_or_default(&config.queue_prefix, locale)
+                .waiting_page_or_default(&site.queue_prefix, locale)
                 .await?,
         ),
     ))
@@ -517,6 +961,8 @@ async fn waiting_page_response(
         ("locale" = String, Path, description = "locale to view for the store"),
         ("position" = u64, Query, description = "position in the store (for testing)"),
         ("size" = u64, Query, description = "size of the store (for testing)"),
+        ("eta" = u64, Query, description = "estimated wait in seconds (for testing)"),
+        ("site" = String, Query, description = "name of the site (default: \"default\")"),
     )
 )]
 async fn get_waiting_page(
@@ -541,6 +987,8 @@ async fn get_waiting_page(
     params(
         ("position" = u64, Query, description = "position in the store (for testing)"),
         ("size" = u64, Query, description = "size of the store (for testing)"),
+        ("eta" = u64, Query, description = "estimated wait in seconds (for testing)"),
+        ("site" = String, Query, description = "name of the site (default: \"default\")"),
     )
 )]
 async fn get_waiting_page_accept_language(
@@ -566,18 +1014,20 @@ async fn get_waiting_page_accept_language(
         (status = 400, description = "Bad Request"),
     ),
     params(
+        SiteQuery,
         ("locale" = String, Path, description = "locale to use when setting the waiting page")
     )
 )]
 async fn set_waiting_page(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Path(locale): Path<String>,
     waiting_page: String,
 ) -> Result<()> {
     let locale = locale.to_lowercase();
 
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     let is_valid = queue.test_waiting_page(&waiting_page);
@@ -586,7 +1036,7 @@ async fn set_waiting_page(
     }
 
     queue
-        .set_waiting_page(&config.queue_prefix, &locale, &waiting_page)
+        .set_waiting_page(&site.queue_prefix, &locale, &waiting_page)
         .await?;
 
     Ok(())
@@ -600,19 +1050,27 @@ async fn set_waiting_page(
     description = "Creates a brand new ID and returns its position in the store/queue",
     responses(
         (status = 201, description = "Created", body = QueuePosition)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn add_store_id(State(state): State<AppState>) -> Result<(StatusCode, Json<QueuePosition>)> {
+async fn add_store_id(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<(StatusCode, Json<QueuePosition>)> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     let uuid = queue.new_id();
-    let position = queue.id_promote(&config.queue_prefix, uuid, None).await?;
+    let position = queue.id_promote(&site.queue_prefix, uuid, None).await?;
 
     Ok((
         StatusCode::CREATED,
-        Json(QueuePosition::new(uuid, position)),
+        Json(QueuePosition::new(
+            uuid,
+            position,
+            QueueThroughput::default(),
+        )),
     ))
 }
 
@@ -624,21 +1082,26 @@ async fn add_store_id(State(state): State<AppState>) -> Result<(StatusCode, Json
     description = "Creates a brand new ID and returns its position in the store/queue",
     responses(
         (status = 201, description = "Created", body = QueuePosition)
-    )
+    ),
+    params(SiteQuery)
 )]
-async fn add_queue_id(State(state): State<AppState>) -> Result<(StatusCode, Json<QueuePosition>)> {
+async fn add_queue_id(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<(StatusCode, Json<QueuePosition>)> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     let uuid = queue.new_id();
     let position = queue
-        .id_position(&config.queue_prefix, uuid, None, true)
+        .id_position(&site.queue_prefix, uuid, None, true)
         .await?;
+    let throughput = queue.throughput(&site.queue_prefix, None).await?;
 
     Ok((
         StatusCode::CREATED,
-        Json(QueuePosition::new(uuid, position)),
+        Json(QueuePosition::new(uuid, position, throughput)),
     ))
 }
 
@@ -647,20 +1110,22 @@ async fn add_queue_id(State(state): State<AppState>) -> Result<(StatusCode, Json
     path = "/api/queue/{id}",
     tag = "queue",
     summary = "User ID Status",
-    description = "Get user position in the store/queue",
+    description = "Get user position in the store/queue, with the estimated wait (in seconds) if queued",
     responses(
         (status = 200, description = "OK", body = QueuePosition)
     ),
     params(
+        SiteQuery,
         ("id" = u64, Path, description = "ID of the user in the store")
     )
 )]
 async fn get_queue_id(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Path(id): Path<String>,
 ) -> Result<Json<QueuePosition>> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     let uuid = match Uuid::try_from(id.clone()) {
@@ -669,10 +1134,11 @@ async fn get_queue_id(
     };
 
     let position = queue
-        .id_position(&config.queue_prefix, uuid, None, false)
+        .id_position(&site.queue_prefix, uuid, None, false)
         .await?;
+    let throughput = queue.throughput(&site.queue_prefix, None).await?;
 
-    Ok(Json(QueuePosition::new(uuid, position)))
+    Ok(Json(QueuePosition::new(uuid, position, throughput)))
 }
 
 #[utoipa::path(
@@ -685,15 +1151,17 @@ async fn get_queue_id(
         (status = 200, description = "OK")
     ),
     params(
+        SiteQuery,
         ("id" = u64, Path, description = "ID of the user in the store")
     )
 )]
 async fn delete_queue_id(
     State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
     Path(id): Path<String>,
 ) -> Result<StatusCode> {
     let state = state.clone();
-    let config = &state.config;
+    let site = state.sites.scoped(query.site.as_deref())?;
     let queue = &state.queue;
 
     let uuid = match Uuid::try_from(id.clone()) {
@@ -701,7 +1169,386 @@ async fn delete_queue_id(
         Err(e) => return Err(Error::QueueIdInvalid(id, e.into())),
     };
 
-    queue.id_remove(&config.queue_prefix, uuid, None).await?;
+    queue.id_remove(&site.queue_prefix, uuid, None).await?;
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/priority_tiers",
+    tag = "queue",
+    summary = "Priority Tiers",
+    description = "All priority tiers, highest priority first",
+    responses(
+        (status = 200, description = "OK", body = Vec<PriorityTier>)
+    ),
+    params(SiteQuery)
+)]
+async fn get_priority_tiers(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Vec<PriorityTier>>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    let tiers = queue.priority_tiers(&site.queue_prefix).await?;
+    Ok(Json(tiers.into_iter().map(PriorityTier::from).collect()))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/priority_tiers/{name}",
+    tag = "queue",
+    summary = "Set Priority Tier",
+    description = "Create or replace a priority tier.  IDs in higher tiers are placed ahead of lower tiers in the queue, and bypass tiers skip the queue entirely",
+    request_body = PriorityTierUpdate,
+    responses(
+        (status = 200, description = "OK", body = PriorityTier),
+        (status = 400, description = "Bad Request", body = String, example = "priority tier names should be letters, numbers, \"-\" or \"_\", with a priority from 1 to 1000"),
+    ),
+    params(
+        SiteQuery,
+        ("name" = String, Path, description = "Name of the priority tier")
+    )
+)]
+async fn set_priority_tier(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(name): Path<String>,
+    Json(update): Json<PriorityTierUpdate>,
+) -> Result<Json<PriorityTier>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    let tier = queue::PriorityTier::new(name, update.priority, update.bypass)?;
+    queue.set_priority_tier(&site.queue_prefix, &tier).await?;
+
+    Ok(Json(PriorityTier::from(tier)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/priority_tiers/{name}",
+    tag = "queue",
+    summary = "Remove Priority Tier",
+    description = "Remove a priority tier.  Tokens for the tier are no longer accepted, but IDs already placed by the tier keep their place in the queue",
+    responses(
+        (status = 200, description = "OK"),
+        (status = 404, description = "Not Found", body = String, example = "priority tier not found: staff"),
+    ),
+    params(
+        SiteQuery,
+        ("name" = String, Path, description = "Name of the priority tier")
+    )
+)]
+async fn remove_priority_tier(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(name): Path<String>,
+) -> Result<StatusCode> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    if !queue
+        .remove_priority_tier(&site.queue_prefix, &name)
+        .await?
+    {
+        return Err(Error::PriorityTierMissing(name));
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/priority_tiers/{name}/token",
+    tag = "queue",
+    summary = "Create Priority Token",
+    description = "Sign a token that places a visitor in the priority tier until it expires.  Visitors present the token in the priority query parameter, header or cookie",
+    request_body = PriorityTokenRequest,
+    responses(
+        (status = 201, description = "Created", body = PriorityToken),
+        (status = 400, description = "Bad Request", body = String, example = "token expiry out of range"),
+        (status = 404, description = "Not Found", body = String, example = "priority tier not found: staff"),
+    ),
+    params(
+        SiteQuery,
+        ("name" = String, Path, description = "Name of the priority tier")
+    )
+)]
+async fn create_priority_token(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(name): Path<String>,
+    Json(request): Json<PriorityTokenRequest>,
+) -> Result<(StatusCode, Json<PriorityToken>)> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let config = &state.config;
+    let queue = &state.queue;
+
+    let Some(tier) = queue.priority_tier(&site.queue_prefix, &name).await? else {
+        return Err(Error::PriorityTierMissing(name));
+    };
+
+    let expires_in = request
+        .expires_in
+        .unwrap_or(DEFAULT_PRIORITY_TOKEN_EXPIRY.as_secs());
+    let expires =
+        tokens::expiry(Utc::now(), expires_in).ok_or(Error::TokenExpiryOutOfRange(expires_in))?;
+    let token = sign_priority_token(config, site, &tier.name, expires)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PriorityToken {
+            tier: tier.name,
+            token,
+            expires,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invites",
+    tag = "queue",
+    summary = "Create Invite",
+    description = "Sign an invite that admits visitors directly to the store, regardless of capacity, until it expires or has been redeemed by `uses` visitors.  Visitors present the invite in the invite query parameter",
+    request_body = InviteRequest,
+    responses(
+        (status = 201, description = "Created", body = Invite),
+        (status = 400, description = "Bad Request", body = String, example = "invite uses should be at least 1"),
+    ),
+    params(SiteQuery)
+)]
+async fn create_invite(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Json(request): Json<InviteRequest>,
+) -> Result<(StatusCode, Json<Invite>)> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let config = &state.config;
+
+    let uses = request.uses.unwrap_or(DEFAULT_INVITE_USES);
+    if uses == 0 {
+        return Err(Error::InviteUsesOutOfRange(uses));
+    }
+
+    let expires_in = request
+        .expires_in
+        .unwrap_or(DEFAULT_INVITE_EXPIRY.as_secs());
+    let expires =
+        tokens::expiry(Utc::now(), expires_in).ok_or(Error::TokenExpiryOutOfRange(expires_in))?;
+    let invite = Uuid::new_v4();
+    let token = sign_invite_token(config, site, invite, uses, expires)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Invite {
+            invite: String::from(invite),
+            uses,
+            token,
+            expires,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/invites/{invite}",
+    tag = "queue",
+    summary = "Invite Redemptions",
+    description = "Number of visitors that have redeemed an invite.  Redemptions are forgotten once the invite expires",
+    responses(
+        (status = 200, description = "OK", body = InviteRedemptions),
+        (status = 400, description = "Bad Request", body = String, example = "queue id was not a valid UUID"),
+    ),
+    params(
+        SiteQuery,
+        ("invite" = String, Path, description = "ID of the invite")
+    )
+)]
+async fn get_invite(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(invite): Path<String>,
+) -> Result<Json<InviteRedemptions>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    let uuid = match Uuid::try_from(invite.clone()) {
+        Ok(uuid) => uuid,
+        Err(e) => return Err(Error::QueueIdInvalid(invite, e.into())),
+    };
+
+    let redeemed = queue.invite_redemptions(&site.queue_prefix, uuid).await?;
+
+    Ok(Json(InviteRedemptions { invite, redeemed }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/schedules",
+    tag = "queue",
+    summary = "Schedules",
+    description = "All schedules, in the order they start",
+    responses(
+        (status = 200, description = "OK", body = Vec<Schedule>)
+    ),
+    params(SiteQuery)
+)]
+async fn get_schedules(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Json<Vec<Schedule>>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    let schedules = queue.schedules(&site.queue_prefix).await?;
+    Ok(Json(schedules.into_iter().map(Schedule::from).collect()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/schedules/{id}",
+    tag = "queue",
+    summary = "Schedule",
+    description = "Single schedule",
+    responses(
+        (status = 200, description = "OK", body = Schedule),
+        (status = 404, description = "Not Found", body = String, example = "schedule not found: 0f7c1a8e-5d0b-4f2e-9a43-6b8d2e1c7f90"),
+    ),
+    params(
+        SiteQuery,
+        ("id" = String, Path, description = "ID of the schedule")
+    )
+)]
+async fn get_schedule(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(id): Path<String>,
+) -> Result<Json<Schedule>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    match queue.schedule(&site.queue_prefix, &id).await? {
+        Some(schedule) => Ok(Json(Schedule::from(schedule))),
+        None => Err(Error::ScheduleMissing(id)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/schedules",
+    tag = "queue",
+    summary = "Create Schedule",
+    description = "Schedule queue settings to be applied at a given time (UTC), either setting the store capacity or ramping it over a number of minutes.  Each change applied emits `settings:updated`",
+    request_body = ScheduleUpdate,
+    responses(
+        (status = 201, description = "Created", body = Schedule),
+        (status = 400, description = "Bad Request", body = String, example = "schedule invalid: a schedule can set the store capacity or ramp it, but not both"),
+    ),
+    params(SiteQuery)
+)]
+async fn create_schedule(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Json(update): Json<ScheduleUpdate>,
+) -> Result<(StatusCode, Json<Schedule>)> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    let schedule = queue::Schedule::new(
+        String::from(queue.new_id()),
+        update.start,
+        update.queue_enabled,
+        update.store_capacity,
+        update.ramp.map(queue::CapacityRamp::from),
+    )?;
+    queue.set_schedule(&site.queue_prefix, &schedule).await?;
+
+    Ok((StatusCode::CREATED, Json(Schedule::from(schedule))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/schedules/{id}",
+    tag = "queue",
+    summary = "Update Schedule",
+    description = "Replace an existing schedule.  The new settings are applied once the start time has been reached, even if an earlier version of the schedule was already applied",
+    request_body = ScheduleUpdate,
+    responses(
+        (status = 200, description = "OK", body = Schedule),
+        (status = 400, description = "Bad Request", body = String, example = "schedule invalid: a ramp should last at least 1 minute"),
+        (status = 404, description = "Not Found", body = String, example = "schedule not found: 0f7c1a8e-5d0b-4f2e-9a43-6b8d2e1c7f90"),
+    ),
+    params(
+        SiteQuery,
+        ("id" = String, Path, description = "ID of the schedule")
+    )
+)]
+async fn set_schedule(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(id): Path<String>,
+    Json(update): Json<ScheduleUpdate>,
+) -> Result<Json<Schedule>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    if queue.schedule(&site.queue_prefix, &id).await?.is_none() {
+        return Err(Error::ScheduleMissing(id));
+    }
+
+    let schedule = queue::Schedule::new(
+        id,
+        update.start,
+        update.queue_enabled,
+        update.store_capacity,
+        update.ramp.map(queue::CapacityRamp::from),
+    )?;
+    queue.set_schedule(&site.queue_prefix, &schedule).await?;
+
+    Ok(Json(Schedule::from(schedule)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/schedules/{id}",
+    tag = "queue",
+    summary = "Remove Schedule",
+    description = "Remove a schedule.  Settings already applied by the schedule are left as they are",
+    responses(
+        (status = 200, description = "OK"),
+        (status = 404, description = "Not Found", body = String, example = "schedule not found: 0f7c1a8e-5d0b-4f2e-9a43-6b8d2e1c7f90"),
+    ),
+    params(
+        SiteQuery,
+        ("id" = String, Path, description = "ID of the schedule")
+    )
+)]
+async fn remove_schedule(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
+    let queue = &state.queue;
+
+    if !queue.remove_schedule(&site.queue_prefix, &id).await? {
+        return Err(Error::ScheduleMissing(id));
+    }
 
     Ok(StatusCode::OK)
 }
@@ -719,16 +1566,24 @@ async fn delete_queue_id(
 * `store:added`
 * `store:expired`
 * `queue:removed`
-
-See [MDN - Using Server Sent Events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events) for more details"
+* `upstream:circuit_opened`
+* `upstream:circuit_closed`
+* `upstream:updated`
+* `upstream:failover`
+* `upstream:failback`
+
+See [MDN - Using Server Sent Events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events) for more details",
+    params(SiteQuery)
 )]
 async fn get_server_sent_events(
     State(state): State<AppState>,
-) -> Sse<impl Stream<Item = core::result::Result<SSEvent, Infallible>>> {
+    Query(query): Query<SiteQuery>,
+) -> Result<Sse<impl Stream<Item = core::result::Result<SSEvent, Infallible>>>> {
     let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
 
     // Create stream of Queue events
-    let subscriber = state.queue_events.clone();
+    let subscriber = site.events.clone();
     let stream = subscriber.into_stream();
 
     // Translate into a public facing API and create Serve Sent Event
@@ -739,7 +1594,49 @@ async fn get_server_sent_events(
     // Ensure that the stream doesn't prevent the server from shutting down
     let safe_stream = cancellable(sse_stream, state.shutdown_notifier.clone());
 
-    Sse::new(safe_stream).keep_alive(KeepAlive::default())
+    Ok(Sse::new(safe_stream).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/upstreams/sse",
+    tag = "stream",
+    summary = "Upstream Server-Sent Events (SSE)",
+    description = "Upstream servers pushed from the server every second, for live load.  Each payload is the JSON list returned by `GET /api/upstreams`, including the live statistics of each upstream.
+
+See [MDN - Using Server Sent Events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events) for more details",
+    params(SiteQuery)
+)]
+async fn get_upstream_events(
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Sse<impl Stream<Item = core::result::Result<SSEvent, Infallible>>>> {
+    let state = state.clone();
+    let site = state.sites.scoped(query.site.as_deref())?.clone();
+
+    // Snapshot the upstreams at a regular interval
+    let sse_stream = stream! {
+        let mut interval = tokio::time::interval(UPSTREAM_STATS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let upstreams: Vec<Upstream> = site
+                .upstream_pool
+                .statuses()
+                .await
+                .iter()
+                .map(Upstream::from)
+                .collect();
+            match serde_json::to_string(&upstreams) {
+                Ok(payload) => yield Ok(SSEvent::default().data(payload)),
+                Err(error) => error!("Failed to serialize upstreams: {:?}", error),
+            }
+        }
+    };
+
+    // Ensure that the stream doesn't prevent the server from shutting down
+    let safe_stream = cancellable(sse_stream, state.shutdown_notifier.clone());
+
+    Ok(Sse::new(safe_stream).keep_alive(KeepAlive::default()))
 }
 
 // Fallback handler for the Control UI Single Page Application (SPA)
@@ -752,49 +1649,237 @@ async fn control_ui_handler() -> Result<Response<axum::body::Body>> {
     Ok(response)
 }
 
-async fn get_web_socket(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+async fn get_web_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<SiteQuery>,
+) -> Result<Response> {
     let state = state.clone();
     let cancel = state.shutdown_notifier.clone();
+    let site = state.sites.scoped(query.site.as_deref())?;
 
     // Create stream of Queue events
-    let subscriber = state.queue_events.clone();
+    let subscriber = site.events.clone();
     let mut receiver = subscriber.receiver();
 
-    ws.on_failed_upgrade(|error| {
-        error!("Failed to upgrade WebSocket: {:?}", error);
-    })
-    .on_upgrade(|mut ws| async move {
-        loop {
-            tokio::select! {
-                _ = cancel.notified() => {
-                    break;
-                },
-                res = ws.recv() => {
-                    // Receive data from web socket
-                    match res {
-                        Some(Ok(ws::Message::Text(s))) => {
-                            error!("Received unexpected text from web socket: {}", s)
-                        },
-                        Some(Ok(ws::Message::Binary(_))) => {
-                            error!("Received unexpected bytes from web socket")
-                        },
-                        Some(Ok(ws::Message::Ping(_))) => {},
-                        Some(Ok(ws::Message::Pong(_))) => {},
-                        Some(Ok(ws::Message::Close(_))) => {},
-                        Some(Err(error)) => debug!("client disconnected abruptly: {error}"),
-                        None => break,
-                    }
-                },
-                queue_event = receiver.recv() => {
-                    // Push data to web socket
-                    if let Ok(queue_event) = queue_event {
-                        let payload = String::from(Event::from(queue_event));
-                        if let Err(error) = ws.send(ws::Message::Text(payload.into())).await {
-                            debug!("client disconnected abruptly: {}", error);
+    Ok(ws
+        .on_failed_upgrade(|error| {
+            error!("Failed to upgrade WebSocket: {:?}", error);
+        })
+        .on_upgrade(|mut ws| async move {
+            loop {
+                tokio::select! {
+                    _ = cancel.notified() => {
+                        break;
+                    },
+                    res = ws.recv() => {
+                        // Receive data from web socket
+                        match res {
+                            Some(Ok(ws::Message::Text(s))) => {
+                                error!("Received unexpected text from web socket: {}", s)
+                            },
+                            Some(Ok(ws::Message::Binary(_))) => {
+                                error!("Received unexpected bytes from web socket")
+                            },
+                            Some(Ok(ws::Message::Ping(_))) => {},
+                            Some(Ok(ws::Message::Pong(_))) => {},
+                            Some(Ok(ws::Message::Close(_))) => {},
+                            Some(Err(error)) => debug!("client disconnected abruptly: {error}"),
+                            None => break,
+                        }
+                    },
+                    queue_event = receiver.recv() => {
+                        // Push data to web socket
+                        if let Ok(queue_event) = queue_event {
+                            let payload = String::from(Event::from(queue_event));
+                            if let Err(error) = ws.send(ws::Message::Text(payload.into())).await {
+                                debug!("client disconnected abruptly: {}", error);
+                            }
                         }
                     }
                 }
             }
-        }
-    })
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum_extra::extract::cookie::Key;
+    use clap::Parser;
+    use http::Method;
+    use http::header::{AUTHORIZATION, CONNECTION, COOKIE, SET_COOKIE, UPGRADE};
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use crate::auth::{ApiToken, Authenticators, ControlUser};
+    use crate::cli::{Cli, Commands};
+    use crate::clients::HttpClients;
+    use crate::config::Config as AppConfig;
+    use crate::database::create_redis_pool;
+    use crate::queue::QueueControl;
+    use crate::sites::Sites;
+
+    fn test_state(key: &Key, password: &str) -> AppState {
+        let cli = Cli::try_parse_from(["omnis-bouncer", "run"]).expect("Failed to parse args");
+        let Some(Commands::Run(args)) = cli.command else {
+            panic!("Expected the run command");
+        };
+        let mut config = AppConfig::try_from(&args).expect("Failed to build config");
+        config.cookie_secret_key = key.clone();
+
+        // The pool only connects when used, and no route under test touches Redis
+        let pool = create_redis_pool(&config.redis_uri).expect("Failed to create pool");
+        let queue = QueueControl::new(
+            pool,
+            config.quarantine_expiry,
+            config.validated_expiry,
+            config.publish_throttle,
+            config.queue_disabled_policy,
+            config.throughput_window,
+        )
+        .expect("Failed to create QueueControl");
+        let http_clients = HttpClients::new(config.connect_timeout, config.upstream_tls.clone())
+            .expect("Failed to create HTTP clients");
+
+        let tokens = [
+            ApiToken::new("dashboard", "read-token", Role::Read),
+            ApiToken::new("oncall", "operator-token", Role::Operator),
+            ApiToken::new("deploy", "admin-token", Role::Admin),
+        ];
+        let users = [ControlUser {
+            username: String::from("oncall"),
+            password: String::from(password),
+            role: Role::Operator,
+        }];
+
+        AppState::new(
+            config,
+            Arc::new(Notify::new()),
+            queue,
+            Sites::new(Vec::new()),
+            http_clients,
+            Authenticators::from_config(&tokens, &users),
+        )
+    }
+
+    fn get(uri: &str) -> http::request::Builder {
+        Request::builder().method(Method::GET).uri(uri)
+    }
+
+    async fn status(state: &AppState, request: http::request::Builder) -> StatusCode {
+        let request = request.body(Body::empty()).expect("Request builder failed");
+        router(state.clone())
+            .oneshot(request)
+            .await
+            .expect("Router failed")
+            .status()
+    }
+
+    /// Sign in with the user's password, returning the session cookie to send back
+    async fn login_cookie(state: &AppState) -> String {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/login")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"username": "oncall", "password": "hunter2"}"#,
+            ))
+            .expect("Request builder failed");
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly"));
+        let (cookie, _) = set_cookie.split_once(';').unwrap();
+        String::from(cookie)
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated() {
+        let state = test_state(&Key::generate(), "hunter2");
+
+        assert_eq!(status(&state, get("/health")).await, StatusCode::OK);
+        assert_eq!(
+            status(&state, get("/api/whoami")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(
+                &state,
+                get("/api/whoami").header(AUTHORIZATION, "Bearer nope")
+            )
+            .await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&state, get("/api/generate_key")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_role() {
+        let state = test_state(&Key::generate(), "hunter2");
+        let bearer = |token: &str| format!("Bearer {}", token);
+
+        let read = get("/api/whoami").header(AUTHORIZATION, bearer("read-token"));
+        assert_eq!(status(&state, read).await, StatusCode::OK);
+
+        let patch_settings = Request::builder()
+            .method(Method::PATCH)
+            .uri("/api/settings")
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, bearer("read-token"));
+        assert_eq!(status(&state, patch_settings).await, StatusCode::FORBIDDEN);
+
+        let operator = get("/api/generate_key").header(AUTHORIZATION, bearer("operator-token"));
+        assert_eq!(status(&state, operator).await, StatusCode::FORBIDDEN);
+
+        let admin = get("/api/generate_key").header(AUTHORIZATION, bearer("admin-token"));
+        assert_eq!(status(&state, admin).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_session_streams() {
+        let state = test_state(&Key::generate(), "hunter2");
+        let cookie = login_cookie(&state).await;
+
+        let whoami = get("/api/whoami").header(COOKIE, &cookie);
+        assert_eq!(status(&state, whoami).await, StatusCode::OK);
+
+        assert_eq!(
+            status(&state, get("/api/sse")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Past the role check, the test state has no sites for the event stream
+        let sse = get("/api/sse").header(COOKIE, &cookie);
+        assert_eq!(status(&state, sse).await, StatusCode::NOT_FOUND);
+
+        let ws = || {
+            get("/api/ws")
+                .header(CONNECTION, "upgrade")
+                .header(UPGRADE, "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+        };
+        assert_eq!(status(&state, ws()).await, StatusCode::UNAUTHORIZED);
+        // Past the role check, a one shot request can't be upgraded
+        let ws = ws().header(COOKIE, &cookie);
+        assert_eq!(status(&state, ws).await, StatusCode::UPGRADE_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_session_ends_with_credential() {
+        let key = Key::generate();
+        let cookie = login_cookie(&test_state(&key, "hunter2")).await;
+
+        // Same user and cookie key, once the password has been changed
+        let state = test_state(&key, "hunter3");
+        let whoami = get("/api/whoami").header(COOKIE, &cookie);
+        assert_eq!(status(&state, whoami).await, StatusCode::UNAUTHORIZED);
+    }
 }
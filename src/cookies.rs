@@ -47,6 +47,7 @@ fn server_cookie<'a>(name: String, value: String, expiry: Option<Duration>) -> C
     let mut cookie = Cookie::build((name, value))
         .same_site(SameSite::Strict)
         .secure(true)
+        .http_only(true)
         .path("/")
         .build();
 
@@ -1,11 +1,12 @@
 use axum::{
-    http::StatusCode,
+    http::{StatusCode, header::WWW_AUTHENTICATE},
     response::{IntoResponse, Response},
 };
 use tokio::sync::broadcast::error::SendError;
 use tracing::error;
 
-use crate::queue::QueueEvent;
+use crate::auth::Role;
+use crate::queue::{PriorityTier, QueueEvent};
 
 // Generic Error type for all errors in handlers
 #[derive(Debug)]
@@ -14,9 +15,24 @@ pub enum Error {
     QueueEventLost(SendError<QueueEvent>),
     ControlUIAppMissing,
     QueueEnabledOutOfRange(String),
+    QueueDisabledPolicyInvalid(String),
+    PriorityTierInvalid(String),
+    PriorityTierMissing(String),
+    InviteUsesOutOfRange(u32),
+    TokenExpiryOutOfRange(u64),
+    ScheduleInvalid(String),
+    ScheduleMissing(String),
+    SiteMissing(String),
+    UpstreamMissing(String),
+    UpstreamTlsInvalid(String),
+    CanaryMissing(String),
+    CanaryWeightOutOfRange(usize),
+    LoadBalancingInvalid(String),
     StoreCapacityOutOfRange(String),
     QueueSyncTimestampOutOfRange(String),
     WaitingPageInvalid,
+    Unauthorized,
+    Forbidden(Role),
     RedisTimeIsNil,
     RedisScriptUnreadable(String),
     RedisEventUnknown(String),
@@ -48,6 +64,102 @@ impl IntoResponse for Error {
                 )
                     .into_response();
             }
+            Error::QueueDisabledPolicyInvalid(policy) => {
+                error!("queue disabled policy invalid: {}", policy);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "queue disabled policy should be \"drain\" or \"keep\"".to_string(),
+                )
+                    .into_response();
+            }
+            Error::PriorityTierInvalid(name) => {
+                error!("priority tier invalid: {}", name);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "priority tier names should be letters, numbers, \"-\" or \"_\", with a priority from 1 to {}",
+                        PriorityTier::MAX_PRIORITY
+                    ),
+                )
+                    .into_response();
+            }
+            Error::PriorityTierMissing(name) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("priority tier not found: {}", name),
+                )
+                    .into_response();
+            }
+            Error::InviteUsesOutOfRange(uses) => {
+                error!("invite uses out of range: {}", uses);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "invite uses should be at least 1".to_string(),
+                )
+                    .into_response();
+            }
+            Error::TokenExpiryOutOfRange(secs) => {
+                error!("token expiry out of range: {}", secs);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "token expiry out of range".to_string(),
+                )
+                    .into_response();
+            }
+            Error::ScheduleInvalid(reason) => {
+                error!("schedule invalid: {}", reason);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("schedule invalid: {}", reason),
+                )
+                    .into_response();
+            }
+            Error::ScheduleMissing(id) => {
+                return (StatusCode::NOT_FOUND, format!("schedule not found: {}", id))
+                    .into_response();
+            }
+            Error::SiteMissing(name) => {
+                return (StatusCode::NOT_FOUND, format!("site not found: {}", name))
+                    .into_response();
+            }
+            Error::UpstreamMissing(uri) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("upstream not found: {}", uri),
+                )
+                    .into_response();
+            }
+            Error::UpstreamTlsInvalid(reason) => {
+                error!("upstream TLS options invalid: {}", reason);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("upstream TLS options invalid: {}", reason),
+                )
+                    .into_response();
+            }
+            Error::CanaryMissing(label) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    format!("canary not found: {}", label),
+                )
+                    .into_response();
+            }
+            Error::CanaryWeightOutOfRange(weight) => {
+                error!("canary weight out of range: {}", weight);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "canary weight should be a percentage from 0 to 100".to_string(),
+                )
+                    .into_response();
+            }
+            Error::LoadBalancingInvalid(strategy) => {
+                error!("load balancing strategy invalid: {}", strategy);
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "load balancing strategy should be \"least_connections\", \"weighted_least_connections\", \"least_sticky_sessions\", \"round_robin\", \"power_of_two_choices\" or \"least_latency\"".to_string(),
+                )
+                    .into_response();
+            }
             Error::StoreCapacityOutOfRange(size) => {
                 error!("store capacity out of range: {}", size);
                 return (
@@ -67,6 +179,21 @@ impl IntoResponse for Error {
                 )
                     .into_response();
             }
+            Error::Unauthorized => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(WWW_AUTHENTICATE, "Bearer")],
+                    "unauthorized".to_string(),
+                )
+                    .into_response();
+            }
+            Error::Forbidden(role) => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    format!("forbidden: requires the {} role", role),
+                )
+                    .into_response();
+            }
             Error::RedisTimeIsNil => error!("redis time is incorrectly returning nil"),
             Error::RedisScriptUnreadable(script) => error!("script unreadable: {}", script),
             Error::RedisEventUnknown(event) => error!("unknown redis event: {}", event),
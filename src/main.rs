@@ -1,7 +1,12 @@
+// The control API config example is a large json! literal (See: control::models::Config)
+#![recursion_limit = "256"]
+
 mod app;
+mod auth;
 mod background;
 mod certs;
 mod cli;
+mod clients;
 mod config;
 mod constants;
 mod control;
@@ -14,8 +19,10 @@ mod queue;
 mod secrets;
 mod servers;
 mod signals;
+mod sites;
 mod state;
 mod stream;
+mod tokens;
 mod upstream;
 mod waiting_room;
 
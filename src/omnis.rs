@@ -1,12 +1,15 @@
+use async_stream::stream;
 use axum::{
-    BoxError, Router,
+    BoxError, Extension, Router,
+    body::Bytes,
     error_handling::HandleErrorLayer,
-    extract::{ConnectInfo, OriginalUri, Request, State},
-    response::IntoResponse,
+    extract::{ConnectInfo, Request, State},
+    response::{IntoResponse, Response},
     routing::{any, get},
 };
 use axum_response_cache::CacheLayer;
 use base64::{Engine, engine::general_purpose::STANDARD};
+use futures_util::{Stream, StreamExt, pin_mut};
 use http::{
     HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
     header::{
@@ -19,19 +22,35 @@ use http::{
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
 use std::time::Instant;
-use std::{collections::HashSet, net::SocketAddr, time::Duration, time::SystemTime};
-use tower::{ServiceBuilder, buffer::BufferLayer, limit::RateLimitLayer, load_shed::LoadShedLayer};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+    time::SystemTime,
+};
+use tower::{
+    ServiceBuilder, ServiceExt, buffer::BufferLayer, limit::RateLimitLayer,
+    load_shed::LoadShedLayer,
+};
 use tower_cookies::{Cookie, CookieManagerLayer, Cookies};
 use tower_http::{compression::CompressionLayer, decompression::RequestDecompressionLayer};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::config::Config;
 use crate::cookies::add_private_server_cookie;
 use crate::errors::Result;
 use crate::locales::header_locale;
+use crate::queue::QueueEvent;
+use crate::sites::{Site, Sites, request_host};
 use crate::state::AppState;
-use crate::upstream::{ConnectionPermit, UpstreamPool};
-use crate::waiting_room::{QueueId, WaitingRoom, check_waiting_page, extract_queue_id};
+use crate::stream::idle_timeout;
+use crate::upstream::{CircuitTransition, ConnectionPermit, RequestError, UpstreamPool};
+use crate::waiting_room::{
+    QueueId, WaitingRoom, check_waiting_page, extract_invite_token, extract_priority_token,
+    extract_queue_id, priority_tier, redeem_invite, strip_token_params,
+};
 
 lazy_static! {
     static ref UPSTREAM_IGNORE: HashSet<HeaderName> = {
@@ -94,6 +113,10 @@ lazy_static! {
         .case_insensitive(true)
         .build()
         .unwrap();
+    static ref PUSH_RE: Regex = RegexBuilder::new(r"^/push")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
     static ref RESTAPI_RE: Regex = RegexBuilder::new(r"^/api")
         .case_insensitive(true)
         .build()
@@ -215,8 +238,9 @@ fn fallback_ultra_thin_router<T>(state: AppState) -> Router<T> {
     fallback_router.with_state(state.clone())
 }
 
-// Build the router for the reverse proxy system
-pub fn router(state: AppState) -> Router {
+// Build the router for a single site of the reverse proxy system.  Every site has its own asset
+// cache and rate limits
+fn site_router(state: AppState, site: Arc<Site>) -> Router {
     // Base routing
     let mut router = Router::new()
         .merge(cache_router(state.clone()))
@@ -232,8 +256,34 @@ pub fn router(state: AppState) -> Router {
         router = router.merge(ultra_thin_router(state.clone()));
     }
 
-    router
-        .with_state(state.clone())
+    router.layer(Extension(site)).with_state(state.clone())
+}
+
+/// Routers for every site, selected by the host and path of each request
+#[derive(Clone)]
+struct SiteRouters {
+    sites: Sites,
+    routers: Arc<HashMap<String, Router>>,
+}
+
+// Build the router for the reverse proxy system
+pub fn router(state: AppState) -> Router {
+    let routers = state
+        .sites
+        .iter()
+        .map(|site| {
+            let router = site_router(state.clone(), site.clone());
+            (site.name.clone(), router)
+        })
+        .collect();
+    let site_routers = SiteRouters {
+        sites: state.sites.clone(),
+        routers: Arc::new(routers),
+    };
+
+    Router::new()
+        .fallback(site_dispatch)
+        .with_state(site_routers)
         .layer(CookieManagerLayer::new())
         .layer(RequestDecompressionLayer::new())
         .layer(CompressionLayer::new())
@@ -247,20 +297,40 @@ pub fn router(state: AppState) -> Router {
         )
 }
 
+/// Pass each request on to the router of the site it belongs to.  The path prefix of the site is
+/// removed, so the site is routed as if it was at the root
+async fn site_dispatch(State(site_routers): State<SiteRouters>, request: Request) -> Response {
+    let host = request_host(request.headers(), request.uri());
+    let site = site_routers
+        .sites
+        .select(host.as_deref(), request.uri().path());
+    let router = site_routers.routers[&site.name].clone();
+
+    let (mut parts, body) = request.into_parts();
+    parts.uri = site.strip_path_prefix(&parts.uri);
+
+    match router.oneshot(Request::from_parts(parts, body)).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn omnis_studio_upstream(
     State(state): State<AppState>,
+    Extension(site): Extension<Arc<Site>>,
     ConnectInfo(connect_info): ConnectInfo<SocketAddr>,
     method: Method,
     cookies: Cookies,
     headers: HeaderMap,
-    uri: OriginalUri,
+    uri: Uri,
     request: Request,
 ) -> Result<impl IntoResponse> {
     // Extract config
     let state = state.clone();
     let config = &state.config;
     let queue = &state.queue;
-    let upstream_pool = &state.upstream_pool;
+    let upstream_pool = &site.upstream_pool;
 
     // Clone properties of the request that are used
     let path_and_query = uri.path_and_query().unwrap();
@@ -285,9 +355,9 @@ pub async fn omnis_studio_upstream(
     let mut upstream_headers = headers.clone();
 
     // Extract cookie values
-    let connection_permit = if connection_type.requires_waiting_room() {
+    let (connection_permit, queue_id) = if connection_type.requires_waiting_room() {
         // Extract Queue ID
-        let id_cookie = private_cookies.get(config.id_cookie_name.clone().as_str());
+        let id_cookie = private_cookies.get(site.id_cookie_name.clone().as_str());
         let queue = &state.queue;
         let queue_id = extract_queue_id(queue, &id_cookie);
 
@@ -295,15 +365,41 @@ pub async fn omnis_studio_upstream(
         if id_cookie.is_none() {
             add_private_server_cookie(
                 &private_cookies,
-                config.id_cookie_name.clone(),
+                site.id_cookie_name.clone(),
                 String::from(queue_id),
                 Some(config.cookie_id_expiration), // 1 day ID expiration
             );
         }
 
+        // Admit the ID directly to the store with a signed invite
+        if let Some(token) = extract_invite_token(config, path_and_query.query()) {
+            redeem_invite(config, &site, queue, queue_id, token).await?;
+        }
+
+        // Resolve a priority tier from a signed token, and keep the token from the upstream
+        let priority = match extract_priority_token(
+            config,
+            &site,
+            &private_cookies,
+            &headers,
+            path_and_query.query(),
+        ) {
+            Some(token) => priority_tier(config, &site, &private_cookies, queue, token).await?,
+            None => None,
+        };
+        upstream_headers.remove(config.priority_http_header.as_str());
+
         // Check if the use is in the store
-        if let Some((waiting_headers, waiting_body)) =
-            check_waiting_page(config, &cookies, &locale, queue, queue_id).await?
+        if let Some((waiting_headers, waiting_body)) = check_waiting_page(
+            config,
+            &site,
+            &cookies,
+            &locale,
+            queue,
+            queue_id,
+            priority.as_ref(),
+        )
+        .await?
         {
             return Ok((
                 StatusCode::SERVICE_UNAVAILABLE,
@@ -319,29 +415,31 @@ pub async fn omnis_studio_upstream(
         );
 
         // Strip waiting room cookies if we've arrived in the store
-        cookies.remove(Cookie::from(config.position_cookie_name.clone()));
-        cookies.remove(Cookie::from(config.queue_size_cookie_name.clone()));
+        cookies.remove(Cookie::from(site.position_cookie_name.clone()));
+        cookies.remove(Cookie::from(site.queue_size_cookie_name.clone()));
+        cookies.remove(Cookie::from(site.eta_cookie_name.clone()));
 
-        get_connection(
-            &state.upstream_pool,
+        let permit = get_connection(
+            upstream_pool,
             connection_type,
             Some(queue_id),
             config.acquire_timeout,
         )
-        .await
+        .await;
+        (permit, Some(queue_id))
     } else {
-        get_connection(
-            &state.upstream_pool,
-            connection_type,
-            None,
-            config.acquire_timeout,
-        )
-        .await
+        let permit =
+            get_connection(upstream_pool, connection_type, None, config.acquire_timeout).await;
+        (permit, None)
     };
 
-    // Process connection permit to determine upstream URI
-    let upstream_uri = match connection_permit {
-        Some(guard) => format!("{}{:?}", guard.uri, path_and_query),
+    // Process connection permit to determine upstream URI.  The permit is held until the response
+    // body is finished
+    let (mut connection_permit, mut upstream_server) = match connection_permit {
+        Some(permit) => {
+            let uri = permit.uri.clone();
+            (permit, uri)
+        }
         None => {
             return Ok((
                 StatusCode::SERVICE_UNAVAILABLE,
@@ -351,27 +449,150 @@ pub async fn omnis_studio_upstream(
         }
     };
 
-    // Build request body
-    let (upstream_method, upstream_uri, upstream_headers, upstream_body) = build_upstream_request(
-        config,
-        connect_info,
-        request,
-        path_and_query,
-        upstream_headers,
-        upstream_uri,
-    )
-    .await?;
-
-    // Process Request on Upstream
-    let start = Instant::now();
-    let client = &state.http_client;
-    let response = client
-        .request(upstream_method.clone(), upstream_uri.clone())
-        .headers(upstream_headers.clone())
-        .body(upstream_body)
-        .send()
+    // Build request body, without the priority tokens and invites meant for the waiting room
+    let upstream_path_and_query = strip_token_params(config, path_and_query)?;
+    let (upstream_method, upstream_target, upstream_headers, upstream_body) =
+        build_upstream_request(
+            config,
+            connect_info,
+            request,
+            &upstream_path_and_query,
+            upstream_headers,
+            upstream_path_and_query.to_string(),
+        )
         .await?;
 
+    // Keep a copy of the body to retry the request with, if it can be sent again
+    let retry_body = match upstream_body.as_bytes() {
+        Some(bytes) => Some(bytes.to_vec()),
+        // GET and HEAD bodies have no meaning, so are left off a retry
+        None if upstream_method == Method::GET || upstream_method == Method::HEAD => {
+            Some(Vec::new())
+        }
+        None => None,
+    };
+    let mut upstream_body = Some(upstream_body);
+
+    // Process Request on Upstream, retrying on another upstream if it can't be reached
+    let timeouts = config.response_timeouts(TrafficClass::new(connection_type, path));
+    let first_attempt = Instant::now();
+    let mut tried: Vec<String> = Vec::new();
+    let (start, upstream_uri, response) = loop {
+        // Client for the upstream, which may have its own TLS options
+        let upstream_tls = upstream_pool
+            .tls(&upstream_server)
+            .await
+            .unwrap_or_default();
+        let upstream_client = match state.http_clients.upstream(&upstream_server, &upstream_tls) {
+            Ok(client) => client,
+            Err(error) => {
+                error!(
+                    "Failed to build client for {}: {:?}",
+                    upstream_server, error
+                );
+                record_upstream_outcome(&state, &site, &upstream_server, Err(RequestError::Other))
+                    .await;
+                return Ok((
+                    StatusCode::BAD_GATEWAY,
+                    HeaderMap::new(),
+                    axum::body::Body::from("Bad Gateway"),
+                ));
+            }
+        };
+        let upstream_uri = format!("{}{}", upstream_client.uri, upstream_target);
+        let body = match upstream_body.take() {
+            Some(body) => body,
+            None => reqwest::Body::from(retry_body.clone().unwrap_or_default()),
+        };
+
+        let start = Instant::now();
+        let mut upstream_request = upstream_client
+            .client
+            .request(upstream_method.clone(), upstream_uri.clone())
+            .headers(upstream_headers.clone())
+            .body(body);
+        if let Some(timeout) = timeouts.response {
+            upstream_request = upstream_request.timeout(timeout);
+        }
+        let error = match upstream_request.send().await {
+            Ok(response) => break (start, upstream_uri, response),
+            Err(error) => error,
+        };
+
+        // Connection errors and timeouts count against the upstream's circuit breaker
+        let request_error = if error.is_timeout() {
+            RequestError::Timeout
+        } else if error.is_connect() {
+            RequestError::Connect
+        } else {
+            RequestError::Other
+        };
+        record_upstream_outcome(&state, &site, &upstream_server, Err(request_error)).await;
+        tried.push(upstream_server);
+
+        // A request that couldn't connect never reached the upstream, so can be sent to another
+        let budget = config.retry_budget.saturating_sub(first_attempt.elapsed());
+        if error.is_connect()
+            && retry_body.is_some()
+            && tried.len() <= config.retry_attempts
+            && !budget.is_zero()
+        {
+            drop(connection_permit);
+            if let Some(permit) = retry_connection(
+                upstream_pool,
+                connection_type,
+                queue_id,
+                config.acquire_timeout.min(budget),
+                &tried,
+            )
+            .await
+            {
+                warn!(
+                    "{} {} -> {:?} failed to connect, retrying on {}",
+                    method, path_and_query, tried, permit.uri
+                );
+                upstream_server = permit.uri.clone();
+                connection_permit = permit;
+                continue;
+            }
+        }
+
+        if error.is_timeout() {
+            warn!(
+                "{} {} -> {:?} timed out after {} ms",
+                method,
+                path_and_query,
+                tried,
+                first_attempt.elapsed().as_millis()
+            );
+            return Ok((
+                StatusCode::GATEWAY_TIMEOUT,
+                HeaderMap::new(),
+                axum::body::Body::from("Gateway Timeout"),
+            ));
+        }
+        return Err(error.into());
+    };
+    let outcome = match response.status().is_server_error() {
+        true => Err(RequestError::ServerError),
+        false => Ok(()),
+    };
+    // Recorded once the body has been streamed, which can still fail or time out
+    let response_outcome = {
+        let state = state.clone();
+        let site = site.clone();
+        let uri = upstream_server.clone();
+        ResponseOutcome::new(connection_permit, outcome, move |outcome, received| {
+            tokio::spawn(async move {
+                record_upstream_outcome(&state, &site, &uri, outcome).await;
+                site.upstream_pool.record_bytes(&uri, received).await;
+            });
+        })
+    };
+    upstream_pool
+        .record_latency(&upstream_server, start.elapsed())
+        .await;
+
     // Extract content type -- maybe don't add header for certain types?
     let content_type = match response.headers().get(CONTENT_TYPE) {
         Some(v) => String::from(v.to_str()?),
@@ -399,16 +620,16 @@ pub async fn omnis_studio_upstream(
     let evict_header = config.id_evict_upstream_http_header.as_str();
     if response.headers().get(evict_header).is_some() {
         // Upstream has specified that this client should be evicted
-        let cookie = private_cookies.get(config.id_cookie_name.clone().as_str());
+        let cookie = private_cookies.get(site.id_cookie_name.clone().as_str());
         if let QueueId::Existing(queue_id) = extract_queue_id(queue, &cookie) {
             // Remove cookie
-            private_cookies.remove(Cookie::from(config.id_cookie_name.clone()));
+            private_cookies.remove(Cookie::from(site.id_cookie_name.clone()));
             // Drop sticky session (if it exists)
             upstream_pool.remove_sticky_session(&queue_id).await;
             // Drop from queue (if it exists)
             if let Err(error) = state
                 .queue
-                .id_remove(&config.queue_prefix, queue_id, None)
+                .id_remove(&site.queue_prefix, queue_id, None)
                 .await
             {
                 error!(
@@ -429,11 +650,121 @@ pub async fn omnis_studio_upstream(
 
     // Copy all response headers except the ones in the ignore list
     let response_status = response.status();
-    let response_body = axum::body::Body::from_stream(response.bytes_stream());
+    let chunks = response.bytes_stream().map(|chunk| {
+        chunk.map_err(|error| match error.is_timeout() {
+            true => io::Error::new(io::ErrorKind::TimedOut, error),
+            false => io::Error::other(error),
+        })
+    });
+    let response_body = upstream_response_body(response_outcome, chunks, timeouts.idle);
 
     Ok((response_status, response_headers, response_body))
 }
 
+type Outcome = core::result::Result<(), RequestError>;
+
+/// Outcome of an upstream request whose response is still being streamed, along with its
+/// connection permit.  The outcome is recorded exactly once, when it is dropped (at the end of the
+/// body, or when the client goes away first)
+struct ResponseOutcome<F: FnOnce(Outcome, u64)> {
+    permit: ConnectionPermit,
+    outcome: Outcome,
+    received: u64,
+    record: Option<F>,
+}
+
+impl<F: FnOnce(Outcome, u64)> ResponseOutcome<F> {
+    fn new(permit: ConnectionPermit, outcome: Outcome, record: F) -> Self {
+        Self {
+            permit,
+            outcome,
+            received: 0,
+            record: Some(record),
+        }
+    }
+}
+
+impl<F: FnOnce(Outcome, u64)> Drop for ResponseOutcome<F> {
+    fn drop(&mut self) {
+        if let Some(record) = self.record.take() {
+            record(self.outcome, self.received);
+        }
+    }
+}
+
+/// Stream the body of an upstream response, holding the connection permit until it is finished.
+/// A body that fails or times out (in total or between parts) is cut short, and is recorded as
+/// the outcome of the request in place of the status it arrived with
+fn upstream_response_body<S, F>(
+    mut response_outcome: ResponseOutcome<F>,
+    chunks: S,
+    idle: Option<Duration>,
+) -> axum::body::Body
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    F: FnOnce(Outcome, u64) + Send + 'static,
+{
+    axum::body::Body::from_stream(stream! {
+        let chunks = idle_timeout(idle, chunks);
+        pin_mut!(chunks);
+
+        while let Some(chunk) = chunks.next().await {
+            let error = match chunk {
+                Ok(Ok(bytes)) => {
+                    response_outcome.received += bytes.len() as u64;
+                    yield Ok(bytes);
+                    continue;
+                }
+                Ok(Err(error)) if error.kind() != io::ErrorKind::TimedOut => {
+                    warn!("Response body from {} failed: {}", response_outcome.permit.uri, error);
+                    response_outcome.outcome = Err(RequestError::Other);
+                    yield Err(error);
+                    break;
+                }
+                Ok(Err(error)) => error,
+                Err(elapsed) => io::Error::new(io::ErrorKind::TimedOut, elapsed),
+            };
+
+            warn!("Response body from {} timed out: {}", response_outcome.permit.uri, error);
+            response_outcome.outcome = Err(RequestError::Timeout);
+            yield Err(error);
+            break;
+        }
+
+        drop(response_outcome);
+    })
+}
+
+/// Feed the outcome of an upstream request into its circuit breaker and statistics, publishing an
+/// event if the circuit opened or closed
+async fn record_upstream_outcome(
+    state: &AppState,
+    site: &Site,
+    uri: &str,
+    outcome: core::result::Result<(), RequestError>,
+) {
+    site.upstream_pool.record_request(uri, outcome).await;
+    let event = match site
+        .upstream_pool
+        .record_outcome(uri, outcome.is_ok())
+        .await
+    {
+        Some(CircuitTransition::Opened) => QueueEvent::UpstreamCircuitOpened,
+        Some(CircuitTransition::Closed) => QueueEvent::UpstreamCircuitClosed,
+        None => return,
+    };
+
+    if let Err(error) = state.queue.emit_event(&site.queue_prefix, event).await {
+        error!("Failed to publish circuit change for {}: {:?}", uri, error);
+    }
+    if let Err(error) = site.check_failover(&state.queue).await {
+        error!(
+            "Failed to publish upstream failover ({}): {:?}",
+            site.name, error
+        );
+    }
+}
+
 fn upstream_header_filter(entry: &(&HeaderName, &HeaderValue)) -> bool {
     let (h, _) = entry;
     !UPSTREAM_IGNORE.contains(*h) && !(*h).as_str().to_lowercase().starts_with("sec")
@@ -451,13 +782,13 @@ async fn build_upstream_request(
     request: Request,
     path_and_query: &PathAndQuery,
     upstream_headers: HeaderMap,
-    upstream_uri: String,
+    upstream_target: String,
 ) -> Result<(Method, String, HeaderMap, reqwest::Body)> {
     let request_method = request.method();
     let request_headers = request.headers();
     let request_path = path_and_query.path();
 
-    let mut upstream_uri = upstream_uri;
+    let mut upstream_target = upstream_target;
     let mut upstream_headers = upstream_headers.clone();
 
     let use_fallback = config.fallback_enabled()
@@ -503,7 +834,7 @@ async fn build_upstream_request(
 
             if is_ultra_thin(request_path) && request_method == Method::GET {
                 // Explicit GET request to /ultra
-                upstream_uri = format!("{}&{}", upstream_uri, ultra_thin_info.join("&"));
+                upstream_target = format!("{}&{}", upstream_target, ultra_thin_info.join("&"));
                 reqwest::Body::wrap_stream(request.into_body().into_data_stream())
             } else if is_ultra_thin(request_path)
                 && request_method == Method::POST
@@ -523,16 +854,7 @@ async fn build_upstream_request(
                 upstream_method = Method::POST;
                 upstream_headers.insert(CONTENT_TYPE, "application/x-www-form-urlencoded".parse()?);
 
-                let uri: Uri = upstream_uri.parse()?;
-                upstream_uri = format!(
-                    "{}://{}{}/ultra",
-                    uri.scheme().unwrap(),
-                    uri.host().unwrap(),
-                    match uri.port() {
-                        Some(p) => format!(":{}", p),
-                        None => String::new(),
-                    },
-                );
+                upstream_target = String::from("/ultra");
 
                 // Add additional info for the ultra-thin server (must be first in payload)
                 ultra_thin_info.insert(
@@ -578,7 +900,7 @@ async fn build_upstream_request(
 
     let ret = (
         upstream_method,
-        upstream_uri,
+        upstream_target,
         upstream_headers,
         upstream_body,
     );
@@ -593,6 +915,10 @@ fn is_javascript_client(path: &str) -> bool {
     JSCLIENT_RE.is_match(path)
 }
 
+fn is_push(path: &str) -> bool {
+    PUSH_RE.is_match(path)
+}
+
 fn is_rest_api(path: &str) -> bool {
     RESTAPI_RE.is_match(path)
 }
@@ -646,6 +972,63 @@ impl ConnectionType {
     }
 }
 
+/// Class of traffic a request belongs to, which sets how long the upstream has to respond
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TrafficClass {
+    CacheLoad,
+    JavascriptClient,
+    Push,
+    RestApi,
+    UltraThin,
+}
+
+impl TrafficClass {
+    fn new(connection_type: ConnectionType, path: &str) -> TrafficClass {
+        match connection_type {
+            ConnectionType::CacheLoad => TrafficClass::CacheLoad,
+            // Push requests are long-polls, which are held open until there is something to send
+            ConnectionType::StickySession if is_push(path) => TrafficClass::Push,
+            ConnectionType::StickySession => TrafficClass::JavascriptClient,
+            _ if is_rest_api(path) => TrafficClass::RestApi,
+            _ => TrafficClass::UltraThin,
+        }
+    }
+}
+
+/// How long an upstream has to respond, where None never times out
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ResponseTimeouts {
+    /// Time to finish the whole response, including the body
+    pub response: Option<Duration>,
+    /// Time between parts of the response body
+    pub idle: Option<Duration>,
+}
+
+/// Get a connection permit on an upstream that hasn't been tried yet, to retry a request that
+/// couldn't connect.  Sticky sessions only move once their upstream is known to be down, so a
+/// sticky session that is still pinned to a tried upstream isn't retried
+async fn retry_connection(
+    pool: &UpstreamPool,
+    connection_type: ConnectionType,
+    queue_token: Option<QueueId>,
+    timeout: Duration,
+    tried: &[String],
+) -> Option<ConnectionPermit> {
+    let permit = match connection_type {
+        ConnectionType::StickySession => {
+            get_connection(pool, connection_type, queue_token, timeout).await
+        }
+        ConnectionType::Regular(_) => {
+            pool.acquire_connection_permit_excluding(timeout, tried)
+                .await
+        }
+        ConnectionType::CacheLoad => pool.acquire_cache_load_permit_excluding(tried).await,
+        ConnectionType::Reject => None,
+    }?;
+
+    (!tried.contains(&permit.uri)).then_some(permit)
+}
+
 // Get a connection permit for the request, based on the method and path.
 pub async fn get_connection(
     pool: &UpstreamPool,
@@ -683,3 +1066,180 @@ async fn build_omnis_body(
 
     Ok(reqwest::Body::from(bytes))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures_util::stream;
+    use http_body_util::BodyExt;
+    use uuid::Uuid;
+
+    use crate::upstream::{CircuitBreakerSettings, CircuitState, Upstream};
+
+    /// Stream a response body from the pool's only upstream, recording its outcome in the pool
+    /// the way the handler does.  Returns the outcome, and whether the body was cut short
+    async fn stream_body<S>(pool: &UpstreamPool, chunks: S) -> (Outcome, u64, bool)
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let permit = pool
+            .acquire_connection_permit(Duration::ZERO)
+            .await
+            .unwrap();
+        let uri = permit.uri.clone();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let response_outcome = ResponseOutcome::new(permit, Ok(()), move |outcome, received| {
+            let _ = sender.send((outcome, received));
+        });
+
+        let body =
+            upstream_response_body(response_outcome, chunks, Some(Duration::from_millis(10)));
+        let cut_short = body.collect().await.is_err();
+        let (outcome, received) = receiver.await.expect("Outcome not recorded");
+        pool.record_request(&uri, outcome).await;
+        pool.record_outcome(&uri, outcome.is_ok()).await;
+        (outcome, received, cut_short)
+    }
+
+    fn body_pool() -> UpstreamPool {
+        let circuit_breaker = CircuitBreakerSettings {
+            failures: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        };
+        UpstreamPool::new(Duration::from_secs(60), circuit_breaker)
+    }
+
+    #[tokio::test]
+    async fn test_body_timeouts_open_circuit() {
+        let pool = body_pool();
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 10)])
+            .await;
+
+        // Headers arrive with a success, then the body hangs
+        for _ in 0..3 {
+            let hanging = stream::iter([Ok(Bytes::from("partial"))]).chain(stream::pending());
+            let (outcome, received, cut_short) = stream_body(&pool, hanging).await;
+            assert_eq!(outcome, Err(RequestError::Timeout));
+            assert_eq!(received, 7);
+            assert!(cut_short);
+        }
+
+        let status = &pool.statuses().await[0];
+        assert_eq!(status.circuit.state, CircuitState::Open);
+        assert_eq!(status.statistics.requests, 0);
+        assert_eq!(status.statistics.errors.timeout, 3);
+        assert_eq!(status.statistics.last_success, None);
+    }
+
+    #[tokio::test]
+    async fn test_body_error_recorded_once() {
+        let pool = body_pool();
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 10)])
+            .await;
+
+        let failing = stream::iter([
+            Ok(Bytes::from("abc")),
+            Err(io::Error::other("connection reset")),
+        ]);
+        let (outcome, received, cut_short) = stream_body(&pool, failing).await;
+        assert_eq!(outcome, Err(RequestError::Other));
+        assert_eq!(received, 3);
+        assert!(cut_short);
+
+        let statistics = &pool.statuses().await[0].statistics;
+        assert_eq!(statistics.requests, 0);
+        assert_eq!(statistics.errors.other, 1);
+        assert_eq!(statistics.last_success, None);
+
+        let complete = stream::iter([Ok(Bytes::from("abc")), Ok(Bytes::from("def"))]);
+        let (outcome, received, cut_short) = stream_body(&pool, complete).await;
+        assert_eq!(outcome, Ok(()));
+        assert_eq!(received, 6);
+        assert!(!cut_short);
+
+        let statistics = &pool.statuses().await[0].statistics;
+        assert_eq!(statistics.requests, 1);
+        assert_eq!(statistics.errors.other, 1);
+        assert!(statistics.last_success.is_some());
+    }
+
+    #[test]
+    fn test_traffic_class() {
+        let class = |method: Method, path: &str| {
+            TrafficClass::new(ConnectionType::new(&method, path, false), path)
+        };
+
+        assert_eq!(
+            class(Method::GET, "/jschtml/images/logo.png"),
+            TrafficClass::CacheLoad
+        );
+        assert_eq!(
+            class(Method::GET, "/jschtml/app.htm"),
+            TrafficClass::JavascriptClient
+        );
+        assert_eq!(
+            class(Method::POST, "/jsclient"),
+            TrafficClass::JavascriptClient
+        );
+        assert_eq!(class(Method::POST, "/push"), TrafficClass::Push);
+        assert_eq!(class(Method::GET, "/api/orders"), TrafficClass::RestApi);
+        assert_eq!(class(Method::GET, "/ultra"), TrafficClass::UltraThin);
+        assert_eq!(class(Method::POST, "/ultra"), TrafficClass::UltraThin);
+    }
+
+    #[tokio::test]
+    async fn test_retry_connection() {
+        let circuit_breaker = CircuitBreakerSettings {
+            failures: 2,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        };
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker);
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+        let timeout = Duration::from_millis(10);
+        let regular = ConnectionType::Regular(WaitingRoom::Skip);
+
+        // Regular requests move to an upstream that hasn't been tried
+        let tried = vec![String::from("http://127.0.0.1:1")];
+        let permit = retry_connection(&pool, regular, None, timeout, &tried)
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+        let tried = vec![
+            String::from("http://127.0.0.1:1"),
+            String::from("http://127.0.0.1:2"),
+        ];
+        assert!(
+            retry_connection(&pool, regular, None, timeout, &tried)
+                .await
+                .is_none()
+        );
+
+        // Sticky sessions stay on their upstream until it is known to be down
+        let queue_id = QueueId::New(Uuid::new_v4());
+        let sticky = ConnectionType::StickySession;
+        let first = get_connection(&pool, sticky, Some(queue_id), timeout)
+            .await
+            .unwrap();
+        let tried = vec![first.uri.clone()];
+        drop(first);
+        pool.record_outcome(&tried[0], false).await;
+        assert!(
+            retry_connection(&pool, sticky, Some(queue_id), timeout, &tried)
+                .await
+                .is_none()
+        );
+
+        pool.record_outcome(&tried[0], false).await;
+        let permit = retry_connection(&pool, sticky, Some(queue_id), timeout, &tried)
+            .await
+            .unwrap();
+        assert_ne!(permit.uri, tried[0]);
+    }
+}
@@ -1,6 +1,11 @@
 mod control;
 mod models;
 mod scripts;
+mod sticky;
 
 pub use self::control::{QueueControl, QueueEvents};
-pub use self::models::{QueueEvent, QueuePosition, QueueSettings, QueueStatus, StoreCapacity};
+pub use self::models::{
+    CapacityRamp, PriorityTier, QueueDisabledPolicy, QueueEvent, QueuePosition, QueueSettings,
+    QueueStatus, QueueThroughput, Schedule, StoreCapacity,
+};
+pub use self::sticky::StickySessions;
@@ -12,20 +12,23 @@ use std::{
 };
 use tokio::sync::{Notify, RwLock, broadcast, broadcast::Receiver};
 use tokio_stream::{StreamExt, wrappers::errors::BroadcastStreamRecvError};
-use tracing::error;
+use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::constants::{DEBOUNCE_INTERVAL, DEFAULT_WAITING_ROOM_PAGE, HTML_TEMPLATE_DIR};
 use crate::database::{RedisSubscriber, current_time, get_connection};
 use crate::errors::Result;
 use crate::queue::models::{
-    QueueEnabled, QueueEvent, QueuePosition, QueueRotate, QueueSettings, QueueStatus, StoreCapacity,
+    PriorityTier, QueueDisabledPolicy, QueueEnabled, QueueEvent, QueuePosition, QueueRotate,
+    QueueSettings, QueueStatus, QueueThroughput, Schedule, StoreCapacity,
 };
 use crate::queue::scripts::{
-    Scripts, queue_enabled_key, queue_ids_key, queue_sync_timestamp_key, store_capacity_key,
-    store_ids_key, waiting_page_key,
+    Scripts, invite_redemptions_key, priority_tiers_key, queue_enabled_key, queue_order_key,
+    queue_sync_timestamp_key, schedules_applied_key, schedules_key, store_capacity_key,
+    store_ids_key, upstreams_key, upstreams_seeded_key, waiting_page_key,
 };
 use crate::stream::debounce;
+use crate::upstream::{Upstream, UpstreamRecord};
 
 lazy_static! {
     static ref minfiy_cfg: Cfg = Cfg {
@@ -131,9 +134,11 @@ pub struct QueueControl {
     pool: RedisPool,
     quarantine_expiry: Duration,
     validated_expiry: Duration,
+    disabled_policy: QueueDisabledPolicy,
+    throughput_window: Duration,
     scripts: Scripts,
     publish_throttle: Duration,
-    throttle_buffer: RwLock<HashMap<QueueEvent, Instant>>,
+    throttle_buffer: RwLock<HashMap<(String, QueueEvent), Instant>>,
     waiting_page_cache: RwLock<HashMap<(String, String), String>>,
 }
 
@@ -143,11 +148,15 @@ impl QueueControl {
         quarantine_expiry: Duration,
         validated_expiry: Duration,
         publish_throttle: Duration,
+        disabled_policy: QueueDisabledPolicy,
+        throughput_window: Duration,
     ) -> Result<Self> {
         let queue = Self {
             pool,
             quarantine_expiry,
             validated_expiry,
+            disabled_policy,
+            throughput_window,
             scripts: Scripts::new()?,
             publish_throttle,
             throttle_buffer: RwLock::new(HashMap::new()),
@@ -168,6 +177,12 @@ impl QueueControl {
 
         let mut conn = self.conn().await?;
         self.scripts.init(&mut conn).await?;
+
+        let migrated = self.scripts.queue_migrate(&mut conn, &prefix).await?;
+        if migrated > 0 {
+            info!("Migrated {} queue IDs to sorted sets", migrated);
+        }
+
         self.verify_keys(&prefix, enabled, store_capacity).await?;
         for locale in locales.iter() {
             self.verify_waiting_page(&prefix, locale).await;
@@ -215,10 +230,10 @@ impl QueueControl {
         let prefix = prefix.into();
 
         // Check if event needs to be throttled
-        {
+        if event.is_throttled() {
             let guard = self.throttle_buffer.read().await;
             let now = now.unwrap_or(Instant::now());
-            let instant = (*guard).get(&event);
+            let instant = (*guard).get(&(prefix.clone(), event.clone()));
 
             if let Some(instant) = instant
                 && now.duration_since(*instant) < self.publish_throttle
@@ -240,7 +255,14 @@ impl QueueControl {
         // Write successful event to the event throttle buffer
         let now = now.unwrap_or(Instant::now());
         let mut guard = self.throttle_buffer.write().await;
-        (*guard).insert(event, now);
+        (*guard).insert((prefix, event), now);
+    }
+
+    /// Emit an event on a connection of its own, for events raised outside of the queue
+    pub async fn emit_event(&self, prefix: impl Into<String>, event: QueueEvent) -> Result<()> {
+        let mut conn = self.conn().await?;
+        self.emit(&mut conn, prefix, event, None).await;
+        Ok(())
     }
 
     /// Flush the event throttle buffer of any stale events
@@ -268,7 +290,7 @@ impl QueueControl {
             .get(queue_enabled_key(&prefix))
             .get(store_capacity_key(&prefix))
             .scard(store_ids_key(&prefix))
-            .llen(queue_ids_key(&prefix))
+            .zcard(queue_order_key(&prefix))
             .get(queue_sync_timestamp_key(&prefix))
             .query_async(&mut conn)
             .await?;
@@ -281,12 +303,25 @@ impl QueueControl {
             capacity: StoreCapacity::try_from(result.1)?,
             store_size: result.2.unwrap_or(0),
             queue_size: result.3.unwrap_or(0),
+            throughput: self.throughput(&prefix, None).await?,
             updated: DateTime::from_timestamp_secs(result.4.unwrap_or(0)),
         };
 
         Ok(status)
     }
 
+    /// Recent throughput of the store, used to estimate the wait for a position in the queue
+    pub async fn throughput(
+        &self,
+        prefix: impl Into<String>,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<QueueThroughput> {
+        let mut conn = self.conn().await?;
+        self.scripts
+            .throughput(&mut conn, prefix, time, self.throughput_window)
+            .await
+    }
+
     /// Set the current status of the queue
     pub async fn queue_settings(&self, prefix: impl Into<String>) -> Result<QueueSettings> {
         let prefix = prefix.into();
@@ -507,6 +542,290 @@ impl QueueControl {
         }
     }
 
+    /// All priority tiers, highest priority first
+    pub async fn priority_tiers(&self, prefix: impl Into<String>) -> Result<Vec<PriorityTier>> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        let values = conn.hvals(priority_tiers_key(&prefix)).await?;
+
+        let mut tiers = Vec::new();
+        for value in values.iter() {
+            tiers.push(serde_json::from_str::<PriorityTier>(value)?);
+        }
+        tiers.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.name.cmp(&b.name)));
+
+        Ok(tiers)
+    }
+
+    /// Single priority tier, if it exists
+    pub async fn priority_tier(
+        &self,
+        prefix: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Result<Option<PriorityTier>> {
+        let prefix = prefix.into();
+        let name = name.into();
+
+        let mut conn = self.conn().await?;
+        let value = conn.hget(priority_tiers_key(&prefix), name).await?;
+
+        match value {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Create or replace a priority tier
+    pub async fn set_priority_tier(
+        &self,
+        prefix: impl Into<String>,
+        tier: &PriorityTier,
+    ) -> Result<()> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        conn.hset(
+            priority_tiers_key(&prefix),
+            &tier.name,
+            serde_json::to_string(tier)?,
+        )
+        .await?;
+
+        self.emit(&mut conn, &prefix, QueueEvent::SettingsChanged, None)
+            .await;
+
+        Ok(())
+    }
+
+    /// Remove a priority tier, returning true if it existed.  IDs already placed by the tier
+    /// keep their place in the queue
+    pub async fn remove_priority_tier(
+        &self,
+        prefix: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Result<bool> {
+        let prefix = prefix.into();
+        let name = name.into();
+
+        let mut conn = self.conn().await?;
+        let removed = conn.hdel(priority_tiers_key(&prefix), name).await?;
+
+        if removed > 0 {
+            self.emit(&mut conn, &prefix, QueueEvent::SettingsChanged, None)
+                .await;
+        }
+
+        Ok(removed > 0)
+    }
+
+    /// Store the initial upstreams, the first time any server starts with the prefix.  After that
+    /// the stored upstreams are only changed through the control API
+    pub async fn verify_upstreams(
+        &self,
+        prefix: impl Into<String>,
+        initial_upstream: &[Upstream],
+    ) -> Result<()> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        if !conn.set_nx(upstreams_seeded_key(&prefix), 1).await? {
+            return Ok(());
+        }
+
+        let mut pipeline = pipe();
+        pipeline.atomic();
+        for upstream in initial_upstream.iter() {
+            let record = UpstreamRecord::new(upstream.clone());
+            pipeline
+                .hset_nx(
+                    upstreams_key(&prefix),
+                    &upstream.uri,
+                    serde_json::to_string(&record)?,
+                )
+                .ignore();
+        }
+        let _: () = pipeline.query_async(&mut conn).await?;
+
+        self.emit(&mut conn, &prefix, QueueEvent::UpstreamsChanged, None)
+            .await;
+
+        Ok(())
+    }
+
+    /// All stored upstreams, in URI order
+    pub async fn upstreams(&self, prefix: impl Into<String>) -> Result<Vec<UpstreamRecord>> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        let values = conn.hvals(upstreams_key(&prefix)).await?;
+
+        let mut upstreams = Vec::new();
+        for value in values.iter() {
+            upstreams.push(serde_json::from_str::<UpstreamRecord>(value)?);
+        }
+        upstreams.sort_by(|a, b| a.upstream.uri.cmp(&b.upstream.uri));
+
+        Ok(upstreams)
+    }
+
+    /// Create or replace stored upstreams
+    pub async fn set_upstreams(
+        &self,
+        prefix: impl Into<String>,
+        upstreams: &[UpstreamRecord],
+    ) -> Result<()> {
+        let prefix = prefix.into();
+        if upstreams.is_empty() {
+            return Ok(());
+        }
+
+        let mut items = Vec::with_capacity(upstreams.len());
+        for record in upstreams.iter() {
+            items.push((record.upstream.uri.clone(), serde_json::to_string(record)?));
+        }
+
+        let mut conn = self.conn().await?;
+        conn.hset_multiple(upstreams_key(&prefix), &items).await?;
+
+        self.emit(&mut conn, &prefix, QueueEvent::UpstreamsChanged, None)
+            .await;
+
+        Ok(())
+    }
+
+    /// Remove stored upstreams, returning the number that existed
+    pub async fn remove_upstreams(
+        &self,
+        prefix: impl Into<String>,
+        uris: &[String],
+    ) -> Result<usize> {
+        let prefix = prefix.into();
+        if uris.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = self.conn().await?;
+        let removed = conn.hdel(upstreams_key(&prefix), uris).await?;
+
+        if removed > 0 {
+            self.emit(&mut conn, &prefix, QueueEvent::UpstreamsChanged, None)
+                .await;
+        }
+
+        Ok(removed)
+    }
+
+    /// All schedules, in the order they start
+    pub async fn schedules(&self, prefix: impl Into<String>) -> Result<Vec<Schedule>> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        let values = conn.hvals(schedules_key(&prefix)).await?;
+
+        let mut schedules = Vec::new();
+        for value in values.iter() {
+            schedules.push(serde_json::from_str::<Schedule>(value)?);
+        }
+        schedules.sort_by(|a, b| a.start.cmp(&b.start).then(a.id.cmp(&b.id)));
+
+        Ok(schedules)
+    }
+
+    /// Single schedule, if it exists
+    pub async fn schedule(
+        &self,
+        prefix: impl Into<String>,
+        id: impl Into<String>,
+    ) -> Result<Option<Schedule>> {
+        let prefix = prefix.into();
+        let id = id.into();
+
+        let mut conn = self.conn().await?;
+        let value = conn.hget(schedules_key(&prefix), id).await?;
+
+        match value {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Create or replace a schedule.  Replacing a schedule forgets the steps already applied, so
+    /// the new settings are applied even if they match an earlier step
+    pub async fn set_schedule(&self, prefix: impl Into<String>, schedule: &Schedule) -> Result<()> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        let _: (usize, usize) = pipe()
+            .atomic()
+            .hset(
+                schedules_key(&prefix),
+                &schedule.id,
+                serde_json::to_string(schedule)?,
+            )
+            .hdel(schedules_applied_key(&prefix), &schedule.id)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove a schedule, returning true if it existed.  Settings already applied by the schedule
+    /// are left as they are
+    pub async fn remove_schedule(
+        &self,
+        prefix: impl Into<String>,
+        id: impl Into<String>,
+    ) -> Result<bool> {
+        let prefix = prefix.into();
+        let id = id.into();
+
+        let mut conn = self.conn().await?;
+        let (removed, _): (usize, usize) = pipe()
+            .atomic()
+            .hdel(schedules_key(&prefix), &id)
+            .hdel(schedules_applied_key(&prefix), &id)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(removed > 0)
+    }
+
+    /// Apply the current step of every schedule that has started, in the order they start.
+    /// Returns the number of schedules that changed the queue settings
+    pub async fn apply_schedules(
+        &self,
+        prefix: impl Into<String>,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<usize> {
+        let prefix = prefix.into();
+        let schedules = self.schedules(&prefix).await?;
+
+        let mut conn = self.conn().await?;
+        let time = match time {
+            Some(t) => t,
+            None => current_time(&mut conn).await?,
+        };
+
+        let mut applied = 0;
+        for schedule in schedules.iter() {
+            let Some(step) = schedule.step_at(time) else {
+                continue;
+            };
+            if self
+                .scripts
+                .schedule_apply(&mut conn, &prefix, &schedule.id, &step, time)
+                .await?
+            {
+                self.emit(&mut conn, &prefix, QueueEvent::SettingsChanged, None)
+                    .await;
+                applied += 1;
+            }
+        }
+
+        Ok(applied)
+    }
+
     /// Check that all keys required for syncing the queue/store are available
     pub async fn check_sync_keys(&self, prefix: impl Into<String>) -> Result<bool> {
         let mut conn = self.conn().await?;
@@ -521,6 +840,20 @@ impl QueueControl {
         id: Uuid,
         time: Option<DateTime<Utc>>,
         create: bool,
+    ) -> Result<QueuePosition> {
+        self.id_position_with_priority(prefix, id, time, create, 0)
+            .await
+    }
+
+    /// Return the position of a UUID in the queue, placing it ahead of any UUIDs with a lower
+    /// priority.  A UUID already in the queue is moved up if the priority is higher than it had
+    pub async fn id_position_with_priority(
+        &self,
+        prefix: impl Into<String>,
+        id: Uuid,
+        time: Option<DateTime<Utc>>,
+        create: bool,
+        priority: u16,
     ) -> Result<QueuePosition> {
         let prefix = prefix.into();
         let mut conn = self.conn().await?;
@@ -535,6 +868,8 @@ impl QueueControl {
                 self.validated_expiry,
                 self.quarantine_expiry,
                 create,
+                self.disabled_policy,
+                priority,
             )
             .await?;
 
@@ -576,6 +911,57 @@ impl QueueControl {
         Ok(position)
     }
 
+    /// Redeem an invite for a given ID, promoting it into the store regardless of capacity.
+    /// Returns the number of IDs that have redeemed the invite, or None if the invite has no
+    /// redemptions left (an ID that already redeemed the invite can always redeem it again)
+    pub async fn invite_redeem(
+        &self,
+        prefix: impl Into<String>,
+        invite: Uuid,
+        id: Uuid,
+        uses: u32,
+        expires: DateTime<Utc>,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<Option<usize>> {
+        let prefix = prefix.into();
+        let mut conn = self.conn().await?;
+
+        let redeemed = self
+            .scripts
+            .invite_redeem(
+                &mut conn,
+                &prefix,
+                invite,
+                id,
+                uses,
+                expires,
+                time,
+                self.validated_expiry,
+            )
+            .await?;
+
+        if redeemed.is_some() {
+            self.emit(&mut conn, &prefix, QueueEvent::StoreAdded, None)
+                .await;
+        }
+
+        Ok(redeemed)
+    }
+
+    /// Number of IDs that have redeemed an invite
+    pub async fn invite_redemptions(
+        &self,
+        prefix: impl Into<String>,
+        invite: Uuid,
+    ) -> Result<usize> {
+        let prefix = prefix.into();
+
+        let mut conn = self.conn().await?;
+        let redeemed = conn.scard(invite_redemptions_key(&prefix, invite)).await?;
+
+        Ok(redeemed)
+    }
+
     /// Remove a given UUID from the queue/store
     pub async fn id_remove(
         &self,
@@ -599,7 +985,16 @@ impl QueueControl {
     ) -> Result<QueueRotate> {
         let prefix = prefix.into();
         let mut conn = self.conn().await?;
-        let rotate = self.scripts.rotate_full(&mut conn, &prefix, time).await?;
+        let rotate = self
+            .scripts
+            .rotate_full(
+                &mut conn,
+                &prefix,
+                time,
+                self.disabled_policy,
+                self.throughput_window,
+            )
+            .await?;
 
         if rotate.promoted > 0 {
             self.emit(&mut conn, &prefix, QueueEvent::StoreAdded, None)
@@ -624,14 +1019,19 @@ mod test {
 
     use tracing_test::traced_test;
 
+    use chrono::TimeDelta;
+
     use crate::database::test::create_test_pool;
+    use crate::queue::models::CapacityRamp;
     use crate::queue::scripts::{
-        queue_expiry_secs_key, queue_position_cache_key, store_expiry_secs_key,
+        legacy_queue_expiry_secs_key, legacy_queue_ids_key, legacy_queue_position_cache_key,
+        queue_expiry_key, queue_join_counter_key, store_expiry_secs_key,
     };
 
     static QUARANTINE: Duration = Duration::from_secs(45);
     static VALIDATED: Duration = Duration::from_secs(600);
     static EMIT_THROTTLE: Duration = Duration::from_secs(100);
+    static THROUGHPUT_WINDOW: Duration = Duration::from_secs(600);
 
     /// Current size of the queue
     pub async fn queue_enabled(queue: &QueueControl, prefix: impl Into<String>) -> Result<bool> {
@@ -655,7 +1055,7 @@ mod test {
         let prefix = prefix.into();
 
         let mut conn = queue.conn().await?;
-        let result = conn.llen(queue_ids_key(&prefix)).await?;
+        let result = conn.zcard(queue_order_key(&prefix)).await?;
 
         Ok(result)
     }
@@ -685,9 +1085,20 @@ mod test {
     }
 
     fn test_queue() -> QueueControl {
+        test_queue_with_policy(QueueDisabledPolicy::Drain)
+    }
+
+    fn test_queue_with_policy(disabled_policy: QueueDisabledPolicy) -> QueueControl {
         let pool = create_test_pool().expect("Failed to create test pool");
-        QueueControl::new(pool, QUARANTINE, VALIDATED, EMIT_THROTTLE)
-            .expect("Failed to create test QueueControl")
+        QueueControl::new(
+            pool,
+            QUARANTINE,
+            VALIDATED,
+            EMIT_THROTTLE,
+            disabled_policy,
+            THROUGHPUT_WINDOW,
+        )
+        .expect("Failed to create test QueueControl")
     }
 
     fn generate_ids(queue: &QueueControl, count: usize) -> Vec<String> {
@@ -702,9 +1113,9 @@ mod test {
         let prefix = prefix.into();
 
         let keys = &[
-            queue_ids_key(&prefix),
-            queue_position_cache_key(&prefix),
-            queue_expiry_secs_key(&prefix),
+            queue_order_key(&prefix),
+            queue_expiry_key(&prefix),
+            queue_join_counter_key(&prefix),
             store_ids_key(&prefix),
             store_expiry_secs_key(&prefix),
         ];
@@ -716,6 +1127,22 @@ mod test {
         }
     }
 
+    /// Add `count` users to the queue, closing the store first so that every ID is queued
+    async fn queued_ids(
+        queue: &QueueControl,
+        conn: &mut Connection,
+        prefix: &str,
+        count: usize,
+    ) -> Vec<Uuid> {
+        clear_store(prefix, conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .await
+            .expect("Failed to set queue status");
+
+        add_many(queue, prefix, count, None).await
+    }
+
     /// Add `count` users to the queue
     async fn add_many(
         queue: &QueueControl,
@@ -770,18 +1197,26 @@ mod test {
     ) {
         let prefix = prefix.into();
 
-        let key = queue_ids_key(prefix);
+        let key = queue_order_key(prefix);
         conn.del(&key)
             .await
             .expect(format!("Failed to delete {}", key).as_ref());
 
         let ids = generate_ids(&queue, count);
 
-        conn.lpush(&key, &ids)
+        conn.zadd_multiple(&key, &ordered_members(&ids))
             .await
             .expect(format!("Failed to queue ids: {:?}", ids).as_ref());
     }
 
+    /// Pair IDs with their join order, for adding directly to the queue order sorted set
+    fn ordered_members(ids: &[String]) -> Vec<(usize, String)> {
+        ids.iter()
+            .enumerate()
+            .map(|(i, id)| (i + 1, id.clone()))
+            .collect()
+    }
+
     async fn push_store_ids(
         prefix: impl Into<String>,
         queue: &QueueControl,
@@ -802,19 +1237,13 @@ mod test {
             .expect(format!("Failed to store ids: {:?}", ids).as_ref());
     }
 
-    async fn hget_u64(conn: &mut Connection, key: &String, value: &String) -> u64 {
-        let result: Option<String> = conn
-            .hget(key, value)
+    async fn zscore_u64(conn: &mut Connection, key: &String, member: &String) -> u64 {
+        let result: Option<f64> = conn
+            .zscore(key, member)
             .await
             .expect(format!("failed to get fetch {}", key).as_ref());
 
-        let parsed = match result {
-            Some(e) => e
-                .parse::<u64>()
-                .expect(format!("failed to parse u64: {}", key).as_ref()),
-            None => 0,
-        };
-        parsed
+        result.unwrap_or(0.0) as u64
     }
 
     async fn test_queue_conn() -> (QueueControl, Connection) {
@@ -834,8 +1263,15 @@ mod test {
             return;
         };
 
-        QueueControl::new(pool, QUARANTINE, VALIDATED, EMIT_THROTTLE)
-            .expect("QueueControl::new() failed");
+        QueueControl::new(
+            pool,
+            QUARANTINE,
+            VALIDATED,
+            EMIT_THROTTLE,
+            QueueDisabledPolicy::Drain,
+            THROUGHPUT_WINDOW,
+        )
+        .expect("QueueControl::new() failed");
     }
 
     async fn clean_keys(prefix: impl Into<String>) {
@@ -1030,14 +1466,14 @@ mod test {
 
         let (queue, mut conn) = test_queue_conn().await;
 
-        let key = queue_ids_key(prefix);
+        let key = queue_order_key(prefix);
         conn.del(&key)
             .await
             .expect(format!("Failed to delete {}", key).as_ref());
 
         let count = 3;
         let ids = generate_ids(&queue, count);
-        conn.lpush(&key, &ids)
+        conn.zadd_multiple(&key, &ordered_members(&ids))
             .await
             .expect(format!("Failed to push ids: {:?}", ids).as_ref());
 
@@ -1262,7 +1698,7 @@ mod test {
         let id = queue.new_id();
         let id_string = String::from(id);
         let time = DateTime::from_timestamp_secs(1758040541).expect("Failed to create timestamp");
-        let redis_key = format!("{}:queue_expiry_secs", prefix);
+        let redis_key = queue_expiry_key(prefix);
 
         // Add item to the queue for quarantine
         queue
@@ -1270,7 +1706,7 @@ mod test {
             .await
             .expect("Failed to add new ID to queue");
 
-        let expiry = hget_u64(&mut conn, &redis_key, &id_string).await;
+        let expiry = zscore_u64(&mut conn, &redis_key, &id_string).await;
         assert_eq!(expiry, time.timestamp() as u64 + QUARANTINE.as_secs());
 
         // Fetch position a second time (upgrading the ID from quarantine to validated)
@@ -1279,7 +1715,7 @@ mod test {
             .await
             .expect("Failed to add new ID to queue");
 
-        let expiry = hget_u64(&mut conn, &redis_key, &id_string).await;
+        let expiry = zscore_u64(&mut conn, &redis_key, &id_string).await;
         assert_eq!(expiry, time.timestamp() as u64 + VALIDATED.as_secs());
 
         clean_keys(prefix).await;
@@ -1287,48 +1723,561 @@ mod test {
 
     #[tokio::test]
     #[traced_test]
-    async fn test_id_promote_add() {
-        let prefix = "test_id_promote_add";
+    async fn test_id_position_disabled_new() {
+        let prefix = "test_id_position_disabled_new";
 
         let (queue, mut conn) = test_queue_conn().await;
 
-        // Clear store and initialize store capacity to 0
+        // Closed store, with the queue disabled
         clear_store(prefix, &mut conn).await;
         queue
-            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .set_queue_settings(prefix, false, StoreCapacity::Sized(0))
             .await
             .expect("Failed to set queue status");
 
-        let id = queue.new_id();
-
-        // Check that the first ID is in the store
+        // New IDs skip the queue entirely
         let position = queue
-            .id_promote(prefix, id, None)
+            .id_position(prefix, queue.new_id(), None, true)
             .await
-            .expect("Failed to add ID");
+            .expect("Failed to get position");
 
         assert_eq!(position, QueuePosition::Store);
 
+        let queue_size = queue_size(&queue, prefix)
+            .await
+            .expect("Failed to get queue size");
+        assert_eq!(queue_size, 0);
+
         clean_keys(prefix).await;
     }
 
     #[tokio::test]
     #[traced_test]
-    async fn test_id_remove_store() {
-        let prefix = "test_id_remove_store";
+    async fn test_id_position_disabled_drain() {
+        let prefix = "test_id_position_disabled_drain";
 
         let (queue, mut conn) = test_queue_conn().await;
 
-        // Clear store and initialize store capacity to 1
-        clear_store(prefix, &mut conn).await;
+        let ids = queued_ids(&queue, &mut conn, prefix, 3).await;
+
+        // Disable the queue, and the queued ID is moved into the store
         queue
-            .set_queue_settings(prefix, true, StoreCapacity::Sized(1))
+            .set_queue_enabled(prefix, false)
             .await
-            .expect("Failed to set queue status");
+            .expect("Failed to disable queue");
 
-        let count = 5;
-        let ids = add_many(&queue, prefix, count, None).await;
-        let store_id = &ids[0];
+        let position = queue
+            .id_position(prefix, ids[1], None, true)
+            .await
+            .expect("Failed to get position");
+
+        assert_eq!(position, QueuePosition::Store);
+        assert!(exists_in_store(prefix, &mut conn, ids[1]).await);
+
+        // Re-enabling the queue does not put the drained ID back in the queue
+        queue
+            .set_queue_enabled(prefix, true)
+            .await
+            .expect("Failed to enable queue");
+
+        let position = queue
+            .id_position(prefix, ids[1], None, true)
+            .await
+            .expect("Failed to get position");
+
+        assert_eq!(position, QueuePosition::Store);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_position_disabled_keep() {
+        let prefix = "test_id_position_disabled_keep";
+
+        let queue = test_queue_with_policy(QueueDisabledPolicy::Keep);
+        let mut conn = get_connection(&queue.pool)
+            .await
+            .expect("Redis connection failed");
+
+        let ids = queued_ids(&queue, &mut conn, prefix, 3).await;
+
+        // Disable the queue, and the queued ID is let through without joining the store
+        queue
+            .set_queue_enabled(prefix, false)
+            .await
+            .expect("Failed to disable queue");
+
+        let position = queue
+            .id_position(prefix, ids[1], None, true)
+            .await
+            .expect("Failed to get position");
+
+        assert_eq!(position, QueuePosition::Store);
+        assert!(!exists_in_store(prefix, &mut conn, ids[1]).await);
+
+        // Re-enabling the queue puts the ID back in its original place
+        queue
+            .set_queue_enabled(prefix, true)
+            .await
+            .expect("Failed to enable queue");
+
+        let position = queue
+            .id_position(prefix, ids[1], None, true)
+            .await
+            .expect("Failed to get position");
+
+        assert_eq!(position, QueuePosition::Queue(2));
+
+        clean_keys(prefix).await;
+    }
+
+    /// Assert that `ids` are the whole queue, in order
+    async fn assert_queue_positions(queue: &QueueControl, prefix: &str, ids: &[Uuid]) {
+        for (i, id) in ids.iter().enumerate() {
+            let position = queue
+                .id_position(prefix, *id, None, false)
+                .await
+                .expect("Failed to get position");
+            assert_eq!(position, QueuePosition::Queue(i + 1));
+        }
+
+        let queue_size = queue_size(queue, prefix)
+            .await
+            .expect("Failed to get queue size");
+        assert_eq!(queue_size, ids.len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_position_after_rotate_promote() {
+        let prefix = "test_id_position_after_rotate_promote";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        let ids = queued_ids(&queue, &mut conn, prefix, 5).await;
+        assert_queue_positions(&queue, prefix, &ids).await;
+
+        // Open the store for 2 IDs, and the remaining IDs move up to the front
+        queue
+            .set_store_capacity(prefix, StoreCapacity::Sized(2))
+            .await
+            .expect("Failed to set store capacity");
+
+        let rotation = queue
+            .rotate_full(prefix, None)
+            .await
+            .expect("Failed to rotate");
+        assert_eq!(rotation.promoted, 2);
+
+        for id in &ids[..2] {
+            let position = queue
+                .id_position(prefix, *id, None, false)
+                .await
+                .expect("Failed to get position");
+            assert_eq!(position, QueuePosition::Store);
+        }
+        assert_queue_positions(&queue, prefix, &ids[2..]).await;
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_position_after_id_promote() {
+        let prefix = "test_id_position_after_id_promote";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        let ids = queued_ids(&queue, &mut conn, prefix, 5).await;
+
+        // Promote an ID from the middle of the queue, without a rotation, and every ID behind it
+        // moves up immediately
+        queue
+            .id_promote(prefix, ids[2], None)
+            .await
+            .expect("Failed to promote ID");
+
+        let remaining = [ids[0], ids[1], ids[3], ids[4]];
+        assert_queue_positions(&queue, prefix, &remaining).await;
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_position_with_priority() {
+        let prefix = "test_id_position_with_priority";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        let ids = queued_ids(&queue, &mut conn, prefix, 3).await;
+
+        // Higher priorities go ahead of lower priorities, in the order they joined
+        let member = queue.new_id();
+        let staff = queue.new_id();
+        let late_member = queue.new_id();
+        for (id, priority, expected) in [(member, 10, 1), (staff, 20, 1), (late_member, 10, 3)] {
+            let position = queue
+                .id_position_with_priority(prefix, id, None, true, priority)
+                .await
+                .expect("Failed to add ID with priority");
+            assert_eq!(position, QueuePosition::Queue(expected));
+        }
+
+        let order = [staff, member, late_member, ids[0], ids[1], ids[2]];
+        assert_queue_positions(&queue, prefix, &order).await;
+
+        // An ID already in the queue moves up to a higher priority, but never down
+        let position = queue
+            .id_position_with_priority(prefix, ids[2], None, false, 20)
+            .await
+            .expect("Failed to upgrade ID");
+        assert_eq!(position, QueuePosition::Queue(2));
+        let position = queue
+            .id_position_with_priority(prefix, staff, None, false, 10)
+            .await
+            .expect("Failed to read ID");
+        assert_eq!(position, QueuePosition::Queue(1));
+
+        // Rotations promote the highest priority first
+        queue
+            .set_store_capacity(prefix, StoreCapacity::Sized(2))
+            .await
+            .expect("Failed to set store capacity");
+        let rotation = queue
+            .rotate_full(prefix, None)
+            .await
+            .expect("Failed to rotate");
+        assert_eq!(rotation.promoted, 2);
+
+        let order = [member, late_member, ids[0], ids[1]];
+        assert_queue_positions(&queue, prefix, &order).await;
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_invite_redeem() {
+        let prefix = "test_invite_redeem";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        // Closed store, so that only invites get in
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .await
+            .expect("Failed to set queue status");
+
+        let ids = add_many(&queue, prefix, 3, None).await;
+        let invite = queue.new_id();
+        let expires = Utc::now() + Duration::from_secs(60);
+
+        let redeemed = queue
+            .invite_redeem(prefix, invite, ids[1], 2, expires, None)
+            .await
+            .expect("Failed to redeem invite");
+        assert_eq!(redeemed, Some(1));
+        let position = queue
+            .id_position(prefix, ids[1], None, false)
+            .await
+            .expect("Failed to get position");
+        assert_eq!(position, QueuePosition::Store);
+
+        // Redeeming again with the same ID doesn't use up the invite
+        let redeemed = queue
+            .invite_redeem(prefix, invite, ids[1], 2, expires, None)
+            .await
+            .expect("Failed to redeem invite");
+        assert_eq!(redeemed, Some(1));
+
+        let redeemed = queue
+            .invite_redeem(prefix, invite, ids[2], 2, expires, None)
+            .await
+            .expect("Failed to redeem invite");
+        assert_eq!(redeemed, Some(2));
+
+        // All uses are gone, so the last ID stays in the queue
+        let redeemed = queue
+            .invite_redeem(prefix, invite, ids[0], 2, expires, None)
+            .await
+            .expect("Failed to redeem invite");
+        assert_eq!(redeemed, None);
+        assert_queue_positions(&queue, prefix, &[ids[0]]).await;
+
+        let redeemed = queue
+            .invite_redemptions(prefix, invite)
+            .await
+            .expect("Failed to read invite redemptions");
+        assert_eq!(redeemed, 2);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_priority_tiers() {
+        let prefix = "test_priority_tiers";
+        clean_keys(prefix).await;
+
+        let queue = test_queue();
+        let members = PriorityTier::new("members", 10, false).unwrap();
+        let staff = PriorityTier::new("staff", 100, true).unwrap();
+
+        for tier in [&members, &staff] {
+            queue
+                .set_priority_tier(prefix, tier)
+                .await
+                .expect("Failed to set priority tier");
+        }
+
+        let tiers = queue
+            .priority_tiers(prefix)
+            .await
+            .expect("Failed to read priority tiers");
+        assert_eq!(tiers, vec![staff.clone(), members.clone()]);
+
+        let tier = queue
+            .priority_tier(prefix, "staff")
+            .await
+            .expect("Failed to read priority tier");
+        assert_eq!(tier, Some(staff));
+
+        let removed = queue
+            .remove_priority_tier(prefix, "staff")
+            .await
+            .expect("Failed to remove priority tier");
+        assert!(removed);
+        let removed = queue
+            .remove_priority_tier(prefix, "staff")
+            .await
+            .expect("Failed to remove priority tier");
+        assert!(!removed);
+
+        let tier = queue
+            .priority_tier(prefix, "staff")
+            .await
+            .expect("Failed to read priority tier");
+        assert_eq!(tier, None);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_upstreams() {
+        let prefix = "test_upstreams";
+        clean_keys(prefix).await;
+
+        let queue = test_queue();
+        let first = Upstream::new("http://127.0.0.1:1", 10, 5);
+        let second = Upstream::new("http://127.0.0.1:2", 20, 5).with_weight(2);
+
+        // Seeded only once, so later starts keep the upstreams changed through the API
+        queue
+            .verify_upstreams(prefix, std::slice::from_ref(&second))
+            .await
+            .expect("Failed to seed upstreams");
+        queue
+            .remove_upstreams(prefix, std::slice::from_ref(&second.uri))
+            .await
+            .expect("Failed to remove upstreams");
+        queue
+            .verify_upstreams(prefix, std::slice::from_ref(&second))
+            .await
+            .expect("Failed to seed upstreams");
+        let upstreams = queue
+            .upstreams(prefix)
+            .await
+            .expect("Failed to read upstreams");
+        assert!(upstreams.is_empty());
+
+        let records = vec![
+            UpstreamRecord::new(first.clone()),
+            UpstreamRecord::new(second.clone()),
+        ];
+        queue
+            .set_upstreams(prefix, &[records[1].clone(), records[0].clone()])
+            .await
+            .expect("Failed to set upstreams");
+        let upstreams = queue
+            .upstreams(prefix)
+            .await
+            .expect("Failed to read upstreams");
+        assert_eq!(upstreams, records);
+
+        let removed = queue
+            .remove_upstreams(
+                prefix,
+                &[first.uri.clone(), String::from("http://127.0.0.1:3")],
+            )
+            .await
+            .expect("Failed to remove upstreams");
+        assert_eq!(removed, 1);
+        let upstreams = queue
+            .upstreams(prefix)
+            .await
+            .expect("Failed to read upstreams");
+        assert_eq!(upstreams, vec![UpstreamRecord::new(second)]);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_schedules() {
+        let prefix = "test_schedules";
+        clean_keys(prefix).await;
+
+        let queue = test_queue();
+        queue
+            .set_queue_settings(prefix, false, StoreCapacity::Sized(10))
+            .await
+            .expect("Failed to set queue status");
+
+        let start = DateTime::from_timestamp_secs(1_700_000_000).unwrap();
+        let opening = Schedule::new("opening", start, Some(true), None, None).unwrap();
+        let ramp = CapacityRamp {
+            from: 100,
+            to: 200,
+            minutes: 10,
+        };
+        let ramp = Schedule::new("ramp", start, None, None, Some(ramp)).unwrap();
+        for schedule in [&ramp, &opening] {
+            queue
+                .set_schedule(prefix, schedule)
+                .await
+                .expect("Failed to set schedule");
+        }
+
+        let schedules = queue
+            .schedules(prefix)
+            .await
+            .expect("Failed to read schedules");
+        assert_eq!(schedules, vec![opening.clone(), ramp.clone()]);
+
+        // Nothing applies before the start
+        let applied = queue
+            .apply_schedules(prefix, Some(start - TimeDelta::seconds(1)))
+            .await
+            .expect("Failed to apply schedules");
+        assert_eq!(applied, 0);
+
+        let applied = queue
+            .apply_schedules(prefix, Some(start + TimeDelta::minutes(5)))
+            .await
+            .expect("Failed to apply schedules");
+        assert_eq!(applied, 2);
+        let settings = queue.queue_settings(prefix).await.unwrap();
+        assert!(settings.enabled);
+        assert_eq!(settings.capacity, StoreCapacity::Sized(150));
+
+        // Steps already applied are left alone, so manual changes stick until the ramp moves on
+        queue.set_queue_enabled(prefix, false).await.unwrap();
+        let applied = queue
+            .apply_schedules(prefix, Some(start + TimeDelta::minutes(5)))
+            .await
+            .expect("Failed to apply schedules");
+        assert_eq!(applied, 0);
+        assert!(!queue.queue_settings(prefix).await.unwrap().enabled);
+
+        let applied = queue
+            .apply_schedules(prefix, Some(start + TimeDelta::minutes(20)))
+            .await
+            .expect("Failed to apply schedules");
+        assert_eq!(applied, 1);
+        let settings = queue.queue_settings(prefix).await.unwrap();
+        assert!(!settings.enabled);
+        assert_eq!(settings.capacity, StoreCapacity::Sized(200));
+
+        let removed = queue
+            .remove_schedule(prefix, "ramp")
+            .await
+            .expect("Failed to remove schedule");
+        assert!(removed);
+        let schedule = queue
+            .schedule(prefix, "ramp")
+            .await
+            .expect("Failed to read schedule");
+        assert_eq!(schedule, None);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_position_after_disabled_drain() {
+        let prefix = "test_id_position_after_disabled_drain";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        let ids = queued_ids(&queue, &mut conn, prefix, 4).await;
+
+        // Drain the front of the queue while disabled, then re-enable the queue
+        queue
+            .set_queue_enabled(prefix, false)
+            .await
+            .expect("Failed to disable queue");
+        queue
+            .id_position(prefix, ids[0], None, false)
+            .await
+            .expect("Failed to drain ID");
+        queue
+            .set_queue_enabled(prefix, true)
+            .await
+            .expect("Failed to enable queue");
+
+        assert_queue_positions(&queue, prefix, &ids[1..]).await;
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_promote_add() {
+        let prefix = "test_id_promote_add";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        // Clear store and initialize store capacity to 0
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .await
+            .expect("Failed to set queue status");
+
+        let id = queue.new_id();
+
+        // Check that the first ID is in the store
+        let position = queue
+            .id_promote(prefix, id, None)
+            .await
+            .expect("Failed to add ID");
+
+        assert_eq!(position, QueuePosition::Store);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_id_remove_store() {
+        let prefix = "test_id_remove_store";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        // Clear store and initialize store capacity to 1
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(1))
+            .await
+            .expect("Failed to set queue status");
+
+        let count = 5;
+        let ids = add_many(&queue, prefix, count, None).await;
+        let store_id = &ids[0];
         let store_id_string = store_id.to_string();
 
         let exists = exists_in_store(prefix, &mut conn, store_id_string.clone()).await;
@@ -1365,30 +2314,37 @@ mod test {
         let id = &ids[1]; // Index 1 is first item in queue
         let id_string = id.to_string();
 
-        let exists: bool = conn
-            .hexists(format!("{}:queue_expiry_secs", prefix), id_string.clone())
+        let expiry: Option<f64> = conn
+            .zscore(queue_expiry_key(prefix), id_string.clone())
             .await
             .expect("Failed to fetch queue expiry value");
 
-        assert_eq!(exists, true);
-
-        let time = DateTime::from_timestamp_secs(175760525).expect("failed to create timestamp");
+        assert!(expiry.is_some());
 
-        // "Remove" ID from queue -- really just marks the ID as expired
         queue
-            .id_remove(prefix, *id, Some(time))
+            .id_remove(prefix, *id, None)
             .await
             .expect("Failed to removed queue ID");
 
-        let result: Option<String> = conn
-            .hget(format!("{}:queue_expiry_secs", prefix), id_string.clone())
+        let expiry: Option<f64> = conn
+            .zscore(queue_expiry_key(prefix), id_string.clone())
             .await
             .expect("Failed to fetch queue expiry value");
 
-        let expiry_time = result.unwrap().parse::<u64>().unwrap();
+        assert!(expiry.is_none());
 
-        // Verify that the "removal" correctly set the time back by 1 second
-        assert_eq!(expiry_time, time.timestamp() as u64 - 1);
+        // Verify that the IDs behind the removed ID moved up immediately
+        let position = queue
+            .id_position(prefix, ids[2], None, false)
+            .await
+            .expect("Failed to get position");
+
+        assert_eq!(position, QueuePosition::Queue(1));
+
+        let queue_size = queue_size(&queue, prefix)
+            .await
+            .expect("Failed to get queue size");
+        assert_eq!(queue_size, count - 2);
 
         clean_keys(prefix).await;
     }
@@ -1478,4 +2434,323 @@ mod test {
 
         clean_keys(prefix).await;
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_rotate_full_disabled_drain() {
+        let prefix = "test_rotate_full_disabled_drain";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        let count = 4;
+        let _ = queued_ids(&queue, &mut conn, prefix, count).await;
+
+        // Disable the queue, and rotation drains the whole queue into the store
+        queue
+            .set_queue_enabled(prefix, false)
+            .await
+            .expect("Failed to disable queue");
+
+        let rotation = queue
+            .rotate_full(prefix, None)
+            .await
+            .expect("Failed to rotate");
+
+        assert_eq!(rotation.promoted, count);
+
+        let queue_size = queue_size(&queue, prefix)
+            .await
+            .expect("Failed to get queue size");
+        assert_eq!(queue_size, 0);
+
+        let store_size = store_size(&queue, prefix)
+            .await
+            .expect("Failed to get store size");
+        assert_eq!(store_size, count);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_throughput_after_rotate() {
+        let prefix = "test_throughput_after_rotate";
+
+        let (queue, mut conn) = test_queue_conn().await;
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(2))
+            .await
+            .expect("Failed to set queue status");
+
+        // Fill the store, and queue up the rest so they don't expire before being promoted
+        let insert_time =
+            DateTime::from_timestamp_secs(1758040800).expect("Failed to create timestamp");
+        let first_rotate = insert_time + VALIDATED + Duration::from_secs(1);
+        let second_rotate = first_rotate + Duration::from_secs(300);
+        let _ = add_many(&queue, prefix, 2, Some(insert_time)).await;
+        let _ = add_many(&queue, prefix, 6, Some(second_rotate)).await;
+
+        // Expire the store twice, five minutes apart, promoting two IDs each time
+        let rotation = queue
+            .rotate_full(prefix, Some(first_rotate))
+            .await
+            .expect("Failed to rotate");
+        assert_eq!(rotation.promoted, 2);
+
+        let store_ids: Vec<String> = conn
+            .smembers(store_ids_key(prefix))
+            .await
+            .expect("Failed to read store")
+            .into_iter()
+            .collect();
+        for id in store_ids.iter() {
+            let _ = conn
+                .hset(store_expiry_secs_key(prefix), id, 1)
+                .await
+                .expect("Failed to expire store ID");
+        }
+
+        let rotation = queue
+            .rotate_full(prefix, Some(second_rotate))
+            .await
+            .expect("Failed to rotate");
+        assert_eq!(rotation.promoted, 2);
+
+        let throughput = queue
+            .throughput(prefix, Some(second_rotate))
+            .await
+            .expect("Failed to read throughput");
+
+        assert_eq!(throughput.promoted, 4);
+        assert_eq!(throughput.store_expired, 4);
+        assert!(throughput.observed >= Duration::from_secs(300));
+        assert!(throughput.observed < Duration::from_secs(360));
+        assert!(throughput.eta(2).is_some());
+
+        // Once the window has passed, the rotations no longer count
+        let later = second_rotate + THROUGHPUT_WINDOW + Duration::from_secs(60);
+        let throughput = queue
+            .throughput(prefix, Some(later))
+            .await
+            .expect("Failed to read throughput");
+        assert_eq!(throughput, QueueThroughput::default());
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_throughput_idle_rotation() {
+        let prefix = "test_throughput_idle_rotation";
+
+        let (queue, mut conn) = test_queue_conn().await;
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(2))
+            .await
+            .expect("Failed to set queue status");
+
+        // Rotations that move nothing still count towards the window, leaving the ETA unknown
+        let time = DateTime::from_timestamp_secs(1758040800).expect("Failed to create timestamp");
+        queue
+            .rotate_full(prefix, Some(time))
+            .await
+            .expect("Failed to rotate");
+        queue
+            .rotate_full(prefix, Some(time + Duration::from_secs(120)))
+            .await
+            .expect("Failed to rotate");
+
+        let throughput = queue
+            .throughput(prefix, Some(time + Duration::from_secs(120)))
+            .await
+            .expect("Failed to read throughput");
+
+        assert_eq!(throughput.promoted, 0);
+        assert_eq!(throughput.store_expired, 0);
+        assert_eq!(throughput.observed, Duration::from_secs(120));
+        assert_eq!(throughput.eta(1), None);
+
+        clean_keys(prefix).await;
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_queue_migrate() {
+        let prefix = "test_queue_migrate";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        // Closed store, with a queue in the original LIST layout
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .await
+            .expect("Failed to set queue status");
+
+        let expiry = 1758040541_u64;
+        let ids = generate_ids(&queue, 3);
+        for (i, id) in ids.iter().enumerate() {
+            let _: () = pipe()
+                .rpush(legacy_queue_ids_key(prefix), id)
+                .hset(legacy_queue_expiry_secs_key(prefix), id, expiry + i as u64)
+                .hset(legacy_queue_position_cache_key(prefix), id, i + 1)
+                .query_async(&mut conn)
+                .await
+                .expect("Failed to create legacy queue");
+        }
+
+        // Migrate, keeping order and expiry, and removing the original keys
+        let mut conn = queue.conn().await.expect("Redis connection failed");
+        let migrated = queue
+            .scripts
+            .queue_migrate(&mut conn, prefix)
+            .await
+            .expect("Failed to migrate queue");
+
+        assert_eq!(migrated, ids.len());
+
+        for (i, id) in ids.iter().enumerate() {
+            let position = queue
+                .id_position(prefix, Uuid::parse_str(id).unwrap(), None, false)
+                .await
+                .expect("Failed to get position");
+            assert_eq!(position, QueuePosition::Queue(i + 1));
+        }
+
+        let legacy_exists: bool = conn
+            .exists(legacy_queue_ids_key(prefix))
+            .await
+            .expect("Failed to check legacy queue");
+        assert!(!legacy_exists);
+
+        // Running the migration again is harmless
+        let migrated = queue
+            .scripts
+            .queue_migrate(&mut conn, prefix)
+            .await
+            .expect("Failed to migrate queue");
+
+        assert_eq!(migrated, 0);
+
+        clean_keys(prefix).await;
+    }
+
+    /// Add `count` IDs directly to the queue, with the first `expired` of them already expired
+    async fn seed_queue(
+        prefix: impl Into<String>,
+        queue: &QueueControl,
+        conn: &mut Connection,
+        count: usize,
+        expired: usize,
+        time: DateTime<Utc>,
+    ) -> Vec<String> {
+        let prefix = prefix.into();
+        let ids = generate_ids(queue, count);
+
+        for (chunk_index, chunk) in ids.chunks(1000).enumerate() {
+            let offset = chunk_index * 1000;
+            let order: Vec<(usize, &String)> = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (offset + i + 1, id))
+                .collect();
+            let expiry: Vec<(i64, &String)> = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, id)| match offset + i < expired {
+                    true => (time.timestamp() - 1, id),
+                    false => (time.timestamp() + VALIDATED.as_secs() as i64, id),
+                })
+                .collect();
+
+            let _: () = pipe()
+                .zadd_multiple(queue_order_key(&prefix), &order)
+                .zadd_multiple(queue_expiry_key(&prefix), &expiry)
+                .query_async(conn)
+                .await
+                .expect("Failed to seed queue");
+        }
+
+        ids
+    }
+
+    /// Benchmark: `cargo test bench_ -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_rotate_full_large_queue() {
+        let prefix = "bench_rotate_full_large_queue";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(100))
+            .await
+            .expect("Failed to set queue status");
+
+        let count = 50_000;
+        let expired = 5_000;
+        let time = DateTime::from_timestamp_secs(1758040541).expect("Failed to create timestamp");
+        let _ = seed_queue(prefix, &queue, &mut conn, count, expired, time).await;
+
+        let start = Instant::now();
+        let rotation = queue
+            .rotate_full(prefix, Some(time))
+            .await
+            .expect("Failed to rotate");
+        let elapsed = start.elapsed();
+
+        println!(
+            "rotate_full: {} queued, {} expired, {} promoted in {:?}",
+            count, rotation.queue_expired, rotation.promoted, elapsed
+        );
+
+        assert_eq!(rotation.queue_expired, expired);
+        assert_eq!(rotation.promoted, 100);
+
+        clean_keys(prefix).await;
+    }
+
+    /// Benchmark: `cargo test bench_ -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_id_position_large_queue() {
+        let prefix = "bench_id_position_large_queue";
+
+        let (queue, mut conn) = test_queue_conn().await;
+
+        clear_store(prefix, &mut conn).await;
+        queue
+            .set_queue_settings(prefix, true, StoreCapacity::Sized(0))
+            .await
+            .expect("Failed to set queue status");
+
+        let count = 50_000;
+        let time = DateTime::from_timestamp_secs(1758040541).expect("Failed to create timestamp");
+        let ids = seed_queue(prefix, &queue, &mut conn, count, 0, time).await;
+        let last_id = Uuid::parse_str(&ids[count - 1]).unwrap();
+
+        let lookups = 1_000;
+        let start = Instant::now();
+        for _ in 0..lookups {
+            let position = queue
+                .id_position(prefix, last_id, Some(time), false)
+                .await
+                .expect("Failed to get position");
+            assert_eq!(position, QueuePosition::Queue(count));
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "id_position: {} lookups at the back of a {} queue in {:?} ({:?} each)",
+            lookups,
+            count,
+            elapsed,
+            elapsed / lookups
+        );
+
+        clean_keys(prefix).await;
+    }
 }
@@ -1,5 +1,7 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 use std::default::Default;
+use std::time::Duration;
 use tracing::error;
 
 use crate::errors::{Error, Result};
@@ -27,6 +29,7 @@ pub struct QueueStatus {
     pub capacity: StoreCapacity,
     pub queue_size: usize,
     pub store_size: usize,
+    pub throughput: QueueThroughput,
     pub updated: Option<DateTime<Utc>>,
 }
 
@@ -51,6 +54,49 @@ impl QueueRotate {
     }
 }
 
+/// Number of IDs that moved through the store over a recent window of queue rotations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueThroughput {
+    pub promoted: usize,
+    pub store_expired: usize,
+    pub observed: Duration,
+}
+
+impl QueueThroughput {
+    /// Shortest period used for the rate, so a single busy rotation doesn't skew the estimate
+    const MINIMUM_OBSERVED: Duration = Duration::from_secs(60);
+
+    pub fn new(promoted: usize, store_expired: usize, observed: Duration) -> Self {
+        Self {
+            promoted,
+            store_expired,
+            observed,
+        }
+    }
+
+    /// IDs leaving the queue per minute.  Promotions are capped by the queue size, so while the
+    /// queue is short the store expiries are a better measure of the capacity being freed up
+    pub fn per_minute(&self) -> f64 {
+        if self.observed.is_zero() {
+            return 0.0;
+        }
+        let observed = self.observed.max(Self::MINIMUM_OBSERVED);
+        let exits = self.promoted.max(self.store_expired) as f64;
+        exits * 60.0 / observed.as_secs_f64()
+    }
+
+    /// Estimated wait until an ID at the given queue position is promoted into the store, or
+    /// `None` if nothing has moved through the store recently
+    pub fn eta(&self, position: usize) -> Option<Duration> {
+        let per_minute = self.per_minute();
+        if per_minute <= 0.0 {
+            return None;
+        }
+        let secs = (position as f64 * 60.0 / per_minute).ceil();
+        Some(Duration::from_secs(secs as u64))
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum QueuePosition {
     NotPresent,
@@ -161,6 +207,208 @@ impl From<StoreCapacity> for isize {
     }
 }
 
+/// Handling of IDs that are already waiting in the queue, while the queue is disabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueDisabledPolicy {
+    /// Move queued IDs into the store, regardless of the store capacity
+    #[default]
+    Drain,
+    /// Let queued IDs through to an upstream, but keep their position in the queue in case the
+    /// queue is re-enabled
+    Keep,
+}
+
+impl TryFrom<&str> for QueueDisabledPolicy {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "drain" => Ok(Self::Drain),
+            "keep" => Ok(Self::Keep),
+            _ => Err(Error::QueueDisabledPolicyInvalid(String::from(value))),
+        }
+    }
+}
+
+impl From<QueueDisabledPolicy> for String {
+    fn from(val: QueueDisabledPolicy) -> String {
+        match val {
+            QueueDisabledPolicy::Drain => String::from("drain"),
+            QueueDisabledPolicy::Keep => String::from("keep"),
+        }
+    }
+}
+
+impl From<QueueDisabledPolicy> for isize {
+    fn from(val: QueueDisabledPolicy) -> isize {
+        match val {
+            QueueDisabledPolicy::Keep => 0,
+            QueueDisabledPolicy::Drain => 1,
+        }
+    }
+}
+
+/// Named tier that moves IDs ahead of lower tiers in the queue.  IDs in a bypass tier skip the
+/// queue entirely
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriorityTier {
+    pub name: String,
+    pub priority: u16,
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+impl PriorityTier {
+    /// Highest priority supported by the queue scripts (See: id_position.lua)
+    pub const MAX_PRIORITY: u16 = 1000;
+
+    pub fn new(name: impl Into<String>, priority: u16, bypass: bool) -> Result<Self> {
+        let name = name.into();
+        let valid_name = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name || priority == 0 || priority > Self::MAX_PRIORITY {
+            return Err(Error::PriorityTierInvalid(name));
+        }
+
+        Ok(Self {
+            name,
+            priority,
+            bypass,
+        })
+    }
+}
+
+/// Linear change of the store capacity, from one size to another over a number of minutes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapacityRamp {
+    pub from: usize,
+    pub to: usize,
+    pub minutes: u32,
+}
+
+/// Queue settings applied at a set time.  The store capacity can either be set outright, or
+/// ramped from one size to another starting at that time
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub start: DateTime<Utc>,
+    #[serde(default)]
+    pub queue_enabled: Option<bool>,
+    #[serde(default)]
+    pub store_capacity: Option<isize>,
+    #[serde(default)]
+    pub ramp: Option<CapacityRamp>,
+}
+
+/// Settings a schedule applies at a point in time.  `None` leaves the current setting unchanged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScheduleStep {
+    pub queue_enabled: Option<bool>,
+    pub store_capacity: Option<StoreCapacity>,
+}
+
+impl ScheduleStep {
+    /// Identifies the settings of the step, so each step is only applied once (See:
+    /// schedule_apply.lua)
+    pub fn key(&self) -> String {
+        let queue_enabled = match self.queue_enabled {
+            Some(enabled) => isize::from(QueueEnabled(enabled)).to_string(),
+            None => String::from("-"),
+        };
+        let store_capacity = match self.store_capacity {
+            Some(capacity) => isize::from(capacity).to_string(),
+            None => String::from("-"),
+        };
+        format!("{}:{}", queue_enabled, store_capacity)
+    }
+}
+
+impl Schedule {
+    pub fn new(
+        id: impl Into<String>,
+        start: DateTime<Utc>,
+        queue_enabled: Option<bool>,
+        store_capacity: Option<isize>,
+        ramp: Option<CapacityRamp>,
+    ) -> Result<Self> {
+        if queue_enabled.is_none() && store_capacity.is_none() && ramp.is_none() {
+            return Err(Error::ScheduleInvalid(String::from(
+                "a schedule should change the queue enabled, store capacity or ramp",
+            )));
+        }
+        if store_capacity.is_some() && ramp.is_some() {
+            return Err(Error::ScheduleInvalid(String::from(
+                "a schedule can set the store capacity or ramp it, but not both",
+            )));
+        }
+        if let Some(capacity) = store_capacity {
+            StoreCapacity::try_from(capacity)?;
+        }
+        if ramp.is_some_and(|ramp| ramp.minutes == 0) {
+            return Err(Error::ScheduleInvalid(String::from(
+                "a ramp should last at least 1 minute",
+            )));
+        }
+        if ramp.is_some_and(|ramp| {
+            start
+                .checked_add_signed(TimeDelta::minutes(ramp.minutes.into()))
+                .is_none()
+        }) {
+            return Err(Error::ScheduleInvalid(String::from(
+                "a ramp should end before the latest supported date",
+            )));
+        }
+
+        Ok(Self {
+            id: id.into(),
+            start,
+            queue_enabled,
+            store_capacity,
+            ramp,
+        })
+    }
+
+    /// Time the schedule has been fully applied, after any ramp has finished
+    pub fn ends(&self) -> DateTime<Utc> {
+        match self.ramp {
+            Some(ramp) => self
+                .start
+                .checked_add_signed(TimeDelta::minutes(ramp.minutes.into()))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            None => self.start,
+        }
+    }
+
+    /// Settings the schedule calls for at the given time, or `None` if it hasn't started yet.
+    /// A ramp moves the capacity in whole steps, so the capacity only changes when the
+    /// interpolated size does
+    pub fn step_at(&self, time: DateTime<Utc>) -> Option<ScheduleStep> {
+        if time < self.start {
+            return None;
+        }
+
+        let store_capacity = match (self.store_capacity, self.ramp) {
+            (Some(capacity), _) => StoreCapacity::try_from(capacity).ok(),
+            (None, Some(ramp)) => {
+                let elapsed = (time - self.start).num_seconds() as f64;
+                let duration = f64::from(ramp.minutes) * 60.0;
+                let progress = (elapsed / duration).min(1.0);
+                let size = ramp.from as f64 + (ramp.to as f64 - ramp.from as f64) * progress;
+                Some(StoreCapacity::Sized(size.round() as usize))
+            }
+            (None, None) => None,
+        };
+
+        Some(ScheduleStep {
+            queue_enabled: self.queue_enabled,
+            store_capacity,
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct QueueEnabled(pub bool);
 
@@ -256,6 +504,29 @@ pub enum QueueEvent {
     QueueRemoved,
     StoreAdded,
     StoreExpired,
+    UpstreamCircuitOpened,
+    UpstreamCircuitClosed,
+    UpstreamsChanged,
+    UpstreamFailover,
+    UpstreamFailback,
+}
+
+impl QueueEvent {
+    /// Whether the event can be dropped if it was already published recently.  Settings,
+    /// waiting page and upstream changes are rare and need to reflect the latest change, so are
+    /// never throttled
+    pub fn is_throttled(&self) -> bool {
+        !matches!(
+            self,
+            QueueEvent::SettingsChanged
+                | QueueEvent::WaitingPageChanged
+                | QueueEvent::UpstreamCircuitOpened
+                | QueueEvent::UpstreamCircuitClosed
+                | QueueEvent::UpstreamsChanged
+                | QueueEvent::UpstreamFailover
+                | QueueEvent::UpstreamFailback
+        )
+    }
 }
 
 impl From<QueueEvent> for String {
@@ -268,6 +539,11 @@ impl From<QueueEvent> for String {
             QueueEvent::StoreAdded => String::from("store:added"),
             QueueEvent::StoreExpired => String::from("store:expired"),
             QueueEvent::QueueRemoved => String::from("queue:removed"),
+            QueueEvent::UpstreamCircuitOpened => String::from("upstream:circuit_opened"),
+            QueueEvent::UpstreamCircuitClosed => String::from("upstream:circuit_closed"),
+            QueueEvent::UpstreamsChanged => String::from("upstream:updated"),
+            QueueEvent::UpstreamFailover => String::from("upstream:failover"),
+            QueueEvent::UpstreamFailback => String::from("upstream:failback"),
         }
     }
 }
@@ -283,6 +559,11 @@ impl TryFrom<&str> for QueueEvent {
             "store:added" => Ok(QueueEvent::StoreAdded),
             "store:expired" => Ok(QueueEvent::StoreExpired),
             "queue:removed" => Ok(QueueEvent::QueueRemoved),
+            "upstream:circuit_opened" => Ok(QueueEvent::UpstreamCircuitOpened),
+            "upstream:circuit_closed" => Ok(QueueEvent::UpstreamCircuitClosed),
+            "upstream:updated" => Ok(QueueEvent::UpstreamsChanged),
+            "upstream:failover" => Ok(QueueEvent::UpstreamFailover),
+            "upstream:failback" => Ok(QueueEvent::UpstreamFailback),
             _ => Err(Error::RedisEventUnknown(String::from(value))),
         }
     }
@@ -431,4 +712,248 @@ mod test {
             assert_eq!(bool::from(QueueEnabled(false)), false);
         }
     }
+
+    mod queue_disabled_policy {
+        use super::*;
+
+        #[test]
+        fn test_queue_disabled_policy_from_string() {
+            assert_eq!(
+                QueueDisabledPolicy::try_from("drain").unwrap(),
+                QueueDisabledPolicy::Drain
+            );
+            assert_eq!(
+                QueueDisabledPolicy::try_from(" Keep ").unwrap(),
+                QueueDisabledPolicy::Keep
+            );
+        }
+
+        #[test]
+        fn test_queue_disabled_policy_from_string_error() {
+            match QueueDisabledPolicy::try_from("ignore") {
+                Err(Error::QueueDisabledPolicyInvalid(v)) => assert_eq!(v, "ignore"),
+                _ => panic!("Should have emitted disabled policy error"),
+            }
+        }
+
+        #[test]
+        fn test_isize_from_queue_disabled_policy() {
+            assert_eq!(isize::from(QueueDisabledPolicy::Keep), 0);
+            assert_eq!(isize::from(QueueDisabledPolicy::Drain), 1);
+        }
+    }
+
+    mod priority_tier {
+        use super::*;
+
+        #[test]
+        fn test_priority_tier_new() {
+            let tier = PriorityTier::new("staff_vip-1", 10, true).unwrap();
+            assert_eq!(tier.name, "staff_vip-1");
+            assert_eq!(tier.priority, 10);
+            assert!(tier.bypass);
+        }
+
+        #[test]
+        fn test_priority_tier_priority_out_of_range() {
+            for priority in [0, PriorityTier::MAX_PRIORITY + 1] {
+                match PriorityTier::new("staff", priority, false) {
+                    Err(Error::PriorityTierInvalid(name)) => assert_eq!(name, "staff"),
+                    _ => panic!("Should have rejected priority {}", priority),
+                }
+            }
+        }
+
+        #[test]
+        fn test_priority_tier_name_invalid() {
+            for name in ["", "has space", "semi;colon", &"x".repeat(65)] {
+                assert!(PriorityTier::new(name, 10, false).is_err());
+            }
+        }
+
+        #[test]
+        fn test_priority_tier_bypass_defaults_off() {
+            let tier: PriorityTier =
+                serde_json::from_str(r#"{"name": "staff", "priority": 5}"#).unwrap();
+            assert!(!tier.bypass);
+        }
+    }
+
+    mod queue_throughput {
+        use super::*;
+
+        #[test]
+        fn test_per_minute() {
+            let throughput = QueueThroughput::new(30, 10, Duration::from_secs(600));
+            assert_eq!(throughput.per_minute(), 3.0);
+        }
+
+        #[test]
+        fn test_per_minute_uses_store_expired_when_higher() {
+            let throughput = QueueThroughput::new(2, 20, Duration::from_secs(600));
+            assert_eq!(throughput.per_minute(), 2.0);
+        }
+
+        #[test]
+        fn test_per_minute_minimum_observed() {
+            let throughput = QueueThroughput::new(5, 0, Duration::from_secs(5));
+            assert_eq!(throughput.per_minute(), 5.0);
+        }
+
+        #[test]
+        fn test_eta() {
+            let throughput = QueueThroughput::new(30, 0, Duration::from_secs(600));
+            assert_eq!(throughput.eta(18), Some(Duration::from_secs(360)));
+            assert_eq!(throughput.eta(1), Some(Duration::from_secs(20)));
+        }
+
+        #[test]
+        fn test_eta_rounds_up() {
+            let throughput = QueueThroughput::new(7, 0, Duration::from_secs(60));
+            assert_eq!(throughput.eta(1), Some(Duration::from_secs(9)));
+        }
+
+        #[test]
+        fn test_eta_unknown_without_throughput() {
+            assert_eq!(QueueThroughput::default().eta(10), None);
+            let idle = QueueThroughput::new(0, 0, Duration::from_secs(600));
+            assert_eq!(idle.eta(10), None);
+        }
+    }
+
+    mod schedule {
+        use super::*;
+
+        fn start() -> DateTime<Utc> {
+            DateTime::from_timestamp_secs(1_700_000_000).unwrap()
+        }
+
+        fn ramp(from: usize, to: usize, minutes: u32) -> Schedule {
+            let ramp = CapacityRamp { from, to, minutes };
+            Schedule::new("ramp", start(), None, None, Some(ramp)).unwrap()
+        }
+
+        #[test]
+        fn test_schedule_invalid() {
+            let ramp = CapacityRamp {
+                from: 0,
+                to: 10,
+                minutes: 10,
+            };
+            let instant = CapacityRamp { minutes: 0, ..ramp };
+            for (queue_enabled, store_capacity, ramp) in [
+                (None, None, None),
+                (None, Some(10), Some(ramp)),
+                (Some(true), Some(-2), None),
+                (None, None, Some(instant)),
+            ] {
+                match Schedule::new("s", start(), queue_enabled, store_capacity, ramp) {
+                    Err(Error::ScheduleInvalid(_)) | Err(Error::StoreCapacityOutOfRange(_)) => {}
+                    _ => panic!("Should have rejected schedule"),
+                }
+            }
+        }
+
+        #[test]
+        fn test_ramp_past_latest_date() {
+            let ramp = CapacityRamp {
+                from: 0,
+                to: 10,
+                minutes: 10,
+            };
+            let latest = DateTime::<Utc>::MAX_UTC;
+            assert!(matches!(
+                Schedule::new("s", latest, None, None, Some(ramp)),
+                Err(Error::ScheduleInvalid(_))
+            ));
+
+            // Stored before the check existed
+            let schedule = Schedule {
+                start: latest,
+                ..Schedule::new("s", start(), None, None, Some(ramp)).unwrap()
+            };
+            assert_eq!(schedule.ends(), latest);
+        }
+
+        #[test]
+        fn test_step_before_start() {
+            let schedule = Schedule::new("s", start(), Some(true), Some(10), None).unwrap();
+            assert_eq!(schedule.step_at(start() - TimeDelta::seconds(1)), None);
+            assert_eq!(
+                schedule.step_at(start()),
+                Some(ScheduleStep {
+                    queue_enabled: Some(true),
+                    store_capacity: Some(StoreCapacity::Sized(10)),
+                })
+            );
+            assert_eq!(schedule.ends(), start());
+        }
+
+        #[test]
+        fn test_step_ramp() {
+            let schedule = ramp(100, 500, 10);
+            let capacity = |minutes: i64| {
+                let time = start() + TimeDelta::minutes(minutes);
+                schedule.step_at(time).unwrap().store_capacity
+            };
+            assert_eq!(capacity(0), Some(StoreCapacity::Sized(100)));
+            assert_eq!(capacity(5), Some(StoreCapacity::Sized(300)));
+            assert_eq!(capacity(10), Some(StoreCapacity::Sized(500)));
+            assert_eq!(capacity(60), Some(StoreCapacity::Sized(500)));
+            assert_eq!(schedule.ends(), start() + TimeDelta::minutes(10));
+        }
+
+        #[test]
+        fn test_step_ramp_down() {
+            let schedule = ramp(50, 10, 4);
+            let time = start() + TimeDelta::minutes(1);
+            assert_eq!(
+                schedule.step_at(time).unwrap().store_capacity,
+                Some(StoreCapacity::Sized(40))
+            );
+        }
+
+        #[test]
+        fn test_step_key() {
+            let step = ScheduleStep {
+                queue_enabled: Some(false),
+                store_capacity: Some(StoreCapacity::Unlimited),
+            };
+            assert_eq!(step.key(), "0:-1");
+            let step = ScheduleStep {
+                queue_enabled: None,
+                store_capacity: Some(StoreCapacity::Sized(30)),
+            };
+            assert_eq!(step.key(), "-:30");
+        }
+    }
+
+    mod queue_event {
+        use super::*;
+
+        #[test]
+        fn test_settings_events_are_not_throttled() {
+            assert!(!QueueEvent::SettingsChanged.is_throttled());
+            assert!(!QueueEvent::WaitingPageChanged.is_throttled());
+            assert!(!QueueEvent::UpstreamCircuitOpened.is_throttled());
+            assert!(!QueueEvent::UpstreamsChanged.is_throttled());
+            assert!(!QueueEvent::UpstreamFailover.is_throttled());
+            assert!(QueueEvent::QueueAdded.is_throttled());
+        }
+
+        #[test]
+        fn test_upstream_event_round_trip() {
+            let event = String::from(QueueEvent::UpstreamsChanged);
+            assert_eq!(event, "upstream:updated");
+            assert_eq!(
+                QueueEvent::try_from(event.as_str()).unwrap(),
+                QueueEvent::UpstreamsChanged
+            );
+
+            for event in [QueueEvent::UpstreamFailover, QueueEvent::UpstreamFailback] {
+                let name = String::from(event.clone());
+                assert_eq!(QueueEvent::try_from(name.as_str()).unwrap(), event);
+            }
+        }
+    }
 }
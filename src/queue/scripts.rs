@@ -7,7 +7,7 @@ use uuid::Uuid;
 use crate::constants::REDIS_FUNCTIONS_DIR;
 use crate::database::current_time;
 use crate::errors::{Error, Result};
-use crate::queue::models::QueueRotate;
+use crate::queue::models::{QueueDisabledPolicy, QueueRotate, QueueThroughput, ScheduleStep};
 
 #[allow(unused)]
 pub fn store_capacity_key(prefix: impl Into<String>) -> String {
@@ -25,17 +25,35 @@ pub fn queue_sync_timestamp_key(prefix: impl Into<String>) -> String {
 }
 
 #[allow(unused)]
-pub fn queue_ids_key(prefix: impl Into<String>) -> String {
+pub fn queue_order_key(prefix: impl Into<String>) -> String {
+    format!("{}:queue_order", prefix.into())
+}
+
+#[allow(unused)]
+pub fn queue_expiry_key(prefix: impl Into<String>) -> String {
+    format!("{}:queue_expiry", prefix.into())
+}
+
+#[allow(unused)]
+pub fn queue_join_counter_key(prefix: impl Into<String>) -> String {
+    format!("{}:queue_join_counter", prefix.into())
+}
+
+/// Original LIST of queue IDs, replaced by `queue_order_key` (See: queue_migrate.lua)
+#[allow(unused)]
+pub fn legacy_queue_ids_key(prefix: impl Into<String>) -> String {
     format!("{}:queue_ids", prefix.into())
 }
 
+/// Original HASH of queue expiry, replaced by `queue_expiry_key` (See: queue_migrate.lua)
 #[allow(unused)]
-pub fn queue_expiry_secs_key(prefix: impl Into<String>) -> String {
+pub fn legacy_queue_expiry_secs_key(prefix: impl Into<String>) -> String {
     format!("{}:queue_expiry_secs", prefix.into())
 }
 
+/// Original HASH of queue positions, no longer needed (See: queue_migrate.lua)
 #[allow(unused)]
-pub fn queue_position_cache_key(prefix: impl Into<String>) -> String {
+pub fn legacy_queue_position_cache_key(prefix: impl Into<String>) -> String {
     format!("{}:queue_position_cache", prefix.into())
 }
 
@@ -49,6 +67,61 @@ pub fn store_expiry_secs_key(prefix: impl Into<String>) -> String {
     format!("{}:store_expiry_secs", prefix.into())
 }
 
+#[allow(unused)]
+pub fn priority_tiers_key(prefix: impl Into<String>) -> String {
+    format!("{}:priority_tiers", prefix.into())
+}
+
+#[allow(unused)]
+pub fn invite_redemptions_key(prefix: impl Into<String>, invite: Uuid) -> String {
+    format!("{}:invite_redemptions:{}", prefix.into(), invite)
+}
+
+#[allow(unused)]
+pub fn schedules_key(prefix: impl Into<String>) -> String {
+    format!("{}:schedules", prefix.into())
+}
+
+#[allow(unused)]
+pub fn schedules_applied_key(prefix: impl Into<String>) -> String {
+    format!("{}:schedules_applied", prefix.into())
+}
+
+#[allow(unused)]
+pub fn sticky_upstreams_key(prefix: impl Into<String>) -> String {
+    format!("{}:sticky_upstreams", prefix.into())
+}
+
+#[allow(unused)]
+pub fn sticky_seen_key(prefix: impl Into<String>) -> String {
+    format!("{}:sticky_seen", prefix.into())
+}
+
+#[allow(unused)]
+pub fn sticky_counts_key(prefix: impl Into<String>) -> String {
+    format!("{}:sticky_counts", prefix.into())
+}
+
+#[allow(unused)]
+pub fn upstreams_key(prefix: impl Into<String>) -> String {
+    format!("{}:upstreams", prefix.into())
+}
+
+#[allow(unused)]
+pub fn upstreams_seeded_key(prefix: impl Into<String>) -> String {
+    format!("{}:upstreams_seeded", prefix.into())
+}
+
+#[allow(unused)]
+pub fn throughput_promoted_key(prefix: impl Into<String>) -> String {
+    format!("{}:throughput_promoted", prefix.into())
+}
+
+#[allow(unused)]
+pub fn throughput_store_expired_key(prefix: impl Into<String>) -> String {
+    format!("{}:throughput_store_expired", prefix.into())
+}
+
 #[allow(unused)]
 pub fn waiting_page_key(prefix: impl Into<String>) -> String {
     format!("{}:waiting_page", prefix.into())
@@ -59,9 +132,18 @@ pub struct Scripts {
     id_position: Script,
     id_promote: Script,
     id_remove: Script,
+    invite_redeem: Script,
+    queue_migrate: Script,
     queue_timeout: Script,
+    schedule_apply: Script,
+    sticky_claim: Script,
+    sticky_expire: Script,
+    sticky_remove: Script,
+    sticky_touch: Script,
     store_promote: Script,
     store_timeout: Script,
+    throughput: Script,
+    throughput_record: Script,
 }
 
 impl Scripts {
@@ -85,9 +167,18 @@ impl Scripts {
             id_position: Self::read("id_position")?,
             id_promote: Self::read("id_promote")?,
             id_remove: Self::read("id_remove")?,
+            invite_redeem: Self::read("invite_redeem")?,
+            queue_migrate: Self::read("queue_migrate")?,
             queue_timeout: Self::read("queue_timeout")?,
+            schedule_apply: Self::read("schedule_apply")?,
+            sticky_claim: Self::read("sticky_claim")?,
+            sticky_expire: Self::read("sticky_expire")?,
+            sticky_remove: Self::read("sticky_remove")?,
+            sticky_touch: Self::read("sticky_touch")?,
             store_promote: Self::read("store_promote")?,
             store_timeout: Self::read("store_timeout")?,
+            throughput: Self::read("throughput")?,
+            throughput_record: Self::read("throughput_record")?,
         };
 
         Ok(functions)
@@ -98,9 +189,18 @@ impl Scripts {
         self.id_position.load_async(conn).await?;
         self.id_promote.load_async(conn).await?;
         self.id_remove.load_async(conn).await?;
+        self.invite_redeem.load_async(conn).await?;
+        self.queue_migrate.load_async(conn).await?;
         self.queue_timeout.load_async(conn).await?;
+        self.schedule_apply.load_async(conn).await?;
+        self.sticky_claim.load_async(conn).await?;
+        self.sticky_expire.load_async(conn).await?;
+        self.sticky_remove.load_async(conn).await?;
+        self.sticky_touch.load_async(conn).await?;
         self.store_promote.load_async(conn).await?;
         self.store_timeout.load_async(conn).await?;
+        self.throughput.load_async(conn).await?;
+        self.throughput_record.load_async(conn).await?;
         Ok(())
     }
 
@@ -134,6 +234,8 @@ impl Scripts {
         validated_expiry: Duration,
         quarantine_expiry: Duration,
         create: bool,
+        disabled_policy: QueueDisabledPolicy,
+        priority: u16,
     ) -> Result<(usize, usize)> {
         let prefix = prefix.into();
 
@@ -153,6 +255,8 @@ impl Scripts {
                 true => 1,
                 false => 0,
             })
+            .arg(isize::from(disabled_policy))
+            .arg(priority)
             .invoke_async(conn)
             .await?;
 
@@ -199,6 +303,178 @@ impl Scripts {
         Ok(())
     }
 
+    /// Redeem an invite for a given ID, promoting it into the store.  Returns the number of IDs
+    /// that have redeemed the invite, or None if it has no redemptions left
+    #[allow(clippy::too_many_arguments)]
+    pub async fn invite_redeem(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        invite: Uuid,
+        id: Uuid,
+        uses: u32,
+        expires: DateTime<Utc>,
+        time: Option<DateTime<Utc>>,
+        validated_expiry: Duration,
+    ) -> Result<Option<usize>> {
+        let prefix = prefix.into();
+
+        let time = match time {
+            Some(t) => t,
+            None => current_time(conn).await?,
+        };
+
+        let redeemed: i64 = self
+            .invite_redeem
+            .arg(prefix)
+            .arg(String::from(invite))
+            .arg(String::from(id))
+            .arg(uses)
+            .arg(expires.timestamp())
+            .arg(time.timestamp())
+            .arg(validated_expiry.as_secs())
+            .invoke_async(conn)
+            .await?;
+
+        Ok(usize::try_from(redeemed).ok())
+    }
+
+    /// Apply a step of a schedule to the queue settings, unless the step has already been applied
+    /// (by this or another server).  Returns true if the settings were changed
+    pub async fn schedule_apply(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        schedule: &str,
+        step: &ScheduleStep,
+        time: DateTime<Utc>,
+    ) -> Result<bool> {
+        let prefix = prefix.into();
+
+        let applied: i32 = self
+            .schedule_apply
+            .arg(prefix)
+            .arg(schedule)
+            .arg(step.key())
+            .arg(match step.queue_enabled {
+                Some(true) => 1,
+                Some(false) => 0,
+                None => -1,
+            })
+            .arg(match step.store_capacity {
+                Some(capacity) => isize::from(capacity),
+                None => -2,
+            })
+            .arg(time.timestamp())
+            .invoke_async(conn)
+            .await?;
+
+        Ok(applied == 1)
+    }
+
+    /// Stick an ID to an upstream URI, unless the upstream already has the maximum number of
+    /// sticky sessions.  Returns the URI the ID is stuck to, which is a different upstream if the
+    /// ID was already stuck to one, or None if the upstream is full
+    pub async fn sticky_claim(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        id: Uuid,
+        upstream: &str,
+        max_sticky_sessions: usize,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<Option<String>> {
+        let prefix = prefix.into();
+
+        let time = match time {
+            Some(t) => t,
+            None => current_time(conn).await?,
+        };
+
+        let uri: Option<String> = self
+            .sticky_claim
+            .arg(prefix)
+            .arg(String::from(id))
+            .arg(upstream)
+            .arg(max_sticky_sessions)
+            .arg(time.timestamp())
+            .invoke_async(conn)
+            .await?;
+
+        Ok(uri)
+    }
+
+    /// Upstream URI an ID is stuck to (if any), marking the sticky session as seen
+    pub async fn sticky_touch(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        id: Uuid,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<Option<String>> {
+        let prefix = prefix.into();
+
+        let time = match time {
+            Some(t) => t,
+            None => current_time(conn).await?,
+        };
+
+        let uri: Option<String> = self
+            .sticky_touch
+            .arg(prefix)
+            .arg(String::from(id))
+            .arg(time.timestamp())
+            .invoke_async(conn)
+            .await?;
+
+        Ok(uri)
+    }
+
+    /// Remove the sticky session of an ID, returning true if it had one
+    pub async fn sticky_remove(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        id: Uuid,
+    ) -> Result<bool> {
+        let prefix = prefix.into();
+
+        let removed: i32 = self
+            .sticky_remove
+            .arg(prefix)
+            .arg(String::from(id))
+            .invoke_async(conn)
+            .await?;
+
+        Ok(removed == 1)
+    }
+
+    /// Remove all sticky sessions that have not been seen within the expiry, returning their IDs
+    pub async fn sticky_expire(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        time: Option<DateTime<Utc>>,
+        expiry: Duration,
+    ) -> Result<Vec<String>> {
+        let prefix = prefix.into();
+
+        let time = match time {
+            Some(t) => t,
+            None => current_time(conn).await?,
+        };
+
+        let expired: Vec<String> = self
+            .sticky_expire
+            .arg(prefix)
+            .arg(time.timestamp())
+            .arg(expiry.as_secs())
+            .invoke_async(conn)
+            .await?;
+
+        Ok(expired)
+    }
+
     /// Remove a given UUID from the queue/store
     pub async fn id_remove(
         &self,
@@ -225,12 +501,27 @@ impl Scripts {
         Ok(())
     }
 
-    /// Full queue/store timeout eviction with queue to store promotion
+    /// Migrate the queue from the original LIST based keys to sorted sets, returning the number of
+    /// IDs that were migrated
+    pub async fn queue_migrate(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+    ) -> Result<usize> {
+        let prefix = prefix.into();
+        let migrated: usize = self.queue_migrate.arg(&prefix).invoke_async(conn).await?;
+        Ok(migrated)
+    }
+
+    /// Full queue/store timeout eviction with queue to store promotion.  The result is recorded
+    /// towards the store throughput over the given window
     pub async fn rotate_full(
         &self,
         conn: &mut Connection,
         prefix: impl Into<String>,
         time: Option<DateTime<Utc>>,
+        disabled_policy: QueueDisabledPolicy,
+        throughput_window: Duration,
     ) -> Result<QueueRotate> {
         let prefix = prefix.into();
 
@@ -245,7 +536,11 @@ impl Scripts {
             .atomic()
             .invoke_script(self.store_timeout.arg(&prefix).arg(time.timestamp()))
             .invoke_script(self.queue_timeout.arg(&prefix).arg(time.timestamp()))
-            .invoke_script(&self.store_promote.arg(&prefix))
+            .invoke_script(
+                self.store_promote
+                    .arg(&prefix)
+                    .arg(isize::from(disabled_policy)),
+            )
             .query_async(conn)
             .await?;
 
@@ -254,8 +549,50 @@ impl Scripts {
         let queue_removed = result.1.unwrap_or(0);
         let promoted = result.2.unwrap_or(0);
 
+        let _: Option<String> = self
+            .throughput_record
+            .arg(&prefix)
+            .arg(time.timestamp())
+            .arg(promoted)
+            .arg(store_removed)
+            .arg(throughput_window.as_secs())
+            .invoke_async(conn)
+            .await?;
+
         Ok(QueueRotate::new(queue_removed, store_removed, promoted))
     }
+
+    /// Store throughput recorded by queue rotations over the given window
+    pub async fn throughput(
+        &self,
+        conn: &mut Connection,
+        prefix: impl Into<String>,
+        time: Option<DateTime<Utc>>,
+        throughput_window: Duration,
+    ) -> Result<QueueThroughput> {
+        let prefix = prefix.into();
+
+        let time = match time {
+            Some(t) => t,
+            None => current_time(conn).await?,
+        };
+
+        let result: [u64; 3] = self
+            .throughput
+            .arg(&prefix)
+            .arg(time.timestamp())
+            .arg(throughput_window.as_secs())
+            .invoke_async(conn)
+            .await?;
+
+        let [promoted, store_expired, observed] = result;
+
+        Ok(QueueThroughput::new(
+            promoted as usize,
+            store_expired as usize,
+            Duration::from_secs(observed),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -276,9 +613,18 @@ mod test {
             "check_sync_keys",
             "id_position",
             "id_remove",
+            "invite_redeem",
+            "queue_migrate",
             "queue_timeout",
+            "schedule_apply",
+            "sticky_claim",
+            "sticky_expire",
+            "sticky_remove",
+            "sticky_touch",
             "store_promote",
             "store_timeout",
+            "throughput",
+            "throughput_record",
         ];
 
         for script in scripts {
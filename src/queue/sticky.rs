@@ -0,0 +1,167 @@
+use deadpool_redis::{Connection, Pool as RedisPool};
+use redis::AsyncTypedCommands;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::database::get_connection;
+use crate::errors::Result;
+use crate::queue::scripts::{Scripts, sticky_counts_key};
+
+/// Sticky sessions kept in Redis under the queue prefix, so that every server sharing the queue
+/// sends an ID to the same upstream.  Only the ID to upstream mapping is shared, connection
+/// permits are still counted by each server
+pub struct StickySessions {
+    pool: RedisPool,
+    prefix: String,
+    scripts: Scripts,
+}
+
+impl StickySessions {
+    pub fn new(pool: RedisPool, prefix: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            pool,
+            prefix: prefix.into(),
+            scripts: Scripts::new()?,
+        })
+    }
+
+    async fn conn(&self) -> Result<Connection> {
+        get_connection(&self.pool).await
+    }
+
+    /// Upstream URI the ID is stuck to, if any.  The sticky session is marked as seen
+    pub async fn touch(&self, id: &Uuid) -> Result<Option<String>> {
+        let mut conn = self.conn().await?;
+        self.scripts
+            .sticky_touch(&mut conn, &self.prefix, *id, None)
+            .await
+    }
+
+    /// Stick the ID to an upstream URI with room for another sticky session.  Returns the URI the
+    /// ID is stuck to (which is a different upstream if another server claimed it first), or None
+    /// if the upstream is full
+    pub async fn claim(
+        &self,
+        id: &Uuid,
+        upstream: &str,
+        max_sticky_sessions: usize,
+    ) -> Result<Option<String>> {
+        let mut conn = self.conn().await?;
+        self.scripts
+            .sticky_claim(
+                &mut conn,
+                &self.prefix,
+                *id,
+                upstream,
+                max_sticky_sessions,
+                None,
+            )
+            .await
+    }
+
+    /// Remove the sticky session of an ID, returning true if it had one
+    pub async fn remove(&self, id: &Uuid) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        self.scripts
+            .sticky_remove(&mut conn, &self.prefix, *id)
+            .await
+    }
+
+    /// Remove all sticky sessions not seen within the expiry, returning their IDs
+    pub async fn expire(&self, expiry: Duration) -> Result<HashSet<Uuid>> {
+        let mut conn = self.conn().await?;
+        let expired = self
+            .scripts
+            .sticky_expire(&mut conn, &self.prefix, None, expiry)
+            .await?;
+
+        Ok(expired
+            .iter()
+            .filter_map(|id| match Uuid::parse_str(id) {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    error!("Expired sticky session has an invalid ID \"{}\": {}", id, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Number of sticky sessions stuck to each upstream URI
+    pub async fn counts(&self) -> Result<HashMap<String, usize>> {
+        let mut conn = self.conn().await?;
+        let counts = conn.hgetall(sticky_counts_key(&self.prefix)).await?;
+
+        Ok(counts
+            .into_iter()
+            .filter_map(|(uri, count)| Some((uri, count.parse().ok()?)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::database::test::create_test_pool;
+    use crate::queue::scripts::{sticky_seen_key, sticky_upstreams_key};
+
+    #[tokio::test]
+    async fn test_shared_sticky_sessions() {
+        let Some(pool) = create_test_pool() else {
+            return;
+        };
+        let prefix = "test_shared_sticky_sessions";
+        let mut conn = get_connection(&pool).await.expect("Failed to connect");
+        for key in [
+            sticky_upstreams_key(prefix),
+            sticky_seen_key(prefix),
+            sticky_counts_key(prefix),
+        ] {
+            conn.del(key)
+                .await
+                .expect("Failed to clear sticky sessions");
+        }
+
+        // Two servers sharing the same prefix
+        let first = StickySessions::new(pool.clone(), prefix).expect("Failed to create");
+        let second = StickySessions::new(pool, prefix).expect("Failed to create");
+
+        let id = Uuid::new_v4();
+        let claimed = first.claim(&id, "http://127.0.0.1:1", 1).await.unwrap();
+        assert_eq!(claimed.as_deref(), Some("http://127.0.0.1:1"));
+
+        // The other server finds the same upstream, and can't move the ID elsewhere
+        let found = second.touch(&id).await.unwrap();
+        assert_eq!(found.as_deref(), Some("http://127.0.0.1:1"));
+        let claimed = second.claim(&id, "http://127.0.0.1:2", 1).await.unwrap();
+        assert_eq!(claimed.as_deref(), Some("http://127.0.0.1:1"));
+
+        // Full upstream
+        let other = Uuid::new_v4();
+        assert_eq!(
+            second.claim(&other, "http://127.0.0.1:1", 1).await.unwrap(),
+            None
+        );
+        let counts = second.counts().await.unwrap();
+        assert_eq!(counts.get("http://127.0.0.1:1"), Some(&1));
+
+        // Expired by either server, only once
+        let expired = second.expire(Duration::from_secs(60)).await.unwrap();
+        assert_eq!(expired, HashSet::new());
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let expired = second.expire(Duration::ZERO).await.unwrap();
+        assert_eq!(expired, HashSet::from([id]));
+        assert!(first.expire(Duration::ZERO).await.unwrap().is_empty());
+        assert_eq!(first.touch(&id).await.unwrap(), None);
+        assert!(first.counts().await.unwrap().is_empty());
+
+        first.claim(&other, "http://127.0.0.1:1", 1).await.unwrap();
+        assert!(second.remove(&other).await.unwrap());
+        assert!(!first.remove(&other).await.unwrap());
+    }
+}
@@ -0,0 +1,308 @@
+use chrono::Utc;
+use http::{HeaderMap, Uri, header::HOST, uri::PathAndQuery};
+use std::{ops::Deref, sync::Arc};
+use tracing::info;
+
+use crate::config::Config;
+use crate::constants::DEFAULT_SITE_NAME;
+use crate::errors::{Error, Result};
+use crate::queue::{QueueControl, QueueEvent, QueueEvents, StoreCapacity};
+use crate::upstream::{FailoverTransition, Upstream, UpstreamPool};
+
+/// Settings for a single site.  Every site has its own queue (under its own Redis prefix), store,
+/// waiting pages, upstream pool and cookies
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteConfig {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub path_prefix: Option<String>,
+    pub queue_prefix: String,
+    pub queue_enabled: bool,
+    pub store_capacity: StoreCapacity,
+    pub initial_upstream: Vec<Upstream>,
+    pub id_cookie_name: String,
+    pub position_cookie_name: String,
+    pub queue_size_cookie_name: String,
+    pub eta_cookie_name: String,
+    pub priority_cookie_name: String,
+}
+
+impl SiteConfig {
+    /// The default site, built from the top level configuration.  It has no hosts or path
+    /// prefix, so it receives every request that no other site matches
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            name: String::from(DEFAULT_SITE_NAME),
+            hosts: Vec::new(),
+            path_prefix: None,
+            queue_prefix: config.queue_prefix.clone(),
+            queue_enabled: config.queue_enabled,
+            store_capacity: config.store_capacity,
+            initial_upstream: config.initial_upstream.clone(),
+            id_cookie_name: config.id_cookie_name.clone(),
+            position_cookie_name: config.position_cookie_name.clone(),
+            queue_size_cookie_name: config.queue_size_cookie_name.clone(),
+            eta_cookie_name: config.eta_cookie_name.clone(),
+            priority_cookie_name: config.priority_cookie_name.clone(),
+        }
+    }
+
+    /// Site names are used in URLs and signed tokens, so are kept to letters, numbers, "-" or "_"
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Check if the site serves the given host (without a port) and path
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        let host_matches =
+            self.hosts.is_empty() || host.is_some_and(|host| self.hosts.iter().any(|h| h == host));
+        let path_matches = match &self.path_prefix {
+            Some(prefix) => strip_prefix(path, prefix).is_some(),
+            None => true,
+        };
+        host_matches && path_matches
+    }
+
+    /// Sites that name their hosts are checked before sites that don't, and longer path prefixes
+    /// before shorter ones, so that the most specific site wins
+    fn specificity(&self) -> (bool, usize) {
+        (
+            !self.hosts.is_empty(),
+            self.path_prefix.as_ref().map_or(0, |prefix| prefix.len()),
+        )
+    }
+
+    /// Remove the path prefix of the site from a URI, so that the request is routed (and passed
+    /// upstream) as if the site was at the root
+    pub fn strip_path_prefix(&self, uri: &Uri) -> Uri {
+        let Some(prefix) = &self.path_prefix else {
+            return uri.clone();
+        };
+        let Some(path) = strip_prefix(uri.path(), prefix) else {
+            return uri.clone();
+        };
+
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => String::from(path),
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = match PathAndQuery::try_from(path_and_query) {
+            Ok(path_and_query) => Some(path_and_query),
+            Err(_) => return uri.clone(),
+        };
+        Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+    }
+}
+
+/// Strip a path prefix at a segment boundary, so "/shop" matches "/shop/cart" but not "/shopping"
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Host a request was sent to, lower case and without a port
+pub fn request_host(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let host = match headers.get(HOST).and_then(|host| host.to_str().ok()) {
+        Some(host) => host,
+        None => uri.host()?,
+    };
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    Some(host.to_lowercase())
+}
+
+/// A running site, with the upstream pool and queue events that belong to it
+pub struct Site {
+    pub config: SiteConfig,
+    pub upstream_pool: UpstreamPool,
+    pub events: QueueEvents,
+}
+
+impl Site {
+    pub fn new(config: SiteConfig, upstream_pool: UpstreamPool, events: QueueEvents) -> Self {
+        Self {
+            config,
+            upstream_pool,
+            events,
+        }
+    }
+
+    /// Sync the upstream pool with the upstreams stored in Redis, which may have been changed by
+    /// another server.  Drains that have passed their deadline are removed from Redis as well
+    pub async fn sync_upstreams(&self, queue: &QueueControl) -> Result<()> {
+        let now = Utc::now();
+        let mut records = queue.upstreams(&self.queue_prefix).await?;
+
+        let finished: Vec<String> = records
+            .iter()
+            .filter(|record| record.drain.is_some_and(|drain| drain.deadline <= now))
+            .map(|record| record.upstream.uri.clone())
+            .collect();
+        if !finished.is_empty() {
+            queue
+                .remove_upstreams(&self.queue_prefix, &finished)
+                .await?;
+            records.retain(|record| !finished.contains(&record.upstream.uri));
+        }
+
+        let sync = self.upstream_pool.sync(&records).await;
+        for uri in sync.added.iter() {
+            info!("Upstream {} added ({})", uri, self.name);
+        }
+        for uri in sync.removed.iter() {
+            info!("Upstream {} removed ({})", uri, self.name);
+        }
+
+        self.check_failover(queue).await
+    }
+
+    /// Publish an event if traffic has moved to a backup group of upstreams (or back again) since
+    /// the last check
+    pub async fn check_failover(&self, queue: &QueueControl) -> Result<()> {
+        let event = match self.upstream_pool.failover().await {
+            Some(FailoverTransition::Failover) => QueueEvent::UpstreamFailover,
+            Some(FailoverTransition::Failback) => QueueEvent::UpstreamFailback,
+            None => return Ok(()),
+        };
+        queue.emit_event(&self.queue_prefix, event).await
+    }
+}
+
+impl Deref for Site {
+    type Target = SiteConfig;
+
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
+
+/// All sites served by the bouncer, in the order they are matched against requests
+#[derive(Clone)]
+pub struct Sites(Arc<Vec<Arc<Site>>>);
+
+impl Sites {
+    pub fn new(sites: Vec<Site>) -> Self {
+        let mut sites: Vec<Arc<Site>> = sites.into_iter().map(Arc::new).collect();
+        sites.sort_by_key(|site| std::cmp::Reverse(site.specificity()));
+        Self(Arc::new(sites))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Site>> {
+        self.0.iter()
+    }
+
+    /// Site with the given name, if it exists
+    pub fn get(&self, name: &str) -> Option<&Arc<Site>> {
+        self.0.iter().find(|site| site.name == name)
+    }
+
+    /// The site matching the host and path of a request, falling back to the default site
+    pub fn select(&self, host: Option<&str>, path: &str) -> &Arc<Site> {
+        self.0
+            .iter()
+            .find(|site| site.matches(host, path))
+            .or_else(|| self.get(DEFAULT_SITE_NAME))
+            .expect("The default site is always configured")
+    }
+
+    /// The site named by a control API request, or the default site if none was named
+    pub fn scoped(&self, name: Option<&str>) -> Result<&Arc<Site>> {
+        let name = name.unwrap_or(DEFAULT_SITE_NAME);
+        match self.get(name) {
+            Some(site) => Ok(site),
+            None => Err(Error::SiteMissing(String::from(name))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(name: &str, hosts: &[&str], path_prefix: Option<&str>) -> SiteConfig {
+        SiteConfig {
+            name: String::from(name),
+            hosts: hosts.iter().map(|host| String::from(*host)).collect(),
+            path_prefix: path_prefix.map(String::from),
+            queue_prefix: format!("test:{}", name),
+            queue_enabled: true,
+            store_capacity: StoreCapacity::Unlimited,
+            initial_upstream: Vec::new(),
+            id_cookie_name: String::from("id"),
+            position_cookie_name: String::from("position"),
+            queue_size_cookie_name: String::from("size"),
+            eta_cookie_name: String::from("eta"),
+            priority_cookie_name: String::from("priority"),
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let shop = site("shop", &[], Some("/shop"));
+        assert!(shop.matches(None, "/shop"));
+        assert!(shop.matches(None, "/shop/jschtml/app.htm"));
+        assert!(!shop.matches(None, "/shopping"));
+        assert!(!shop.matches(None, "/"));
+
+        let tickets = site("tickets", &["tickets.example.com"], None);
+        assert!(tickets.matches(Some("tickets.example.com"), "/jsclient"));
+        assert!(!tickets.matches(Some("example.com"), "/jsclient"));
+        assert!(!tickets.matches(None, "/jsclient"));
+
+        let default = site(DEFAULT_SITE_NAME, &[], None);
+        assert!(default.matches(None, "/anything"));
+    }
+
+    #[test]
+    fn test_specificity() {
+        let mut configs = [
+            site(DEFAULT_SITE_NAME, &[], None),
+            site("shop", &[], Some("/shop")),
+            site("shop-eu", &[], Some("/shop/eu")),
+            site("tickets", &["tickets.example.com"], None),
+        ];
+        configs.sort_by_key(|site| std::cmp::Reverse(site.specificity()));
+        let names: Vec<&str> = configs.iter().map(|site| site.name.as_str()).collect();
+        assert_eq!(names, vec!["tickets", "shop-eu", "shop", DEFAULT_SITE_NAME]);
+    }
+
+    #[test]
+    fn test_strip_path_prefix() {
+        let shop = site("shop", &[], Some("/shop"));
+        let uri: Uri = "/shop/jschtml/app.htm?x=1".parse().unwrap();
+        assert_eq!(shop.strip_path_prefix(&uri), "/jschtml/app.htm?x=1");
+        let uri: Uri = "/shop".parse().unwrap();
+        assert_eq!(shop.strip_path_prefix(&uri), "/");
+
+        let default = site(DEFAULT_SITE_NAME, &[], None);
+        let uri: Uri = "/jsclient".parse().unwrap();
+        assert_eq!(default.strip_path_prefix(&uri), "/jsclient");
+    }
+
+    #[test]
+    fn test_request_host() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, "Tickets.Example.com:3001".parse().unwrap());
+        let uri: Uri = "/".parse().unwrap();
+        assert_eq!(
+            request_host(&headers, &uri),
+            Some(String::from("tickets.example.com"))
+        );
+
+        let headers = HeaderMap::new();
+        let uri: Uri = "https://[::1]/".parse().unwrap();
+        assert_eq!(request_host(&headers, &uri), Some(String::from("[::1]")));
+        let uri: Uri = "/".parse().unwrap();
+        assert_eq!(request_host(&headers, &uri), None);
+    }
+}
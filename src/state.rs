@@ -1,9 +1,11 @@
 use std::{ops::Deref, sync::Arc};
 use tokio::sync::Notify;
 
+use crate::auth::Authenticators;
+use crate::clients::HttpClients;
 use crate::config::Config;
-use crate::queue::{QueueControl, QueueEvents};
-use crate::upstream::UpstreamPool;
+use crate::queue::QueueControl;
+use crate::sites::Sites;
 
 // Our app state type
 #[derive(Clone)]
@@ -13,9 +15,9 @@ pub struct State {
     pub config: Config,
     pub shutdown_notifier: Arc<Notify>,
     pub queue: QueueControl,
-    pub queue_events: QueueEvents,
-    pub upstream_pool: UpstreamPool,
-    pub http_client: reqwest::Client,
+    pub sites: Sites,
+    pub http_clients: HttpClients,
+    pub authenticators: Authenticators,
 }
 
 impl AppState {
@@ -23,17 +25,17 @@ impl AppState {
         config: Config,
         shutdown_notifier: Arc<Notify>,
         queue: QueueControl,
-        queue_events: QueueEvents,
-        upstream_pool: UpstreamPool,
-        http_client: reqwest::Client,
+        sites: Sites,
+        http_clients: HttpClients,
+        authenticators: Authenticators,
     ) -> Self {
         Self(Arc::new(State {
             config,
             shutdown_notifier,
             queue,
-            queue_events,
-            upstream_pool,
-            http_client,
+            sites,
+            http_clients,
+            authenticators,
         }))
     }
 }
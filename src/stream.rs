@@ -6,7 +6,10 @@ use std::{
     hash::Hash,
     time::{Duration, Instant},
 };
-use tokio::{select, time::sleep_until};
+use tokio::{
+    select,
+    time::{error::Elapsed, sleep_until, timeout},
+};
 
 pub fn debounce<S>(duration: Duration, stream: S) -> impl Stream<Item = S::Item>
 where
@@ -40,3 +43,73 @@ where
         }
     }
 }
+
+/// Pass on the items of a stream, ending with an error if the next item takes longer than the
+/// duration to arrive.  None never times out
+pub fn idle_timeout<S>(
+    duration: Option<Duration>,
+    stream: S,
+) -> impl Stream<Item = Result<S::Item, Elapsed>>
+where
+    S: Stream,
+{
+    stream! {
+        pin_mut!(stream);
+        loop {
+            let item = match duration {
+                Some(duration) => match timeout(duration, stream.next()).await {
+                    Ok(item) => item,
+                    Err(elapsed) => {
+                        yield Err(elapsed);
+                        break;
+                    }
+                },
+                None => stream.next().await,
+            };
+
+            match item {
+                Some(item) => yield Ok(item),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        // Items that keep arriving are passed on
+        let items = futures_util::stream::iter([1, 2, 3]);
+        let passed: Vec<_> = idle_timeout(Some(Duration::from_millis(50)), items)
+            .collect()
+            .await;
+        assert_eq!(passed, vec![Ok(1), Ok(2), Ok(3)]);
+
+        // Quiet streams end with an error
+        let quiet = stream! {
+            yield 1;
+            sleep(Duration::from_millis(200)).await;
+            yield 2;
+        };
+        let passed: Vec<_> = idle_timeout(Some(Duration::from_millis(50)), quiet)
+            .collect()
+            .await;
+        assert_eq!(passed.len(), 2);
+        assert_eq!(passed[0], Ok(1));
+        assert!(passed[1].is_err());
+
+        // No timeout waits for every item
+        let quiet = stream! {
+            yield 1;
+            sleep(Duration::from_millis(100)).await;
+            yield 2;
+        };
+        let passed: Vec<_> = idle_timeout(None, quiet).collect().await;
+        assert_eq!(passed, vec![Ok(1), Ok(2)]);
+    }
+}
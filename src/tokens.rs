@@ -0,0 +1,220 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reasons a signed token can be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    InvalidSignature,
+    WrongPurpose,
+    Expired,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TokenError::Malformed => "malformed token",
+            TokenError::InvalidSignature => "invalid token signature",
+            TokenError::WrongPurpose => "token issued for a different purpose",
+            TokenError::Expired => "token expired",
+        })
+    }
+}
+
+/// Contents of a token, before signing.  The purpose stops a token minted for one feature being
+/// accepted by another
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    purpose: String,
+    expires: i64,
+    claims: T,
+}
+
+fn mac(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Fingerprint of a secret, so that it can be checked again later without storing the secret
+pub fn fingerprint(key: &[u8], secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(mac(key, secret).finalize().into_bytes())
+}
+
+/// Check a secret against a fingerprint, in constant time
+pub fn verify_fingerprint(key: &[u8], secret: &str, fingerprint: &str) -> bool {
+    URL_SAFE_NO_PAD
+        .decode(fingerprint)
+        .is_ok_and(|fingerprint| mac(key, secret).verify_slice(&fingerprint).is_ok())
+}
+
+/// Sign claims into a URL safe token (`payload.signature`), valid until the expiry
+pub fn sign<T: Serialize>(
+    key: &[u8],
+    purpose: &str,
+    claims: T,
+    expires: DateTime<Utc>,
+) -> serde_json::Result<String> {
+    let envelope = Envelope {
+        purpose: String::from(purpose),
+        expires: expires.timestamp(),
+        claims,
+    };
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&envelope)?);
+    let signature = URL_SAFE_NO_PAD.encode(mac(key, &payload).finalize().into_bytes());
+    Ok(format!("{}.{}", payload, signature))
+}
+
+/// Time a token lasting the given seconds from now expires, or None if that is past the latest
+/// date supported
+pub fn expiry(now: DateTime<Utc>, secs: u64) -> Option<DateTime<Utc>> {
+    let lifetime = TimeDelta::try_seconds(i64::try_from(secs).ok()?)?;
+    now.checked_add_signed(lifetime)
+}
+
+/// Verify a token signed for the given purpose, and return its claims along with its expiry
+pub fn verify<T: DeserializeOwned>(
+    key: &[u8],
+    purpose: &str,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<(T, DateTime<Utc>), TokenError> {
+    let Some((payload, signature)) = token.trim().split_once('.') else {
+        return Err(TokenError::Malformed);
+    };
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+        return Err(TokenError::Malformed);
+    };
+    if mac(key, payload).verify_slice(&signature).is_err() {
+        return Err(TokenError::InvalidSignature);
+    }
+
+    let Ok(payload) = URL_SAFE_NO_PAD.decode(payload) else {
+        return Err(TokenError::Malformed);
+    };
+    let Ok(envelope) = serde_json::from_slice::<Envelope<T>>(&payload) else {
+        return Err(TokenError::Malformed);
+    };
+    if envelope.purpose != purpose {
+        return Err(TokenError::WrongPurpose);
+    }
+    let Some(expires) = DateTime::from_timestamp_secs(envelope.expires) else {
+        return Err(TokenError::Malformed);
+    };
+    if expires <= now {
+        return Err(TokenError::Expired);
+    }
+
+    Ok((envelope.claims, expires))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeDelta;
+
+    static KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Claims {
+        name: String,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            name: String::from("staff"),
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let now = Utc::now();
+        let expires = now + TimeDelta::hours(1);
+        let token = sign(KEY, "test", claims(), expires).unwrap();
+
+        let (verified, verified_expires) = verify::<Claims>(KEY, "test", &token, now).unwrap();
+        assert_eq!(verified, claims());
+        assert_eq!(verified_expires.timestamp(), expires.timestamp());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let print = fingerprint(KEY, "hunter2");
+        assert!(!print.contains("hunter2"));
+        assert!(verify_fingerprint(KEY, "hunter2", &print));
+        assert!(!verify_fingerprint(KEY, "hunter3", &print));
+        assert!(!verify_fingerprint(b"another key", "hunter2", &print));
+        assert!(!verify_fingerprint(KEY, "hunter2", "not a fingerprint"));
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let now = Utc::now();
+        let token = sign(KEY, "test", claims(), now - TimeDelta::seconds(1)).unwrap();
+        assert_eq!(
+            verify::<Claims>(KEY, "test", &token, now).unwrap_err(),
+            TokenError::Expired
+        );
+    }
+
+    #[test]
+    fn test_verify_wrong_key() {
+        let now = Utc::now();
+        let token = sign(KEY, "test", claims(), now + TimeDelta::hours(1)).unwrap();
+        assert_eq!(
+            verify::<Claims>(b"another key", "test", &token, now).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn test_verify_wrong_purpose() {
+        let now = Utc::now();
+        let token = sign(KEY, "test", claims(), now + TimeDelta::hours(1)).unwrap();
+        assert_eq!(
+            verify::<Claims>(KEY, "other", &token, now).unwrap_err(),
+            TokenError::WrongPurpose
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let now = Utc::now();
+        let token = sign(KEY, "test", claims(), now + TimeDelta::hours(1)).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = sign(
+            KEY,
+            "test",
+            Claims {
+                name: String::from("admin"),
+            },
+            now + TimeDelta::hours(1),
+        )
+        .unwrap();
+        let (payload, _) = forged.split_once('.').unwrap();
+
+        assert_eq!(
+            verify::<Claims>(KEY, "test", &format!("{}.{}", payload, signature), now).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+        assert_eq!(
+            verify::<Claims>(KEY, "test", "not-a-token", now).unwrap_err(),
+            TokenError::Malformed
+        );
+    }
+
+    #[test]
+    fn test_expiry_out_of_range() {
+        let now = Utc::now();
+        assert_eq!(expiry(now, 60), Some(now + TimeDelta::seconds(60)));
+        assert_eq!(expiry(now, u64::MAX), None);
+        assert_eq!(expiry(now, i64::MAX as u64), None);
+        assert_eq!(expiry(DateTime::<Utc>::MAX_UTC, 1), None);
+    }
+}
@@ -1,22 +1,128 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{self, AtomicBool, AtomicUsize},
+    },
     time::{Duration, Instant},
 };
 use tokio::{
-    sync::{OwnedSemaphorePermit, RwLock, RwLockReadGuard, RwLockWriteGuard, Semaphore},
-    task::JoinSet,
-    time::sleep,
+    sync::{
+        Notify, OwnedSemaphorePermit, RwLock, RwLockReadGuard, RwLockWriteGuard, Semaphore,
+        TryAcquireError,
+    },
+    time,
 };
-use tracing::error;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::constants::{
+    LATENCY_SAMPLES, LATENCY_SMOOTHING, SHARED_STICKY_RECHECK, SLOW_START_RECHECK, SLOW_START_SHARE,
+};
+use crate::errors::Error;
+use crate::queue::StickySessions;
+
 /// Upstream specification
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Upstream {
     pub uri: String,
     pub connections: usize,
     pub sticky_sessions: usize,
+    /// Share of the traffic sent to the upstream by the weighted strategies, relative to the
+    /// weight of the other upstreams
+    #[serde(default = "default_weight")]
+    pub weight: usize,
+    /// Name of the group of upstreams the upstream belongs to, such as a primary or backup farm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Upstreams with a lower priority are preferred.  Upstreams with a higher priority (backups)
+    /// only get traffic when every upstream with a lower priority is unhealthy, removed or full
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: usize,
+    /// Label of the canary release the upstream runs, such as a new Omnis library version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<String>,
+    /// Percentage of new sticky sessions and regular requests sent to the upstreams with the same
+    /// canary label (the largest, if they differ).  Sessions already stuck to an upstream stay
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub canary_weight: usize,
+    /// Options for connecting to the upstream over HTTPS, on top of the global options
+    #[serde(default, flatten)]
+    pub tls: UpstreamTls,
+}
+
+fn default_weight() -> usize {
+    1
+}
+
+/// Options for connecting to HTTPS upstream servers.  Each upstream can override the global
+/// options, with the client certificate and key overridden together
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UpstreamTls {
+    /// Path to a PEM bundle of CA certificates to trust, on top of the built-in roots
+    #[serde(
+        rename = "tls_ca_path",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ca_path: Option<String>,
+    /// Path to a PEM client certificate, for mutual TLS
+    #[serde(
+        rename = "tls_certificate_path",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub certificate_path: Option<String>,
+    /// Path to the PEM private key of the client certificate
+    #[serde(
+        rename = "tls_key_path",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub key_path: Option<String>,
+    /// Hostname sent in SNI and checked against the upstream's certificate, rather than the host
+    /// of the upstream URI
+    #[serde(
+        rename = "tls_server_name",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub server_name: Option<String>,
+    /// Accept any certificate from the upstream, which should only be used for testing
+    #[serde(
+        rename = "tls_insecure_skip_verify",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub insecure_skip_verify: Option<bool>,
+}
+
+impl UpstreamTls {
+    /// Options of the upstream, falling back to the global options for anything not set
+    pub fn or(&self, global: &UpstreamTls) -> UpstreamTls {
+        let (certificate_path, key_path) =
+            match self.certificate_path.is_some() || self.key_path.is_some() {
+                true => (self.certificate_path.clone(), self.key_path.clone()),
+                false => (global.certificate_path.clone(), global.key_path.clone()),
+            };
+        UpstreamTls {
+            ca_path: self.ca_path.clone().or_else(|| global.ca_path.clone()),
+            certificate_path,
+            key_path,
+            server_name: self
+                .server_name
+                .clone()
+                .or_else(|| global.server_name.clone()),
+            insecure_skip_verify: self.insecure_skip_verify.or(global.insecure_skip_verify),
+        }
+    }
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
 }
 
 impl Upstream {
@@ -25,8 +131,35 @@ impl Upstream {
             uri: uri.into(),
             connections,
             sticky_sessions,
+            weight: 1,
+            group: None,
+            priority: 0,
+            canary: None,
+            canary_weight: 0,
+            tls: UpstreamTls::default(),
         }
     }
+
+    /// Set the weight of the upstream, which is at least 1
+    pub fn with_weight(mut self, weight: usize) -> Self {
+        self.weight = weight.max(1);
+        self
+    }
+
+    /// Put the upstream in a group, with the priority the group is used in
+    pub fn with_group(mut self, group: Option<String>, priority: usize) -> Self {
+        self.group = group;
+        self.priority = priority;
+        self
+    }
+
+    /// Make the upstream a canary with the given label, taking a percentage (at most 100) of new
+    /// sticky sessions and regular requests
+    pub fn with_canary(mut self, canary: Option<String>, weight: usize) -> Self {
+        self.canary = canary;
+        self.canary_weight = weight.min(100);
+        self
+    }
 }
 
 impl From<&UpstreamServer> for Upstream {
@@ -35,6 +168,12 @@ impl From<&UpstreamServer> for Upstream {
             uri: upstream_server.uri.clone(),
             connections: upstream_server.max_connections,
             sticky_sessions: upstream_server.max_sticky_sessions,
+            weight: upstream_server.weight,
+            group: upstream_server.group.clone(),
+            priority: upstream_server.priority,
+            canary: upstream_server.canary.clone(),
+            canary_weight: upstream_server.canary_weight,
+            tls: upstream_server.tls.clone(),
         }
     }
 }
@@ -45,6 +184,732 @@ impl Default for Upstream {
             uri: String::new(),
             connections: 100,
             sticky_sessions: 10,
+            weight: 1,
+            group: None,
+            priority: 0,
+            canary: None,
+            canary_weight: 0,
+            tls: UpstreamTls::default(),
+        }
+    }
+}
+
+/// Strategy for choosing which upstream server gets a request, out of those that can take it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadBalancing {
+    /// Fewest requests in progress
+    LeastConnections,
+    /// Fewest requests in progress relative to the weight of the upstream
+    WeightedLeastConnections,
+    /// Fewest sticky sessions, then fewest requests in progress
+    LeastStickySessions,
+    /// Each upstream in turn, with as many turns in a row as its weight
+    RoundRobin,
+    /// The less loaded of two upstreams picked at random, which avoids every server sending
+    /// traffic to the same "least loaded" upstream at once
+    PowerOfTwoChoices,
+    /// Lowest average response time (upstreams without a response time are tried first)
+    LeastLatency,
+}
+
+impl TryFrom<&str> for LoadBalancing {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Error> {
+        match value.trim().to_lowercase().as_str() {
+            "least_connections" => Ok(Self::LeastConnections),
+            "weighted_least_connections" => Ok(Self::WeightedLeastConnections),
+            "least_sticky_sessions" => Ok(Self::LeastStickySessions),
+            "round_robin" => Ok(Self::RoundRobin),
+            "power_of_two_choices" => Ok(Self::PowerOfTwoChoices),
+            "least_latency" => Ok(Self::LeastLatency),
+            _ => Err(Error::LoadBalancingInvalid(String::from(value))),
+        }
+    }
+}
+
+impl From<LoadBalancing> for String {
+    fn from(strategy: LoadBalancing) -> Self {
+        String::from(match strategy {
+            LoadBalancing::LeastConnections => "least_connections",
+            LoadBalancing::WeightedLeastConnections => "weighted_least_connections",
+            LoadBalancing::LeastStickySessions => "least_sticky_sessions",
+            LoadBalancing::RoundRobin => "round_robin",
+            LoadBalancing::PowerOfTwoChoices => "power_of_two_choices",
+            LoadBalancing::LeastLatency => "least_latency",
+        })
+    }
+}
+
+/// Load balancing strategy for each class of traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadBalancingSettings {
+    /// Regular requests (REST API and ultra-thin)
+    pub regular: LoadBalancing,
+    /// New Javascript Client sticky sessions
+    pub sticky: LoadBalancing,
+    /// Static assets loaded into the cache
+    pub cache_load: LoadBalancing,
+}
+
+impl Default for LoadBalancingSettings {
+    fn default() -> Self {
+        Self {
+            regular: LoadBalancing::LeastConnections,
+            sticky: LoadBalancing::LeastStickySessions,
+            cache_load: LoadBalancing::LeastConnections,
+        }
+    }
+}
+
+/// Result of the active health checks against an upstream server.  Servers start healthy, are
+/// taken out of rotation after `fall` failed checks in a row, and put back after `rise` successful
+/// checks in a row
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamHealth {
+    pub healthy: bool,
+    pub successes: usize,
+    pub failures: usize,
+    pub checked: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+impl Default for UpstreamHealth {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            successes: 0,
+            failures: 0,
+            checked: None,
+            error: None,
+        }
+    }
+}
+
+impl UpstreamHealth {
+    /// Record the result of a single health check, returning true if the server changed between
+    /// healthy and unhealthy
+    pub fn record(
+        &mut self,
+        result: Result<(), String>,
+        rise: usize,
+        fall: usize,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.checked = Some(now);
+        match result {
+            Ok(()) => {
+                self.successes += 1;
+                self.failures = 0;
+                self.error = None;
+                if !self.healthy && self.successes >= rise {
+                    self.healthy = true;
+                    return true;
+                }
+            }
+            Err(error) => {
+                self.failures += 1;
+                self.successes = 0;
+                self.error = Some(error);
+                if self.healthy && self.failures >= fall {
+                    self.healthy = false;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Settings for the circuit breaker in front of each upstream server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerSettings {
+    /// Failed requests in a row that open the circuit (0 disables the circuit breaker)
+    pub failures: usize,
+    /// Period the failures must fall within
+    pub window: Duration,
+    /// Time the circuit stays open before a trial request is let through
+    pub cooldown: Duration,
+}
+
+impl CircuitBreakerSettings {
+    fn enabled(&self) -> bool {
+        self.failures > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl From<CircuitState> for String {
+    fn from(state: CircuitState) -> Self {
+        match state {
+            CircuitState::Closed => String::from("closed"),
+            CircuitState::Open => String::from("open"),
+            CircuitState::HalfOpen => String::from("half_open"),
+        }
+    }
+}
+
+/// Change in the circuit of an upstream server, caused by the outcome of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitTransition {
+    Opened,
+    Closed,
+}
+
+/// Change in the priority of the upstreams taking traffic, after the upstreams preferred over the
+/// others went down or came back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverTransition {
+    /// Traffic moved to upstreams with a higher priority (backups)
+    Failover,
+    /// Traffic moved back to upstreams with a lower priority
+    Failback,
+}
+
+/// Passive circuit breaker, fed by the outcome of requests proxied to an upstream server.  The
+/// circuit opens after a number of failed requests in a row, and the server gets no new traffic
+/// until the cooldown has passed and a single trial request (half open) succeeds
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    settings: CircuitBreakerSettings,
+    pub state: CircuitState,
+    pub trips: usize,
+    pub opened: Option<DateTime<Utc>>,
+    failures: usize,
+    first_failure: Option<Instant>,
+    changed: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: CircuitBreakerSettings) -> Self {
+        Self {
+            settings,
+            state: CircuitState::Closed,
+            trips: 0,
+            opened: None,
+            failures: 0,
+            first_failure: None,
+            changed: Instant::now(),
+        }
+    }
+
+    /// Check if a new request could be sent to the upstream server, without claiming the half open
+    /// trial
+    pub fn admits(&self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open | CircuitState::HalfOpen => {
+                now.duration_since(self.changed) >= self.settings.cooldown
+            }
+        }
+    }
+
+    /// Check if a new request can be sent to the upstream server.  Once the cooldown has passed,
+    /// the first request checked becomes the trial.  If the trial never reports back, another is
+    /// let through after a further cooldown
+    pub fn admit(&mut self, now: Instant) -> bool {
+        if !self.admits(now) {
+            return false;
+        }
+        if self.state != CircuitState::Closed {
+            self.state = CircuitState::HalfOpen;
+            self.changed = now;
+        }
+        true
+    }
+
+    /// Record the outcome of a request, returning the transition if the circuit opened or closed
+    pub fn record(
+        &mut self,
+        success: bool,
+        now: Instant,
+        now_utc: DateTime<Utc>,
+    ) -> Option<CircuitTransition> {
+        if !self.settings.enabled() {
+            return None;
+        }
+
+        if success {
+            self.failures = 0;
+            self.first_failure = None;
+            if self.state == CircuitState::HalfOpen {
+                self.state = CircuitState::Closed;
+                self.opened = None;
+                self.changed = now;
+                return Some(CircuitTransition::Closed);
+            }
+            return None;
+        }
+
+        match self.state {
+            // Requests sent before the circuit opened are still finishing
+            CircuitState::Open => None,
+            // The trial failed, so wait out another cooldown
+            CircuitState::HalfOpen => Some(self.open(now, now_utc)),
+            CircuitState::Closed => {
+                let in_window = self
+                    .first_failure
+                    .is_some_and(|first| now.duration_since(first) <= self.settings.window);
+                if !in_window {
+                    self.first_failure = Some(now);
+                    self.failures = 0;
+                }
+                self.failures += 1;
+                match self.failures >= self.settings.failures {
+                    true => Some(self.open(now, now_utc)),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    fn open(&mut self, now: Instant, now_utc: DateTime<Utc>) -> CircuitTransition {
+        self.state = CircuitState::Open;
+        self.trips += 1;
+        self.opened = Some(now_utc);
+        self.failures = 0;
+        self.first_failure = None;
+        self.changed = now;
+        CircuitTransition::Opened
+    }
+}
+
+/// Warm-up of an upstream server that was just added to the pool or became healthy again.  Its
+/// connection and sticky session limits ramp up linearly over the window, starting from a small
+/// share of the limits, so that it isn't flooded while it is still loading
+#[derive(Debug, Clone, Copy)]
+struct SlowStart {
+    window: Duration,
+    started: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl SlowStart {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started: None,
+        }
+    }
+
+    /// Start the warm-up over, unless slow start is disabled
+    fn start(&mut self, now: Instant, now_utc: DateTime<Utc>) {
+        if !self.window.is_zero() {
+            self.started = Some((now, now_utc));
+        }
+    }
+
+    /// Share of its limits the upstream server may use, or None once it has warmed up
+    fn share(&self, now: Instant) -> Option<f64> {
+        let (started, _) = self.started?;
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed >= self.window {
+            return None;
+        }
+
+        let progress = elapsed.as_secs_f64() / self.window.as_secs_f64();
+        Some(SLOW_START_SHARE + (1.0 - SLOW_START_SHARE) * progress)
+    }
+
+    /// Limit scaled down to the share the upstream server may use, which is at least 1
+    fn limit(&self, max: usize, now: Instant) -> usize {
+        match self.share(now) {
+            Some(share) => ((max as f64 * share) as usize).clamp(max.min(1), max),
+            None => max,
+        }
+    }
+
+    /// Time the warm-up ends, if the upstream server is still warming up
+    fn ends(&self, now: Instant) -> Option<DateTime<Utc>> {
+        self.share(now)?;
+        let (_, started) = self.started?;
+        let window = TimeDelta::from_std(self.window).unwrap_or(TimeDelta::MAX);
+        Some(
+            started
+                .checked_add_signed(window)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        )
+    }
+}
+
+/// Drain of an upstream server that is being taken out of the pool.  A draining server gets no
+/// new connections or sticky sessions, and is removed once its sticky sessions have ended or the
+/// deadline has passed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Drain {
+    pub started: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+}
+
+impl Drain {
+    fn new(started: DateTime<Utc>, timeout: Duration) -> Self {
+        let timeout = TimeDelta::from_std(timeout).unwrap_or(TimeDelta::MAX);
+        Self {
+            started,
+            deadline: started
+                .checked_add_signed(timeout)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+/// Upstream as stored in Redis, which is shared by every server using the same queue.  The
+/// health, circuit and connections of the upstream are kept by each server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpstreamRecord {
+    #[serde(flatten)]
+    pub upstream: Upstream,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain: Option<Drain>,
+}
+
+impl UpstreamRecord {
+    pub fn new(upstream: Upstream) -> Self {
+        Self {
+            upstream,
+            drain: None,
+        }
+    }
+}
+
+impl From<&UpstreamServer> for UpstreamRecord {
+    fn from(upstream_server: &UpstreamServer) -> Self {
+        Self {
+            upstream: Upstream::from(upstream_server),
+            drain: upstream_server.drain,
+        }
+    }
+}
+
+/// URIs added to or removed from a pool when it was synced with the stored upstreams
+#[derive(Debug, Default, PartialEq)]
+pub struct UpstreamSync {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Progress of a draining upstream server, for reporting
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrainStatus {
+    pub drain: Drain,
+    /// Sticky sessions still pinned to the server
+    pub sticky_sessions: usize,
+    /// Requests still in progress against the server
+    pub connections: usize,
+}
+
+/// Progress of an upstream server warming up, for reporting
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowStartStatus {
+    pub ends: DateTime<Utc>,
+    /// Connection limit for now
+    pub connections: usize,
+    /// Sticky session limit for now
+    pub sticky_sessions: usize,
+}
+
+/// Kind of error a request to an upstream server ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    /// Couldn't connect to the upstream
+    Connect,
+    /// Upstream took too long to respond
+    Timeout,
+    /// Upstream responded with a 5xx status
+    ServerError,
+    Other,
+}
+
+/// Number of requests to an upstream server that ran into each kind of error
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestErrors {
+    pub connect: u64,
+    pub timeout: u64,
+    pub server_error: u64,
+    pub other: u64,
+}
+
+/// Running totals of the requests made to an upstream server
+#[derive(Debug, Default)]
+struct RequestStats {
+    /// Responses received, including those with a 5xx status
+    requests: u64,
+    errors: RequestErrors,
+    /// Bytes of response bodies received
+    bytes_received: u64,
+    last_success: Option<DateTime<Utc>>,
+    /// Most recent response times, oldest first
+    latencies: VecDeque<Duration>,
+}
+
+impl RequestStats {
+    fn record(&mut self, outcome: Result<(), RequestError>, now: DateTime<Utc>) {
+        match outcome {
+            Ok(()) => {
+                self.requests += 1;
+                self.last_success = Some(now);
+            }
+            Err(RequestError::ServerError) => {
+                self.requests += 1;
+                self.errors.server_error += 1;
+            }
+            Err(RequestError::Connect) => self.errors.connect += 1,
+            Err(RequestError::Timeout) => self.errors.timeout += 1,
+            Err(RequestError::Other) => self.errors.other += 1,
+        }
+    }
+
+    fn record_latency(&mut self, sample: Duration) {
+        if self.latencies.len() >= LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(sample);
+    }
+
+    /// Response time that the given share (0 to 1) of the recent requests were at or under
+    fn percentile(sorted: &[Duration], share: f64) -> Option<Duration> {
+        let last = sorted.len().checked_sub(1)?;
+        Some(sorted[(last as f64 * share).round() as usize])
+    }
+}
+
+/// Current load of an upstream server and the requests it has served, for reporting
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpstreamStatistics {
+    /// Requests in progress
+    pub current_connections: usize,
+    /// Sticky sessions pinned to the server
+    pub current_sticky_sessions: usize,
+    /// Responses received, including those with a 5xx status
+    pub requests: u64,
+    pub errors: RequestErrors,
+    /// Median of the recent response times
+    pub latency_p50: Option<Duration>,
+    /// 95th percentile of the recent response times
+    pub latency_p95: Option<Duration>,
+    /// Bytes of response bodies received
+    pub bytes_received: u64,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// Upstream specification along with its current health, circuit and drain, for reporting
+#[derive(Debug, Clone)]
+pub struct UpstreamStatus {
+    pub upstream: Upstream,
+    pub health: UpstreamHealth,
+    pub circuit: CircuitBreaker,
+    /// Average response time, once the upstream has served a request
+    pub latency: Option<Duration>,
+    pub slow_start: Option<SlowStartStatus>,
+    pub drain: Option<DrainStatus>,
+    pub statistics: UpstreamStatistics,
+}
+
+/// Upstreams sharing a canary label (or none, for the upstreams taking the rest of the traffic),
+/// with the sessions and connections they are serving
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanaryStatus {
+    pub label: Option<String>,
+    /// Percentage of new sticky sessions and regular requests sent to the canary
+    pub weight: usize,
+    pub upstreams: Vec<String>,
+    pub sticky_sessions: usize,
+    pub connections: usize,
+}
+
+/// Lock a mutex that only guards plain state, which is still usable if a holder panicked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Request waiting in a `WaitQueue`
+#[derive(Default)]
+struct Waiter {
+    woken: AtomicBool,
+    notify: Notify,
+}
+
+/// Requests waiting for a permit to be freed, woken one at a time in the order they started
+/// waiting.  A woken request that still can't get a permit keeps its place in the queue
+#[derive(Default)]
+struct WaitQueue {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+impl WaitQueue {
+    /// Wake the first waiter that isn't already awake
+    fn wake_one(&self) {
+        let waiters = lock(&self.waiters);
+        if let Some(waiter) = waiters
+            .iter()
+            .find(|w| !w.woken.swap(true, atomic::Ordering::AcqRel))
+        {
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Wake every waiter
+    fn wake_all(&self) {
+        for waiter in lock(&self.waiters).iter() {
+            waiter.woken.store(true, atomic::Ordering::Release);
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Make attempts until one succeeds or the timeout passes, waiting in the queue to be woken
+    /// between them.  `recheck` also wakes the waiter after an interval, for permits freed by
+    /// other servers
+    async fn wait_for<T, F>(
+        &self,
+        timeout: Duration,
+        recheck: Option<Duration>,
+        mut attempt: impl FnMut() -> F,
+    ) -> Option<T>
+    where
+        F: Future<Output = Option<T>>,
+    {
+        // EARLY EXIT: Nothing to wait for, with no other request waiting ahead of this one
+        if lock(&self.waiters).is_empty()
+            && let Some(acquired) = attempt().await
+        {
+            return Some(acquired);
+        }
+
+        let deadline = Instant::now() + timeout;
+        let place = QueuePlace::new(self);
+        // Behind other requests, a permit that is free now was freed for them, so wait to be woken
+        let mut turn = place.first();
+        loop {
+            if turn {
+                // Clear the wake before the attempt, so a permit freed during it wakes us again
+                place.waiter.woken.store(false, atomic::Ordering::Release);
+                if let Some(acquired) = attempt().await {
+                    return Some(acquired);
+                }
+            }
+            turn = true;
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            let wait = recheck.map_or(deadline - now, |r| r.min(deadline - now));
+            let _ = time::timeout(wait, place.waiter.notify.notified()).await;
+        }
+    }
+}
+
+/// Place of a request in a `WaitQueue`, which is given up when dropped (including when the
+/// request is cancelled)
+struct QueuePlace<'a> {
+    queue: &'a WaitQueue,
+    waiter: Arc<Waiter>,
+}
+
+impl<'a> QueuePlace<'a> {
+    fn new(queue: &'a WaitQueue) -> Self {
+        let waiter = Arc::new(Waiter::default());
+        lock(&queue.waiters).push_back(waiter.clone());
+        Self { queue, waiter }
+    }
+
+    /// Whether the request is at the front of the queue
+    fn first(&self) -> bool {
+        lock(&self.queue.waiters)
+            .front()
+            .is_some_and(|w| Arc::ptr_eq(w, &self.waiter))
+    }
+}
+
+impl Drop for QueuePlace<'_> {
+    fn drop(&mut self) {
+        lock(&self.queue.waiters).retain(|w| !Arc::ptr_eq(w, &self.waiter));
+        // Pass on a wake that arrived after our last attempt
+        if self.waiter.woken.load(atomic::Ordering::Acquire) {
+            self.queue.wake_one();
+        }
+    }
+}
+
+/// Requests of a pool waiting for a connection permit or a sticky session to be freed
+#[derive(Default)]
+struct Waiters {
+    connection: WaitQueue,
+    sticky: WaitQueue,
+}
+
+impl Waiters {
+    /// A connection permit was returned, which a new sticky session may also need
+    fn connection_freed(&self) {
+        self.connection.wake_one();
+        self.sticky.wake_one();
+    }
+
+    /// A sticky session ended
+    fn sticky_freed(&self) {
+        self.sticky.wake_one();
+    }
+
+    /// The pool changed (upstreams added, limits raised or back in rotation), so every waiter
+    /// should look again
+    fn wake_all(&self) {
+        self.connection.wake_all();
+        self.sticky.wake_all();
+    }
+}
+
+/// Connection permit of a single upstream server.  If the server's connection limit was lowered
+/// while the permit was held, the permit is forgotten rather than returned to the server
+struct UpstreamPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    excess: Arc<AtomicUsize>,
+    waiters: Arc<Waiters>,
+}
+
+impl UpstreamPermit {
+    fn new(permit: OwnedSemaphorePermit, excess: Arc<AtomicUsize>, waiters: Arc<Waiters>) -> Self {
+        Self {
+            permit: Some(permit),
+            excess,
+            waiters,
+        }
+    }
+
+    /// Return a permit that was only held while looking for a sticky session.  Sticky waiters
+    /// aren't woken, as they would take it just the same and return it again
+    fn release_unused(mut self) {
+        if let Some(permit) = self.permit.take() {
+            drop(permit);
+            self.waiters.connection.wake_one();
+        }
+    }
+}
+
+impl Drop for UpstreamPermit {
+    fn drop(&mut self) {
+        let forget = self
+            .excess
+            .fetch_update(atomic::Ordering::AcqRel, atomic::Ordering::Acquire, |e| {
+                e.checked_sub(1)
+            })
+            .is_ok();
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        match forget {
+            true => permit.forget(),
+            false => {
+                drop(permit);
+                self.waiters.connection_freed();
+            }
         }
     }
 }
@@ -53,11 +918,11 @@ impl Default for Upstream {
 /// when the guard is dropped, the permit for that URI is dropped along with it.
 pub struct ConnectionPermit {
     pub uri: String,
-    _permit: Option<OwnedSemaphorePermit>,
+    _permit: Option<UpstreamPermit>,
 }
 
 impl ConnectionPermit {
-    fn new(uri: impl Into<String>, permit: Option<OwnedSemaphorePermit>) -> Self {
+    fn new(uri: impl Into<String>, permit: Option<UpstreamPermit>) -> Self {
         Self {
             uri: uri.into(),
             _permit: permit,
@@ -75,10 +940,25 @@ struct UpstreamServer {
     id: usize,
     max_connections: usize,
     connection_permits: Arc<Semaphore>,
+    /// Permits to forget as they are returned, after the connection limit was lowered below the
+    /// number of connections in progress
+    excess_permits: Arc<AtomicUsize>,
     max_sticky_sessions: usize,
     sticky_sessions: Arc<RwLock<HashMap<Uuid, Instant>>>,
     uri: String,
-    removed: bool,
+    weight: usize,
+    group: Option<String>,
+    priority: usize,
+    canary: Option<String>,
+    canary_weight: usize,
+    tls: UpstreamTls,
+    drain: Option<Drain>,
+    health: Mutex<UpstreamHealth>,
+    circuit: Mutex<CircuitBreaker>,
+    latency: Mutex<Option<Duration>>,
+    stats: Mutex<RequestStats>,
+    slow_start: Mutex<SlowStart>,
+    waiters: Arc<Waiters>,
 }
 
 impl UpstreamServer {
@@ -87,22 +967,45 @@ impl UpstreamServer {
     /// # Parameters
     /// * `id` - Unique identifier for this upstream instance
     /// * `upstream` - Configuration containing connection and client limits, URI, and other upstream settings
+    /// * `circuit_breaker` - Settings for the circuit breaker in front of the upstream
+    /// * `slow_start` - Time the upstream warms up for, once started (when added to a running
+    ///   pool, or recovered from unhealthy)
+    /// * `waiters` - Requests of the pool waiting for a connection permit or sticky session
     ///
     /// # Example
     /// ```rust
-    /// UpstreamInner::new(1, Upstream::new("http://example.com", 100, 50));
+    /// UpstreamInner::new(1, Upstream::new("http://example.com", 100, 50), settings, Duration::ZERO, waiters);
     /// ```
-    fn new(id: usize, upstream: Upstream) -> Self {
+    fn new(
+        id: usize,
+        upstream: Upstream,
+        circuit_breaker: CircuitBreakerSettings,
+        slow_start: Duration,
+        waiters: Arc<Waiters>,
+    ) -> Self {
         Self {
             id,
             max_connections: upstream.connections,
             connection_permits: Arc::new(Semaphore::new(upstream.connections)),
+            excess_permits: Arc::new(AtomicUsize::new(0)),
             max_sticky_sessions: upstream.sticky_sessions,
             sticky_sessions: Arc::new(RwLock::new(HashMap::with_capacity(
                 upstream.sticky_sessions,
             ))),
             uri: upstream.uri,
-            removed: false,
+            weight: upstream.weight.max(1),
+            group: upstream.group,
+            priority: upstream.priority,
+            canary: upstream.canary,
+            canary_weight: upstream.canary_weight.min(100),
+            tls: upstream.tls,
+            drain: None,
+            health: Mutex::new(UpstreamHealth::default()),
+            circuit: Mutex::new(CircuitBreaker::new(circuit_breaker)),
+            latency: Mutex::new(None),
+            stats: Mutex::new(RequestStats::default()),
+            slow_start: Mutex::new(SlowStart::new(slow_start)),
+            waiters,
         }
     }
 
@@ -153,7 +1056,7 @@ impl UpstreamServer {
         }
 
         let mut guard = self.sticky_sessions.write().await;
-        if guard.len() >= self.max_sticky_sessions {
+        if guard.len() >= self.sticky_limit() {
             return Err(UpstreamStickyError::Full);
         }
         guard.insert(*id, Instant::now());
@@ -184,47 +1087,275 @@ impl UpstreamServer {
     /// This function acquires an internal read lock to ensure thread-safe
     /// modification of the sticky sessions.
     async fn full_sticky(&self) -> bool {
-        self.current_sticky().await >= self.max_sticky_sessions
+        self.current_sticky().await >= self.sticky_limit()
     }
 
     /// Number of current connections against the upstream server
     fn current_connections(&self) -> usize {
-        self.max_connections - self.connection_permits.available_permits()
+        let excess = self.excess_permits.load(atomic::Ordering::Acquire);
+        (self.max_connections + excess).saturating_sub(self.connection_permits.available_permits())
     }
 
-    /// Check if the upstream connection pool is currently full
-    fn full(&self) -> bool {
-        self.connection_permits.available_permits() == 0
+    /// Connection limit for now, which is lower while the upstream server is warming up
+    fn connection_limit(&self) -> usize {
+        lock(&self.slow_start).limit(self.max_connections, Instant::now())
     }
-}
 
-// Locked pool of upstream servers (controls locking for public usage)
-pub struct UpstreamPool {
-    pool: RwLock<Pool>,
-    sticky_expiry_secs: Duration,
-}
+    /// Sticky session limit for now, which is lower while the upstream server is warming up
+    fn sticky_limit(&self) -> usize {
+        lock(&self.slow_start).limit(self.max_sticky_sessions, Instant::now())
+    }
 
-impl UpstreamPool {
-    /// Create a new pool of upstream servers
-    pub fn new(sticky_expiry_secs: Duration) -> Self {
-        Self {
-            pool: RwLock::new(Pool::new()),
-            sticky_expiry_secs,
-        }
+    /// Check if the upstream server is still warming up
+    fn warming(&self) -> bool {
+        lock(&self.slow_start).share(Instant::now()).is_some()
     }
 
-    // Utility for generic read lock on the pool
-    async fn _read_lock(&self) -> RwLockReadGuard<'_, Pool> {
-        self.pool.read().await
+    /// Progress of the warm-up, if the upstream server is still warming up
+    fn slow_start_status(&self) -> Option<SlowStartStatus> {
+        let ends = lock(&self.slow_start).ends(Instant::now())?;
+        Some(SlowStartStatus {
+            ends,
+            connections: self.connection_limit(),
+            sticky_sessions: self.sticky_limit(),
+        })
+    }
+
+    /// Try to acquire a connection permit without waiting
+    fn try_acquire(&self) -> Result<UpstreamPermit, TryAcquireError> {
+        if self.current_connections() >= self.connection_limit() {
+            // EARLY EXIT: Warming up, with as many connections as it can take for now
+            return Err(TryAcquireError::NoPermits);
+        }
+        let permit = self.connection_permits.clone().try_acquire_owned()?;
+        Ok(UpstreamPermit::new(
+            permit,
+            self.excess_permits.clone(),
+            self.waiters.clone(),
+        ))
+    }
+
+    /// Change the maximum number of connections.  Lowering the limit below the connections in
+    /// progress leaves them running, and their permits are forgotten as they are returned
+    fn set_max_connections(&mut self, connections: usize) {
+        if connections < self.max_connections {
+            let shrink = self.max_connections - connections;
+            let forgotten = self.connection_permits.forget_permits(shrink);
+            self.excess_permits
+                .fetch_add(shrink - forgotten, atomic::Ordering::AcqRel);
+        } else {
+            let grow = connections - self.max_connections;
+            // Permits still waiting to be forgotten are kept instead of adding new ones
+            let excess = self
+                .excess_permits
+                .fetch_update(atomic::Ordering::AcqRel, atomic::Ordering::Acquire, |e| {
+                    Some(e.saturating_sub(grow))
+                })
+                .unwrap_or_default();
+            self.connection_permits.add_permits(grow - excess.min(grow));
+        }
+        self.max_connections = connections;
+    }
+
+    /// Check if the upstream connection pool is currently full
+    fn full(&self) -> bool {
+        self.connection_permits.available_permits() == 0
+            || self.current_connections() >= self.connection_limit()
+    }
+
+    /// Check if the upstream server is being drained out of the pool
+    fn draining(&self) -> bool {
+        self.drain.is_some()
+    }
+
+    /// Check if a draining upstream server can be removed, as its sticky sessions have ended or
+    /// the deadline has passed
+    fn drained(&self, sticky_sessions: usize, now: DateTime<Utc>) -> bool {
+        match self.drain {
+            Some(drain) => drain.deadline <= now || sticky_sessions == 0,
+            None => false,
+        }
+    }
+
+    /// Current result of the health checks against the upstream server
+    fn health(&self) -> UpstreamHealth {
+        lock(&self.health).clone()
+    }
+
+    /// Check if the upstream server passed its recent health checks
+    fn healthy(&self) -> bool {
+        self.health().healthy
+    }
+
+    /// Record the result of a health check, returning true if the health of the server changed.
+    /// A server that becomes healthy again warms up before it takes its full share of traffic
+    fn record_health(
+        &self,
+        result: Result<(), String>,
+        rise: usize,
+        fall: usize,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let mut health = lock(&self.health);
+        let changed = health.record(result, rise, fall, now);
+        if changed && health.healthy {
+            lock(&self.slow_start).start(Instant::now(), now);
+        }
+        changed
+    }
+
+    /// Current state of the circuit breaker in front of the upstream server
+    fn circuit(&self) -> CircuitBreaker {
+        lock(&self.circuit).clone()
+    }
+
+    /// Check if the upstream server is up and in rotation, however busy it is.  A recovering
+    /// server is only up once its circuit has closed again
+    fn up(&self) -> bool {
+        !self.draining() && self.healthy() && lock(&self.circuit).state == CircuitState::Closed
+    }
+
+    /// Check if the circuit would let a new request through, leaving the half open trial for the
+    /// request that actually gets a permit
+    fn circuit_admits(&self) -> bool {
+        lock(&self.circuit).admits(Instant::now())
+    }
+
+    /// Let a new request through the circuit.  This may claim the half open trial, so should only
+    /// be called once the request is otherwise sure to be sent (holding a permit for the server)
+    fn circuit_admit(&self) -> bool {
+        lock(&self.circuit).admit(Instant::now())
+    }
+
+    /// Acquire a permit for a request that the circuit lets through.  The permit is released again
+    /// if the circuit turns the request away
+    fn try_acquire_admitted(&self) -> Option<UpstreamPermit> {
+        let permit = self.try_acquire().ok()?;
+        if !self.circuit_admit() {
+            permit.release_unused();
+            return None;
+        }
+        Some(permit)
+    }
+
+    /// Record the outcome of a request against the circuit breaker
+    fn record_outcome(&self, success: bool) -> Option<CircuitTransition> {
+        lock(&self.circuit).record(success, Instant::now(), Utc::now())
+    }
+
+    /// Average (exponentially weighted) response time of the upstream server
+    fn latency(&self) -> Option<Duration> {
+        *lock(&self.latency)
+    }
+
+    /// Add the response time of a request to the average and the recent samples
+    fn record_latency(&self, sample: Duration) {
+        let mut latency = lock(&self.latency);
+        *latency = Some(match *latency {
+            Some(average) => {
+                let average = average.as_secs_f64();
+                let sample = sample.as_secs_f64();
+                Duration::from_secs_f64(average + LATENCY_SMOOTHING * (sample - average))
+            }
+            None => sample,
+        });
+        lock(&self.stats).record_latency(sample);
+    }
+
+    /// Record the outcome of a request in the statistics
+    fn record_request(&self, outcome: Result<(), RequestError>) {
+        lock(&self.stats).record(outcome, Utc::now());
+    }
+
+    /// Record the bytes of a response body received
+    fn record_bytes(&self, bytes: u64) {
+        lock(&self.stats).bytes_received += bytes;
+    }
+
+    /// Statistics of the requests served, along with the current load
+    fn statistics(&self, sticky_sessions: usize) -> UpstreamStatistics {
+        let stats = lock(&self.stats);
+        let mut sorted: Vec<Duration> = stats.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        UpstreamStatistics {
+            current_connections: self.current_connections(),
+            current_sticky_sessions: sticky_sessions,
+            requests: stats.requests,
+            errors: stats.errors.clone(),
+            latency_p50: RequestStats::percentile(&sorted, 0.5),
+            latency_p95: RequestStats::percentile(&sorted, 0.95),
+            bytes_received: stats.bytes_received,
+            last_success: stats.last_success,
+        }
+    }
+
+    /// Compare the connections in progress of two upstreams, relative to their weights
+    fn weighted_cmp(&self, other: &UpstreamServer) -> Ordering {
+        let load = (self.current_connections() + 1) * other.weight;
+        let other_load = (other.current_connections() + 1) * self.weight;
+        load.cmp(&other_load)
+    }
+}
+
+// Locked pool of upstream servers (controls locking for public usage)
+pub struct UpstreamPool {
+    pool: RwLock<Pool>,
+    sticky_expiry_secs: Duration,
+    waiters: Arc<Waiters>,
+}
+
+impl UpstreamPool {
+    /// Create a new pool of upstream servers
+    pub fn new(sticky_expiry_secs: Duration, circuit_breaker: CircuitBreakerSettings) -> Self {
+        let pool = Pool::new(circuit_breaker);
+        let waiters = pool.waiters.clone();
+        Self {
+            pool: RwLock::new(pool),
+            sticky_expiry_secs,
+            waiters,
+        }
+    }
+
+    /// Use the given load balancing strategies, rather than the defaults
+    pub fn with_load_balancing(mut self, load_balancing: LoadBalancingSettings) -> Self {
+        self.pool.get_mut().load_balancing = load_balancing;
+        self
+    }
+
+    /// Warm up upstream servers for the given time after they are added or become healthy again
+    pub fn with_slow_start(mut self, slow_start: Duration) -> Self {
+        self.pool.get_mut().slow_start = slow_start;
+        self
+    }
+
+    /// Keep sticky sessions in Redis rather than in this process, so that they are shared with
+    /// every server using the same queue
+    pub fn with_shared_sticky_sessions(mut self, shared_sticky: StickySessions) -> Self {
+        self.pool.get_mut().shared_sticky = Some(shared_sticky);
+        self
+    }
+
+    // Utility for generic read lock on the pool
+    async fn _read_lock(&self) -> RwLockReadGuard<'_, Pool> {
+        self.pool.read().await
     }
 
     /// Return the URI in the pool with the least connections for a cache load.  This circumvents
     /// most locks, since follow-up connections will be fully cached
     pub async fn acquire_cache_load_permit(&self) -> Option<ConnectionPermit> {
+        self.acquire_cache_load_permit_excluding(&[]).await
+    }
+
+    /// Return the URI in the pool with the least connections for a cache load, other than the
+    /// excluded URIs
+    pub async fn acquire_cache_load_permit_excluding(
+        &self,
+        exclude: &[String],
+    ) -> Option<ConnectionPermit> {
         // Acquire the URI, holding the read lock for as little as possible
         let result = {
             let guard = self._read_lock().await;
-            (*guard).acquire_cache_load_permit()
+            (*guard).acquire_cache_load_permit(exclude).await
         };
 
         // Transform into URIGuard for consumption, or None if no permits were available
@@ -237,13 +1368,29 @@ impl UpstreamPool {
         }
     }
 
-    /// Return the next available URI in the pool, along with the permit to use it
+    /// Return the next available URI in the pool, along with the permit to use it.  If every
+    /// upstream is full, wait (without holding the lock) for a permit to be returned
     pub async fn acquire_connection_permit(&self, timeout: Duration) -> Option<ConnectionPermit> {
-        // Acquire the URI, holding the read lock for as little as possible
-        let result = {
-            let guard = self._read_lock().await;
-            (*guard).acquire_connection_permit(timeout).await
-        };
+        self.acquire_connection_permit_excluding(timeout, &[]).await
+    }
+
+    /// Return the next available URI in the pool other than the excluded URIs, along with the
+    /// permit to use it.  If every other upstream is full, wait for a permit to be returned
+    pub async fn acquire_connection_permit_excluding(
+        &self,
+        timeout: Duration,
+        exclude: &[String],
+    ) -> Option<ConnectionPermit> {
+        let recheck = self._read_lock().await.recheck(false);
+        let result = self
+            .waiters
+            .connection
+            .wait_for(timeout, recheck, || async move {
+                // Acquire the URI, holding the read lock for as little as possible
+                let guard = self._read_lock().await;
+                (*guard).acquire_connection_permit(exclude).await
+            })
+            .await;
 
         // Transform into URIGuard for consumption, or None if no permits were available
         match result {
@@ -255,17 +1402,25 @@ impl UpstreamPool {
         }
     }
 
-    /// Return the next available sticky URI in the pool, along with the permit to use it
+    /// Return the next available sticky URI in the pool, along with the permit to use it.  If no
+    /// upstream has room, wait (without holding the lock) for a permit or sticky session to be
+    /// freed.  Shared sticky sessions are also checked again every so often, as they may be freed
+    /// by another server
     pub async fn acquire_sticky_session_permit(
         &self,
         id: &Uuid,
         timeout: Duration,
     ) -> Option<ConnectionPermit> {
-        // Acquire the URI, holding the read lock for as little as possible
-        let result = {
-            let guard = self._read_lock().await;
-            (*guard).acquire_sticky_permit(id, timeout).await
-        };
+        let recheck = self._read_lock().await.recheck(true);
+        let result = self
+            .waiters
+            .sticky
+            .wait_for(timeout, recheck, || async move {
+                // Acquire the URI, holding the read lock for as little as possible
+                let guard = self._read_lock().await;
+                (*guard).acquire_sticky_permit(id).await
+            })
+            .await;
 
         // Transform into URIGuard for consumption, or None if no permits were available
         match result {
@@ -293,15 +1448,140 @@ impl UpstreamPool {
         (*guard).upstreams()
     }
 
+    /// Stored form of the upstreams with the given URIs, for those still in the pool
+    pub async fn records(&self, uris: &[String]) -> Vec<UpstreamRecord> {
+        let guard = self._read_lock().await;
+        (*guard).records(uris)
+    }
+
+    /// Bring the pool in line with the stored upstreams.  Upstreams missing from the records are
+    /// removed, and the limits, weight and drain of the rest are updated in place.  Draining
+    /// records are not added, as they are on their way out
+    pub async fn sync(&self, records: &[UpstreamRecord]) -> UpstreamSync {
+        let mut guard = self._write_lock().await;
+        (*guard).sync(records)
+    }
+
+    /// All upstreams in the pool, along with their health and drain progress
+    pub async fn statuses(&self) -> Vec<UpstreamStatus> {
+        let guard = self._read_lock().await;
+        (*guard).statuses().await
+    }
+
+    /// Record the result of a health check against an upstream URI.  Upstreams that change
+    /// health are logged, as they are taken out of (or put back into) rotation
+    pub async fn record_health(
+        &self,
+        uri: &str,
+        result: Result<(), String>,
+        rise: usize,
+        fall: usize,
+    ) {
+        let error = result.clone().err();
+        let changed = {
+            let guard = self._read_lock().await;
+            (*guard).record_health(uri, result, rise, fall)
+        };
+
+        if changed {
+            match error {
+                Some(error) => warn!("Upstream {} is unhealthy: {}", uri, error),
+                None => {
+                    info!("Upstream {} is healthy", uri);
+                    self.waiters.wake_all();
+                }
+            }
+        }
+    }
+
+    /// Record the outcome of a request proxied to an upstream URI (connection errors, timeouts
+    /// and server errors are failures), returning the transition if its circuit opened or closed
+    pub async fn record_outcome(&self, uri: &str, success: bool) -> Option<CircuitTransition> {
+        let transition = {
+            let guard = self._read_lock().await;
+            (*guard).record_outcome(uri, success)
+        };
+
+        match transition {
+            Some(CircuitTransition::Opened) => warn!("Upstream {} circuit opened", uri),
+            Some(CircuitTransition::Closed) => info!("Upstream {} circuit closed", uri),
+            None => {}
+        }
+        transition
+    }
+
+    /// Add the response time of a request proxied to an upstream URI to its average, used by the
+    /// least latency strategy
+    pub async fn record_latency(&self, uri: &str, latency: Duration) {
+        let guard = self._read_lock().await;
+        (*guard).record_latency(uri, latency)
+    }
+
+    /// Record the outcome of a request in the statistics of the upstream with the given URI
+    pub async fn record_request(&self, uri: &str, outcome: Result<(), RequestError>) {
+        let guard = self._read_lock().await;
+        (*guard).record_request(uri, outcome)
+    }
+
+    /// Record the bytes of a response body received from the upstream with the given URI
+    pub async fn record_bytes(&self, uri: &str, bytes: u64) {
+        let guard = self._read_lock().await;
+        (*guard).record_bytes(uri, bytes)
+    }
+
+    /// Check if traffic has moved to upstreams with a different priority since the last check,
+    /// because the preferred upstreams went down or came back
+    pub async fn failover(&self) -> Option<FailoverTransition> {
+        let guard = self._read_lock().await;
+        (*guard).failover()
+    }
+
+    /// Upstreams grouped by canary label, with the sessions stuck to each label
+    pub async fn canaries(&self) -> Vec<CanaryStatus> {
+        let guard = self._read_lock().await;
+        (*guard).canaries().await
+    }
+
+    /// TLS options of the upstream with the given URI, if it is in the pool
+    pub async fn tls(&self, uri: &str) -> Option<UpstreamTls> {
+        let guard = self._read_lock().await;
+        (*guard).tls(uri)
+    }
+
     // Utility for generic write lock on the pool
     async fn _write_lock(&self) -> RwLockWriteGuard<'_, Pool> {
         self.pool.write().await
     }
 
-    /// Add a vector of upstream URIs to the pool
+    /// Add a vector of upstream URIs to the pool.  New upstreams warm up over the slow start
+    /// window, unlike those loaded from Redis (which may already be serving other servers)
     pub async fn add_upstreams(&self, uris: &[Upstream]) {
         let mut guard = self._write_lock().await;
-        (*guard).add_upstreams(uris);
+        (*guard).add_upstreams(uris, true);
+    }
+
+    /// Change the connection and sticky session limits of an upstream in place, keeping its
+    /// connections and sticky sessions.  Returns the URI if it is not in the pool
+    pub async fn update_limits(
+        &self,
+        uri: &str,
+        connections: Option<usize>,
+        sticky_sessions: Option<usize>,
+    ) -> Result<(), String> {
+        let mut guard = self._write_lock().await;
+        (*guard).update_limits(uri, connections, sticky_sessions)
+    }
+
+    /// Change the percentage of new sticky sessions and regular requests sent to a canary, which
+    /// is set on every upstream with the label.  Returns the URIs of the upstreams changed, or the
+    /// label if no upstream has it
+    pub async fn set_canary_weight(
+        &self,
+        label: &str,
+        weight: usize,
+    ) -> Result<Vec<String>, String> {
+        let mut guard = self._write_lock().await;
+        (*guard).set_canary_weight(label, weight)
     }
 
     /// Remove a vector of URIs from the pool
@@ -309,29 +1589,109 @@ impl UpstreamPool {
         let mut guard = self._write_lock().await;
         (*guard).remove_uris(uris);
     }
+
+    /// Start draining URIs out of the pool.  Existing sticky sessions are served until they end or
+    /// the timeout passes (by default, the sticky session expiry).  Nothing is drained if any URI
+    /// is not in the pool, and the first missing URI is returned
+    pub async fn drain_uris(&self, uris: &[(String, Option<Duration>)]) -> Result<(), String> {
+        let now = Utc::now();
+        let drains: Vec<(String, Drain)> = uris
+            .iter()
+            .map(|(uri, timeout)| {
+                let timeout = timeout.unwrap_or(self.sticky_expiry_secs);
+                (uri.clone(), Drain::new(now, timeout))
+            })
+            .collect();
+        let mut guard = self._write_lock().await;
+        (*guard).drain_uris(&drains)
+    }
+
+    /// Remove all draining upstreams that have finished draining, returning their URIs
+    pub async fn remove_drained(&self) -> Vec<String> {
+        let uris = {
+            let guard = self._read_lock().await;
+            (*guard).drained(Utc::now()).await
+        };
+        if uris.is_empty() {
+            return uris;
+        }
+
+        let mut guard = self._write_lock().await;
+        (*guard).remove_uris(&uris);
+        uris
+    }
 }
 
 // Internal pool structure with no locking
 struct Pool {
     pool: Vec<UpstreamServer>,
     next_id: usize,
+    circuit_breaker: CircuitBreakerSettings,
+    shared_sticky: Option<StickySessions>,
+    load_balancing: LoadBalancingSettings,
+    slow_start: Duration,
+    turn: AtomicUsize,
+    /// Lowest priority of the upstreams that were up when last checked
+    active_priority: Mutex<Option<usize>>,
+    waiters: Arc<Waiters>,
 }
 
 impl Pool {
     /// Create a new pool of upstream servers
-    fn new() -> Self {
+    fn new(circuit_breaker: CircuitBreakerSettings) -> Self {
         Self {
             pool: Vec::new(),
             next_id: 1,
+            circuit_breaker,
+            shared_sticky: None,
+            load_balancing: LoadBalancingSettings::default(),
+            slow_start: Duration::ZERO,
+            turn: AtomicUsize::new(0),
+            active_priority: Mutex::new(None),
+            waiters: Arc::new(Waiters::default()),
+        }
+    }
+
+    /// Interval to look again for a permit while waiting, for permits that come free without waking
+    /// the waiters (sticky sessions ended by other servers, or limits ramping up during slow start)
+    fn recheck(&self, sticky: bool) -> Option<Duration> {
+        let shared = (sticky && self.shared_sticky.is_some()).then_some(SHARED_STICKY_RECHECK);
+        let warming = self
+            .pool
+            .iter()
+            .any(UpstreamServer::warming)
+            .then_some(SLOW_START_RECHECK);
+        shared.into_iter().chain(warming).min()
+    }
+
+    /// Number of sticky sessions stuck to each upstream URI, across every server if the sticky
+    /// sessions are shared
+    async fn sticky_counts(&self) -> HashMap<String, usize> {
+        match &self.shared_sticky {
+            Some(shared_sticky) => match shared_sticky.counts().await {
+                Ok(counts) => counts,
+                Err(e) => {
+                    error!("Failed to read shared sticky sessions: {:?}", e);
+                    HashMap::new()
+                }
+            },
+            None => {
+                let mut counts = HashMap::with_capacity(self.pool.len());
+                for upstream in self.pool.iter() {
+                    counts.insert(upstream.uri.clone(), upstream.current_sticky().await);
+                }
+                counts
+            }
         }
     }
 
     /// Vector of UpstreamServer references sorted by least sticky sessions
     async fn least_sticky_sessions(&self) -> Vec<&UpstreamServer> {
+        let counts = self.sticky_counts().await;
         let mut upstreams: Vec<(usize, usize, &UpstreamServer)> = Vec::new();
 
         for upstream in self.pool.iter() {
-            let current_sticky = upstream.current_sticky().await;
+            let current_sticky = counts.get(&upstream.uri).copied().unwrap_or(0);
             let current_conns = upstream.current_connections();
             upstreams.push((current_sticky, current_conns, upstream))
         }
@@ -354,78 +1714,234 @@ impl Pool {
         upstreams.iter().map(|(_, u)| *u).collect()
     }
 
+    /// Vector of UpstreamServer references sorted by least connections relative to their weight
+    fn weighted_least_connections(&self) -> Vec<&UpstreamServer> {
+        let mut upstreams: Vec<&UpstreamServer> = self.pool.iter().collect();
+        upstreams.sort_by(|a, b| a.weighted_cmp(b));
+        upstreams
+    }
+
+    /// Vector of UpstreamServer references starting from the upstream whose turn it is.  Each
+    /// upstream gets as many turns in a row as its weight
+    fn round_robin(&self) -> Vec<&UpstreamServer> {
+        let total: usize = self.pool.iter().map(|u| u.weight).sum();
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let mut turn = self.turn.fetch_add(1, atomic::Ordering::Relaxed) % total;
+        let start = self
+            .pool
+            .iter()
+            .position(|u| match turn < u.weight {
+                true => true,
+                false => {
+                    turn -= u.weight;
+                    false
+                }
+            })
+            .unwrap_or(0);
+
+        let mut upstreams: Vec<&UpstreamServer> = self.pool.iter().collect();
+        upstreams.rotate_left(start);
+        upstreams
+    }
+
+    /// Vector of UpstreamServer references starting with two upstreams picked at random (the less
+    /// loaded first), followed by the rest sorted by weighted least connections
+    fn power_of_two_choices(&self) -> Vec<&UpstreamServer> {
+        let mut upstreams = self.weighted_least_connections();
+        if upstreams.len() < 2 {
+            return upstreams;
+        }
+
+        let picked = rand::seq::index::sample(&mut rand::rng(), self.pool.len(), 2);
+        let mut choices = [&self.pool[picked.index(0)], &self.pool[picked.index(1)]];
+        choices.sort_by(|a, b| a.weighted_cmp(b));
+
+        upstreams.retain(|u| choices.iter().all(|c| c.id != u.id));
+        upstreams.splice(0..0, choices);
+        upstreams
+    }
+
+    /// Vector of UpstreamServer references sorted by lowest average response time, with upstreams
+    /// that have no response time yet first
+    fn least_latency(&self) -> Vec<&UpstreamServer> {
+        let mut upstreams: Vec<&UpstreamServer> = self.pool.iter().collect();
+        upstreams.sort_by_cached_key(|u| (u.latency(), u.current_connections()));
+        upstreams
+    }
+
+    /// Vector of UpstreamServer references in the order the load balancing strategy prefers them,
+    /// with every upstream of a lower priority ahead of those with a higher priority
+    async fn ordered(&self, strategy: LoadBalancing) -> Vec<&UpstreamServer> {
+        let mut upstreams = match strategy {
+            LoadBalancing::LeastConnections => self.least_connections(),
+            LoadBalancing::WeightedLeastConnections => self.weighted_least_connections(),
+            LoadBalancing::LeastStickySessions => self.least_sticky_sessions().await,
+            LoadBalancing::RoundRobin => self.round_robin(),
+            LoadBalancing::PowerOfTwoChoices => self.power_of_two_choices(),
+            LoadBalancing::LeastLatency => self.least_latency(),
+        };
+        upstreams.sort_by_key(|u| u.priority);
+        upstreams
+    }
+
+    /// Canary label picked for a new sticky session or regular request, from a roll out of 100.
+    /// Each label takes its weight in percent of the rolls, and the rest (None) go to the
+    /// upstreams without a label
+    fn canary_pick(&self, roll: usize) -> Option<&str> {
+        let mut weights: BTreeMap<&str, usize> = BTreeMap::new();
+        for upstream in self.pool.iter() {
+            if let Some(label) = upstream.canary.as_deref() {
+                let weight = weights.entry(label).or_default();
+                *weight = (*weight).max(upstream.canary_weight);
+            }
+        }
+
+        let mut total = 0;
+        weights.into_iter().find_map(|(label, weight)| {
+            total += weight;
+            (roll < total).then_some(label)
+        })
+    }
+
+    /// Vector of UpstreamServer references in the order the load balancing strategy prefers them,
+    /// with the upstreams of the picked canary (or those without a canary label) ahead of the
+    /// rest within each priority.  The rest only get the traffic when those are down or full
+    async fn canary_ordered(&self, strategy: LoadBalancing) -> Vec<&UpstreamServer> {
+        let mut upstreams = self.ordered(strategy).await;
+        if upstreams.iter().any(|u| u.canary.is_some()) {
+            let picked = self.canary_pick(rand::random_range(0..100));
+            upstreams.sort_by_key(|u| (u.priority, u.canary.as_deref() != picked));
+        }
+        upstreams
+    }
+
+    /// Upstreams grouped by canary label, with the upstreams without a label first
+    async fn canaries(&self) -> Vec<CanaryStatus> {
+        let counts = self.sticky_counts().await;
+        let mut canaries: BTreeMap<Option<&str>, CanaryStatus> = BTreeMap::new();
+        for upstream in self.pool.iter() {
+            let label = upstream.canary.as_deref();
+            let canary = canaries.entry(label).or_insert_with(|| CanaryStatus {
+                label: label.map(String::from),
+                ..CanaryStatus::default()
+            });
+            if label.is_some() {
+                canary.weight = canary.weight.max(upstream.canary_weight);
+            }
+            canary.upstreams.push(upstream.uri.clone());
+            canary.sticky_sessions += counts.get(&upstream.uri).copied().unwrap_or(0);
+            canary.connections += upstream.current_connections();
+        }
+
+        let mut canaries: Vec<CanaryStatus> = canaries.into_values().collect();
+        let taken: usize = canaries
+            .iter()
+            .filter(|c| c.label.is_some())
+            .map(|c| c.weight)
+            .sum();
+        if let Some(rest) = canaries.iter_mut().find(|c| c.label.is_none()) {
+            rest.weight = 100usize.saturating_sub(taken);
+        }
+        canaries
+    }
+
+    /// Check if the lowest priority with an upstream up has changed since the last check.  While
+    /// no upstream is up, the last priority is kept, so traffic coming back to the same upstreams
+    /// isn't reported as a change
+    fn failover(&self) -> Option<FailoverTransition> {
+        let active = self
+            .pool
+            .iter()
+            .filter(|u| u.up())
+            .map(|u| u.priority)
+            .min()?;
+        let previous = lock(&self.active_priority).replace(active)?;
+
+        let transition = match active.cmp(&previous) {
+            Ordering::Greater => FailoverTransition::Failover,
+            Ordering::Less => FailoverTransition::Failback,
+            Ordering::Equal => return None,
+        };
+        let mut groups: Vec<&str> = self
+            .pool
+            .iter()
+            .filter(|u| u.priority == active)
+            .filter_map(|u| u.group.as_deref())
+            .collect();
+        groups.sort_unstable();
+        groups.dedup();
+        match transition {
+            FailoverTransition::Failover => warn!(
+                "Upstreams failed over from priority {} to priority {} {:?}",
+                previous, active, groups
+            ),
+            FailoverTransition::Failback => info!(
+                "Upstreams failed back from priority {} to priority {} {:?}",
+                previous, active, groups
+            ),
+        }
+        Some(transition)
+    }
+
     fn cache_load_filter(u: &&UpstreamServer) -> bool {
-        !u.removed && !u.full()
+        !u.draining() && u.healthy() && !u.full() && u.circuit_admits()
     }
 
     fn acquire_filter(u: &&UpstreamServer) -> bool {
-        !u.removed && !u.full()
+        !u.draining() && u.healthy() && !u.full() && u.circuit_admits()
     }
 
-    /// Acquire cache loading URI
-    fn acquire_cache_load_permit(&self) -> Option<String> {
+    /// Acquire cache loading URI, other than the excluded URIs
+    async fn acquire_cache_load_permit(&self, exclude: &[String]) -> Option<String> {
         let upstream = self
-            .least_connections()
+            .ordered(self.load_balancing.cache_load)
+            .await
             .into_iter()
-            .find(Self::cache_load_filter);
+            .filter(|u| !exclude.contains(&u.uri))
+            .filter(Self::cache_load_filter)
+            .find(|u| u.circuit_admit());
 
         upstream.map(|u| u.uri.clone())
     }
 
-    /// Acquire a connection URI
+    /// Acquire a connection URI, if any upstream (other than the excluded URIs) has a permit free
     async fn acquire_connection_permit(
         &self,
-        timeout: Duration,
-    ) -> Option<(OwnedSemaphorePermit, String)> {
-        let least_connections = self
-            .least_connections()
+        exclude: &[String],
+    ) -> Option<(UpstreamPermit, String)> {
+        let ordered = self
+            .canary_ordered(self.load_balancing.regular)
+            .await
             .into_iter()
+            .filter(|u| !exclude.contains(&u.uri))
             .filter(Self::acquire_filter);
 
-        for upstream in least_connections {
-            if let Ok(permit) = upstream.connection_permits.clone().try_acquire_owned() {
+        for upstream in ordered {
+            if let Some(permit) = upstream.try_acquire_admitted() {
                 return Some((permit, upstream.uri.clone()));
             }
         }
 
-        // Unable to quickly find a permit.  Create a join set and wait for the next available
-        // semaphore to complete
-        let mut set = JoinSet::new();
-
-        // Add timeout value
-        set.spawn(async move {
-            sleep(timeout).await;
-            None
-        });
-
-        // Add all upstreams
-        for upstream in self.pool.iter().filter(|u| !u.removed) {
-            let permits = upstream.connection_permits.clone();
-            let uri = upstream.uri.clone();
-            set.spawn(async move {
-                match permits.acquire_owned().await {
-                    Ok(permit) => Some((permit, uri)),
-                    Err(e) => {
-                        error!("Connection permit error: {}", e);
-                        None
-                    }
-                }
-            });
-        }
+        None
+    }
 
-        match set.join_next().await {
-            Some(Ok(permit_pair)) => permit_pair,
-            _ => None,
+    /// Acquire a sticky session URI, if the ID's upstream (or an upstream with room for a new
+    /// sticky session) has a permit free
+    async fn acquire_sticky_permit(&self, id: &Uuid) -> Option<(UpstreamPermit, String)> {
+        if let Some(shared_sticky) = &self.shared_sticky {
+            return self.acquire_shared_sticky_permit(shared_sticky, id).await;
         }
-    }
 
-    /// Acquire a sticky session URI
-    async fn acquire_sticky_permit(
-        &self,
-        id: &Uuid,
-        timeout: Duration,
-    ) -> Option<(OwnedSemaphorePermit, String)> {
         for upstream in self.pool.iter() {
+            if upstream.contains_id(id).await && !(upstream.healthy() && upstream.circuit_admits())
+            {
+                // Move the session to an available upstream, this one can't serve it
+                upstream.sticky_sessions.write().await.remove(id);
+                break;
+            }
             if upstream.contains_id(id).await {
                 // Mark sticky session with new date
                 upstream.update_id(id).await;
@@ -433,30 +1949,84 @@ impl Pool {
                 return Self::existing_sticky_uri(upstream);
             }
         }
-        self.new_sticky_uri(id, timeout).await
+        self.new_sticky_uri(id).await
     }
 
-    fn existing_sticky_uri(upstream: &UpstreamServer) -> Option<(OwnedSemaphorePermit, String)> {
-        // ID already exists in a given upstream, just return the URI if it's not full
-        match upstream.connection_permits.clone().try_acquire_owned() {
-            Ok(permit) => Some((permit, upstream.uri.clone())),
-            Err(error) => {
-                error!("Failed to acquire sticky permit: {}", error);
+    /// Acquire a sticky session URI, looking up the session in Redis
+    async fn acquire_shared_sticky_permit(
+        &self,
+        shared_sticky: &StickySessions,
+        id: &Uuid,
+    ) -> Option<(UpstreamPermit, String)> {
+        let uri = match shared_sticky.touch(id).await {
+            Ok(uri) => uri,
+            Err(e) => {
+                error!("Failed to read shared sticky session: {:?}", e);
+                return None;
+            }
+        };
+
+        if let Some(uri) = uri {
+            match self.pool.iter().find(|u| u.uri == uri) {
+                Some(upstream) if upstream.healthy() && upstream.circuit_admits() => {
+                    return Self::existing_sticky_uri(upstream);
+                }
+                // Move the session to an available upstream, this one can't serve it (or isn't in
+                // this server's pool)
+                _ => self.remove_sticky_session(id).await,
+            }
+        }
+        self.new_sticky_uri(id).await
+    }
+
+    /// Stick an ID to an upstream server.  Returns the upstream the ID is stuck to, which is a
+    /// different upstream if another server sharing the sticky sessions got there first, or None
+    /// if the upstream is full
+    async fn add_sticky<'a>(
+        &'a self,
+        upstream: &'a UpstreamServer,
+        id: &Uuid,
+    ) -> Option<&'a UpstreamServer> {
+        let Some(shared_sticky) = &self.shared_sticky else {
+            return upstream.try_add_sticky(id).await.ok().map(|()| upstream);
+        };
+
+        match shared_sticky
+            .claim(id, &upstream.uri, upstream.sticky_limit())
+            .await
+        {
+            Ok(Some(uri)) => match self.pool.iter().find(|u| u.uri == uri) {
+                Some(stuck) => Some(stuck),
+                None => {
+                    // Stuck to an upstream this server doesn't have, so free it up to try again
+                    self.remove_sticky_session(id).await;
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                error!("Failed to claim shared sticky session: {:?}", e);
                 None
             }
         }
     }
 
+    fn existing_sticky_uri(upstream: &UpstreamServer) -> Option<(UpstreamPermit, String)> {
+        // ID already exists in a given upstream, just return the URI if it's not full
+        let permit = upstream.try_acquire_admitted()?;
+        Some((permit, upstream.uri.clone()))
+    }
+
     // Acquire a connection URI
-    async fn acquire_sticky_connection_permit(&self) -> Option<(usize, OwnedSemaphorePermit)> {
-        let least_sessions = self
-            .least_sticky_sessions()
+    async fn acquire_sticky_connection_permit(&self) -> Option<(usize, UpstreamPermit)> {
+        let ordered = self
+            .canary_ordered(self.load_balancing.sticky)
             .await
             .into_iter()
             .filter(Self::acquire_filter);
 
-        for upstream in least_sessions {
-            if let Ok(permit) = upstream.connection_permits.clone().try_acquire_owned() {
+        for upstream in ordered {
+            if let Ok(permit) = upstream.try_acquire() {
                 return Some((upstream.id, permit));
             }
         }
@@ -464,36 +2034,39 @@ impl Pool {
         None
     }
 
-    /// Attempt to find a new sticky URI by looping for a specified time period until a session is
-    /// found or time runs out
-    async fn new_sticky_uri(
-        &self,
-        id: &Uuid,
-        timeout: Duration,
-    ) -> Option<(OwnedSemaphorePermit, String)> {
-        let start = Instant::now();
-        loop {
-            // Try to acquire a connection and a stick session together
-            if let Some((upstream_id, permit)) = self.acquire_sticky_connection_permit().await
-                && let Some(upstream) = self.pool.iter().find(|u| u.id == upstream_id)
-                && let Ok(()) = upstream.try_add_sticky(id).await
-            {
-                // Found both a connection and a sticky session.
-                return Some((permit, upstream.uri.clone()));
-            }
-
-            // Couldn't find any sessions.  Check if timeout expired
-            let current = Instant::now();
-            if current.duration_since(start) >= timeout {
+    /// Attempt to find a new sticky URI, with both a connection and a sticky session free
+    async fn new_sticky_uri(&self, id: &Uuid) -> Option<(UpstreamPermit, String)> {
+        // Try to acquire a connection and a stick session together
+        let (upstream_id, permit) = self.acquire_sticky_connection_permit().await?;
+        let upstream = self.pool.iter().find(|u| u.id == upstream_id)?;
+        let Some(stuck) = self.add_sticky(upstream, id).await else {
+            permit.release_unused();
+            return None;
+        };
+        if stuck.id == upstream.id {
+            // Found both a connection and a sticky session, if the circuit still lets it through
+            if !upstream.circuit_admit() {
+                permit.release_unused();
                 return None;
             }
-
-            // Not timed out, wait a second (to save CPU) and try again
-            sleep(Duration::from_secs(1)).await;
+            return Some((permit, upstream.uri.clone()));
         }
+
+        // Another server stuck the ID to a different upstream first
+        permit.release_unused();
+        Self::existing_sticky_uri(stuck)
     }
 
     async fn remove_sticky_session(&self, id: &Uuid) {
+        if let Some(shared_sticky) = &self.shared_sticky {
+            match shared_sticky.remove(id).await {
+                Ok(true) => self.waiters.sticky_freed(),
+                Ok(false) => {}
+                Err(e) => error!("Failed to remove shared sticky session: {:?}", e),
+            }
+            return;
+        }
+
         for upstream in self.pool.iter() {
             // Get read lock to see if the ID is in this pool
             let mut found = false;
@@ -507,6 +2080,7 @@ impl Pool {
             // If the session is found then get the write lock to remove it
             if found {
                 upstream.sticky_sessions.write().await.remove(id);
+                self.waiters.sticky_freed();
                 return;
             }
         }
@@ -514,38 +2088,250 @@ impl Pool {
 
     /// Remove all sticky sessions that have retired
     async fn expire_sticky(&self, expiry: Duration) -> HashSet<Uuid> {
+        if let Some(shared_sticky) = &self.shared_sticky {
+            return match shared_sticky.expire(expiry).await {
+                Ok(expired) => {
+                    self.wake_sticky(expired.len());
+                    expired
+                }
+                Err(e) => {
+                    error!("Failed to expire shared sticky sessions: {:?}", e);
+                    HashSet::new()
+                }
+            };
+        }
+
         let now = Instant::now();
         let mut removed: HashSet<Uuid> = HashSet::new();
         for upstream in self.pool.iter() {
             let upstream_removed = upstream.expire_sticky(now, expiry).await;
             removed = removed.union(&upstream_removed).copied().collect();
         }
+        self.wake_sticky(removed.len());
         removed
     }
 
-    /// vector of all current IDs and URIs in the pool
+    /// Wake a waiter for each sticky session freed
+    fn wake_sticky(&self, freed: usize) {
+        for _ in 0..freed {
+            self.waiters.sticky_freed();
+        }
+    }
+
+    /// vector of all current IDs and URIs in the pool, including those still draining
     fn upstreams(&self) -> Vec<Upstream> {
+        self.pool.iter().map(Upstream::from).collect()
+    }
+
+    fn records(&self, uris: &[String]) -> Vec<UpstreamRecord> {
+        self.pool
+            .iter()
+            .filter(|server| uris.contains(&server.uri))
+            .map(UpstreamRecord::from)
+            .collect()
+    }
+
+    /// Record a health check result against the upstream with the given URI, returning true if its
+    /// health changed.  Upstreams removed while they were being checked are ignored
+    fn record_health(
+        &self,
+        uri: &str,
+        result: Result<(), String>,
+        rise: usize,
+        fall: usize,
+    ) -> bool {
+        match self.pool.iter().find(|u| u.uri == uri) {
+            Some(upstream) => upstream.record_health(result, rise, fall, Utc::now()),
+            None => false,
+        }
+    }
+
+    /// Record the outcome of a request against the upstream with the given URI
+    fn record_outcome(&self, uri: &str, success: bool) -> Option<CircuitTransition> {
+        self.pool
+            .iter()
+            .find(|u| u.uri == uri)
+            .and_then(|upstream| upstream.record_outcome(success))
+    }
+
+    /// Add a response time to the average of the upstream with the given URI
+    fn record_latency(&self, uri: &str, latency: Duration) {
+        if let Some(upstream) = self.pool.iter().find(|u| u.uri == uri) {
+            upstream.record_latency(latency);
+        }
+    }
+
+    fn record_request(&self, uri: &str, outcome: Result<(), RequestError>) {
+        if let Some(upstream) = self.pool.iter().find(|u| u.uri == uri) {
+            upstream.record_request(outcome);
+        }
+    }
+
+    fn record_bytes(&self, uri: &str, bytes: u64) {
+        if let Some(upstream) = self.pool.iter().find(|u| u.uri == uri) {
+            upstream.record_bytes(bytes);
+        }
+    }
+
+    fn tls(&self, uri: &str) -> Option<UpstreamTls> {
+        self.pool
+            .iter()
+            .find(|u| u.uri == uri)
+            .map(|upstream| upstream.tls.clone())
+    }
+
+    /// vector of all current upstreams in the pool, along with their health and drain progress
+    async fn statuses(&self) -> Vec<UpstreamStatus> {
+        let counts = self.sticky_counts().await;
+        self.pool
+            .iter()
+            .map(|u| {
+                let sticky_sessions = counts.get(&u.uri).copied().unwrap_or(0);
+                UpstreamStatus {
+                    upstream: Upstream::from(u),
+                    health: u.health(),
+                    circuit: u.circuit(),
+                    latency: u.latency(),
+                    slow_start: u.slow_start_status(),
+                    drain: u.drain.map(|drain| DrainStatus {
+                        drain,
+                        sticky_sessions,
+                        connections: u.current_connections(),
+                    }),
+                    statistics: u.statistics(sticky_sessions),
+                }
+            })
+            .collect()
+    }
+
+    /// URIs of all draining upstreams that are ready to be removed
+    async fn drained(&self, now: DateTime<Utc>) -> Vec<String> {
+        if !self.pool.iter().any(UpstreamServer::draining) {
+            return Vec::new();
+        }
+
+        let counts = self.sticky_counts().await;
         self.pool
             .iter()
-            .filter(|u| !u.removed)
-            .map(Upstream::from)
+            .filter(|u| u.drained(counts.get(&u.uri).copied().unwrap_or(0), now))
+            .map(|u| u.uri.clone())
             .collect()
     }
 
-    /// Add 1+ URIs to the upstream pool
-    fn add_upstreams(&mut self, upstreams: &[Upstream]) {
+    /// Add 1+ URIs to the upstream pool, warming up the new upstreams if `warm_up` is set
+    fn add_upstreams(&mut self, upstreams: &[Upstream], warm_up: bool) {
         // Create unique set of URIs for comparison
         let uri_set: HashSet<String> = self.pool.iter().map(|s| s.uri.clone()).collect();
 
+        // Adding an upstream that is still draining puts it back into rotation
+        for server in self.pool.iter_mut() {
+            if upstreams.iter().any(|u| u.uri == server.uri) {
+                server.drain = None;
+            }
+        }
+
         // Limit to only URIs that don't exist in the pool already
         let new_upstreams = upstreams.iter().filter(|u| !uri_set.contains(&u.uri));
 
         // Add new upstream instances to the pool
         for upstream in new_upstreams {
-            self.pool
-                .push(UpstreamServer::new(self.next_id, upstream.clone()));
+            let server = UpstreamServer::new(
+                self.next_id,
+                upstream.clone(),
+                self.circuit_breaker,
+                self.slow_start,
+                self.waiters.clone(),
+            );
+            if warm_up {
+                lock(&server.slow_start).start(Instant::now(), Utc::now());
+            }
+            self.pool.push(server);
             self.next_id += 1;
         }
+        self.waiters.wake_all();
+    }
+
+    /// Change the limits of an upstream, failing if the URI is not in the pool.  Sticky sessions
+    /// over a lowered limit are kept until they expire
+    fn update_limits(
+        &mut self,
+        uri: &str,
+        connections: Option<usize>,
+        sticky_sessions: Option<usize>,
+    ) -> Result<(), String> {
+        let Some(server) = self.pool.iter_mut().find(|server| server.uri == uri) else {
+            return Err(String::from(uri));
+        };
+
+        if let Some(connections) = connections {
+            server.set_max_connections(connections);
+        }
+        if let Some(sticky_sessions) = sticky_sessions {
+            server.max_sticky_sessions = sticky_sessions;
+        }
+        self.waiters.wake_all();
+        Ok(())
+    }
+
+    /// Set the canary weight of every upstream with the label, failing if no upstream has it
+    fn set_canary_weight(&mut self, label: &str, weight: usize) -> Result<Vec<String>, String> {
+        let mut uris = Vec::new();
+        for server in self.pool.iter_mut() {
+            if server.canary.as_deref() == Some(label) {
+                server.canary_weight = weight.min(100);
+                uris.push(server.uri.clone());
+            }
+        }
+        if uris.is_empty() {
+            return Err(String::from(label));
+        }
+
+        self.waiters.wake_all();
+        Ok(uris)
+    }
+
+    /// Sync the pool with the stored upstreams
+    fn sync(&mut self, records: &[UpstreamRecord]) -> UpstreamSync {
+        let uris: HashSet<&str> = records.iter().map(|r| r.upstream.uri.as_str()).collect();
+        let removed: Vec<String> = self
+            .pool
+            .iter()
+            .filter(|server| !uris.contains(server.uri.as_str()))
+            .map(|server| server.uri.clone())
+            .collect();
+        self.remove_uris(&removed);
+
+        let mut added = Vec::new();
+        for record in records {
+            let upstream = &record.upstream;
+            match self
+                .pool
+                .iter_mut()
+                .find(|server| server.uri == upstream.uri)
+            {
+                Some(server) => {
+                    if server.max_connections != upstream.connections {
+                        server.set_max_connections(upstream.connections);
+                    }
+                    server.max_sticky_sessions = upstream.sticky_sessions;
+                    server.weight = upstream.weight.max(1);
+                    server.group = upstream.group.clone();
+                    server.priority = upstream.priority;
+                    server.canary = upstream.canary.clone();
+                    server.canary_weight = upstream.canary_weight.min(100);
+                    server.tls = upstream.tls.clone();
+                    server.drain = record.drain;
+                }
+                None if record.drain.is_none() => {
+                    self.add_upstreams(std::slice::from_ref(upstream), false);
+                    added.push(upstream.uri.clone());
+                }
+                None => {}
+            }
+        }
+
+        self.waiters.wake_all();
+        UpstreamSync { added, removed }
     }
 
     /// Remove 1+ of URIs from the service
@@ -556,14 +2342,1327 @@ impl Pool {
         // Strip all matching URIs from the set
         self.pool.retain(|server| !uri_set.contains(&server.uri));
     }
+
+    /// Drain 1+ URIs out of the service, failing with the first URI not in the pool.  Draining an
+    /// upstream that is already draining replaces its deadline
+    fn drain_uris(&mut self, drains: &[(String, Drain)]) -> Result<(), String> {
+        if let Some((missing, _)) = drains
+            .iter()
+            .find(|(uri, _)| !self.pool.iter().any(|server| &server.uri == uri))
+        {
+            return Err(missing.clone());
+        }
+
+        for (uri, drain) in drains {
+            if let Some(server) = self.pool.iter_mut().find(|server| &server.uri == uri) {
+                let started = server.drain.map_or(drain.started, |d| d.started);
+                server.drain = Some(Drain { started, ..*drain });
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn circuit_breaker() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            failures: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
     #[test]
     fn test_create_pool() {
-        Pool::new();
+        Pool::new(circuit_breaker());
+    }
+
+    #[test]
+    fn test_circuit_opens_after_failures() {
+        let now = Instant::now();
+        let mut circuit = CircuitBreaker::new(circuit_breaker());
+
+        assert_eq!(circuit.record(false, now, Utc::now()), None);
+        assert_eq!(circuit.record(false, now, Utc::now()), None);
+        assert_eq!(
+            circuit.record(false, now, Utc::now()),
+            Some(CircuitTransition::Opened)
+        );
+        assert_eq!(circuit.state, CircuitState::Open);
+        assert_eq!(circuit.trips, 1);
+        assert!(!circuit.admit(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_circuit_failures_outside_window() {
+        let now = Instant::now();
+        let mut circuit = CircuitBreaker::new(circuit_breaker());
+
+        circuit.record(false, now, Utc::now());
+        circuit.record(false, now, Utc::now());
+        let later = now + Duration::from_secs(61);
+        assert_eq!(circuit.record(false, later, Utc::now()), None);
+        assert_eq!(circuit.state, CircuitState::Closed);
+
+        // A success in between also starts over
+        circuit.record(true, later, Utc::now());
+        circuit.record(false, later, Utc::now());
+        circuit.record(false, later, Utc::now());
+        assert_eq!(circuit.state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_half_open_trial() {
+        let now = Instant::now();
+        let mut circuit = CircuitBreaker::new(circuit_breaker());
+        for _ in 0..3 {
+            circuit.record(false, now, Utc::now());
+        }
+
+        // A single trial after the cooldown, which fails and opens the circuit again
+        let cooled = now + Duration::from_secs(30);
+        assert!(circuit.admit(cooled));
+        assert_eq!(circuit.state, CircuitState::HalfOpen);
+        assert!(!circuit.admit(cooled));
+        assert_eq!(
+            circuit.record(false, cooled, Utc::now()),
+            Some(CircuitTransition::Opened)
+        );
+        assert_eq!(circuit.trips, 2);
+
+        // Next trial succeeds and closes it
+        let cooled = cooled + Duration::from_secs(30);
+        assert!(circuit.admit(cooled));
+        assert_eq!(
+            circuit.record(true, cooled, Utc::now()),
+            Some(CircuitTransition::Closed)
+        );
+        assert_eq!(circuit.state, CircuitState::Closed);
+        assert!(circuit.admit(cooled));
+    }
+
+    #[test]
+    fn test_circuit_disabled() {
+        let settings = CircuitBreakerSettings {
+            failures: 0,
+            ..circuit_breaker()
+        };
+        let now = Instant::now();
+        let mut circuit = CircuitBreaker::new(settings);
+        for _ in 0..10 {
+            assert_eq!(circuit.record(false, now, Utc::now()), None);
+        }
+        assert!(circuit.admit(now));
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_out_of_rotation() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        for _ in 0..2 {
+            assert_eq!(pool.record_outcome("http://127.0.0.1:1", false).await, None);
+        }
+        assert_eq!(
+            pool.record_outcome("http://127.0.0.1:1", false).await,
+            Some(CircuitTransition::Opened)
+        );
+
+        for _ in 0..5 {
+            let permit = pool
+                .acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+        }
+
+        let statuses = pool.statuses().await;
+        assert_eq!(statuses[0].circuit.state, CircuitState::Open);
+        assert_eq!(statuses[0].circuit.trips, 1);
+        assert_eq!(statuses[1].circuit.state, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_failed_acquire_keeps_half_open_trial() {
+        let settings = CircuitBreakerSettings {
+            cooldown: Duration::ZERO,
+            ..circuit_breaker()
+        };
+        let pool = UpstreamPool::new(Duration::from_secs(60), settings);
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 1, 10)])
+            .await;
+
+        let id = Uuid::new_v4();
+        let permit = pool
+            .acquire_sticky_session_permit(&id, Duration::ZERO)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            pool.record_outcome("http://127.0.0.1:1", false).await;
+        }
+
+        // The cooldown has passed, but the only connection is taken
+        assert!(
+            pool.acquire_connection_permit(Duration::ZERO)
+                .await
+                .is_none()
+        );
+        assert!(
+            pool.acquire_sticky_session_permit(&id, Duration::ZERO)
+                .await
+                .is_none()
+        );
+        assert_eq!(pool.statuses().await[0].circuit.state, CircuitState::Open);
+
+        // The request that gets the connection is the trial
+        drop(permit);
+        let permit = pool
+            .acquire_sticky_session_permit(&id, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:1");
+        assert_eq!(
+            pool.statuses().await[0].circuit.state,
+            CircuitState::HalfOpen
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_excluding() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        let exclude = [String::from("http://127.0.0.1:1")];
+        for _ in 0..5 {
+            let permit = pool
+                .acquire_connection_permit_excluding(Duration::from_millis(10), &exclude)
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+            let permit = pool
+                .acquire_cache_load_permit_excluding(&exclude)
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+        }
+
+        let exclude = [
+            String::from("http://127.0.0.1:1"),
+            String::from("http://127.0.0.1:2"),
+        ];
+        assert!(
+            pool.acquire_connection_permit_excluding(Duration::from_millis(10), &exclude)
+                .await
+                .is_none()
+        );
+        assert!(
+            pool.acquire_cache_load_permit_excluding(&exclude)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_statistics() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 10)])
+            .await;
+        let uri = "http://127.0.0.1:1";
+
+        let statistics = pool.statuses().await[0].statistics.clone();
+        assert_eq!(statistics, UpstreamStatistics::default());
+
+        let _permit = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        for millis in 1..=100 {
+            pool.record_request(uri, Ok(())).await;
+            pool.record_latency(uri, Duration::from_millis(millis))
+                .await;
+        }
+        pool.record_request(uri, Err(RequestError::ServerError))
+            .await;
+        pool.record_request(uri, Err(RequestError::Connect)).await;
+        pool.record_request(uri, Err(RequestError::Timeout)).await;
+        pool.record_bytes(uri, 1024).await;
+        pool.record_bytes(uri, 512).await;
+
+        let statistics = pool.statuses().await[0].statistics.clone();
+        assert_eq!(statistics.current_connections, 1);
+        assert_eq!(statistics.requests, 101);
+        assert_eq!(
+            statistics.errors,
+            RequestErrors {
+                connect: 1,
+                timeout: 1,
+                server_error: 1,
+                other: 0,
+            }
+        );
+        assert_eq!(statistics.latency_p50, Some(Duration::from_millis(51)));
+        assert_eq!(statistics.latency_p95, Some(Duration::from_millis(95)));
+        assert_eq!(statistics.bytes_received, 1536);
+        assert!(statistics.last_success.is_some());
+
+        // Only the most recent response times are kept
+        for _ in 0..LATENCY_SAMPLES {
+            pool.record_latency(uri, Duration::from_millis(500)).await;
+        }
+        let statistics = pool.statuses().await[0].statistics.clone();
+        assert_eq!(statistics.latency_p50, Some(Duration::from_millis(500)));
+    }
+
+    #[tokio::test]
+    async fn test_draining_keeps_sticky_sessions() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        // Pin an ID to the first upstream, which has the fewest sticky sessions
+        let pinned = Uuid::new_v4();
+        let permit = pool
+            .acquire_sticky_session_permit(&pinned, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:1");
+        drop(permit);
+
+        assert_eq!(
+            pool.drain_uris(&[(String::from("http://127.0.0.1:3"), None)])
+                .await,
+            Err(String::from("http://127.0.0.1:3"))
+        );
+        pool.drain_uris(&[(String::from("http://127.0.0.1:1"), None)])
+            .await
+            .unwrap();
+
+        // The pinned ID stays, everything new goes to the other upstream
+        for _ in 0..3 {
+            let permit = pool
+                .acquire_sticky_session_permit(&pinned, Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:1");
+            let permit = pool
+                .acquire_sticky_session_permit(&Uuid::new_v4(), Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+            let permit = pool
+                .acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+            let permit = pool.acquire_cache_load_permit().await.unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+        }
+
+        let statuses = pool.statuses().await;
+        let drain = statuses[0].drain.as_ref().unwrap();
+        assert_eq!(drain.sticky_sessions, 1);
+        assert_eq!(
+            drain.drain.deadline - drain.drain.started,
+            TimeDelta::seconds(60)
+        );
+        assert!(statuses[1].drain.is_none());
+        assert!(pool.remove_drained().await.is_empty());
+
+        // Once the last sticky session ends, the upstream is removed
+        pool.remove_sticky_session(&pinned).await;
+        assert_eq!(
+            pool.remove_drained().await,
+            vec![String::from("http://127.0.0.1:1")]
+        );
+        let upstreams = pool.upstreams().await;
+        assert_eq!(upstreams.len(), 1);
+        assert_eq!(upstreams[0].uri, "http://127.0.0.1:2");
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 10)])
+            .await;
+        pool.acquire_sticky_session_permit(&Uuid::new_v4(), Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        // Sessions are cut off once the deadline passes
+        pool.drain_uris(&[(String::from("http://127.0.0.1:1"), Some(Duration::ZERO))])
+            .await
+            .unwrap();
+        assert!(pool.acquire_cache_load_permit().await.is_none());
+        assert_eq!(pool.remove_drained().await.len(), 1);
+        assert!(pool.upstreams().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_cancels_drain() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        let upstreams = [Upstream::new("http://127.0.0.1:1", 10, 10)];
+        pool.add_upstreams(&upstreams).await;
+        pool.drain_uris(&[(String::from("http://127.0.0.1:1"), None)])
+            .await
+            .unwrap();
+        assert!(pool.acquire_cache_load_permit().await.is_none());
+
+        pool.add_upstreams(&upstreams).await;
+        assert!(pool.acquire_cache_load_permit().await.is_some());
+        assert!(pool.statuses().await[0].drain.is_none());
+    }
+
+    #[test]
+    fn test_health_fall_and_rise() {
+        let now = Utc::now();
+        let mut health = UpstreamHealth::default();
+        assert!(health.healthy);
+
+        assert!(!health.record(Err(String::from("refused")), 2, 3, now));
+        assert!(!health.record(Err(String::from("refused")), 2, 3, now));
+        assert!(health.healthy);
+        assert!(health.record(Err(String::from("refused")), 2, 3, now));
+        assert!(!health.healthy);
+        assert_eq!(health.error, Some(String::from("refused")));
+
+        assert!(!health.record(Ok(()), 2, 3, now));
+        assert!(!health.healthy);
+        assert!(health.record(Ok(()), 2, 3, now));
+        assert!(health.healthy);
+        assert_eq!(health.error, None);
+        assert_eq!(health.checked, Some(now));
+    }
+
+    #[test]
+    fn test_slow_start_ramp() {
+        let now = Instant::now();
+        let mut slow_start = SlowStart::new(Duration::from_secs(100));
+        assert_eq!(slow_start.limit(20, now), 20);
+
+        slow_start.start(now, Utc::now());
+        assert_eq!(slow_start.limit(20, now), 2);
+        assert_eq!(slow_start.limit(5, now), 1);
+        assert_eq!(slow_start.limit(0, now), 0);
+        assert_eq!(slow_start.limit(20, now + Duration::from_secs(50)), 11);
+        assert_eq!(slow_start.limit(20, now + Duration::from_secs(99)), 19);
+        assert_eq!(slow_start.limit(20, now + Duration::from_secs(100)), 20);
+        assert!(slow_start.ends(now + Duration::from_secs(100)).is_none());
+
+        // Disabled
+        let mut slow_start = SlowStart::new(Duration::ZERO);
+        slow_start.start(now, Utc::now());
+        assert_eq!(slow_start.limit(20, now), 20);
+        assert!(slow_start.ends(now).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_slow_start_new_upstream() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker())
+            .with_slow_start(Duration::from_secs(60));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 20, 10)])
+            .await;
+
+        let held: Vec<ConnectionPermit> = [
+            pool.acquire_connection_permit(Duration::ZERO).await,
+            pool.acquire_connection_permit(Duration::ZERO).await,
+        ]
+        .into_iter()
+        .map(|permit| permit.expect("Missing permit"))
+        .collect();
+        assert!(
+            pool.acquire_connection_permit(Duration::ZERO)
+                .await
+                .is_none()
+        );
+        drop(held);
+
+        assert!(
+            pool.acquire_sticky_session_permit(&Uuid::new_v4(), Duration::ZERO)
+                .await
+                .is_some()
+        );
+        assert!(
+            pool.acquire_sticky_session_permit(&Uuid::new_v4(), Duration::ZERO)
+                .await
+                .is_none()
+        );
+
+        let status = pool.statuses().await[0]
+            .slow_start
+            .clone()
+            .expect("Missing slow start");
+        assert_eq!(status.connections, 2);
+        assert_eq!(status.sticky_sessions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_slow_start_when_loaded() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker())
+            .with_slow_start(Duration::from_secs(60));
+        let records = [UpstreamRecord::new(Upstream::new(
+            "http://127.0.0.1:1",
+            20,
+            10,
+        ))];
+        pool.sync(&records).await;
+
+        let statuses = pool.statuses().await;
+        assert!(statuses[0].slow_start.is_none());
+        let held: Vec<Option<ConnectionPermit>> = futures_util::future::join_all(
+            (0..20).map(|_| pool.acquire_connection_permit(Duration::ZERO)),
+        )
+        .await;
+        assert!(held.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_slow_start_after_recovery() {
+        let server = UpstreamServer::new(
+            1,
+            Upstream::new("http://127.0.0.1:1", 20, 10),
+            circuit_breaker(),
+            Duration::from_secs(60),
+            Arc::new(Waiters::default()),
+        );
+        assert!(!server.warming());
+        assert_eq!(server.connection_limit(), 20);
+
+        let now = Utc::now();
+        for _ in 0..3 {
+            server.record_health(Err(String::from("refused")), 2, 3, now);
+        }
+        assert!(!server.healthy());
+        assert!(!server.warming());
+
+        server.record_health(Ok(()), 2, 3, now);
+        assert!(server.record_health(Ok(()), 2, 3, now));
+        assert!(server.warming());
+        assert_eq!(server.connection_limit(), 2);
+        assert_eq!(server.sticky_limit(), 1);
+    }
+
+    #[test]
+    fn test_health_failures_reset_by_success() {
+        let now = Utc::now();
+        let mut health = UpstreamHealth::default();
+        health.record(Err(String::from("timeout")), 1, 2, now);
+        health.record(Ok(()), 1, 2, now);
+        health.record(Err(String::from("timeout")), 1, 2, now);
+        assert!(health.healthy);
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_out_of_rotation() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        pool.record_health("http://127.0.0.1:1", Err(String::from("refused")), 1, 1)
+            .await;
+        for _ in 0..5 {
+            let permit = pool.acquire_cache_load_permit().await.unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+            let permit = pool
+                .acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+        }
+
+        let statuses = pool.statuses().await;
+        assert!(!statuses[0].health.healthy);
+        assert!(statuses[1].health.healthy);
+
+        pool.record_health("http://127.0.0.1:2", Err(String::from("refused")), 1, 1)
+            .await;
+        assert!(pool.acquire_cache_load_permit().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backup_group_used_when_primary_full() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:2", 10, 10).with_group(Some(String::from("dr")), 1),
+            Upstream::new("http://127.0.0.1:1", 1, 10).with_group(Some(String::from("main")), 0),
+        ])
+        .await;
+
+        let first = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(first.uri, "http://127.0.0.1:1");
+        let second = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(second.uri, "http://127.0.0.1:2");
+        drop(first);
+        drop(second);
+
+        let permit = pool.acquire_cache_load_permit().await.unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:1");
+    }
+
+    #[tokio::test]
+    async fn test_failover_and_failback() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10).with_group(Some(String::from("main")), 0),
+            Upstream::new("http://127.0.0.1:2", 10, 10).with_group(Some(String::from("dr")), 1),
+        ])
+        .await;
+        assert_eq!(pool.failover().await, None);
+
+        // Primary goes down
+        pool.record_health("http://127.0.0.1:1", Err(String::from("refused")), 1, 1)
+            .await;
+        assert_eq!(pool.failover().await, Some(FailoverTransition::Failover));
+        assert_eq!(pool.failover().await, None);
+        let permit = pool.acquire_cache_load_permit().await.unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+        drop(permit);
+
+        // Nothing up keeps the backup as the last priority
+        for _ in 0..circuit_breaker().failures {
+            pool.record_outcome("http://127.0.0.1:2", false).await;
+        }
+        assert_eq!(pool.failover().await, None);
+
+        // Primary comes back
+        pool.record_health("http://127.0.0.1:1", Ok(()), 1, 1).await;
+        assert_eq!(pool.failover().await, Some(FailoverTransition::Failback));
+        let permit = pool.acquire_cache_load_permit().await.unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:1");
+    }
+
+    #[test]
+    fn test_canary_pick() {
+        let mut pool = Pool::new(circuit_breaker());
+        pool.add_upstreams(
+            &[
+                Upstream::new("http://127.0.0.1:1", 10, 10),
+                Upstream::new("http://127.0.0.1:2", 10, 10)
+                    .with_canary(Some(String::from("b")), 20),
+                Upstream::new("http://127.0.0.1:3", 10, 10).with_canary(Some(String::from("a")), 5),
+                Upstream::new("http://127.0.0.1:4", 10, 10)
+                    .with_canary(Some(String::from("a")), 10),
+            ],
+            false,
+        );
+
+        // Labels take their largest weight, in order of label
+        assert_eq!(pool.canary_pick(0), Some("a"));
+        assert_eq!(pool.canary_pick(9), Some("a"));
+        assert_eq!(pool.canary_pick(10), Some("b"));
+        assert_eq!(pool.canary_pick(29), Some("b"));
+        assert_eq!(pool.canary_pick(30), None);
+        assert_eq!(pool.canary_pick(99), None);
+
+        pool.set_canary_weight("b", 0).unwrap();
+        assert_eq!(pool.canary_pick(10), None);
+        assert_eq!(pool.set_canary_weight("c", 10), Err(String::from("c")));
+    }
+
+    #[tokio::test]
+    async fn test_canary_routing() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10)
+                .with_canary(Some(String::from("next")), 100),
+        ])
+        .await;
+
+        let pinned = Uuid::new_v4();
+        let permit = pool
+            .acquire_sticky_session_permit(&pinned, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+        drop(permit);
+        let permit = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+        drop(permit);
+
+        // New sessions stop going to the canary, the pinned session stays
+        let uris = pool.set_canary_weight("next", 0).await.unwrap();
+        assert_eq!(uris, vec![String::from("http://127.0.0.1:2")]);
+        for _ in 0..5 {
+            let permit = pool
+                .acquire_sticky_session_permit(&Uuid::new_v4(), Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:1");
+            let permit = pool
+                .acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:1");
+        }
+        let permit = pool
+            .acquire_sticky_session_permit(&pinned, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+
+        let canaries = pool.canaries().await;
+        assert_eq!(
+            canaries,
+            vec![
+                CanaryStatus {
+                    label: None,
+                    weight: 100,
+                    upstreams: vec![String::from("http://127.0.0.1:1")],
+                    sticky_sessions: 5,
+                    connections: 0,
+                },
+                CanaryStatus {
+                    label: Some(String::from("next")),
+                    weight: 0,
+                    upstreams: vec![String::from("http://127.0.0.1:2")],
+                    sticky_sessions: 1,
+                    connections: 1,
+                },
+            ]
+        );
+    }
+
+    fn load_balancing(strategy: LoadBalancing) -> LoadBalancingSettings {
+        LoadBalancingSettings {
+            regular: strategy,
+            sticky: strategy,
+            cache_load: strategy,
+        }
+    }
+
+    #[test]
+    fn test_load_balancing_names() {
+        for strategy in [
+            LoadBalancing::LeastConnections,
+            LoadBalancing::WeightedLeastConnections,
+            LoadBalancing::LeastStickySessions,
+            LoadBalancing::RoundRobin,
+            LoadBalancing::PowerOfTwoChoices,
+            LoadBalancing::LeastLatency,
+        ] {
+            let name = String::from(strategy);
+            assert_eq!(LoadBalancing::try_from(name.as_str()).unwrap(), strategy);
+        }
+        assert!(LoadBalancing::try_from("random").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker())
+            .with_load_balancing(load_balancing(LoadBalancing::RoundRobin));
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10).with_weight(2),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        let mut uris = Vec::new();
+        for _ in 0..6 {
+            let permit = pool.acquire_cache_load_permit().await.unwrap();
+            uris.push(permit.uri);
+        }
+        assert_eq!(
+            uris,
+            [
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:2",
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_weighted_least_connections() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker())
+            .with_load_balancing(load_balancing(LoadBalancing::WeightedLeastConnections));
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10).with_weight(3),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        let mut permits = Vec::new();
+        for _ in 0..8 {
+            permits.push(
+                pool.acquire_connection_permit(Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+            );
+        }
+        let heavier = permits
+            .iter()
+            .filter(|p| p.uri == "http://127.0.0.1:1")
+            .count();
+        assert_eq!(heavier, 6);
+    }
+
+    #[tokio::test]
+    async fn test_least_latency() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker())
+            .with_load_balancing(load_balancing(LoadBalancing::LeastLatency));
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+
+        pool.record_latency("http://127.0.0.1:1", Duration::from_millis(300))
+            .await;
+        // Upstreams without a response time are tried first
+        let permit = pool.acquire_cache_load_permit().await.unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+
+        pool.record_latency("http://127.0.0.1:2", Duration::from_millis(100))
+            .await;
+        for _ in 0..3 {
+            let permit = pool.acquire_cache_load_permit().await.unwrap();
+            assert_eq!(permit.uri, "http://127.0.0.1:2");
+        }
+
+        // A slow response moves the average, but a single sample doesn't replace it
+        pool.record_latency("http://127.0.0.1:2", Duration::from_millis(1000))
+            .await;
+        let statuses = pool.statuses().await;
+        let latency = statuses[1].latency.unwrap().as_secs_f64();
+        assert!((latency - 0.28).abs() < 0.001);
+        let permit = pool.acquire_cache_load_permit().await.unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:2");
+    }
+
+    #[tokio::test]
+    async fn test_shrink_connections_while_held() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 4, 10)])
+            .await;
+
+        let mut permits = Vec::new();
+        for _ in 0..3 {
+            permits.push(
+                pool.acquire_connection_permit(Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        pool.update_limits("http://127.0.0.1:1", Some(1), None)
+            .await
+            .unwrap();
+        assert_eq!(pool.upstreams().await[0].connections, 1);
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+
+        // Returned permits are forgotten until the upstream is back under its limit
+        permits.pop();
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+        permits.pop();
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+        permits.pop();
+        let permit = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+        drop(permit);
+    }
+
+    #[tokio::test]
+    async fn test_grow_connections_while_shrinking() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 2, 10)])
+            .await;
+
+        let first = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        let second = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        pool.update_limits("http://127.0.0.1:1", Some(0), None)
+            .await
+            .unwrap();
+        pool.update_limits("http://127.0.0.1:1", Some(3), None)
+            .await
+            .unwrap();
+        let statuses = pool.statuses().await;
+        assert_eq!(statuses[0].upstream.connections, 3);
+
+        let third = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+
+        // Every returned permit is kept, as the upstream is under its raised limit
+        drop((first, second, third));
+        let mut permits = Vec::new();
+        for _ in 0..3 {
+            permits.push(
+                pool.acquire_connection_permit(Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert!(
+            pool.acquire_connection_permit(Duration::from_millis(10))
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_sticky_limit() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 2)])
+            .await;
+
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        for id in [first, second] {
+            pool.acquire_sticky_session_permit(&id, Duration::ZERO)
+                .await
+                .unwrap();
+        }
+
+        pool.update_limits("http://127.0.0.1:1", None, Some(1))
+            .await
+            .unwrap();
+        assert!(
+            pool.acquire_sticky_session_permit(&Uuid::new_v4(), Duration::ZERO)
+                .await
+                .is_none()
+        );
+        // Existing sticky sessions keep their upstream
+        assert!(
+            pool.acquire_sticky_session_permit(&second, Duration::ZERO)
+                .await
+                .is_some()
+        );
+
+        pool.remove_sticky_session(&first).await;
+        pool.remove_sticky_session(&second).await;
+        assert!(
+            pool.acquire_sticky_session_permit(&Uuid::new_v4(), Duration::ZERO)
+                .await
+                .is_some()
+        );
+
+        assert_eq!(
+            pool.update_limits("http://127.0.0.1:2", Some(1), None)
+                .await,
+            Err(String::from("http://127.0.0.1:2"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_waiters_in_order() {
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 1, 1)])
+            .await;
+        let held = pool.acquire_connection_permit(Duration::ZERO).await;
+        assert!(held.is_some());
+
+        // Each waiter starts waiting before the next one is spawned
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut waiters = Vec::new();
+        for n in 0..3 {
+            let pool = pool.clone();
+            let sender = sender.clone();
+            waiters.push(tokio::spawn(async move {
+                let permit = pool
+                    .acquire_connection_permit(Duration::from_secs(5))
+                    .await
+                    .expect("Waiter timed out");
+                sender.send(n).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(permit);
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        drop(sender);
+        let mut order = Vec::new();
+        while let Some(n) = receiver.recv().await {
+            order.push(n);
+        }
+        assert_eq!(order, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_new_request_does_not_jump_waiters() {
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 1, 1)])
+            .await;
+        let held = pool.acquire_connection_permit(Duration::ZERO).await;
+        assert!(held.is_some());
+
+        let waiter = {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.acquire_connection_permit(Duration::from_secs(5))
+                    .await
+                    .is_some()
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // A new request arrives after the permit is freed, before the woken waiter runs
+        drop(held);
+        assert!(
+            pool.acquire_connection_permit(Duration::ZERO)
+                .await
+                .is_none()
+        );
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sticky_waiter_woken_when_freed() {
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 10, 1)])
+            .await;
+        let first = Uuid::new_v4();
+        pool.acquire_sticky_session_permit(&first, Duration::ZERO)
+            .await
+            .unwrap();
+
+        let waiter = {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let start = Instant::now();
+                let permit = pool
+                    .acquire_sticky_session_permit(&Uuid::new_v4(), Duration::from_secs(5))
+                    .await;
+                (permit.is_some(), start.elapsed())
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pool.remove_sticky_session(&first).await;
+
+        let (acquired, elapsed) = waiter.await.unwrap();
+        assert!(acquired);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_waiter_woken_by_new_upstream() {
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        let waiter = {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                pool.acquire_connection_permit(Duration::from_secs(5))
+                    .await
+                    .map(|permit| permit.uri)
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 1, 1)])
+            .await;
+
+        let uri = tokio::time::timeout(Duration::from_millis(500), waiter)
+            .await
+            .expect("Waiter was not woken")
+            .unwrap();
+        assert_eq!(uri.as_deref(), Some("http://127.0.0.1:1"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_passes_on_wake() {
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", 1, 1)])
+            .await;
+        let held = pool.acquire_connection_permit(Duration::ZERO).await;
+
+        let cancelled = {
+            let pool = pool.clone();
+            tokio::spawn(
+                async move { pool.acquire_connection_permit(Duration::from_secs(5)).await },
+            )
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let waiter = {
+            let pool = pool.clone();
+            tokio::spawn(
+                async move { pool.acquire_connection_permit(Duration::from_secs(5)).await },
+            )
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Wake the first waiter and cancel it before it takes the permit
+        pool.waiters.connection.wake_one();
+        cancelled.abort();
+        let _ = cancelled.await;
+        drop(held);
+
+        let permit = tokio::time::timeout(Duration::from_millis(500), waiter)
+            .await
+            .expect("Waiter was not woken")
+            .unwrap();
+        assert!(permit.is_some());
+    }
+
+    /// Wait for sticky sessions freed one at a time, either woken by the pool or polling once a
+    /// second (as before waiters were woken), returning the delay between each sticky session
+    /// being freed and the next one acquired, and the number of attempts made
+    async fn sticky_session_wait(polling: bool) -> (Vec<Duration>, usize) {
+        let count = 50;
+        let pool = Arc::new(UpstreamPool::new(
+            Duration::from_secs(60),
+            circuit_breaker(),
+        ));
+        pool.add_upstreams(&[Upstream::new("http://127.0.0.1:1", count * 2, count)])
+            .await;
+        let held: Vec<Uuid> = (0..count).map(|_| Uuid::new_v4()).collect();
+        for id in &held {
+            pool.acquire_sticky_session_permit(id, Duration::ZERO)
+                .await
+                .unwrap();
+        }
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut waiters = Vec::new();
+        for _ in 0..count {
+            let pool = pool.clone();
+            let attempts = attempts.clone();
+            waiters.push(tokio::spawn(async move {
+                let id = Uuid::new_v4();
+                if polling {
+                    loop {
+                        attempts.fetch_add(1, atomic::Ordering::Relaxed);
+                        if let Some(permit) = pool
+                            .acquire_sticky_session_permit(&id, Duration::ZERO)
+                            .await
+                        {
+                            return (Instant::now(), permit);
+                        }
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+
+                let acquired = pool
+                    .waiters
+                    .sticky
+                    .wait_for(Duration::from_secs(60), None, || {
+                        attempts.fetch_add(1, atomic::Ordering::Relaxed);
+                        let pool = pool.clone();
+                        async move {
+                            let guard = pool._read_lock().await;
+                            (*guard).acquire_sticky_permit(&id).await
+                        }
+                    })
+                    .await
+                    .expect("Waiter timed out");
+                (
+                    Instant::now(),
+                    ConnectionPermit::new(acquired.1, Some(acquired.0)),
+                )
+            }));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut freed = Vec::new();
+        for id in &held {
+            freed.push(Instant::now());
+            pool.remove_sticky_session(id).await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let mut acquired = Vec::new();
+        for waiter in waiters {
+            acquired.push(waiter.await.unwrap().0);
+        }
+        acquired.sort();
+        let delays = freed
+            .iter()
+            .zip(&acquired)
+            .map(|(freed, acquired)| acquired.saturating_duration_since(*freed))
+            .collect();
+        (delays, attempts.load(atomic::Ordering::Relaxed))
+    }
+
+    /// Benchmark: `cargo test bench_ -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn bench_sticky_session_wait() {
+        for polling in [true, false] {
+            let start = Instant::now();
+            let (delays, attempts) = sticky_session_wait(polling).await;
+            let elapsed = start.elapsed();
+            let average = delays.iter().sum::<Duration>() / delays.len() as u32;
+            let max = delays.iter().max().unwrap();
+
+            println!(
+                "sticky session wait ({}): {} waiters, {:?} average / {:?} max delay, {} attempts in {:?}",
+                if polling { "polling" } else { "woken" },
+                delays.len(),
+                average,
+                max,
+                attempts,
+                elapsed
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_records() {
+        let pool = UpstreamPool::new(Duration::from_secs(60), circuit_breaker());
+        pool.add_upstreams(&[
+            Upstream::new("http://127.0.0.1:1", 10, 10),
+            Upstream::new("http://127.0.0.1:2", 10, 10),
+        ])
+        .await;
+        let permit = pool
+            .acquire_connection_permit(Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(permit.uri, "http://127.0.0.1:1");
+
+        let drain = Drain::new(Utc::now(), Duration::from_secs(60));
+        let records = [
+            UpstreamRecord::new(Upstream::new("http://127.0.0.1:1", 5, 2).with_weight(3)),
+            UpstreamRecord::new(Upstream::new("http://127.0.0.1:3", 10, 10)),
+            UpstreamRecord {
+                upstream: Upstream::new("http://127.0.0.1:4", 10, 10),
+                drain: Some(drain),
+            },
+        ];
+        let sync = pool.sync(&records).await;
+        assert_eq!(
+            sync,
+            UpstreamSync {
+                added: vec![String::from("http://127.0.0.1:3")],
+                removed: vec![String::from("http://127.0.0.1:2")],
+            }
+        );
+
+        // Updated in place, while the connection is still in progress
+        let statuses = pool.statuses().await;
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(
+            statuses[0].upstream,
+            Upstream::new("http://127.0.0.1:1", 5, 2).with_weight(3)
+        );
+        assert_eq!(
+            pool.records(&[String::from("http://127.0.0.1:1")]).await,
+            vec![records[0].clone()]
+        );
+        drop(permit);
+
+        // Drains are applied to upstreams already in the pool, and cancelled when the record is
+        // no longer draining
+        let mut draining = records.clone();
+        draining[1].drain = Some(drain);
+        pool.sync(&draining).await;
+        assert_eq!(
+            pool.records(&[String::from("http://127.0.0.1:3")]).await[0].drain,
+            Some(drain)
+        );
+        let sync = pool.sync(&records).await;
+        assert_eq!(sync, UpstreamSync::default());
+        assert_eq!(
+            pool.records(&[String::from("http://127.0.0.1:3")]).await[0].drain,
+            None
+        );
+    }
+
+    #[test]
+    fn test_record_serialization() {
+        let record = UpstreamRecord::new(Upstream::new("http://127.0.0.1:1", 10, 5));
+        let value = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            value,
+            r#"{"uri":"http://127.0.0.1:1","connections":10,"sticky_sessions":5,"weight":1}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<UpstreamRecord>(&value).unwrap(),
+            record
+        );
+
+        let value = r#"{"uri":"http://127.0.0.1:1","connections":10,"sticky_sessions":5}"#;
+        assert_eq!(
+            serde_json::from_str::<UpstreamRecord>(value).unwrap(),
+            record
+        );
+
+        let mut record = record;
+        record.upstream.tls.server_name = Some(String::from("omnis.internal"));
+        let value = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            value,
+            r#"{"uri":"http://127.0.0.1:1","connections":10,"sticky_sessions":5,"weight":1,"tls_server_name":"omnis.internal"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<UpstreamRecord>(&value).unwrap(),
+            record
+        );
     }
 }
@@ -1,12 +1,80 @@
 use axum_extra::extract::cookie::Cookie;
-use http::{HeaderMap, HeaderName, header::CONTENT_TYPE};
-use tower_cookies::Cookies;
-use tracing::error;
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, HeaderName, header::CONTENT_TYPE, uri::PathAndQuery};
+use serde::{Deserialize, Serialize};
+use tower_cookies::{Cookies, PrivateCookies};
+use tracing::{error, info};
 use uuid::Uuid;
 
 use crate::config::Config;
-use crate::queue::{QueueControl, QueuePosition};
-use crate::{cookies, errors};
+use crate::cookies::add_private_server_cookie;
+use crate::queue::{PriorityTier, QueueControl, QueuePosition};
+use crate::sites::SiteConfig;
+use crate::{cookies, errors, tokens};
+
+/// Purpose of signed tokens that place a visitor in a priority tier
+pub const PRIORITY_TOKEN_PURPOSE: &str = "priority";
+
+/// Claims of a signed priority token.  Tokens are only accepted by the site they were signed for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriorityClaims {
+    pub site: String,
+    pub tier: String,
+}
+
+/// Sign a token that places a visitor in the named priority tier until it expires
+pub fn sign_priority_token(
+    config: &Config,
+    site: &SiteConfig,
+    tier: impl Into<String>,
+    expires: DateTime<Utc>,
+) -> errors::Result<String> {
+    let claims = PriorityClaims {
+        site: site.name.clone(),
+        tier: tier.into(),
+    };
+    let token = tokens::sign(
+        config.cookie_secret_key.signing(),
+        PRIORITY_TOKEN_PURPOSE,
+        claims,
+        expires,
+    )?;
+    Ok(token)
+}
+
+/// Purpose of signed tokens that admit a visitor directly to the store
+pub const INVITE_TOKEN_PURPOSE: &str = "invite";
+
+/// Claims of a signed invite.  Redemptions are counted against the invite ID in Redis, and
+/// invites are only accepted by the site they were signed for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub site: String,
+    pub invite: String,
+    pub uses: u32,
+}
+
+/// Sign an invite that admits up to `uses` visitors directly to the store until it expires
+pub fn sign_invite_token(
+    config: &Config,
+    site: &SiteConfig,
+    invite: Uuid,
+    uses: u32,
+    expires: DateTime<Utc>,
+) -> errors::Result<String> {
+    let claims = InviteClaims {
+        site: site.name.clone(),
+        invite: String::from(invite),
+        uses,
+    };
+    let token = tokens::sign(
+        config.cookie_secret_key.signing(),
+        INVITE_TOKEN_PURPOSE,
+        claims,
+        expires,
+    )?;
+    Ok(token)
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum QueueId {
@@ -63,32 +131,246 @@ pub fn extract_queue_id(queue: &QueueControl, cookie: &Option<Cookie>) -> QueueI
     }
 }
 
+/// Extract a single (decoded) parameter from a query string
+fn query_param(query: Option<&str>, param: &str) -> Option<String> {
+    query?
+        .split('&')
+        .find_map(|pair| match pair.split_once('=') {
+            Some((name, value)) if name == param => {
+                urlencoding::decode(value).ok().map(|v| v.into_owned())
+            }
+            _ => None,
+        })
+}
+
+/// Remove the waiting room's own parameters (priority tokens and invites) from a path and query,
+/// so the tokens aren't passed on to the upstream
+pub fn strip_token_params(
+    config: &Config,
+    path_and_query: &PathAndQuery,
+) -> errors::Result<PathAndQuery> {
+    strip_query_params(
+        path_and_query,
+        &[&config.priority_query_param, &config.invite_query_param],
+    )
+}
+
+/// Remove every instance of the named parameters from a path and query
+fn strip_query_params(
+    path_and_query: &PathAndQuery,
+    params: &[&str],
+) -> errors::Result<PathAndQuery> {
+    let Some(query) = path_and_query.query() else {
+        return Ok(path_and_query.clone());
+    };
+
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            !params.contains(&name)
+        })
+        .collect();
+    if kept.len() == query.split('&').count() {
+        return Ok(path_and_query.clone());
+    }
+
+    let stripped = match kept.is_empty() {
+        true => String::from(path_and_query.path()),
+        false => format!("{}?{}", path_and_query.path(), kept.join("&")),
+    };
+    Ok(PathAndQuery::try_from(stripped)?)
+}
+
+/// Extract a priority token from the query string, header or cookie (in that order), so that a
+/// new token replaces one remembered from an earlier visit
+pub fn extract_priority_token(
+    config: &Config,
+    site: &SiteConfig,
+    private_cookies: &PrivateCookies<'_>,
+    headers: &HeaderMap,
+    query: Option<&str>,
+) -> Option<String> {
+    let query_token = query_param(query, &config.priority_query_param);
+    if query_token.is_some() {
+        return query_token;
+    }
+
+    if let Some(header) = headers.get(config.priority_http_header.as_str())
+        && let Ok(header) = header.to_str()
+    {
+        return Some(String::from(header));
+    }
+
+    private_cookies
+        .get(site.priority_cookie_name.as_str())
+        .map(|cookie| String::from(cookie.value()))
+}
+
+/// Resolve the priority tier of a signed token, remembering the token in a private cookie until
+/// it expires.  Invalid tokens, and tokens for tiers that have since been removed, are forgotten
+pub async fn priority_tier(
+    config: &Config,
+    site: &SiteConfig,
+    private_cookies: &PrivateCookies<'_>,
+    queue: &QueueControl,
+    token: impl Into<String>,
+) -> errors::Result<Option<PriorityTier>> {
+    let token = token.into();
+    let now = Utc::now();
+
+    let verified = tokens::verify::<PriorityClaims>(
+        config.cookie_secret_key.signing(),
+        PRIORITY_TOKEN_PURPOSE,
+        &token,
+        now,
+    );
+    let (claims, expires) = match verified {
+        Ok(verified) if verified.0.site == site.name => verified,
+        Ok((claims, _)) => {
+            info!("Rejected priority token for another site: {}", claims.site);
+            private_cookies.remove(Cookie::from(site.priority_cookie_name.clone()));
+            return Ok(None);
+        }
+        Err(e) => {
+            info!("Rejected priority token: {}", e);
+            private_cookies.remove(Cookie::from(site.priority_cookie_name.clone()));
+            return Ok(None);
+        }
+    };
+
+    let Some(tier) = queue
+        .priority_tier(&site.queue_prefix, &claims.tier)
+        .await?
+    else {
+        info!("Rejected priority token for missing tier: {}", claims.tier);
+        private_cookies.remove(Cookie::from(site.priority_cookie_name.clone()));
+        return Ok(None);
+    };
+
+    add_private_server_cookie(
+        private_cookies,
+        site.priority_cookie_name.clone(),
+        token,
+        (expires - now).to_std().ok(),
+    );
+
+    Ok(Some(tier))
+}
+
+/// Extract an invite from the query string.  Invites are sent as links, so they are only ever
+/// read from the query string
+pub fn extract_invite_token(config: &Config, query: Option<&str>) -> Option<String> {
+    query_param(query, &config.invite_query_param)
+}
+
+/// Redeem a signed invite for the queue ID, admitting it directly to the store.  Returns true if
+/// the ID was admitted, and false if the invite is invalid, expired or used up
+pub async fn redeem_invite(
+    config: &Config,
+    site: &SiteConfig,
+    queue: &QueueControl,
+    queue_id: QueueId,
+    token: impl Into<String>,
+) -> errors::Result<bool> {
+    let token = token.into();
+
+    let verified = tokens::verify::<InviteClaims>(
+        config.cookie_secret_key.signing(),
+        INVITE_TOKEN_PURPOSE,
+        &token,
+        Utc::now(),
+    );
+    let (claims, expires) = match verified {
+        Ok(verified) if verified.0.site == site.name => verified,
+        Ok((claims, _)) => {
+            info!("Rejected invite for another site: {}", claims.site);
+            return Ok(false);
+        }
+        Err(e) => {
+            info!("Rejected invite: {}", e);
+            return Ok(false);
+        }
+    };
+
+    let Ok(invite) = Uuid::parse_str(&claims.invite) else {
+        info!("Rejected invite with invalid ID: {}", claims.invite);
+        return Ok(false);
+    };
+
+    let redeemed = queue
+        .invite_redeem(
+            &site.queue_prefix,
+            invite,
+            queue_id.into(),
+            claims.uses,
+            expires,
+            None,
+        )
+        .await?;
+
+    match redeemed {
+        Some(redeemed) => {
+            info!(
+                "Redeemed invite {} ({} of {} uses)",
+                claims.invite, redeemed, claims.uses
+            );
+            Ok(true)
+        }
+        None => {
+            info!("Rejected invite {}: no uses left", claims.invite);
+            Ok(false)
+        }
+    }
+}
+
 // Build a
 pub async fn check_waiting_page(
     config: &Config,
+    site: &SiteConfig,
     cookies: &Cookies,
     locale: impl Into<String>,
     queue: &QueueControl,
     queue_id: QueueId,
+    priority: Option<&PriorityTier>,
 ) -> errors::Result<Option<(HeaderMap, axum::body::Body)>> {
     let locale = locale.into();
 
-    let queue_prefix = config.queue_prefix.clone();
+    let queue_prefix = site.queue_prefix.clone();
 
     let position = queue
-        .id_position(queue_prefix.clone(), queue_id.into(), None, true)
+        .id_position_with_priority(
+            queue_prefix.clone(),
+            queue_id.into(),
+            None,
+            true,
+            priority.map_or(0, |tier| tier.priority),
+        )
         .await?;
 
     let position = match position {
         QueuePosition::NotPresent => unreachable!(),
+        QueuePosition::Queue(_) if priority.is_some_and(|tier| tier.bypass) => {
+            // Bypass tiers skip the queue entirely
+            queue
+                .id_promote(queue_prefix.clone(), queue_id.into(), None)
+                .await?;
+            return Ok(None);
+        }
         QueuePosition::Queue(pos) => pos,
         QueuePosition::Store => return Ok(None),
     };
 
-    // Determine general queue status
+    // Determine general queue status.  Positions are read live from Redis, but the queue can
+    // shrink between reading the position and the size, so never report a size smaller than
+    // the position
     let status = queue.queue_status(queue_prefix.clone()).await?;
     let position_string = position.to_string();
-    let size_string = status.queue_size.to_string();
+    let size_string = status.queue_size.max(position).to_string();
+    let eta_string = status
+        .throughput
+        .eta(position)
+        .map(|eta| eta.as_secs().to_string());
 
     // Fetch waiting page
     let mut waiting_headers = HeaderMap::new();
@@ -108,12 +390,47 @@ pub async fn check_waiting_page(
         size_string.parse()?,
     );
 
-    cookies::add_browser_cookie(
-        cookies,
-        config.position_cookie_name.clone(),
-        position_string,
-    );
-    cookies::add_browser_cookie(cookies, config.queue_size_cookie_name.clone(), size_string);
+    cookies::add_browser_cookie(cookies, site.position_cookie_name.clone(), position_string);
+    cookies::add_browser_cookie(cookies, site.queue_size_cookie_name.clone(), size_string);
+
+    // The estimate is only available once IDs have moved through the store recently
+    match eta_string {
+        Some(eta_string) => {
+            waiting_headers.insert(
+                HeaderName::from_lowercase(config.eta_http_header.as_bytes())?,
+                eta_string.parse()?,
+            );
+            cookies::add_browser_cookie(cookies, site.eta_cookie_name.clone(), eta_string);
+        }
+        None => cookies.remove(Cookie::from(site.eta_cookie_name.clone())),
+    }
 
     Ok(Some((waiting_headers, waiting_page_body)))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_query_params() {
+        let params = ["priority", "invite"];
+        let strip = |uri: &'static str| {
+            strip_query_params(&PathAndQuery::from_static(uri), &params)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(strip("/jsclient?app=a"), "/jsclient?app=a");
+        assert_eq!(strip("/jsclient"), "/jsclient");
+        assert_eq!(strip("/jsclient?priority=abc.def"), "/jsclient");
+        assert_eq!(
+            strip("/jsclient?app=a&priority=abc.def&invite=x&b=2"),
+            "/jsclient?app=a&b=2"
+        );
+        assert_eq!(strip("/jsclient?priority&app=a"), "/jsclient?app=a");
+        assert_eq!(
+            strip("/jsclient?priority_tier=1"),
+            "/jsclient?priority_tier=1"
+        );
+    }
+}
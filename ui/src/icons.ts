@@ -60,3 +60,18 @@ export const STORE_ICON = `
      />
 </svg>
 `
+
+export const CLOCK_ICON = `
+<svg
+    xmlns="http://www.w3.org/2000/svg"
+    viewBox="0 0 24 24"
+    class="inline-block h-8 w-8 stroke-current"
+>
+    <circle fill="none" stroke-width="2" cx="12" cy="12" r="9"></circle>
+    <polyline
+      fill="none"
+      stroke-linecap="round"
+      stroke-linejoin="round"
+      stroke-width="2" points="12 7, 12 12, 15 14"></polyline>
+</svg>
+`
@@ -2,7 +2,7 @@ import type { Store, StoreDefinition } from 'pinia'
 import type { Mock } from 'vitest'
 import type { UnwrapRef } from 'vue'
 
-import type { Config, QueueStatus, Upstream } from '@/models.ts'
+import type { Config, QueueStatus, Site, Upstream } from '@/models.ts'
 
 // Helper function for mocking stores
 export function mockedStore<TStoreDef extends () => unknown>(
@@ -27,6 +27,7 @@ export function mockedStore<TStoreDef extends () => unknown>(
 
 export const mockConfig: Config = {
   name: 'My Bouncer',
+  site: 'default',
   default_locale: 'en',
   locales: ['en'],
   redis_uri: 'redis://127.0.0.1',
@@ -35,20 +36,50 @@ export const mockConfig: Config = {
       uri: 'http://127.0.0.1:63111',
       connections: 20,
       sticky_sessions: 20,
+      weight: 1,
+      priority: 0,
+      canary_weight: 0,
+    },
+    {
+      uri: 'http://127.0.0.1:63112',
+      connections: 20,
+      sticky_sessions: 20,
+      weight: 1,
+      priority: 0,
+      canary: 'studio-11.1',
+      canary_weight: 10,
     },
-    { uri: 'http://127.0.0.1:63112', connections: 20, sticky_sessions: 20 },
   ],
   id_cookie_name: 'omnis-bouncer-id',
   position_cookie_name: 'omnis-bouncer-queue-position',
   id_upstream_http_header: 'x-omnis-bouncer-id',
   id_evict_upstream_http_header: 'x-omnis-bouncer-id-evict',
   queue_size_cookie_name: 'omnis-bouncer-queue-size',
+  eta_cookie_name: 'omnis-bouncer-queue-eta',
+  priority_cookie_name: 'omnis-bouncer-priority',
   position_http_header: 'x-omnis-bouncer-queue-position',
   queue_size_http_header: 'x-omnis-bouncer-queue-size',
+  eta_http_header: 'x-omnis-bouncer-queue-eta',
+  priority_http_header: 'x-omnis-bouncer-priority',
+  priority_query_param: 'omnis-bouncer-priority',
+  invite_query_param: 'omnis-bouncer-invite',
   acquire_timeout: 10,
   connect_timeout: 10,
+  response_timeout_cache_load: 30,
+  response_timeout_js_client: 60,
+  response_timeout_push: 300,
+  response_timeout_rest_api: 60,
+  response_timeout_ultra_thin: 120,
+  idle_timeout_cache_load: 10,
+  idle_timeout_js_client: 30,
+  idle_timeout_push: 300,
+  idle_timeout_rest_api: 30,
+  idle_timeout_ultra_thin: 60,
+  retry_attempts: 2,
+  retry_budget: 10,
   cookie_id_expiration: 86400,
   sticky_session_timeout: 60,
+  shared_sticky_sessions: false,
   asset_cache_secs: 60,
   buffer_connections: 1000,
   js_client_rate_limit_per_sec: 0,
@@ -57,13 +88,29 @@ export const mockConfig: Config = {
   public_http_port: 3000,
   public_https_port: 3001,
   monitor_https_port: 2999,
+  control_session_expiration: 28800,
   queue_enabled: true,
   queue_rotation_enabled: true,
+  queue_disabled_policy: 'drain',
   store_capacity: 5,
   redis_prefix: 'omnis_bouncer',
   quarantine_expiry: 30,
   validated_expiry: 60,
   publish_throttle: 0,
+  throughput_window: 900,
+  health_check_enabled: true,
+  health_check_path: '/',
+  health_check_interval: 10,
+  health_check_timeout: 5,
+  health_check_rise: 2,
+  health_check_fall: 3,
+  circuit_breaker_failures: 5,
+  circuit_breaker_window: 60,
+  circuit_breaker_cooldown: 30,
+  load_balancing_regular: 'least_connections',
+  load_balancing_sticky: 'least_sticky_sessions',
+  load_balancing_cache_load: 'least_connections',
+  slow_start: 0,
   ultra_thin_inject_headers: true,
   fallback_ultra_thin_library: 'jsclientmethods',
   fallback_ultra_thin_class: 'rtUltra',
@@ -74,6 +121,8 @@ export const mockStatus: QueueStatus = {
   store_capacity: 10,
   queue_size: 123,
   store_size: 10,
+  throughput_per_minute: 20.5,
+  queue_eta: 363,
   updated: new Date(2025, 9, 27, 5, 31),
 }
 
@@ -82,6 +131,61 @@ export const mockUpstreams: Upstream[] = [
     uri: 'http://127.0.0.1:63111',
     connections: 20,
     sticky_sessions: 20,
+    weight: 2,
+    group: 'primary',
+    priority: 0,
+    canary_weight: 0,
+    latency: 85,
+    current_connections: 6,
+    current_sticky_sessions: 5,
+    requests: 5120,
+    errors: { connect: 0, timeout: 1, server_error: 12, other: 0 },
+    latency_p50: 72,
+    latency_p95: 240,
+    bytes_received: 73400320,
+    last_success: '2025-09-23T10:43:58Z',
+    slow_start: { ends: '2025-09-23T10:46:00Z', connections: 8, sticky_sessions: 8 },
+    health: { healthy: true, checked: '2025-09-23T10:44:00Z', error: null },
+    circuit: { state: 'closed', trips: 0, opened: null },
+  },
+  {
+    uri: 'http://127.0.0.1:63112',
+    connections: 20,
+    sticky_sessions: 20,
+    weight: 1,
+    group: 'dr',
+    priority: 1,
+    canary: 'studio-11.1',
+    canary_weight: 10,
+    current_connections: 1,
+    current_sticky_sessions: 4,
+    requests: 812,
+    errors: { connect: 14, timeout: 3, server_error: 0, other: 1 },
+    latency_p50: 130,
+    latency_p95: 910,
+    bytes_received: 10485760,
+    last_success: '2025-09-23T10:43:10Z',
+    health: {
+      healthy: false,
+      checked: '2025-09-23T10:44:00Z',
+      error: 'error sending request for url (http://127.0.0.1:63112/)',
+    },
+    circuit: { state: 'open', trips: 2, opened: '2025-09-23T10:43:30Z' },
+    drain: {
+      started: '2025-09-23T10:40:00Z',
+      deadline: '2025-09-23T10:50:00Z',
+      sticky_sessions: 4,
+      connections: 1,
+    },
+  },
+]
+
+export const mockSites: Site[] = [
+  { name: 'default', hosts: [], path_prefix: null, redis_prefix: 'omnis_bouncer' },
+  {
+    name: 'tickets',
+    hosts: ['tickets.example.com'],
+    path_prefix: null,
+    redis_prefix: 'omnis_bouncer:tickets',
   },
-  { uri: 'http://127.0.0.1:63112', connections: 20, sticky_sessions: 20 },
 ]
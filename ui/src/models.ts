@@ -1,5 +1,6 @@
 export interface Config {
   name: string
+  site: string
   default_locale: string
   locales: string[]
   redis_uri: string
@@ -7,14 +8,33 @@ export interface Config {
   id_cookie_name: string
   position_cookie_name: string
   queue_size_cookie_name: string
+  eta_cookie_name: string
+  priority_cookie_name: string
   id_upstream_http_header: string
   id_evict_upstream_http_header: string
   position_http_header: string
   queue_size_http_header: string
+  eta_http_header: string
+  priority_http_header: string
+  priority_query_param: string
+  invite_query_param: string
   acquire_timeout: number
   connect_timeout: number
+  response_timeout_cache_load: number
+  response_timeout_js_client: number
+  response_timeout_push: number
+  response_timeout_rest_api: number
+  response_timeout_ultra_thin: number
+  idle_timeout_cache_load: number
+  idle_timeout_js_client: number
+  idle_timeout_push: number
+  idle_timeout_rest_api: number
+  idle_timeout_ultra_thin: number
+  retry_attempts: number
+  retry_budget: number
   cookie_id_expiration: number
   sticky_session_timeout: number
+  shared_sticky_sessions: boolean
   asset_cache_secs: number
   buffer_connections: number
   js_client_rate_limit_per_sec: number
@@ -23,13 +43,29 @@ export interface Config {
   public_http_port: number
   public_https_port: number
   monitor_https_port: number
+  control_session_expiration: number
   queue_enabled: boolean
   queue_rotation_enabled: boolean
+  queue_disabled_policy: 'drain' | 'keep'
   store_capacity: number
   redis_prefix: string
   quarantine_expiry: number
   validated_expiry: number
   publish_throttle: number
+  throughput_window: number
+  health_check_enabled: boolean
+  health_check_path: string
+  health_check_interval: number
+  health_check_timeout: number
+  health_check_rise: number
+  health_check_fall: number
+  circuit_breaker_failures: number
+  circuit_breaker_window: number
+  circuit_breaker_cooldown: number
+  load_balancing_regular: string
+  load_balancing_sticky: string
+  load_balancing_cache_load: string
+  slow_start: number
   ultra_thin_inject_headers: boolean
   fallback_ultra_thin_library: string | null
   fallback_ultra_thin_class: string | null
@@ -40,13 +76,83 @@ export interface QueueStatus {
   store_capacity: number
   queue_size: number
   store_size: number
+  throughput_per_minute: number
+  queue_eta: number | null
   updated?: Date
 }
 
+export interface Site {
+  name: string
+  hosts: string[]
+  path_prefix: string | null
+  redis_prefix: string
+}
+
+export interface UpstreamHealth {
+  healthy: boolean
+  checked: string | null
+  error: string | null
+}
+
+export interface UpstreamCircuit {
+  state: 'closed' | 'open' | 'half_open'
+  trips: number
+  opened: string | null
+}
+
+export interface UpstreamErrors {
+  connect: number
+  timeout: number
+  server_error: number
+  other: number
+}
+
+export interface UpstreamSlowStart {
+  ends: string
+  connections: number
+  sticky_sessions: number
+}
+
+export interface UpstreamDraining {
+  started: string
+  deadline: string
+  sticky_sessions: number
+  connections: number
+}
+
 export interface Upstream {
   uri: string
   connections: number
   sticky_sessions: number
+  weight: number
+  group?: string
+  priority: number
+  canary?: string
+  canary_weight: number
+  tls_ca_path?: string
+  tls_certificate_path?: string
+  tls_key_path?: string
+  tls_server_name?: string
+  tls_insecure_skip_verify?: boolean
+  latency?: number
+  current_connections?: number
+  current_sticky_sessions?: number
+  requests?: number
+  errors?: UpstreamErrors
+  latency_p50?: number
+  latency_p95?: number
+  bytes_received?: number
+  last_success?: string
+  slow_start?: UpstreamSlowStart
+  health?: UpstreamHealth
+  circuit?: UpstreamCircuit
+  drain?: UpstreamDraining
+}
+
+export interface Whoami {
+  name: string
+  method: string
+  role: 'read' | 'operator' | 'admin'
 }
 
 export const INTERESTING_EVENTS_RE = /^(settings|queue|store):/i
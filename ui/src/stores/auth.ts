@@ -0,0 +1,47 @@
+import { defineStore } from 'pinia'
+import { type Ref, ref } from 'vue'
+
+import { API_URI } from '@/constants'
+import type { Whoami } from '@/models.ts'
+
+export const useAuth = defineStore('auth', () => {
+  const identity: Ref<Whoami | null> = ref(null)
+  const checked: Ref<boolean> = ref(false)
+  const error: Ref<string | null> = ref(null)
+
+  // Check if the browser already has a session (or credentials) for the control server
+  async function check() {
+    try {
+      const response = await fetch(API_URI + 'api/whoami', { credentials: 'include' })
+      identity.value = response.ok ? await response.json() : null
+    } catch {
+      identity.value = null
+    }
+    checked.value = true
+  }
+
+  async function login(username: string, password: string) {
+    error.value = null
+    const response = await fetch(API_URI + 'api/login', {
+      method: 'POST',
+      credentials: 'include',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ username, password }),
+    })
+    if (response.ok) {
+      identity.value = await response.json()
+    } else {
+      identity.value = null
+      error.value = 'Invalid username or password'
+    }
+  }
+
+  async function logout() {
+    await fetch(API_URI + 'api/logout', { method: 'POST', credentials: 'include' })
+    identity.value = null
+  }
+
+  check()
+
+  return { identity, checked, error, login, logout }
+})
@@ -1,12 +1,16 @@
 import { useFetch, useTitle } from '@vueuse/core'
-import { defineStore } from 'pinia'
-import { type ShallowRef, watchEffect } from 'vue'
+import { defineStore, storeToRefs } from 'pinia'
+import { type ShallowRef, computed, watch, watchEffect } from 'vue'
 
 import { API_URI } from '@/constants'
 import { type Config, INTERESTING_EVENTS_RE, type QueueStatus } from '@/models.ts'
+import { DEFAULT_SITE, useSites } from '@/stores/sites.ts'
 
 export const useQueueStatus = defineStore('queue', () => {
   const title = useTitle('')
+  const { siteQuery } = storeToRefs(useSites())
+  const configURI = computed(() => API_URI + 'api/config' + siteQuery.value)
+  const statusURI = computed(() => API_URI + 'api/status' + siteQuery.value)
 
   const {
     data: config,
@@ -16,13 +20,16 @@ export const useQueueStatus = defineStore('queue', () => {
     data: ShallowRef<Config | null>
     error: ShallowRef<any>
     execute: (throwOnFailed?: boolean) => void
-  } = useFetch(API_URI + 'api/config')
+  } = useFetch(configURI, { credentials: 'include', refetch: true })
     .get()
     .json()
 
   watchEffect(() => {
     if (config.value != undefined) {
-      title.value = config.value.name
+      title.value =
+        config.value.site == DEFAULT_SITE
+          ? config.value.name
+          : config.value.name + ' - ' + config.value.site
     }
   })
 
@@ -34,23 +41,35 @@ export const useQueueStatus = defineStore('queue', () => {
     data: ShallowRef<QueueStatus | null>
     error: ShallowRef<any>
     execute: (throwOnFailed?: boolean) => void
-  } = useFetch(API_URI + 'api/status')
+  } = useFetch(statusURI, { credentials: 'include', refetch: true })
     .get()
     .json()
 
-  const eventSource = new EventSource(API_URI + 'api/sse')
-  eventSource.onerror = function (/* event: Event */) {
-    // TODO: Add reconnect if needed
-    //console.error("SSE Error: ", event);
-  }
-  eventSource.onmessage = function (messageEvent: MessageEvent) {
-    const event = messageEvent.data
-    const is_interesting = INTERESTING_EVENTS_RE.test(event)
-    if (is_interesting) {
-      configExecute()
-      statusExecute()
+  // Events are per site, so reconnect whenever the selected site changes
+  function connectEvents(): EventSource {
+    const eventSource = new EventSource(API_URI + 'api/sse' + siteQuery.value, {
+      withCredentials: true,
+    })
+    eventSource.onerror = function (/* event: Event */) {
+      // TODO: Add reconnect if needed
+      //console.error("SSE Error: ", event);
+    }
+    eventSource.onmessage = function (messageEvent: MessageEvent) {
+      const event = messageEvent.data
+      const is_interesting = INTERESTING_EVENTS_RE.test(event)
+      if (is_interesting) {
+        configExecute()
+        statusExecute()
+      }
     }
+    return eventSource
   }
 
+  let eventSource = connectEvents()
+  watch(siteQuery, () => {
+    eventSource.close()
+    eventSource = connectEvents()
+  })
+
   return { config, configError, status, statusError }
 })
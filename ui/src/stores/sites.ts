@@ -0,0 +1,30 @@
+import { useFetch } from '@vueuse/core'
+import { defineStore } from 'pinia'
+import { type Ref, type ShallowRef, computed, ref } from 'vue'
+
+import { API_URI } from '@/constants'
+import type { Site } from '@/models.ts'
+
+export const DEFAULT_SITE = 'default'
+
+export const useSites = defineStore('sites', () => {
+  const {
+    data: sites,
+    error,
+  }: {
+    data: ShallowRef<Site[] | null>
+    error: ShallowRef<any>
+  } = useFetch(API_URI + 'api/sites', { credentials: 'include' })
+    .get()
+    .json()
+
+  // Every other route is scoped to the selected site
+  const selected: Ref<string> = ref(DEFAULT_SITE)
+  const siteQuery = computed(() => '?site=' + encodeURIComponent(selected.value))
+
+  function select(site: string) {
+    selected.value = site
+  }
+
+  return { sites, error, selected, siteQuery, select }
+})
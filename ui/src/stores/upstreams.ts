@@ -1,11 +1,15 @@
 import { useFetch } from '@vueuse/core'
-import { defineStore } from 'pinia'
-import type { ShallowRef } from 'vue'
+import { defineStore, storeToRefs } from 'pinia'
+import { type ShallowRef, computed, watch } from 'vue'
 
 import { API_URI } from '@/constants'
 import type { Upstream } from '@/models.ts'
+import { useSites } from '@/stores/sites.ts'
 
 export const useUpstreams = defineStore('upstreams', () => {
+  const { siteQuery } = storeToRefs(useSites())
+  const upstreamsURI = computed(() => API_URI + 'api/upstreams' + siteQuery.value)
+
   const {
     data,
     error,
@@ -14,12 +18,31 @@ export const useUpstreams = defineStore('upstreams', () => {
     data: ShallowRef<Upstream[] | null>
     error: ShallowRef<any>
     execute: (throwOnFailed?: boolean) => Promise<any>
-  } = useFetch(API_URI + 'api/upstreams')
+  } = useFetch(upstreamsURI, { credentials: 'include', refetch: true })
     .get()
     .json()
 
-  // WORKAROUND: Refetch every 10 seconds until signals are setup for upstreams
-  setInterval(execute, 10 * 1000)
+  // Live statistics are pushed every second, and are per site, so reconnect whenever the selected
+  // site changes
+  function connectEvents(): EventSource {
+    const eventSource = new EventSource(API_URI + 'api/upstreams/sse' + siteQuery.value, {
+      withCredentials: true,
+    })
+    eventSource.onerror = function (/* event: Event */) {
+      // Pick up any changes missed while the events were down
+      execute()
+    }
+    eventSource.onmessage = function (messageEvent: MessageEvent) {
+      data.value = JSON.parse(messageEvent.data)
+    }
+    return eventSource
+  }
+
+  let eventSource = connectEvents()
+  watch(siteQuery, () => {
+    eventSource.close()
+    eventSource = connectEvents()
+  })
 
   return { upstreams: data, error }
 })